@@ -0,0 +1,84 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Queries sub-slices of one large buffer with `knn_with`, and checks that nothing the size of a query is allocated:
+//!
+//! ```text
+//! cargo run --release -p grandma --example borrowed_query_bench
+//! ```
+//!
+//! The points only vary in their first two coordinates, so the nodes stay small and no other buffer the query makes is
+//! the size of a query vector. An allocation of exactly that size would be a copy of the query.
+
+extern crate grandma;
+extern crate pointcloud;
+mod common;
+use common::*;
+use grandma::query_tools::QueryContext;
+use grandma::*;
+use pointcloud::*;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+const DIM: usize = 613;
+
+fn main() {
+    WATCHED_SIZE.store(DIM * std::mem::size_of::<f32>(), Ordering::Relaxed);
+    let mut random = lcg(12345);
+    let (count, k, queries) = (5_000, 10, 2_000);
+    let mut data = vec![0.0; count * DIM];
+    for point in data.chunks_mut(DIM) {
+        point[0] = random();
+        point[1] = random();
+    }
+    // The queries are laid end to end, with an offset so they don't start on a boundary of their own
+    let mut buffer = vec![0.0; queries * DIM + 3];
+    for query in buffer[3..].chunks_mut(DIM) {
+        query[0] = random();
+        query[1] = random();
+    }
+    let labels = vec![0.0; count];
+    let point_cloud = PointCloud::<L2>::simple_from_ram(Box::from(data), DIM, Box::from(labels), 1).unwrap();
+    let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+    let reader = tree.reader();
+    let mut context = QueryContext::new();
+    // Warm the context so its buffers are allocated before counting
+    reader.knn_with(&mut context, &buffer[3..3 + DIM], k).unwrap();
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let query_sized = WATCHED.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut total = 0;
+    for i in 0..queries {
+        let query = &buffer[3 + i * DIM..3 + (i + 1) * DIM];
+        total += reader.knn_with(&mut context, query, k).unwrap()[0].1;
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    let query_sized = WATCHED.load(Ordering::Relaxed) - query_sized;
+
+    println!(
+        "knn_with on borrowed slices: {:?}, {:.1} allocations a query, {} the size of a query (checksum {})",
+        elapsed,
+        allocations as f64 / queries as f64,
+        query_sized,
+        total
+    );
+    assert_eq!(query_sized, 0, "A query vector was copied");
+}
@@ -0,0 +1,75 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! What the benches share, an allocator that counts what's allocated and a small LCG so every run uses the same points.
+//! Each bench takes what it needs with `mod common;`.
+#![allow(dead_code)]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The allocations made so far, a `realloc` counts as one.
+pub static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+/// The bytes asked for so far, a `realloc` counts its new size.
+pub static BYTES: AtomicUsize = AtomicUsize::new(0);
+/// The size in bytes that `WATCHED` counts the allocations of, 0 counts none.
+pub static WATCHED_SIZE: AtomicUsize = AtomicUsize::new(0);
+/// The allocations made so far of exactly `WATCHED_SIZE` bytes.
+pub static WATCHED: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts into the statics above and hands everything to the system allocator.
+pub struct CountingAllocator;
+
+impl CountingAllocator {
+    fn count(size: usize) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(size, Ordering::Relaxed);
+        if size == WATCHED_SIZE.load(Ordering::Relaxed) {
+            WATCHED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        CountingAllocator::count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        CountingAllocator::count(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Numbers in `[0, 1)` from a small LCG, the same ones for the same seed.
+pub fn lcg(seed: u32) -> impl FnMut() -> f32 {
+    let mut state = seed;
+    move || {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        (state >> 8) as f32 / (1 << 24) as f32
+    }
+}
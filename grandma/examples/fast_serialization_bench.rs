@@ -0,0 +1,87 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Saves and loads many small trees with the protobuf of `to_bytes` and with the bincode of `save_fast`:
+//!
+//! ```text
+//! cargo run --release -p grandma --features bincode --example fast_serialization_bench
+//! ```
+//!
+//! The point clouds the loads need are made before the clock starts, so only the decoding and the loading of the nodes
+//! is timed.
+
+extern crate grandma;
+extern crate pointcloud;
+use grandma::*;
+use pointcloud::*;
+use std::time::{Duration, Instant};
+
+const DIM: usize = 8;
+
+fn main() {
+    let mut state: u32 = 12345;
+    let mut random = || {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        (state >> 8) as f32 / (1 << 24) as f32
+    };
+    let (trees, count) = (200, 500);
+    let data: Vec<Vec<f32>> = (0..trees).map(|_i| (0..count * DIM).map(|_j| random()).collect()).collect();
+    let point_cloud = |i: usize| {
+        PointCloud::<L2>::simple_from_ram(Box::from(data[i].clone()), DIM, Box::from(vec![0.0; count]), 1).unwrap()
+    };
+    let built: Vec<CoverTreeWriter<L2>> = (0..trees)
+        .map(|i| CoverTreeBuilder::new().set_verbosity(0).build(point_cloud(i)).unwrap())
+        .collect();
+
+    let time = |name: &str, save: &dyn Fn(&CoverTreeWriter<L2>) -> Vec<u8>, load: &dyn Fn(&[u8], PointCloud<L2>)| {
+        let start = Instant::now();
+        let encoded: Vec<Vec<u8>> = built.iter().map(save).collect();
+        let saving = start.elapsed();
+        let clouds: Vec<PointCloud<L2>> = (0..trees).map(&point_cloud).collect();
+        let mut loading = Duration::new(0, 0);
+        for (bytes, cloud) in encoded.iter().zip(clouds) {
+            let start = Instant::now();
+            load(bytes, cloud);
+            loading += start.elapsed();
+        }
+        let size: usize = encoded.iter().map(|bytes| bytes.len()).sum();
+        println!(
+            "{}: saved {} trees in {:?}, loaded them in {:?}, {} bytes a tree",
+            name,
+            trees,
+            saving,
+            loading,
+            size / trees
+        );
+    };
+    time(
+        "protobuf",
+        &|tree| tree.to_bytes().unwrap(),
+        &|bytes, cloud| {
+            CoverTreeWriter::from_bytes(bytes, cloud).unwrap();
+        },
+    );
+    time(
+        "bincode",
+        &|tree| tree.save_fast().unwrap(),
+        &|bytes, cloud| {
+            CoverTreeWriter::load_fast(bytes, cloud).unwrap();
+        },
+    );
+}
@@ -0,0 +1,73 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Counts the allocations and times `knn_indices` against `knn_names` on a random tree:
+//!
+//! ```text
+//! cargo run --release -p grandma --example knn_indices_bench
+//! ```
+
+extern crate grandma;
+extern crate pointcloud;
+mod common;
+use common::*;
+use grandma::*;
+use pointcloud::*;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+fn main() {
+    let mut random = lcg(12345);
+    let (count, dim, k, queries) = (20_000, 8, 10, 2_000);
+    let data: Vec<f32> = (0..count * dim).map(|_| random()).collect();
+    let queries: Vec<Vec<f32>> = (0..queries).map(|_| (0..dim).map(|_| random()).collect()).collect();
+    let labels = vec![0.0; count];
+    let point_cloud = PointCloud::<L2>::simple_from_ram(Box::from(data), dim, Box::from(labels), 1).unwrap();
+    let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+    let reader = tree.reader();
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut total = 0;
+    for query in &queries {
+        total += reader.knn_indices(query, k).unwrap()[0].0;
+    }
+    let indices_time = start.elapsed();
+    let indices_allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for query in &queries {
+        total += reader.knn_names(query, k).unwrap()[0].1.len();
+    }
+    let names_time = start.elapsed();
+    let names_allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    println!(
+        "knn_indices: {:?}, {:.1} allocations a query",
+        indices_time,
+        indices_allocations as f64 / queries.len() as f64
+    );
+    println!(
+        "knn_names:   {:?}, {:.1} allocations a query (checksum {})",
+        names_time,
+        names_allocations as f64 / queries.len() as f64,
+        total
+    );
+}
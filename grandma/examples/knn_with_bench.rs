@@ -0,0 +1,79 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Counts the allocations and times `knn` against `knn_with` and a reused `QueryContext` on a random tree, and checks
+//! that they give the same results:
+//!
+//! ```text
+//! cargo run --release -p grandma --example knn_with_bench
+//! ```
+
+extern crate grandma;
+extern crate pointcloud;
+mod common;
+use common::*;
+use grandma::query_tools::QueryContext;
+use grandma::*;
+use pointcloud::*;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+fn main() {
+    let mut random = lcg(12345);
+    let (count, dim, k, queries) = (20_000, 8, 10, 2_000);
+    let data: Vec<f32> = (0..count * dim).map(|_| random()).collect();
+    let queries: Vec<Vec<f32>> = (0..queries).map(|_| (0..dim).map(|_| random()).collect()).collect();
+    let labels = vec![0.0; count];
+    let point_cloud = PointCloud::<L2>::simple_from_ram(Box::from(data), dim, Box::from(labels), 1).unwrap();
+    let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+    let reader = tree.reader();
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut expected = Vec::with_capacity(queries.len());
+    for query in &queries {
+        expected.push(reader.knn(query, k).unwrap());
+    }
+    let knn_time = start.elapsed();
+    let knn_allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    let mut context = QueryContext::new();
+    // The first query sizes the context's buffers
+    reader.knn_with(&mut context, &queries[0], k).unwrap();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut same = true;
+    for (query, expected) in queries.iter().zip(&expected) {
+        same &= reader.knn_with(&mut context, query, k).unwrap() == &expected[..];
+    }
+    let with_time = start.elapsed();
+    let with_allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    assert!(same, "knn_with gave different results to knn");
+
+    println!(
+        "knn:      {:?}, {:.1} allocations a query",
+        knn_time,
+        knn_allocations as f64 / queries.len() as f64
+    );
+    println!(
+        "knn_with: {:?}, {:.1} allocations a query",
+        with_time,
+        with_allocations as f64 / queries.len() as f64
+    );
+}
@@ -0,0 +1,77 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Times `nearest` against `knn(query, 1)` over a large batch of queries on a random tree, counting their
+//! allocations, and checks that they find the same distances:
+//!
+//! ```text
+//! cargo run --release -p grandma --example nearest_bench
+//! ```
+
+extern crate grandma;
+extern crate pointcloud;
+mod common;
+use common::*;
+use grandma::*;
+use pointcloud::*;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+fn main() {
+    let mut random = lcg(12345);
+    let (count, dim, queries) = (50_000, 8, 5_000);
+    let data: Vec<f32> = (0..count * dim).map(|_| random()).collect();
+    let queries: Vec<Vec<f32>> = (0..queries).map(|_| (0..dim).map(|_| random()).collect()).collect();
+    let labels = vec![0.0; count];
+    let point_cloud = PointCloud::<L2>::simple_from_ram(Box::from(data), dim, Box::from(labels), 1).unwrap();
+    let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+    let reader = tree.reader();
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut knn_distances = Vec::with_capacity(queries.len());
+    for query in &queries {
+        knn_distances.push(reader.knn(query, 1).unwrap()[0].0);
+    }
+    let knn_time = start.elapsed();
+    let knn_allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut nearest_distances = Vec::with_capacity(queries.len());
+    for query in &queries {
+        nearest_distances.push(reader.nearest(query).unwrap().unwrap().0);
+    }
+    let nearest_time = start.elapsed();
+    let nearest_allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    assert_eq!(knn_distances, nearest_distances);
+
+    println!(
+        "knn(query, 1): {:?}, {:.0} queries a second, {:.1} allocations a query",
+        knn_time,
+        queries.len() as f64 / knn_time.as_secs_f64(),
+        knn_allocations as f64 / queries.len() as f64
+    );
+    println!(
+        "nearest:       {:?}, {:.0} queries a second, {:.1} allocations a query",
+        nearest_time,
+        queries.len() as f64 / nearest_time.as_secs_f64(),
+        nearest_allocations as f64 / queries.len() as f64
+    );
+}
@@ -0,0 +1,81 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Times the KNN queries with and without `CoverTreeReader::set_rank_singletons` on a low dimensional tree that's
+//! mostly singletons, and counts what they allocate with a reused `QueryContext`:
+//!
+//! ```text
+//! cargo run --release -p grandma --example rank_singletons_bench
+//! ```
+
+extern crate grandma;
+extern crate pointcloud;
+mod common;
+use common::*;
+use grandma::query_tools::QueryContext;
+use grandma::*;
+use pointcloud::*;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+fn run(reader: &CoverTreeReader<L2>, queries: &[Vec<f32>], k: usize) -> (Duration, usize, f32) {
+    let mut context = QueryContext::new();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut total = 0.0;
+    for query in queries {
+        total += reader.knn_with(&mut context, query, k).unwrap()[k - 1].0;
+    }
+    (start.elapsed(), ALLOCATIONS.load(Ordering::Relaxed) - allocations, total)
+}
+
+fn main() {
+    let mut random = lcg(12345);
+    let (count, dim, k, queries) = (100_000, 3, 10, 5_000);
+    let data: Vec<f32> = (0..count * dim).map(|_| random()).collect();
+    let queries: Vec<Vec<f32>> = (0..queries).map(|_| (0..dim).map(|_| random()).collect()).collect();
+    let labels = vec![0.0; count];
+    let point_cloud = PointCloud::<L2>::simple_from_ram(Box::from(data), dim, Box::from(labels), 1).unwrap();
+    let tree = CoverTreeBuilder::new()
+        .set_verbosity(0)
+        .set_cutoff(50)
+        .build(point_cloud)
+        .unwrap();
+    let mut reader = tree.reader();
+
+    // Warm up the caches and the context's buffers
+    run(&reader, &queries[..100], k);
+    let (plain_time, plain_allocations, plain_total) = run(&reader, &queries, k);
+    reader.set_rank_singletons(true);
+    run(&reader, &queries[..100], k);
+    let (ranked_time, ranked_allocations, ranked_total) = run(&reader, &queries, k);
+    assert_eq!(plain_total, ranked_total);
+
+    println!(
+        "distances: {:?}, {:.1} allocations a query",
+        plain_time,
+        plain_allocations as f64 / queries.len() as f64
+    );
+    println!(
+        "ranks:     {:?}, {:.1} allocations a query (checksum {})",
+        ranked_time,
+        ranked_allocations as f64 / queries.len() as f64,
+        ranked_total
+    );
+}
@@ -0,0 +1,73 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Inserts a big batch into two copies of a tree, one with `reserve` called first, and counts the allocations and the
+//! bytes allocated during each `insert_batch`:
+//!
+//! ```text
+//! cargo run --release -p grandma --example reserve_bench
+//! ```
+//!
+//! The reserved tree should make fewer allocations, the maps of its layers don't have to grow as the new nodes go in.
+//! The rest of the allocations, the nodes themselves and the copy of the point cloud, are the same for both.
+
+extern crate grandma;
+extern crate pointcloud;
+mod common;
+use common::*;
+use grandma::*;
+use pointcloud::*;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+const DIM: usize = 8;
+
+fn main() {
+    let mut random = lcg(12345);
+    let (count, added) = (20_000, 100_000);
+    let data: Vec<f32> = (0..count * DIM).map(|_i| random()).collect();
+    let batch: Vec<(String, Vec<f32>)> = (0..added)
+        .map(|i| (format!("new {}", i), (0..DIM).map(|_j| random()).collect()))
+        .collect();
+    let point_cloud = || {
+        PointCloud::<L2>::simple_from_ram(Box::from(data.clone()), DIM, Box::from(vec![0.0; count]), 1).unwrap()
+    };
+
+    for reserved in &[false, true] {
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud()).unwrap();
+        if *reserved {
+            tree.reserve(added);
+        }
+        let (allocations, bytes) = (ALLOCATIONS.load(Ordering::Relaxed), BYTES.load(Ordering::Relaxed));
+        let start = Instant::now();
+        tree.insert_batch(&batch).unwrap();
+        let elapsed = start.elapsed();
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+        let bytes = BYTES.load(Ordering::Relaxed) - bytes;
+        println!(
+            "insert_batch of {} points into {}, {}: {:?}, {} allocations, {} MB allocated",
+            added,
+            count,
+            if *reserved { "reserved first" } else { "not reserved" },
+            elapsed,
+            allocations,
+            bytes / (1 << 20)
+        );
+    }
+}
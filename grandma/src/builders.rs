@@ -21,26 +21,63 @@ use crate::*;
 use data_caches::*;
 use layer::*;
 use node::*;
+use tree::{check_resolution, first_insertion};
 use pbr::ProgressBar;
 //use pointcloud::*;
 use std::cmp::{max, min};
 use std::sync::{atomic, Arc};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 
 use crossbeam_channel::{unbounded, Receiver, Sender};
-use errors::MalwareBrotResult;
+use errors::{MalwareBrotError, MalwareBrotResult};
 
 use std::time::Instant;
 
+/// A split node on its way to the tree, with its address and the groups of bitwise equal points it found.
+type SplitNode<T = f32> = (i32, PointIndex, CoverNode<T>, Vec<Vec<PointIndex>>);
+
 #[derive(Debug)]
-struct BuilderNode {
+struct BuilderNode<T: Scalar = f32> {
     scale_index: i32,
-    covered: CoveredData,
+    covered: CoveredData<T>,
 }
 
-impl BuilderNode {
-    fn new<M: Metric>(parameters: &CoverTreeParameters<M>) -> MalwareBrotResult<BuilderNode> {
+impl<T: Scalar> BuilderNode<T> {
+    fn new<M: Metric<Scalar = T>>(parameters: &CoverTreeParameters<M>) -> MalwareBrotResult<BuilderNode<T>> {
         let covered = CoveredData::new(&parameters.point_cloud)?;
-        let scale_index = (covered.max_distance()).log(parameters.scale_base).ceil() as i32;
+        // A cloud of copies of one point has no spread to take the log of, the root is just a leaf at the resolution
+        let max_distance = covered.max_distance();
+        let scale_index = if max_distance > T::zero() {
+            max_distance.log(parameters.scale_base).ceil().as_f64() as i32
+        } else {
+            parameters.resolution
+        };
+        Ok(BuilderNode {
+            scale_index,
+            covered,
+        })
+    }
+
+    /// Starts a subtree that covers just the given points. Degenerate coverings (one point, or all copies of the center)
+    /// have no meaningful scale, so they start at the resolution and become leaves.
+    fn from_indexes<M: Metric<Scalar = T>>(
+        parameters: &CoverTreeParameters<M>,
+        center_index: PointIndex,
+        indexes: Vec<PointIndex>,
+    ) -> MalwareBrotResult<BuilderNode<T>> {
+        let covered = CoveredData::from_indexes(center_index, indexes, &parameters.point_cloud)?;
+        let max_distance = covered.max_distance();
+        let scale_index = if max_distance > T::zero() {
+            max(
+                max_distance.log(parameters.scale_base).ceil().as_f64() as i32,
+                parameters.resolution,
+            )
+        } else {
+            parameters.resolution
+        };
         Ok(BuilderNode {
             scale_index,
             covered,
@@ -52,18 +89,18 @@ impl BuilderNode {
         (self.scale_index, self.covered.center_index)
     }
 
-    fn split_parallel<M: Metric>(
+    fn split_parallel<M: Metric<Scalar = T>>(
         self,
         parameters: &Arc<CoverTreeParameters<M>>,
-        node_sender: &Arc<Sender<MalwareBrotResult<(i32, PointIndex, CoverNode)>>>,
+        node_sender: &Arc<Sender<MalwareBrotResult<SplitNode<T>>>>,
     ) {
         let parameters = Arc::clone(parameters);
         let node_sender = Arc::clone(node_sender);
         rayon::spawn(move || {
             let (si, pi) = self.address();
             match self.split(&parameters) {
-                Ok((new_node, mut new_nodes)) => {
-                    node_sender.send(Ok((si, pi, new_node))).unwrap();
+                Ok((new_node, mut new_nodes, duplicates)) => {
+                    node_sender.send(Ok((si, pi, new_node, duplicates))).unwrap();
                     while let Some(node) = new_nodes.pop() {
                         node.split_parallel(&parameters, &node_sender);
                     }
@@ -73,10 +110,12 @@ impl BuilderNode {
         });
     }
 
-    fn split<M: Metric>(
+    /// Splits this node into a `CoverNode` and the nodes for its children. When the parameters ask for it, a node that
+    /// ends up a leaf also returns the groups of its points that are equal bit for bit, see `build_report_duplicates`.
+    fn split<M: Metric<Scalar = T>>(
         self,
         parameters: &Arc<CoverTreeParameters<M>>,
-    ) -> MalwareBrotResult<(CoverNode, Vec<BuilderNode>)> {
+    ) -> MalwareBrotResult<(CoverNode<T>, Vec<BuilderNode<T>>, Vec<Vec<PointIndex>>)> {
         //println!("=====================");
         //println!("Splitting node with address {:?} and covered: {:?}", self.address(),self.covered);
 
@@ -85,18 +124,24 @@ impl BuilderNode {
         let mut node = CoverNode::new((scale_index, covered.center_index));
         let radius = covered.max_distance();
         let mut new_nodes = Vec::new();
+        let mut duplicates = Vec::new();
         node.set_radius(radius);
         /* Occasionally there's a small cluster split off of at a low resolution.
         This brings the scale-index down/resolution up quickly, locally.
         */
         if covered.len() <= parameters.cutoff || scale_index < parameters.resolution {
             //println!("== This is getting cut down by parameters ==");
+            // Equal points stay together all the way down, whichever center they're handed to they're the same distance
+            // from it, so each group ends up in one leaf
+            if parameters.report_duplicates {
+                duplicates = covered.bitwise_duplicates(&parameters.point_cloud)?;
+            }
             node.insert_singletons(covered.to_indexes());
         } else {
             let next_scale_index = min(
                 scale_index - 1,
                 max(
-                    radius.log(parameters.scale_base).ceil() as i32,
+                    radius.log(parameters.scale_base).ceil().as_f64() as i32,
                     parameters.resolution,
                 ),
             );
@@ -112,6 +157,25 @@ impl BuilderNode {
             //println!("\tCovered: {:?}", close);
             //println!("\tNot Covered: {:?}", fars);
 
+            /*
+            We have the core loop that makes new centers. While there are points further than next_scale from
+            every center so far we pick a new one. The UncoveredData is responsible for picking new centers each
+            time (to ensure it always returns a valid CoveredData).
+            */
+            let mut clusters = vec![close];
+            while fars.len() > 0 {
+                clusters.push(fars.pick_center(next_scale, parameters.deterministic, &parameters.point_cloud)?);
+            }
+            if parameters.insertion_strategy == InsertionStrategy::NearestParent {
+                assign_to_nearest(&mut clusters, &parameters.point_cloud)?;
+            }
+
+            /*
+            First we make the covered child. This child has the same center as its parent and it
+            covers the points that are in the "close" set.
+            */
+            let mut clusters = clusters.into_iter();
+            let close = clusters.next().unwrap();
             node.insert_nested_child(next_scale_index, close.len())?;
             let new_node = BuilderNode {
                 scale_index: next_scale_index,
@@ -121,22 +185,8 @@ impl BuilderNode {
             parameters
                 .total_nodes
                 .fetch_add(1, atomic::Ordering::SeqCst);
-            /*
-            First we make the covered child. This child has the same center as it's parent and it
-            covers the points that are in the "close" set.
-            */
 
-            /*
-            We have the core loop that makes new points. We check that the new_fars' exist (split
-            returns None if there arn't any points more than next_scale from the center), then if
-            it does we split it again.
-
-            The DistCache is responsible for picking new centers each time there's a split (to
-            ensure it always returns a valid DistCache).
-            */
-
-            while fars.len() > 0 {
-                let new_close = fars.pick_center(next_scale, &parameters.point_cloud)?;
+            for new_close in clusters {
                 //println!("\t\t [{}] New Covered: {:?}",split_count, new_close);
                 if new_close.len() == 1 && parameters.use_singletons {
                     /*
@@ -173,17 +223,394 @@ impl BuilderNode {
         node.update_metasummary(&parameters.point_cloud)?;
         // This node is done, send it in
         //println!("=====================");
+        Ok((node, new_nodes, duplicates))
+    }
+}
+
+/// Nodes covering fewer points than this are split in ram by `build_external`, bigger ones are split from their files.
+pub const EXTERNAL_RESIDENT_POINTS: usize = 1 << 20;
+
+/// How many records `build_external` reads, and computes distances for, at once when it streams a file.
+const SPILL_CHUNK: usize = 1 << 16;
+
+/// How many new centers `build_external` picks in each pass over a node's far points. Each has a file open while the
+/// pass runs.
+const SPILL_CENTERS: usize = 64;
+
+/// A record is the point index as a little endian `u64`, then its distance to the center as a little endian scalar of
+/// the tree's type, so 12 bytes for `f32` and 16 for `f64`.
+fn spill_record_bytes<T: Scalar>() -> usize {
+    8 + T::WIDTH as usize
+}
+
+/// The directory `build_external` keeps its files in. It's made fresh for each build and removed, with everything in
+/// it, when this is dropped, so the files are cleaned up however the build ends.
+struct SpillDir {
+    path: PathBuf,
+    file_count: usize,
+}
+
+impl SpillDir {
+    fn new(temp_dir: &Path) -> MalwareBrotResult<SpillDir> {
+        let path = temp_dir.join(format!("grandma_build_{}", rand::random::<u64>()));
+        fs::create_dir_all(&path)?;
+        Ok(SpillDir {
+            path,
+            file_count: 0,
+        })
+    }
+
+    fn create<T: Scalar>(&mut self) -> MalwareBrotResult<SpillWriter<T>> {
+        self.file_count += 1;
+        let path = self.path.join(format!("{}.spill", self.file_count));
+        let file = BufWriter::new(File::create(&path)?);
+        Ok(SpillWriter {
+            path,
+            file,
+            len: 0,
+            max_distance: T::neg_infinity(),
+        })
+    }
+}
+
+impl Drop for SpillDir {
+    fn drop(&mut self) {
+        // Nothing can be done about a failure here, and the build's own result matters more
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+struct SpillWriter<T: Scalar = f32> {
+    path: PathBuf,
+    file: BufWriter<File>,
+    len: usize,
+    max_distance: T,
+}
+
+impl<T: Scalar> SpillWriter<T> {
+    fn push(&mut self, pi: PointIndex, dist: T) -> io::Result<()> {
+        self.file.write_all(&pi.to_le_bytes())?;
+        if T::WIDTH == 8 {
+            self.file.write_all(&dist.as_f64().to_le_bytes())?;
+        } else {
+            self.file.write_all(&(dist.as_f64() as f32).to_le_bytes())?;
+        }
+        self.len += 1;
+        self.max_distance = self.max_distance.max(dist);
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<SpillFile<T>> {
+        self.file.flush()?;
+        Ok(SpillFile {
+            path: self.path,
+            len: self.len,
+            max_distance: self.max_distance,
+        })
+    }
+}
+
+/// A finished file of records, it's removed by whoever reads it last.
+struct SpillFile<T: Scalar = f32> {
+    path: PathBuf,
+    len: usize,
+    max_distance: T,
+}
+
+impl<T: Scalar> SpillFile<T> {
+    /// Calls `f` on the indexes and distances of the records in order, `SPILL_CHUNK` at a time.
+    fn for_each_chunk<F>(&self, mut f: F) -> MalwareBrotResult<()>
+    where
+        F: FnMut(&[PointIndex], &[T]) -> MalwareBrotResult<()>,
+    {
+        let record_bytes = spill_record_bytes::<T>();
+        let mut file = BufReader::new(File::open(&self.path)?);
+        let mut buf = vec![0u8; SPILL_CHUNK * record_bytes];
+        let mut indexes = Vec::with_capacity(SPILL_CHUNK);
+        let mut dists = Vec::with_capacity(SPILL_CHUNK);
+        let mut remaining = self.len;
+        while remaining > 0 {
+            let count = min(remaining, SPILL_CHUNK);
+            let bytes = &mut buf[..count * record_bytes];
+            file.read_exact(bytes)?;
+            indexes.clear();
+            dists.clear();
+            for record in bytes.chunks_exact(record_bytes) {
+                let (pi, dist) = decode_record(record);
+                indexes.push(pi);
+                dists.push(dist);
+            }
+            f(&indexes, &dists)?;
+            remaining -= count;
+        }
+        Ok(())
+    }
+
+    fn record_at(&self, position: usize) -> MalwareBrotResult<(PointIndex, T)> {
+        let record_bytes = spill_record_bytes::<T>();
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start((position * record_bytes) as u64))?;
+        let mut record = vec![0u8; record_bytes];
+        file.read_exact(&mut record)?;
+        Ok(decode_record(&record))
+    }
+
+    fn remove(self) -> MalwareBrotResult<()> {
+        Ok(fs::remove_file(&self.path)?)
+    }
+}
+
+/// Writes the points of `indexes` that are within `scale` of the center to `close`, and returns the rest in order.
+fn take_covered<M: Metric>(
+    point_cloud: &PointCloud<M>,
+    center_index: PointIndex,
+    indexes: &[PointIndex],
+    scale: M::Scalar,
+    close: &mut SpillWriter<M::Scalar>,
+) -> MalwareBrotResult<Vec<PointIndex>> {
+    let mut uncovered = Vec::new();
+    if indexes.is_empty() {
+        return Ok(uncovered);
+    }
+    let dists = point_cloud.distances_to_point_index(center_index, indexes)?;
+    for (pi, dist) in indexes.iter().zip(dists) {
+        if dist < scale {
+            close.push(*pi, dist)?;
+        } else {
+            uncovered.push(*pi);
+        }
+    }
+    Ok(uncovered)
+}
+
+fn decode_record<T: Scalar>(record: &[u8]) -> (PointIndex, T) {
+    let mut pi = [0u8; 8];
+    pi.copy_from_slice(&record[..8]);
+    let dist = if T::WIDTH == 8 {
+        let mut dist = [0u8; 8];
+        dist.copy_from_slice(&record[8..]);
+        f64::from_le_bytes(dist)
+    } else {
+        let mut dist = [0u8; 4];
+        dist.copy_from_slice(&record[8..]);
+        f32::from_le_bytes(dist) as f64
+    };
+    (PointIndex::from_le_bytes(pi), T::from_f64(dist))
+}
+
+/// A `BuilderNode` whose covered points, less the center, are in a file rather than in ram.
+struct SpilledNode<T: Scalar = f32> {
+    scale_index: i32,
+    center_index: PointIndex,
+    file: SpillFile<T>,
+}
+
+impl<T: Scalar> SpilledNode<T> {
+    /// The root covering the whole point cloud, with the same center and scale index `BuilderNode::new` picks.
+    fn root<M: Metric<Scalar = T>>(
+        parameters: &CoverTreeParameters<M>,
+        spill_dir: &mut SpillDir,
+    ) -> MalwareBrotResult<SpilledNode<T>> {
+        let point_cloud = &parameters.point_cloud;
+        // The indexes are streamed a chunk at a time, so there's never a list of all of them in ram
+        let mut coverage = point_cloud.iter_reference_indexes();
+        let center_index = coverage.next_back().ok_or_else(empty_cloud)?;
+        let mut writer = spill_dir.create()?;
+        let mut chunk = Vec::with_capacity(SPILL_CHUNK);
+        loop {
+            chunk.clear();
+            chunk.extend(coverage.by_ref().take(SPILL_CHUNK));
+            if chunk.is_empty() {
+                break;
+            }
+            let dists = point_cloud.distances_to_point_index(center_index, &chunk)?;
+            for (pi, dist) in chunk.iter().zip(dists) {
+                writer.push(*pi, dist)?;
+            }
+        }
+        let file = writer.finish()?;
+        let scale_index = if file.max_distance > T::zero() {
+            file.max_distance.log(parameters.scale_base).ceil().as_f64() as i32
+        } else {
+            parameters.resolution
+        };
+        Ok(SpilledNode {
+            scale_index,
+            center_index,
+            file,
+        })
+    }
+
+    #[inline]
+    fn address(&self) -> NodeAddress {
+        (self.scale_index, self.center_index)
+    }
+
+    fn read_back(self) -> MalwareBrotResult<BuilderNode<T>> {
+        let mut coverage = Vec::with_capacity(self.file.len);
+        let mut dists = Vec::with_capacity(self.file.len);
+        self.file.for_each_chunk(|indexes, chunk_dists| {
+            coverage.extend_from_slice(indexes);
+            dists.extend_from_slice(chunk_dists);
+            Ok(())
+        })?;
+        self.file.remove()?;
+        Ok(BuilderNode {
+            scale_index: self.scale_index,
+            covered: CoveredData::from_parts(self.center_index, coverage, dists),
+        })
+    }
+
+    /// The same split as `BuilderNode::split` with `FirstFit`, done a chunk at a time. The children are written to files
+    /// of their own and this node's file is removed.
+    fn split<M: Metric<Scalar = T>>(
+        self,
+        parameters: &Arc<CoverTreeParameters<M>>,
+        spill_dir: &mut SpillDir,
+    ) -> MalwareBrotResult<(CoverNode<T>, Vec<SpilledNode<T>>)> {
+        let point_cloud = &parameters.point_cloud;
+        let scale_index = self.scale_index;
+        let radius = self.file.max_distance;
+        let mut node = CoverNode::new(self.address());
+        let mut new_nodes = Vec::new();
+        node.set_radius(radius);
+        if self.file.len < parameters.cutoff || scale_index < parameters.resolution {
+            self.file.for_each_chunk(|indexes, _dists| {
+                node.insert_singletons(indexes.to_vec());
+                Ok(())
+            })?;
+            self.file.remove()?;
+        } else {
+            let next_scale_index = min(
+                scale_index - 1,
+                max(
+                    radius.log(parameters.scale_base).ceil().as_f64() as i32,
+                    parameters.resolution,
+                ),
+            );
+            let next_scale = parameters.scale_base.powi(next_scale_index);
+
+            // The far points only need their index, the distance to this center means nothing to the new centers
+            let mut close = spill_dir.create()?;
+            let mut fars = spill_dir.create()?;
+            self.file.for_each_chunk(|indexes, dists| {
+                for (pi, dist) in indexes.iter().zip(dists) {
+                    if *dist < next_scale {
+                        close.push(*pi, *dist)?;
+                    } else {
+                        fars.push(*pi, T::zero())?;
+                    }
+                }
+                Ok(())
+            })?;
+            self.file.remove()?;
+            let close = close.finish()?;
+            node.insert_nested_child(next_scale_index, close.len + 1)?;
+            new_nodes.push(SpilledNode {
+                scale_index: next_scale_index,
+                center_index: self.center_index,
+                file: close,
+            });
+            parameters
+                .total_nodes
+                .fetch_add(1, atomic::Ordering::SeqCst);
+
+            // Each pass over the far points picks up to `SPILL_CENTERS` new centers as it goes. The first far point that
+            // none of the pass's centers cover becomes the next one, so a pass covers as much as that many passes of
+            // `UncoveredData::pick_center` with `deterministic` set, and the far points are read once per that many
+            // children rather than once per child.
+            let mut fars = fars.finish()?;
+            while fars.len > 0 {
+                let mut centers: Vec<(PointIndex, SpillWriter<T>)> = Vec::with_capacity(SPILL_CENTERS);
+                let mut new_fars = spill_dir.create()?;
+                fars.for_each_chunk(|indexes, _dists| {
+                    let mut uncovered = indexes.to_vec();
+                    for (center_index, close) in centers.iter_mut() {
+                        uncovered = take_covered(point_cloud, *center_index, &uncovered, next_scale, close)?;
+                    }
+                    while !uncovered.is_empty() && centers.len() < SPILL_CENTERS {
+                        let center_index = uncovered[0];
+                        let mut close = spill_dir.create()?;
+                        uncovered = take_covered(point_cloud, center_index, &uncovered[1..], next_scale, &mut close)?;
+                        centers.push((center_index, close));
+                    }
+                    for pi in uncovered {
+                        new_fars.push(pi, T::zero())?;
+                    }
+                    Ok(())
+                })?;
+                fars.remove()?;
+                fars = new_fars.finish()?;
+                for (center_index, new_close) in centers {
+                    let new_close = new_close.finish()?;
+                    if new_close.len == 0 && parameters.use_singletons {
+                        node.insert_singleton(center_index);
+                        new_close.remove()?;
+                    } else {
+                        node.insert_child((next_scale_index, center_index), new_close.len + 1)?;
+                        new_nodes.push(SpilledNode {
+                            scale_index: next_scale_index,
+                            center_index,
+                            file: new_close,
+                        });
+                        parameters
+                            .total_nodes
+                            .fetch_add(1, atomic::Ordering::SeqCst);
+                    }
+                }
+            }
+            fars.remove()?;
+        }
+
+        if new_nodes.len() == 1 && new_nodes[0].file.len == 0 {
+            node.remove_children();
+            parameters
+                .total_nodes
+                .fetch_sub(1, atomic::Ordering::SeqCst);
+            // The only child is the nested one, covering nothing but this node's own center
+            new_nodes.pop().unwrap().file.remove()?;
+        }
+
+        node.update_metasummary(point_cloud)?;
         Ok((node, new_nodes))
     }
 }
 
-/// A construction object for a covertree.
+/// How the builder hands the points of a node out to its children. The children's centers are picked the same way
+/// under both strategies, they only differ in which child each of the other points ends up under. Both preserve the
+/// cover tree invariants, so queries return the same results, only the structure of the tree differs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InsertionStrategy {
+    /// A point goes under the first child that covers it, the parent's own nested child before the others. This is
+    /// the default and is cheaper to build.
+    FirstFit,
+    /// A point goes under the child whose center is nearest to it. This makes tighter children, with smaller radii,
+    /// at the cost of a distance to every child's center for every point.
+    NearestParent,
+}
+
+/// A construction object for a covertree. Start from `new`, chain the setters for the parameters you need, then call
+/// one of the builds with the point cloud. The metric is the point cloud's. The parameters are checked once, by
+/// `validate`, at the start of every build, so a bad builder fails before any work is done.
 pub struct CoverTreeBuilder {
-    /// See paper or main description, governs the number of children of each node. Higher is more.
-    pub scale_base: f32,
-    /// If a node covers less than or equal to this number of points, it becomes a leaf.
+    /// See paper or main description, governs the number of children of each node. Higher is more. It has to be a
+    /// finite number more than 1, building with anything else is an `InvalidScaleBase` error. Values between 1.3 and 2.0 are common.
+    /// Closer to 1 makes more layers with fewer children each, so a deeper tree that's slower to build but prunes
+    /// more tightly when querying. Larger values make a shallow tree that's faster to build, but each node has more
+    /// children to check.
+    pub scale_base: f64,
+    /// The leaf size. If a node covers less than or equal to this number of points, it becomes a leaf and keeps them as a
+    /// flat list of singletons that a query scans, rather than splitting them into children. The default of 1 splits
+    /// down to single points. Larger values make a shallower tree with fewer nodes to visit, at the cost of a distance
+    /// to every point in each leaf a query reaches. Queries are exact whatever this is, only the work is traded.
     pub cutoff: usize,
-    /// If a node has scale index less than or equal to this, it becomes a leaf
+    /// The minimum level, it caps the depth of the tree. A node with a scale index below this becomes a leaf, so the
+    /// lowest layer is one under it and a node at the resolution splits straight into leaves. The points a leaf covers
+    /// are kept as singletons that a query scans, however many there are, so data with points packed tighter than
+    /// `scale_base` to this power gets a bucket of them rather than ever deeper layers. Queries stay exact.
+    ///
+    /// With the `cutoff`, a node becomes a leaf when either says so. The cutoff stops the split of nodes with few points
+    /// at any level, the resolution stops all of them at the bottom, so a leaf there can have more than `cutoff` points.
     pub resolution: i32,
     /// If you don't want singletons messing with your tree and want everything to be a node or a element of leaf node, make this true. 
     pub use_singletons: bool,
@@ -192,6 +619,17 @@ pub struct CoverTreeBuilder {
     /// Printing verbosity. 2 is the default and gives a progress bar. Still not fully pulled thru the codebase. 
     /// This should be replaced by a logging solution
     pub verbosity: u32,
+    /// How points are handed out to the children of a node, `FirstFit` by default.
+    pub insertion_strategy: InsertionStrategy,
+    /// Whether inserted and query vectors are checked for NaN and infinite coordinates, true by default.
+    pub check_finite: bool,
+    /// How the point cloud keeps the names of the points, `Owned` by default. See `NameStorage`.
+    pub name_storage: NameStorage,
+    /// Whether each new center is the first uncovered point, in the order of the point cloud, rather than a random one.
+    /// False by default. With it the same point cloud always builds the same tree, see `utils::build_deterministic`.
+    pub deterministic: bool,
+    /// The most nodes the tree can hold, `None` by default for no limit. See `with_arena`.
+    pub arena: Option<usize>,
 }
 
 impl CoverTreeBuilder {
@@ -204,20 +642,46 @@ impl CoverTreeBuilder {
             use_singletons: true,
             cluster_min: 5,
             verbosity: 2,
+            insertion_strategy: InsertionStrategy::FirstFit,
+            check_finite: true,
+            name_storage: NameStorage::Owned,
+            deterministic: false,
+            arena: None,
+        }
+    }
+
+    /// Creates a builder with the defaults of `new` for a tree that holds at most `nodes` nodes. The layers' maps are
+    /// sized for all of them when the tree is built, so they don't grow as points go in, and any build or change that
+    /// would take the tree past `nodes` returns `ArenaCapacityExceeded` rather than making room. A refused insert,
+    /// batch, update or removal is undone like a failed `insert_batch`, so the tree is left as it was. Queries don't
+    /// make nodes and work as on any other tree.
+    ///
+    /// This is for a tree that has to stay within a memory budget you know up front, a cache or an embedded index say,
+    /// and the price is that it stops taking points once it's full. It's a fixed number of nodes, not a fixed block of
+    /// memory. The maps are still the two copies of each layer the readers need, see `layer`, each node still has its
+    /// own lists of children and singletons, and a layer gets its share of the room in proportion to the nodes it has
+    /// when the tree is built, so inserts that land somewhere new can still grow one. Each refused change has been
+    /// made and undone by the time it's refused, so it costs as much as one that goes in. A cutoff above 1 puts more
+    /// points in each leaf, so the same number of nodes holds more points. A tree that's saved and loaded has no
+    /// arena.
+    pub fn with_arena(nodes: usize) -> CoverTreeBuilder {
+        CoverTreeBuilder {
+            arena: Some(nodes),
+            ..CoverTreeBuilder::new()
         }
     }
 
-    /// 
-    pub fn set_scale_base(&mut self, x: f32) -> &mut Self {
+    /// Sets the scale base, see the `scale_base` field for the range.
+    pub fn set_scale_base(&mut self, x: f64) -> &mut Self {
         self.scale_base = x;
         self
     }
-    ///
+    /// Sets the leaf size, see the `cutoff` field.
     pub fn set_cutoff(&mut self, x: usize) -> &mut Self {
         self.cutoff = x;
         self
     }
-    ///
+    /// Sets the minimum level, see the `resolution` field.
     pub fn set_resolution(&mut self, x: i32) -> &mut Self {
         self.resolution = x;
         self
@@ -232,57 +696,297 @@ impl CoverTreeBuilder {
         self.verbosity = x;
         self
     }
+    /// Sets the insertion strategy, see `InsertionStrategy`.
+    pub fn set_insertion_strategy(&mut self, x: InsertionStrategy) -> &mut Self {
+        self.insertion_strategy = x;
+        self
+    }
+    /// Turns off the check that inserted and query vectors have no NaN or infinite coordinates, which is a
+    /// `NonFiniteCoordinate` error. Only do this if you've checked the vectors yourself, a NaN distance breaks every
+    /// comparison the queries make. The points the tree is built on aren't checked either way.
+    pub fn set_check_finite(&mut self, x: bool) -> &mut Self {
+        self.check_finite = x;
+        self
+    }
+    /// Picks the first uncovered point as each new center instead of a random one, so that building twice over the
+    /// same point cloud gives the same tree. The tree is as good either way, the covering and separation don't depend
+    /// on which point becomes a center.
+    pub fn set_deterministic(&mut self, x: bool) -> &mut Self {
+        self.deterministic = x;
+        self
+    }
+    /// Interns the names of the points into one shared pool when the tree is built, or leaves each name as a vector of
+    /// its own. Queries return the same names either way. Interning saves more than half of the memory the names take for
+    /// names a few dozen bytes long, but costs a copy of every name at build. See `NameStorage`.
+    pub fn set_name_storage(&mut self, x: NameStorage) -> &mut Self {
+        self.name_storage = x;
+        self
+    }
+    /// Checks the parameters can build a tree, every build does this first. Returns `InvalidScaleBase` if the scale
+    /// base isn't a finite number more than 1, and `CapacityExceeded` if the resolution is too close to the ends of an
+    /// `i32` to fit the layers, see `MAX_LAYERS`.
+    pub fn validate(&self) -> MalwareBrotResult<()> {
+        // Written this way so NaN is rejected too
+        if !(self.scale_base > 1.0 && self.scale_base.is_finite()) {
+            return Err(MalwareBrotError::InvalidScaleBase(self.scale_base));
+        }
+        check_resolution(self.resolution)
+    }
+    /// Gives a freshly built tree the builder's arena, see `with_arena`, and makes room in its layers for the rest of it.
+    fn fit_arena<M: Metric>(&self, mut cover_tree: CoverTreeWriter<M>) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        if let Some(capacity) = self.arena {
+            cover_tree.arena = Some(capacity);
+            cover_tree.check_arena()?;
+            let room = capacity - cover_tree.node_count();
+            cover_tree.reserve(room);
+        }
+        Ok(cover_tree)
+    }
     /// Pass a point cloud object when ready. 
     /// To do, make this point cloud an Arc
     pub fn build<M: Metric>(
         &self,
         point_cloud: PointCloud<M>,
     ) -> MalwareBrotResult<CoverTreeWriter<M>> {
-        let parameters = CoverTreeParameters {
+        Ok(self.build_on(point_cloud, None, None, false)?.0)
+    }
+
+    /// Builds the tree like `build`, calling `progress` with the number of points placed so far and the total each time
+    /// another `every` points are placed, and once more when the build is done. A point is placed when the node it ends
+    /// up in, as a singleton or as the center of a leaf, is written into the tree. Nodes are split on the thread pool
+    /// but written on the calling thread, so `progress` is only ever called from the calling thread, one call at a time.
+    ///
+    /// Many points are placed at once when a leaf is written, so the counts don't go up by exactly `every`. An `every`
+    /// of 0 is treated as 1.
+    pub fn build_with_progress<M: Metric, F: FnMut(usize, usize)>(
+        &self,
+        point_cloud: PointCloud<M>,
+        every: usize,
+        mut progress: F,
+    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        Ok(self.build_on(point_cloud, None, Some((max(every, 1), &mut progress)), false)?.0)
+    }
+
+    /// Builds the tree like `build`, and also returns the names of the points that are duplicates of each other, grouped.
+    /// Only vectors that are equal bit for bit are grouped, so `0.0` and `-0.0` or two vectors a rounding error apart
+    /// aren't duplicates even though they're at distance 0 or close to it. Each group is in index order, the groups are
+    /// in the index order of their first point, and points without a duplicate aren't in any group. The tree is the same
+    /// as the one `build` would give.
+    ///
+    /// The duplicates are found as the leaves are split off, there's no pass over the points of its own. Equal points
+    /// are always the same distance from a center, so they end up in the same leaf, and only the points of a leaf that
+    /// are the same distance from its center are compared bit for bit.
+    pub fn build_report_duplicates<M: Metric>(
+        &self,
+        point_cloud: PointCloud<M>,
+    ) -> MalwareBrotResult<(CoverTreeWriter<M>, Vec<Vec<PointName>>)> {
+        let (tree, mut groups) = self.build_on(point_cloud, None, None, true)?;
+        groups.sort_unstable();
+        let point_cloud = &tree.parameters.point_cloud;
+        let mut duplicates = Vec::with_capacity(groups.len());
+        for group in &groups {
+            let names: Option<Vec<PointName>> =
+                group.iter().map(|i| point_cloud.get_name(i).map(|name| name.to_vec())).collect();
+            duplicates.push(names.ok_or(MalwareBrotError::IndexNotInTree(group[0]))?);
+        }
+        Ok((tree, duplicates))
+    }
+
+    /// Builds the tree on a dedicated rayon thread pool with `threads` threads, rather than the global pool `build` uses.
+    /// If `threads` is 1 the tree is built serially on the calling thread. The knn results are the same either way,
+    /// the structure of the tree may differ as the node centers are picked randomly.
+    pub fn build_parallel<M: Metric>(
+        &self,
+        point_cloud: PointCloud<M>,
+        threads: usize,
+    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        if threads == 1 {
+            self.build_serial(point_cloud)
+        } else {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .map_err(|e| MalwareBrotError::from(e))?;
+            Ok(self.build_on(point_cloud, Some(&pool), None, false)?.0)
+        }
+    }
+
+    /// Builds the tree like `build_serial` would, but keeps the points covered by the nodes it hasn't split yet in files
+    /// under `temp_dir` rather than in ram. A node covering fewer than `EXTERNAL_RESIDENT_POINTS` points is read back and
+    /// split in ram as usual. Bigger nodes are split by streaming their file a chunk at a time, partitioning the points
+    /// into a file per child. Each pass over the points not yet covered picks up to 64 new children as it goes, so a
+    /// node with `k` children is read about `k / 64` times. So the ram used for the intermediate data is bounded by
+    /// `EXTERNAL_RESIDENT_POINTS`, however many points there are. The finished tree and
+    /// the point cloud's names are still held in ram, use a memmapped point cloud so the vectors aren't, see
+    /// `utils::build_external`.
+    ///
+    /// The files go in a directory of their own made inside `temp_dir`, which is removed when the build returns, both on
+    /// success and on an error. Nodes that are split from files hand their points out `FirstFit`, whatever the
+    /// insertion strategy is, as `NearestParent` needs all of a node's points at once, and pick their children's centers
+    /// in file order as if `deterministic` was set. Failing to write or read the
+    /// files is an `IoError`.
+    pub fn build_external<M: Metric, P: AsRef<Path>>(
+        &self,
+        point_cloud: PointCloud<M>,
+        temp_dir: P,
+    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        self.build_external_with(point_cloud, temp_dir.as_ref(), EXTERNAL_RESIDENT_POINTS)
+    }
+
+    fn build_external_with<M: Metric>(
+        &self,
+        point_cloud: PointCloud<M>,
+        temp_dir: &Path,
+        resident_points: usize,
+    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        let start = Instant::now();
+        let parameters = self.parameters(point_cloud)?;
+        let mut spill_dir = SpillDir::new(temp_dir)?;
+        let root = SpilledNode::root(&parameters, &mut spill_dir)?;
+        let parameters = Arc::new(parameters);
+        let mut cover_tree = CoverTreeBuilder::blank_tree(&parameters, root.address())?;
+
+        let now = Instant::now();
+        let mut unsplit = vec![root];
+        while let Some(node) = unsplit.pop() {
+            if node.file.len < resident_points {
+                split_serial(&mut cover_tree, node.read_back()?)?;
+            } else {
+                let (scale_index, point_index) = node.address();
+                let (new_node, new_nodes) = node.split(&parameters, &mut spill_dir)?;
+                unsafe {cover_tree.insert_raw(scale_index, point_index, new_node);}
+                unsplit.extend(new_nodes);
+            }
+        }
+        cover_tree.refresh();
+        if parameters.verbosity > 1 {
+            println!("Finished building externally, took {:?}", now.elapsed());
+        }
+        cover_tree.build_time = Some(start.elapsed());
+        self.fit_arena(cover_tree)
+    }
+
+    fn parameters<M: Metric>(
+        &self,
+        mut point_cloud: PointCloud<M>,
+    ) -> MalwareBrotResult<CoverTreeParameters<M>> {
+        self.validate()?;
+        point_cloud.set_name_storage(self.name_storage);
+        Ok(CoverTreeParameters {
             total_nodes: atomic::AtomicUsize::new(1),
-            scale_base: self.scale_base,
+            scale_base: M::Scalar::from_f64(self.scale_base),
             cutoff: self.cutoff,
             resolution: self.resolution,
             use_singletons: self.use_singletons,
             cluster_min: self.cluster_min,
             point_cloud: point_cloud,
             verbosity: self.verbosity,
-        };
+            insertion_strategy: self.insertion_strategy,
+            check_finite: atomic::AtomicBool::new(self.check_finite),
+            deterministic: self.deterministic,
+            report_duplicates: false,
+        })
+    }
 
-        let root = BuilderNode::new(&parameters)?;
-        let root_address = root.address();
-        let scale_range = root_address.0 - parameters.resolution;
-        let mut layers = Vec::with_capacity(scale_range as usize);
+    fn blank_tree<M: Metric>(
+        parameters: &Arc<CoverTreeParameters<M>>,
+        root_address: NodeAddress,
+    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        let root_layer = parameters.checked_internal_index(root_address.0)?;
+        let mut layers = Vec::with_capacity(root_layer + 1);
         layers.push(CoverLayerWriter::new(parameters.resolution));
-        for i in 0..(scale_range + 1) {
-            layers.push(CoverLayerWriter::new(parameters.resolution + i as i32));
+        for i in 1..(root_layer + 1) {
+            layers.push(CoverLayerWriter::new(parameters.resolution + i as i32 - 1));
         }
+        Ok(CoverTreeWriter {
+            parameters: Arc::clone(parameters),
+            layers,
+            root_address,
+            version: 0,
+            node_versions: HashMap::new(),
+            base_version: 0,
+            build_time: None,
+            removal_mode: RemovalMode::Restructure,
+            tombstones: Arc::new(HashSet::new()),
+            absent: Arc::new(HashSet::new()),
+            insertion_sequences: HashMap::new(),
+            next_insertion: first_insertion(&parameters.point_cloud),
+            point_count: parameters.point_cloud.len(),
+            journal: None,
+            arena: None,
+        })
+    }
+
+    fn build_serial<M: Metric>(
+        &self,
+        point_cloud: PointCloud<M>,
+    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        let start = Instant::now();
+        let parameters = self.parameters(point_cloud)?;
+        let root = BuilderNode::new(&parameters)?;
+        let parameters = Arc::new(parameters);
+        let mut cover_tree = CoverTreeBuilder::blank_tree(&parameters, root.address())?;
+
+        let now = Instant::now();
+        split_serial(&mut cover_tree, root)?;
+        cover_tree.refresh();
+        if parameters.verbosity > 1 {
+            println!("Finished building serially, took {:?}", now.elapsed());
+        }
+        cover_tree.build_time = Some(start.elapsed());
+        self.fit_arena(cover_tree)
+    }
+
+    fn build_on<M: Metric>(
+        &self,
+        point_cloud: PointCloud<M>,
+        pool: Option<&rayon::ThreadPool>,
+        mut progress: Option<(usize, &mut dyn FnMut(usize, usize))>,
+        report_duplicates: bool,
+    ) -> MalwareBrotResult<(CoverTreeWriter<M>, Vec<Vec<PointIndex>>)> {
+        let start = Instant::now();
+        let mut parameters = self.parameters(point_cloud)?;
+        parameters.report_duplicates = report_duplicates;
+
+        let root = BuilderNode::new(&parameters)?;
+        let root_address = root.address();
+        let total_points = root.covered.len();
+        let mut placed_points: usize = 0;
 
         let (node_sender, node_receiver): (
-            Sender<MalwareBrotResult<(i32, PointIndex, CoverNode)>>,
-            Receiver<MalwareBrotResult<(i32, PointIndex, CoverNode)>>,
+            Sender<MalwareBrotResult<SplitNode<M::Scalar>>>,
+            Receiver<MalwareBrotResult<SplitNode<M::Scalar>>>,
         ) = unbounded();
 
         let node_sender = Arc::new(node_sender);
         let parameters = Arc::new(parameters);
-        root.split_parallel(&parameters, &node_sender);
+        // The layers are checked before any splits start, so they never send to a receiver that's been dropped
+        let mut cover_tree = CoverTreeBuilder::blank_tree(&parameters, root_address)?;
+        // Splits spawn their children onto the pool they're running in, so we only need to start the root in the right pool.
+        match pool {
+            Some(pool) => pool.install(|| root.split_parallel(&parameters, &node_sender)),
+            None => root.split_parallel(&parameters, &node_sender),
+        }
         let mut pb = ProgressBar::new(1u64);
         if parameters.verbosity > 1 {
             pb.format("╢▌▌░╟");
         }
 
-        let mut cover_tree = CoverTreeWriter {
-            parameters: Arc::clone(&parameters),
-            layers,
-            root_address,
-        };
-
         let mut inserted_nodes: usize = 0;
+        let mut duplicates = Vec::new();
         let now = Instant::now();
         loop {
             match node_receiver.recv() {
                 Ok(res) => {
-                    let (scale_index, point_index, new_node) = res.unwrap();
+                    let (scale_index, point_index, new_node, node_duplicates) = res.unwrap();
+                    duplicates.extend(node_duplicates);
+                    if let Some((every, callback)) = progress.as_mut() {
+                        let placed = new_node.singleton_len() + if new_node.is_leaf() { 1 } else { 0 };
+                        if (placed_points + placed) / *every > placed_points / *every {
+                            callback(placed_points + placed, total_points);
+                        }
+                        placed_points += placed;
+                    }
                     unsafe {cover_tree.insert_raw(scale_index, point_index, new_node);}
                     inserted_nodes += 1;
                     if parameters.verbosity > 1 {
@@ -297,6 +1001,11 @@ impl CoverTreeBuilder {
                 break;
             }
         }
+        if let Some((every, callback)) = progress {
+            if placed_points % every != 0 {
+                callback(placed_points, total_points);
+            }
+        }
         if parameters.verbosity > 1 {
             println!("\nWriting layers...");
         }
@@ -308,10 +1017,51 @@ impl CoverTreeBuilder {
                 (inserted_nodes as f32) / now.elapsed().as_secs_f32()
             );
         }
-        Ok(cover_tree)
+        cover_tree.build_time = Some(start.elapsed());
+        Ok((self.fit_arena(cover_tree)?, duplicates))
     }
 }
 
+/// Splits the node and all its descendants on the calling thread, writing them into the tree. Does not refresh.
+fn split_serial<M: Metric>(
+    cover_tree: &mut CoverTreeWriter<M>,
+    root: BuilderNode<M::Scalar>,
+) -> MalwareBrotResult<()> {
+    let parameters = Arc::clone(&cover_tree.parameters);
+    let mut unsplit = vec![root];
+    while let Some(node) = unsplit.pop() {
+        let (scale_index, point_index) = node.address();
+        let (new_node, new_nodes, _duplicates) = node.split(&parameters)?;
+        unsafe {cover_tree.insert_raw(scale_index, point_index, new_node);}
+        unsplit.extend(new_nodes);
+    }
+    Ok(())
+}
+
+/// Builds a subtree centered on `center_index` that covers it and `indexes`, and writes it into the tree without refreshing.
+/// Any layers the new subtree needs are added to the tree. Returns the address of the subtree's root, it's up to the caller
+/// to attach it to a parent.
+pub(crate) fn build_subtree<M: Metric>(
+    cover_tree: &mut CoverTreeWriter<M>,
+    center_index: PointIndex,
+    indexes: Vec<PointIndex>,
+) -> MalwareBrotResult<NodeAddress> {
+    let root = BuilderNode::from_indexes(&cover_tree.parameters, center_index, indexes)?;
+    let address = root.address();
+    cover_tree.reserve_layers(address.0)?;
+    split_serial(cover_tree, root)?;
+    Ok(address)
+}
+
+/// The scale index `build_subtree` would give the root of a subtree centered on `center_index` over `indexes`.
+pub(crate) fn subtree_scale_index<M: Metric>(
+    parameters: &CoverTreeParameters<M>,
+    center_index: PointIndex,
+    indexes: &[PointIndex],
+) -> MalwareBrotResult<i32> {
+    Ok(BuilderNode::from_indexes(parameters, center_index, indexes.to_vec())?.address().0)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -334,9 +1084,149 @@ mod tests {
             cluster_min: 0,
             point_cloud,
             verbosity: 0,
+            insertion_strategy: InsertionStrategy::FirstFit,
+            check_finite: atomic::AtomicBool::new(true),
+            deterministic: false,
+            report_duplicates: false,
         })
     }
 
+    #[test]
+    fn rejects_bad_scale_base() {
+        let data = vec![0.0, 1.0, 2.0, 3.0];
+        let labels = vec![0.0; 4];
+        for scale_base in &[1.0, 0.5, -2.0, std::f64::NAN, std::f64::INFINITY] {
+            let point_cloud = PointCloud::<L2>::simple_from_ram(
+                Box::from(data.clone()),
+                1,
+                Box::from(labels.clone()),
+                1,
+            )
+            .unwrap();
+            let mut builder = CoverTreeBuilder::new();
+            builder.set_scale_base(*scale_base).set_verbosity(0);
+            match builder.build(point_cloud) {
+                Err(MalwareBrotError::InvalidScaleBase(_)) => {}
+                _ => panic!("Expected {} to be an invalid scale base", scale_base),
+            }
+        }
+
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_scale_base(1.3).set_verbosity(0);
+        assert!(builder.validate().is_ok());
+        let tree = builder.build_parallel(point_cloud, 1).unwrap();
+        assert_eq!(tree.reader().scale_base(), 1.3);
+    }
+
+    #[test]
+    fn layers_stop_at_the_capacity() {
+        // The center is the last point and the furthest point is exactly 1 away, so the root's scale index is 0
+        let data = vec![0.5, 0.0, 1.0];
+        let labels = vec![0.0; 3];
+        let build = |resolution: i32| {
+            let point_cloud = PointCloud::<L2>::simple_from_ram(
+                Box::from(data.clone()),
+                1,
+                Box::from(labels.clone()),
+                1,
+            )
+            .unwrap();
+            let mut builder = CoverTreeBuilder::new();
+            builder
+                .set_scale_base(2.0)
+                .set_cutoff(1)
+                .set_resolution(resolution)
+                .set_verbosity(0);
+            builder.build(point_cloud)
+        };
+
+        let tree = build(2 - MAX_LAYERS as i32).unwrap();
+        let reader = tree.reader();
+        assert_eq!(reader.root_address().0, 0);
+        assert_eq!(reader.layers().count(), MAX_LAYERS);
+        assert_eq!(reader.knn(&[0.4], 1).unwrap()[0].1, 0);
+
+        match build(1 - MAX_LAYERS as i32) {
+            Err(MalwareBrotError::CapacityExceeded { scale_index: 0, .. }) => {}
+            Err(e) => panic!("Expected the layers to run out, got {:?}", e),
+            Ok(_) => panic!("Expected the layers to run out"),
+        }
+        for resolution in &[i32::min_value(), i32::max_value()] {
+            let mut builder = CoverTreeBuilder::new();
+            builder.set_resolution(*resolution);
+            match builder.validate() {
+                Err(MalwareBrotError::CapacityExceeded { .. }) => {}
+                _ => panic!("Expected {} to leave no room for the layers", resolution),
+            }
+        }
+    }
+
+    #[test]
+    fn leaf_sizes_keep_knn_exact() {
+        let data: Vec<f32> = (0..1000).map(|_i| rand::random::<f32>()).collect();
+        let labels = vec![0.0; 500];
+        let queries: Vec<[f32; 2]> = (0..20).map(|_i| [rand::random::<f32>(), rand::random::<f32>()]).collect();
+        let mut node_counts = Vec::new();
+        for cutoff in &[1, 5, 20, 100] {
+            let point_cloud =
+                PointCloud::<L2>::simple_from_ram(Box::from(data.clone()), 2, Box::from(labels.clone()), 1).unwrap();
+            let tree = CoverTreeBuilder::new().set_cutoff(*cutoff).set_verbosity(0).build(point_cloud).unwrap();
+            let reader = tree.reader();
+            reader.validate().unwrap();
+            node_counts.push(reader.stats().node_count);
+            for query in &queries {
+                let mut expected: Vec<(f32, PointIndex)> = data
+                    .chunks(2)
+                    .enumerate()
+                    .map(|(i, point)| (L2::dense(point, query), i as PointIndex))
+                    .collect();
+                expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                expected.truncate(10);
+                assert_eq!(reader.knn(query, 10).unwrap(), expected);
+            }
+        }
+        // Bigger leaves make fewer nodes
+        assert!(node_counts[3] < node_counts[0]);
+    }
+
+    #[test]
+    fn resolution_caps_the_depth() {
+        // Each point is half as far from 0 as the last, so without a cap every one would get a layer of its own
+        let mut data: Vec<f32> = (0..60).map(|i| 0.5f32.powi(i)).collect();
+        data.extend((0..100).map(|_i| rand::random::<f32>()));
+        let labels = vec![0.0; data.len()];
+        let queries: Vec<[f32; 1]> = (0..20).map(|_i| [rand::random::<f32>() * 0.01]).collect();
+        let mut depths = Vec::new();
+        for resolution in &[-3, -6, -10, -20] {
+            let point_cloud =
+                PointCloud::<L2>::simple_from_ram(Box::from(data.clone()), 1, Box::from(labels.clone()), 1).unwrap();
+            let tree = CoverTreeBuilder::new()
+                .set_resolution(*resolution)
+                .set_verbosity(0)
+                .build(point_cloud)
+                .unwrap();
+            let reader = tree.reader();
+            reader.validate().unwrap();
+            let stats = reader.stats();
+            assert!(stats.min_scale_index >= resolution - 1);
+            assert!(stats.max_depth as i32 <= reader.root_address().0 - resolution + 2);
+            depths.push(stats.max_depth);
+            for query in &queries {
+                let mut expected: Vec<(f32, PointIndex)> = data
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| (L2::dense(&[*x], query), i as PointIndex))
+                    .collect();
+                expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                expected.truncate(10);
+                assert_eq!(reader.knn(query, 10).unwrap(), expected);
+            }
+        }
+        assert!(depths[0] < depths[3]);
+    }
+
     #[test]
     fn splits_conditions() {
         let mut data = Vec::with_capacity(20);
@@ -359,7 +1249,7 @@ mod tests {
         println!("The scale_index should be 0, but is {}", scale_index);
         assert!(scale_index == 0);
 
-        let (new_node, unfinished_nodes) = build_node.split(&test_parameters).unwrap();
+        let (new_node, unfinished_nodes, _duplicates) = build_node.split(&test_parameters).unwrap();
         let split_count = test_parameters.total_nodes.load(atomic::Ordering::SeqCst) - 1;
         println!(
             "We should have split count be equal to the work count: split {} , work {}",
@@ -385,8 +1275,8 @@ mod tests {
         let build_node = BuilderNode::new(&test_parameters).unwrap();
 
         let (node_sender, node_receiver): (
-            Sender<MalwareBrotResult<(i32, PointIndex, CoverNode)>>,
-            Receiver<MalwareBrotResult<(i32, PointIndex, CoverNode)>>,
+            Sender<MalwareBrotResult<SplitNode>>,
+            Receiver<MalwareBrotResult<SplitNode>>,
         ) = unbounded();
         let node_sender = Arc::new(node_sender);
 
@@ -401,7 +1291,7 @@ mod tests {
         assert!(split_count + 1 == node_receiver.len());
         assert!(split_count == 3);
         while let Ok(pat) = node_receiver.try_recv() {
-            let (scale_index, center_index, node) = pat.unwrap();
+            let (scale_index, center_index, node, _duplicates) = pat.unwrap();
             println!("{:?}", node);
             match (scale_index, center_index) {
                 (-1, 3) => assert!(!node.is_leaf()),
@@ -422,12 +1312,9 @@ mod tests {
         let point_cloud =
             PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
         let builder = CoverTreeBuilder {
-            scale_base: 2.0,
-            cutoff: 1,
             resolution: -9,
-            use_singletons: true,
-            cluster_min: 5,
             verbosity: 0,
+            ..CoverTreeBuilder::new()
         };
         let tree = builder.build(point_cloud).unwrap();
         let reader = tree.reader();
@@ -466,12 +1353,10 @@ mod tests {
         let point_cloud =
             PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
         let builder = CoverTreeBuilder {
-            scale_base: 2.0,
-            cutoff: 1,
             resolution: -9,
             use_singletons: false,
-            cluster_min: 5,
             verbosity: 0,
+            ..CoverTreeBuilder::new()
         };
         let tree = builder.build(point_cloud).unwrap();
         let reader = tree.reader();
@@ -480,4 +1365,230 @@ mod tests {
         assert!(reader.get_node_and((-2, 2), |n| n.is_leaf()).is_some());
         assert!(reader.no_dangling_refs());
     }
+
+    #[test]
+    fn parallel_and_serial_builds_agree() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0, 0.3, -0.2, 0.25];
+        let labels = vec![0.0; 8];
+
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_cutoff(1).set_resolution(-9).set_verbosity(0);
+        let point_cloud = PointCloud::<L2>::simple_from_ram(
+            Box::from(data.clone()),
+            1,
+            Box::from(labels.clone()),
+            1,
+        )
+        .unwrap();
+        let global_tree = builder.build(point_cloud).unwrap();
+        let point_cloud = PointCloud::<L2>::simple_from_ram(
+            Box::from(data.clone()),
+            1,
+            Box::from(labels.clone()),
+            1,
+        )
+        .unwrap();
+        let serial_tree = builder.build_parallel(point_cloud, 1).unwrap();
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
+        let pooled_tree = builder.build_parallel(point_cloud, 3).unwrap();
+
+        assert!(serial_tree.reader().no_dangling_refs());
+        assert!(pooled_tree.reader().no_dangling_refs());
+        for query in &[[0.1], [-0.3], [0.495]] {
+            let expected = global_tree.reader().knn(query, 3).unwrap();
+            let serial = serial_tree.reader().knn(query, 3).unwrap();
+            let pooled = pooled_tree.reader().knn(query, 3).unwrap();
+            println!("{:?} {:?} {:?}", expected, serial, pooled);
+            assert_eq!(expected, serial);
+            assert_eq!(expected, pooled);
+        }
+    }
+
+    #[test]
+    fn build_reports_the_bitwise_duplicates() {
+        let data = vec![0.0, 1.0, 0.5, 1.0, 0.0, -0.0, 0.5, 1.0 + std::f32::EPSILON, 0.5];
+        let labels = vec![0.0; 9];
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
+        let (tree, duplicates) = CoverTreeBuilder::new()
+            .set_verbosity(0)
+            .build_report_duplicates(point_cloud)
+            .unwrap();
+        let names = |names: &[&str]| names.iter().map(|n| n.as_bytes().to_vec()).collect::<Vec<PointName>>();
+        assert_eq!(duplicates, vec![names(&["0", "4"]), names(&["1", "3"]), names(&["2", "6", "8"])]);
+        assert_eq!(tree.reader().knn(&[0.5], 3).unwrap().len(), 3);
+
+        // A coarse grid, so most points have copies scattered all over the tree
+        let count = 2000;
+        let data: Vec<f32> = (0..2 * count).map(|_| (rand::random::<f32>() * 8.0).floor()).collect();
+        let mut expected: Vec<Vec<PointName>> = Vec::new();
+        let mut seen: HashMap<(u32, u32), usize> = HashMap::new();
+        for i in 0..count {
+            let name = format!("{}", i).into_bytes();
+            match seen.get(&(data[2 * i].to_bits(), data[2 * i + 1].to_bits())) {
+                Some(group) => expected[*group].push(name),
+                None => {
+                    seen.insert((data[2 * i].to_bits(), data[2 * i + 1].to_bits()), expected.len());
+                    expected.push(vec![name]);
+                }
+            }
+        }
+        expected.retain(|group| group.len() > 1);
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 2, Box::from(vec![0.0; count]), 1).unwrap();
+        let (tree, duplicates) = CoverTreeBuilder::new()
+            .set_verbosity(0)
+            .build_report_duplicates(point_cloud)
+            .unwrap();
+        assert_eq!(duplicates, expected);
+        tree.reader().validate().unwrap();
+    }
+
+    #[test]
+    fn both_insertion_strategies_give_correct_knn() {
+        let count = 300;
+        let data: Vec<f32> = (0..2 * count).map(|_| rand::random::<f32>()).collect();
+        let labels = vec![0.0; count];
+        let pc = PointCloud::<L2>::simple_from_ram(Box::from(data.clone()), 2, Box::from(labels.clone()), 1)
+            .unwrap();
+
+        for strategy in &[InsertionStrategy::FirstFit, InsertionStrategy::NearestParent] {
+            let point_cloud =
+                PointCloud::<L2>::simple_from_ram(Box::from(data.clone()), 2, Box::from(labels.clone()), 1)
+                    .unwrap();
+            let mut builder = CoverTreeBuilder::new();
+            builder.set_verbosity(0).set_insertion_strategy(*strategy);
+            let tree = builder.build(point_cloud).unwrap();
+            let reader = tree.reader();
+            reader.validate().unwrap();
+            for _ in 0..10 {
+                let query = [rand::random::<f32>(), rand::random::<f32>()];
+                let mut expected = pc.distances_to_point(&query, &pc.reference_indexes()).unwrap();
+                expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let found: Vec<f32> = reader.knn(&query, 5).unwrap().iter().map(|(d, _)| *d).collect();
+                assert_eq!(found, &expected[..5], "{:?}", strategy);
+            }
+        }
+    }
+
+    #[test]
+    fn progress_counts_every_point_on_the_calling_thread() {
+        let count = 1000;
+        let data: Vec<f32> = (0..2 * count).map(|_| rand::random::<f32>()).collect();
+        let labels = vec![0.0; count];
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 2, Box::from(labels), 1).unwrap();
+        let caller = thread::current().id();
+        let mut calls = Vec::new();
+        let tree = CoverTreeBuilder::new()
+            .set_verbosity(0)
+            .build_with_progress(point_cloud, 100, |done, total| {
+                assert_eq!(thread::current().id(), caller);
+                calls.push((done, total));
+            })
+            .unwrap();
+        assert_eq!(calls.last(), Some(&(count, count)));
+        assert!(calls.len() >= 10);
+        for pair in calls.windows(2) {
+            assert!(pair[0].0 < pair[1].0);
+            assert!(pair[1].0 / 100 > pair[0].0 / 100 || pair[1].0 == count);
+        }
+        assert_eq!(tree.reader().knn(&[0.5, 0.5], 5).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn external_build_gives_correct_knn_and_cleans_up() {
+        let count = 500;
+        let data: Vec<f32> = (0..2 * count).map(|_| rand::random::<f32>()).collect();
+        let labels = vec![0.0; count];
+        let pc = PointCloud::<L2>::simple_from_ram(Box::from(data.clone()), 2, Box::from(labels.clone()), 1)
+            .unwrap();
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 2, Box::from(labels), 1).unwrap();
+
+        let temp_dir = std::env::temp_dir().join(format!("grandma_external_{}", rand::random::<u32>()));
+        fs::create_dir_all(&temp_dir).unwrap();
+        // Small enough that the top of the tree is split from files
+        let tree = CoverTreeBuilder::new()
+            .set_verbosity(0)
+            .build_external_with(point_cloud, &temp_dir, 16)
+            .unwrap();
+        assert_eq!(fs::read_dir(&temp_dir).unwrap().count(), 0);
+        fs::remove_dir(&temp_dir).unwrap();
+
+        let reader = tree.reader();
+        reader.validate().unwrap();
+        assert!(reader.no_dangling_refs());
+        let (_addresses, indexes) = reader.subtree_contents(reader.root_address());
+        let mut all = pc.reference_indexes();
+        all.sort();
+        assert_eq!(indexes, all);
+        for _ in 0..10 {
+            let query = [rand::random::<f32>(), rand::random::<f32>()];
+            let mut expected = pc.distances_to_point(&query, &pc.reference_indexes()).unwrap();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let found: Vec<f32> = reader.knn(&query, 5).unwrap().iter().map(|(d, _)| *d).collect();
+            assert_eq!(found, &expected[..5]);
+        }
+
+        // Points on a line, spread so that the root has more children than a pass over its far points picks
+        let count = 9000;
+        let data: Vec<f32> = (0..count).map(|i| i as f32).collect();
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(vec![0.0; count]), 1).unwrap();
+        let tree = CoverTreeBuilder::new()
+            .set_scale_base(100.0)
+            .set_verbosity(0)
+            .build_external_with(point_cloud, &std::env::temp_dir(), 16)
+            .unwrap();
+        let reader = tree.reader();
+        reader.validate().unwrap();
+        let children = reader.get_node_and(reader.root_address(), |n| n.children_len()).unwrap();
+        assert!(children > SPILL_CENTERS);
+        assert_eq!(reader.subtree_contents(reader.root_address()).1.len(), count);
+        let found: Vec<f32> = reader.knn(&[4500.25], 3).unwrap().iter().map(|(d, _)| *d).collect();
+        assert_eq!(found, vec![0.25, 0.75, 1.25]);
+
+        // The records of a tree of f64s keep their distances as f64s
+        let data: Vec<f64> = (0..2000).map(|i| i as f64 + 0.1).collect();
+        let point_cloud =
+            PointCloud::<L2<f64>>::simple_from_ram(Box::from(data), 1, Box::from(vec![0.0; 2000]), 1).unwrap();
+        let tree = CoverTreeBuilder::new()
+            .set_verbosity(0)
+            .build_external_with(point_cloud, &std::env::temp_dir(), 16)
+            .unwrap();
+        let reader = tree.reader();
+        reader.validate().unwrap();
+        let query = [1000.35];
+        let mut expected = reader.point_cloud().distances_to_point(&query, &(0..2000).collect::<Vec<_>>()).unwrap();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let found: Vec<f64> = reader.knn(&query, 3).unwrap().iter().map(|(d, _)| *d).collect();
+        assert_eq!(found, &expected[..3]);
+
+        let not_a_dir = std::env::temp_dir().join(format!("grandma_external_{}", rand::random::<u32>()));
+        fs::write(&not_a_dir, b"").unwrap();
+        let point_cloud = PointCloud::<L2>::simple_from_ram(Box::from(vec![0.0; 2]), 2, Box::from(vec![0.0]), 1)
+            .unwrap();
+        match CoverTreeBuilder::new().build_external(point_cloud, &not_a_dir) {
+            Err(MalwareBrotError::IoError(_)) => {}
+            _ => panic!("Expected an IoError"),
+        }
+        fs::remove_file(&not_a_dir).unwrap();
+
+        for external in &[false, true] {
+            let labels = pointcloud::labels::LabelScheme::new().empty();
+            let point_cloud = PointCloud::<L2>::from_ram(Box::from(vec![]), 2, labels).unwrap();
+            let builder = CoverTreeBuilder::new();
+            let built = if *external {
+                builder.build_external(point_cloud, std::env::temp_dir())
+            } else {
+                builder.build(point_cloud)
+            };
+            match built {
+                Err(MalwareBrotError::ParsingError(_)) => {}
+                _ => panic!("Expected a ParsingError for an empty point cloud"),
+            }
+        }
+    }
 }
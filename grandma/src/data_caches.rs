@@ -17,14 +17,14 @@
 * under the License.
 */
 
-use crate::errors::MalwareBrotResult;
+use crate::errors::{MalwareBrotError, MalwareBrotResult, ParsingError};
 use pointcloud::*;
 use rand::{thread_rng, Rng};
 use std::fmt;
 
 #[derive(Clone)]
-pub(crate) struct CoveredData {
-    dists: Vec<f32>,
+pub(crate) struct CoveredData<T: Scalar = f32> {
+    dists: Vec<T>,
     coverage: Vec<PointIndex>,
     pub(crate) center_index: PointIndex,
 }
@@ -37,11 +37,15 @@ pub(crate) struct UncoveredData {
 impl UncoveredData {
     pub(crate) fn pick_center<M: Metric>(
         &mut self,
-        radius: f32,
+        radius: M::Scalar,
+        deterministic: bool,
         point_cloud: &PointCloud<M>,
-    ) -> MalwareBrotResult<CoveredData> {
-        let mut rng = thread_rng();
-        let new_center: usize = rng.gen_range(0, self.coverage.len());
+    ) -> MalwareBrotResult<CoveredData<M::Scalar>> {
+        let new_center: usize = if deterministic {
+            0
+        } else {
+            thread_rng().gen_range(0, self.coverage.len())
+        };
         let center_index = self.coverage.remove(new_center);
         let dists = point_cloud.distances_to_point_index(center_index, &self.coverage)?;
 
@@ -70,7 +74,7 @@ impl UncoveredData {
     }
 }
 
-impl fmt::Debug for CoveredData {
+impl<T: Scalar> fmt::Debug for CoveredData<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
@@ -100,10 +104,31 @@ fn find_split(dist_indexes: &[(f32, usize)], thresh: f32) -> usize {
     smaller
 }
 
-impl CoveredData {
-    pub(crate) fn new<M: Metric>(point_cloud: &PointCloud<M>) -> MalwareBrotResult<CoveredData> {
+/// There's no center to cover an empty point cloud with.
+pub(crate) fn empty_cloud() -> MalwareBrotError {
+    MalwareBrotError::ParsingError(ParsingError::RegularParsingError(
+        "The point cloud is empty, there's no point to root the tree at",
+    ))
+}
+
+impl<T: Scalar> CoveredData<T> {
+    pub(crate) fn new<M: Metric<Scalar = T>>(point_cloud: &PointCloud<M>) -> MalwareBrotResult<CoveredData<T>> {
         let mut coverage = point_cloud.reference_indexes();
-        let center_index = coverage.pop().unwrap();
+        let center_index = coverage.pop().ok_or_else(empty_cloud)?;
+        let dists = point_cloud.distances_to_point_index(center_index, &coverage)?;
+        Ok(CoveredData {
+            dists,
+            coverage,
+            center_index,
+        })
+    }
+
+    /// Covers an explicit set of points with the given center, used when a subtree has to be rebuilt.
+    pub(crate) fn from_indexes<M: Metric<Scalar = T>>(
+        center_index: PointIndex,
+        coverage: Vec<PointIndex>,
+        point_cloud: &PointCloud<M>,
+    ) -> MalwareBrotResult<CoveredData<T>> {
         let dists = point_cloud.distances_to_point_index(center_index, &coverage)?;
         Ok(CoveredData {
             dists,
@@ -112,7 +137,16 @@ impl CoveredData {
         })
     }
 
-    pub(crate) fn split(self, thresh: f32) -> MalwareBrotResult<(CoveredData, UncoveredData)> {
+    /// Covers points whose distances to the center are already known, used when a spilled node is read back.
+    pub(crate) fn from_parts(center_index: PointIndex, coverage: Vec<PointIndex>, dists: Vec<T>) -> CoveredData<T> {
+        CoveredData {
+            dists,
+            coverage,
+            center_index,
+        }
+    }
+
+    pub(crate) fn split(self, thresh: T) -> MalwareBrotResult<(CoveredData<T>, UncoveredData)> {
         let mut close_index = Vec::with_capacity(self.coverage.len());
         let mut close_dist = Vec::with_capacity(self.coverage.len());
         let mut far = Vec::new();
@@ -137,16 +171,91 @@ impl CoveredData {
         self.coverage
     }
 
-    pub(crate) fn max_distance(&self) -> f32 {
+    pub(crate) fn max_distance(&self) -> T {
         self.dists
             .iter()
             .cloned()
-            .fold(-1. / 0. /* -inf */, f32::max)
+            .fold(T::neg_infinity(), T::max)
     }
 
     pub(crate) fn len(&self) -> usize {
         self.coverage.len() + 1
     }
+
+    /// The groups of these points that are equal bit for bit, each in index order, and the groups in the order of their
+    /// first index. Equal points are the same distance from the center, the center's own distance is what the metric
+    /// gives it against itself, so only points with the same distance are compared.
+    pub(crate) fn bitwise_duplicates<M: Metric<Scalar = T>>(
+        &self,
+        point_cloud: &PointCloud<M>,
+    ) -> MalwareBrotResult<Vec<Vec<PointIndex>>> {
+        let center = point_cloud.get_point(self.center_index)?;
+        let mut by_distance: Vec<(u64, PointIndex)> = self
+            .dists
+            .iter()
+            .map(|d| d.as_f64().to_bits())
+            .zip(self.coverage.iter().cloned())
+            .collect();
+        by_distance.push(((M::dense)(&center, &center).as_f64().to_bits(), self.center_index));
+        by_distance.sort_unstable();
+
+        let mut groups = Vec::new();
+        let mut start = 0;
+        while start < by_distance.len() {
+            let end = start + by_distance[start..].iter().take_while(|(d, _pi)| *d == by_distance[start].0).count();
+            // The points at this distance, grouped by the first of them each is equal to
+            let mut run: Vec<Vec<PointIndex>> = Vec::new();
+            for (_d, pi) in &by_distance[start..end] {
+                let point = point_cloud.get_point(*pi)?;
+                let mut found = None;
+                for (i, group) in run.iter().enumerate() {
+                    let first = point_cloud.get_point(group[0])?;
+                    if first.iter().zip(point.iter()).all(|(x, y)| x.as_f64().to_bits() == y.as_f64().to_bits()) {
+                        found = Some(i);
+                        break;
+                    }
+                }
+                match found {
+                    Some(i) => run[i].push(*pi),
+                    None => run.push(vec![*pi]),
+                }
+            }
+            groups.extend(run.into_iter().filter(|group| group.len() > 1));
+            start = end;
+        }
+        groups.sort_unstable();
+        Ok(groups)
+    }
+}
+
+/// Moves every point that isn't a center to the cluster whose center is nearest to it. The points of a cluster are within
+/// its radius of its center, and the nearest center is no further, so the clusters still cover their points. Ties stay
+/// where they are, so earlier clusters keep their points.
+pub(crate) fn assign_to_nearest<M: Metric>(
+    clusters: &mut [CoveredData<M::Scalar>],
+    point_cloud: &PointCloud<M>,
+) -> MalwareBrotResult<()> {
+    let mut points = Vec::new();
+    let mut nearest = Vec::new();
+    for (ci, cluster) in clusters.iter_mut().enumerate() {
+        for (i, d) in cluster.coverage.drain(..).zip(cluster.dists.drain(..)) {
+            points.push(i);
+            nearest.push((d, ci));
+        }
+    }
+    for (ci, cluster) in clusters.iter().enumerate() {
+        let dists = point_cloud.distances_to_point_index(cluster.center_index, &points)?;
+        for (n, d) in nearest.iter_mut().zip(dists) {
+            if d < n.0 {
+                *n = (d, ci);
+            }
+        }
+    }
+    for (i, (d, ci)) in points.into_iter().zip(nearest) {
+        clusters[ci].coverage.push(i);
+        clusters[ci].dists.push(d);
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -198,7 +307,7 @@ mod tests {
         let mut cache = UncoveredData {
             coverage: (0..19 as PointIndex).collect(),
         };
-        let close = cache.pick_center(1.0, &point_cloud).unwrap();
+        let close = cache.pick_center(1.0, false, &point_cloud).unwrap();
 
         assert!(!close.coverage.contains(&close.center_index));
         assert!(!cache.coverage.contains(&close.center_index));
@@ -21,7 +21,10 @@
 //! Most errors are floated up from `PointCloud` as that's the i/o layer.
 
 use pointcloud::errors::PointCloudError;
+use pointcloud::{PointIndex, PointName};
+use crate::NodeAddress;
 use protobuf::ProtobufError;
+use rayon::ThreadPoolBuildError;
 use std::error::Error;
 use std::fmt;
 use std::io;
@@ -35,8 +38,11 @@ pub type MalwareBrotResult<T> = Result<T, MalwareBrotError>;
 pub enum MalwareBrotError {
     /// Unable to retrieve some data point (given by index) in a file (slice name)
     PointCloudError(PointCloudError),
-    /// Most common error, the given point name isn't present in the training data
-    NameNotInTree(String),
+    /// Most common error, the given point name isn't present in the training data. This holds the raw bytes of the name,
+    /// the display formats them as hex.
+    NameNotInTree(PointName),
+    /// A point index that should be in the tree has no corresponding entry in the point cloud
+    IndexNotInTree(PointIndex),
     /// IO error when opening files
     IoError(io::Error),
     /// Parsing error when loading a CSV file
@@ -45,6 +51,86 @@ pub enum MalwareBrotError {
     DoubleNest,
     /// Inserted a node before you changed it from a leaf node into a normal node. Insert the nested child first.
     InsertBeforeNest,
+    /// Unable to create the thread pool for a parallel build
+    ThreadPoolError(ThreadPoolBuildError),
+    /// Tried to remove the only point left in the tree, a tree can't be empty
+    RemoveLastPoint,
+    /// The scale base has to be a finite number more than 1, otherwise the scales don't shrink as you go down the tree
+    InvalidScaleBase(f64),
+    /// A query point doesn't have the same number of coordinates as the points in the tree
+    DimensionMismatch {
+        /// The dimension of the tree's point cloud
+        expected: usize,
+        /// The length of the point that was passed in
+        got: usize,
+    },
+    /// A node references a child node that isn't in the tree
+    DanglingReference {
+        /// The node with the reference
+        parent: NodeAddress,
+        /// The missing child
+        child: NodeAddress,
+    },
+    /// A child or singleton of a node is further from the node's center than the node's scale, or the child's scale
+    /// isn't below the node's
+    CoveringViolation {
+        /// The node that should cover the point
+        parent: NodeAddress,
+        /// The center of the child, or the singleton
+        child: PointIndex,
+        /// The distance from the node's center to the child
+        distance: f64,
+    },
+    /// Two children of a node at the same scale, or singletons, are closer than that scale
+    SeparationViolation {
+        /// The node the children belong to
+        parent: NodeAddress,
+        /// The first of the pair
+        first: PointIndex,
+        /// The second of the pair
+        second: PointIndex,
+        /// The distance between them
+        distance: f64,
+    },
+    /// Two trees that were going to be merged were built with different scale bases
+    ScaleBaseMismatch {
+        /// The scale base of the tree being merged into
+        ours: f64,
+        /// The scale base of the other tree
+        theirs: f64,
+    },
+    /// A name is in both trees that were going to be merged. This holds the raw bytes of the name.
+    NameCollision(PointName),
+    /// The reader can't see any point to query against
+    EmptyTree,
+    /// An inserted or query vector has a NaN or infinite coordinate
+    NonFiniteCoordinate {
+        /// The position of the first such coordinate in the vector
+        index: usize,
+    },
+    /// The tree would need more layers than it can address, see `tree::MAX_LAYERS`. The scale index is too far above the
+    /// resolution, or the resolution is too close to the ends of an `i32` to leave room for the layers.
+    CapacityExceeded {
+        /// The scale index that didn't fit
+        scale_index: i32,
+        /// The resolution of the tree
+        resolution: i32,
+    },
+    /// The tree has a fixed number of nodes, see `CoverTreeBuilder::with_arena`, and the build or the change would have
+    /// needed more. A refused change leaves the tree as it was.
+    ArenaCapacityExceeded {
+        /// The most nodes the tree can hold
+        capacity: usize,
+        /// The nodes it would have needed
+        needed: usize,
+    },
+    /// An `extend` or `extend_from_csv` stopped part way through. The points before the one that failed are in the tree.
+    PartialInsert {
+        /// How many points went in before the error
+        inserted: usize,
+        /// What went wrong with the next point
+        error: Box<MalwareBrotError>,
+    },
 }
 
 impl fmt::Display for MalwareBrotError {
@@ -54,8 +140,13 @@ impl fmt::Display for MalwareBrotError {
             &MalwareBrotError::IoError(ref e) => write!(f,"{}",e),
             &MalwareBrotError::ParsingError(ref e) => write!(f,"{}",e),
             &MalwareBrotError::PointCloudError(ref e) => write!(f,"{}",e),
-            &MalwareBrotError::NameNotInTree { .. } => {
-                write!(f,"there was an issue grabbing a name from the known names")
+            &MalwareBrotError::NameNotInTree(ref name) => {
+                write!(f,"there was an issue grabbing the name ")?;
+                write_hex(f, name)?;
+                write!(f," from the known names")
+            }
+            &MalwareBrotError::IndexNotInTree { .. } => {
+                write!(f,"there was an issue grabbing an index from the point cloud")
             }
             &MalwareBrotError::DoubleNest => {
                 write!(f,"Inserted a nested node into a node that already had a nested child")
@@ -63,10 +154,61 @@ impl fmt::Display for MalwareBrotError {
             &MalwareBrotError::InsertBeforeNest => {
                 write!(f,"Inserted a node into a node that does not have a nested child")
             }
+            &MalwareBrotError::ThreadPoolError(ref e) => write!(f,"{}",e),
+            &MalwareBrotError::RemoveLastPoint => {
+                write!(f,"Tried to remove the only point left in the tree")
+            }
+            &MalwareBrotError::InvalidScaleBase(scale_base) => {
+                write!(f,"The scale base {} is not a finite number more than 1", scale_base)
+            }
+            &MalwareBrotError::DimensionMismatch { expected, got } => {
+                write!(f,"The point has {} coordinates, but the tree's points have {}", got, expected)
+            }
+            &MalwareBrotError::DanglingReference { parent, child } => {
+                write!(f,"The node {:?} references {:?}, which is not in the tree", parent, child)
+            }
+            &MalwareBrotError::CoveringViolation { parent, child, distance } => {
+                write!(f,"The node {:?} does not cover {}, which is {} from its center", parent, child, distance)
+            }
+            &MalwareBrotError::SeparationViolation { parent, first, second, distance } => {
+                write!(f,"The children {} and {} of the node {:?} are only {} apart", first, second, parent, distance)
+            }
+            &MalwareBrotError::ScaleBaseMismatch { ours, theirs } => {
+                write!(f,"Can't merge a tree with scale base {} into one with scale base {}", theirs, ours)
+            }
+            &MalwareBrotError::NameCollision(ref name) => {
+                write!(f,"The name ")?;
+                write_hex(f, name)?;
+                write!(f," is in both trees")
+            }
+            &MalwareBrotError::EmptyTree => {
+                write!(f,"The tree has no points to query against")
+            }
+            &MalwareBrotError::NonFiniteCoordinate { index } => {
+                write!(f,"The coordinate {} of the point is not finite", index)
+            }
+            &MalwareBrotError::CapacityExceeded { scale_index, resolution } => {
+                write!(f,"The scale index {} is too far from the resolution {} for the tree to address", scale_index, resolution)
+            }
+            &MalwareBrotError::ArenaCapacityExceeded { capacity, needed } => {
+                write!(f,"The tree would need {} nodes but its arena holds {}", needed, capacity)
+            }
+            &MalwareBrotError::PartialInsert { inserted, ref error } => {
+                write!(f,"Inserted {} points, then the next failed: {}", inserted, error)
+            }
         }
     }
 }
 
+/// Names are raw bytes, so they're shown as hex
+fn write_hex(f: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+    write!(f,"0x")?;
+    for byte in bytes {
+        write!(f,"{:02x}",byte)?;
+    }
+    Ok(())
+}
+
 #[allow(deprecated)]
 impl Error for MalwareBrotError {
     fn description(&self) -> &str {
@@ -78,12 +220,55 @@ impl Error for MalwareBrotError {
             &MalwareBrotError::NameNotInTree { .. } => {
                 "there was an issue grabbing a name from the known names"
             }
+            &MalwareBrotError::IndexNotInTree { .. } => {
+                "there was an issue grabbing an index from the point cloud"
+            }
             &MalwareBrotError::DoubleNest => {
                 "Inserted a nested node into a node that already had a nested child"
             }
             &MalwareBrotError::InsertBeforeNest => {
                 "Inserted a node into a node that does not have a nested child"
             }
+            &MalwareBrotError::ThreadPoolError(ref e) => e.description(),
+            &MalwareBrotError::RemoveLastPoint => {
+                "Tried to remove the only point left in the tree"
+            }
+            &MalwareBrotError::InvalidScaleBase(..) => {
+                "The scale base is not a finite number more than 1"
+            }
+            &MalwareBrotError::DimensionMismatch { .. } => {
+                "The point does not have the same dimension as the tree"
+            }
+            &MalwareBrotError::DanglingReference { .. } => {
+                "A node references a node that is not in the tree"
+            }
+            &MalwareBrotError::CoveringViolation { .. } => {
+                "A node does not cover one of its children"
+            }
+            &MalwareBrotError::SeparationViolation { .. } => {
+                "Two children of a node are closer than their scale"
+            }
+            &MalwareBrotError::ScaleBaseMismatch { .. } => {
+                "The trees have different scale bases"
+            }
+            &MalwareBrotError::NameCollision(..) => {
+                "A name is in both trees"
+            }
+            &MalwareBrotError::EmptyTree => {
+                "The tree has no points"
+            }
+            &MalwareBrotError::NonFiniteCoordinate { .. } => {
+                "A coordinate of the point is not finite"
+            }
+            &MalwareBrotError::CapacityExceeded { .. } => {
+                "The tree would need more layers than it can address"
+            }
+            &MalwareBrotError::ArenaCapacityExceeded { .. } => {
+                "The tree would need more nodes than its arena holds"
+            }
+            &MalwareBrotError::PartialInsert { .. } => {
+                "Only some of the points were inserted"
+            }
         }
     }
 
@@ -93,8 +278,23 @@ impl Error for MalwareBrotError {
             &MalwareBrotError::ParsingError(ref e) => Some(e),
             &MalwareBrotError::PointCloudError(ref e) => Some(e),
             &MalwareBrotError::NameNotInTree { .. } => None,
+            &MalwareBrotError::IndexNotInTree { .. } => None,
             &MalwareBrotError::DoubleNest => None,
             &MalwareBrotError::InsertBeforeNest => None,
+            &MalwareBrotError::ThreadPoolError(ref e) => Some(e),
+            &MalwareBrotError::RemoveLastPoint => None,
+            &MalwareBrotError::InvalidScaleBase(..) => None,
+            &MalwareBrotError::DimensionMismatch { .. } => None,
+            &MalwareBrotError::DanglingReference { .. } => None,
+            &MalwareBrotError::CoveringViolation { .. } => None,
+            &MalwareBrotError::SeparationViolation { .. } => None,
+            &MalwareBrotError::ScaleBaseMismatch { .. } => None,
+            &MalwareBrotError::NameCollision(..) => None,
+            &MalwareBrotError::EmptyTree => None,
+            &MalwareBrotError::NonFiniteCoordinate { .. } => None,
+            &MalwareBrotError::CapacityExceeded { .. } => None,
+            &MalwareBrotError::ArenaCapacityExceeded { .. } => None,
+            &MalwareBrotError::PartialInsert { ref error, .. } => Some(error.as_ref()),
         }
     }
 }
@@ -105,6 +305,12 @@ impl From<PointCloudError> for MalwareBrotError {
     }
 }
 
+impl From<ThreadPoolBuildError> for MalwareBrotError {
+    fn from(err: ThreadPoolBuildError) -> Self {
+        MalwareBrotError::ThreadPoolError(err)
+    }
+}
+
 impl From<io::Error> for MalwareBrotError {
     fn from(err: io::Error) -> Self {
         MalwareBrotError::IoError(err)
@@ -156,6 +362,75 @@ pub enum ParsingError {
     },
     /// Something else happened parsing a string
     RegularParsingError(&'static str),
+    /// A delta was applied to a tree that isn't at the version the delta was taken from, or was asked for since a
+    /// version from before the tree was built, loaded or merged
+    VersionMismatch {
+        /// The version the delta expects the tree to be at, or was asked for since
+        expected: u64,
+        /// The version the tree is actually at, or the earliest it has changes since
+        found: u64,
+    },
+    /// The layers of a tree file don't match the checksum saved with them, the file is corrupt
+    ChecksumMismatch {
+        /// The checksum saved in the file
+        expected: u32,
+        /// The checksum of the layers that were read
+        found: u32,
+    },
+    /// A layer of a tree file isn't where its scale index puts it. Layer `i` above the bottom one has to have scale index
+    /// `resolution - 1 + i`, and there can't be more than `tree::MAX_LAYERS` of them.
+    MisplacedLayer {
+        /// The position of the layer in the file
+        position: usize,
+        /// The scale index the layer has
+        scale_index: i32,
+    },
+    /// A scale index in a tree file has no layer, or it's a child's scale index that isn't on a layer below its parent's
+    ScaleOutOfRange {
+        /// The node with the scale index, or the root
+        node: NodeAddress,
+        /// The scale index that's out of place
+        scale_index: i32,
+    },
+    /// The root or a child in a tree file isn't a node on its layer
+    MissingNode {
+        /// The node that references it, the root references itself
+        parent: NodeAddress,
+        /// The missing node
+        child: NodeAddress,
+    },
+    /// A point index in a tree file isn't in the point cloud
+    UnknownIndex {
+        /// The node with the index
+        node: NodeAddress,
+        /// The index
+        index: PointIndex,
+    },
+    /// A node in a tree file doesn't have as many child scale indexes as child centers
+    MismatchedChildren {
+        /// The node
+        node: NodeAddress,
+    },
+    /// A tombstone in a tree file or delta is a point that isn't in the tree
+    UnknownTombstone {
+        /// The index of the tombstoned point
+        index: PointIndex,
+    },
+    /// A tree encoded by `CoverTreeWriter::save_fast` starts with a format version this build can't read
+    UnsupportedVersion {
+        /// The version at the start of the encoding
+        found: u8,
+        /// The version this build reads and writes
+        supported: u8,
+    },
+    /// A tree file was saved from a tree of another scalar type than the metric it's loaded with, an `f64` tree loaded
+    /// with `L2` say. See `CoreProto.scalar_width`.
+    ScalarWidthMismatch {
+        /// The bytes of the metric's scalar
+        expected: u32,
+        /// The bytes of the scalar the file was saved with
+        found: u32,
+    },
 }
 
 impl fmt::Display for ParsingError {
@@ -167,6 +442,16 @@ impl fmt::Display for ParsingError {
             &ParsingError::MissingYamlError { .. } => write!(f,"not all message fields set"),
             &ParsingError::CSVReadError { .. } => write!(f,"issue reading a CSV entry"),
             &ParsingError::RegularParsingError(..) => write!(f,"Error parsing a string"),
+            &ParsingError::VersionMismatch { expected, found } => write!(f,"the delta expects a tree at version {}, but the tree is at version {}", expected, found),
+            &ParsingError::ChecksumMismatch { expected, found } => write!(f,"the tree file has checksum {:08x}, but its layers have checksum {:08x}", expected, found),
+            &ParsingError::MisplacedLayer { position, scale_index } => write!(f,"the layer at position {} of the tree file has scale index {}, which doesn't belong there", position, scale_index),
+            &ParsingError::ScaleOutOfRange { node, scale_index } => write!(f,"the node {:?} in the tree file has scale index {} out of range", node, scale_index),
+            &ParsingError::MissingNode { parent, child } => write!(f,"the node {:?} in the tree file references {:?}, which is not in the file", parent, child),
+            &ParsingError::UnknownIndex { node, index } => write!(f,"the node {:?} in the tree file references the point {}, which is not in the point cloud", node, index),
+            &ParsingError::MismatchedChildren { node } => write!(f,"the node {:?} in the tree file has different numbers of child scales and child centers", node),
+            &ParsingError::UnknownTombstone { index } => write!(f,"the tree file tombstones the point {}, which is not in the tree", index),
+            &ParsingError::UnsupportedVersion { found, supported } => write!(f,"the tree is encoded with format version {}, but only version {} can be read", found, supported),
+            &ParsingError::ScalarWidthMismatch { expected, found } => write!(f,"the tree file has {} byte scalars, but the metric's are {} bytes", found, expected),
         }
     }
 }
@@ -181,6 +466,16 @@ impl Error for ParsingError {
             &ParsingError::MissingYamlError { .. } => "not all message fields set",
             &ParsingError::CSVReadError { .. } => "issue reading a CSV entry",
             &ParsingError::RegularParsingError(..) => "Error parsing a string",
+            &ParsingError::VersionMismatch { .. } => "the delta does not apply to this version of the tree",
+            &ParsingError::ChecksumMismatch { .. } => "the tree file is corrupt",
+            &ParsingError::MisplacedLayer { .. } => "a layer of the tree file is out of place",
+            &ParsingError::ScaleOutOfRange { .. } => "a scale index in the tree file is out of range",
+            &ParsingError::MissingNode { .. } => "a node in the tree file references a node that is not in the file",
+            &ParsingError::UnknownIndex { .. } => "a node in the tree file references a point that is not in the point cloud",
+            &ParsingError::MismatchedChildren { .. } => "a node in the tree file has mismatched children",
+            &ParsingError::UnknownTombstone { .. } => "the tree file tombstones a point that is not in the tree",
+            &ParsingError::UnsupportedVersion { .. } => "the tree is encoded with an unsupported format version",
+            &ParsingError::ScalarWidthMismatch { .. } => "the tree file has a different scalar type than the metric",
         }
     }
 
@@ -191,6 +486,16 @@ impl Error for ParsingError {
             &ParsingError::MissingYamlError { .. } => None,
             &ParsingError::CSVReadError { .. } => None,
             &ParsingError::RegularParsingError(..) => None,
+            &ParsingError::VersionMismatch { .. } => None,
+            &ParsingError::ChecksumMismatch { .. } => None,
+            &ParsingError::MisplacedLayer { .. } => None,
+            &ParsingError::ScaleOutOfRange { .. } => None,
+            &ParsingError::MissingNode { .. } => None,
+            &ParsingError::UnknownIndex { .. } => None,
+            &ParsingError::MismatchedChildren { .. } => None,
+            &ParsingError::UnknownTombstone { .. } => None,
+            &ParsingError::UnsupportedVersion { .. } => None,
+            &ParsingError::ScalarWidthMismatch { .. } => None,
         }
     }
 }
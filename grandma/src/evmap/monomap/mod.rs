@@ -58,6 +58,8 @@ pub enum MonoOperation<K, V> {
     ///
     /// Note that this will iterate once over all the keys internally.
     Purge,
+    /// Make room in the map for at least this many more keys.
+    Reserve(usize),
     // Since we have a feature that adds an enum variant, features are only additive (as they need
     // to be) if users never try to exhaustively match on this enum. Once rust-lang/rust#44109
     // lands, we'll have a more standard way to do this, but for now we rely on this trick:
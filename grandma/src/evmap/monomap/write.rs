@@ -388,6 +388,14 @@ where
         self.add_op(MonoOperation::Purge)
     }
 
+    /// Reserve room for at least `additional` more keys, so the map doesn't have to grow while they're inserted.
+    ///
+    /// Like the other operations this only reaches the map the readers see after the next call to `refresh()`, and
+    /// the other copy of the map after the one after that.
+    pub fn reserve(&mut self, additional: usize) -> &mut Self {
+        self.add_op(MonoOperation::Reserve(additional))
+    }
+
     /// Apply ops in such a way that no values are dropped, only forgotten
     fn apply_first(inner: &mut Inner<K, V, M, S>, op: &mut MonoOperation<K, V>) {
         match *op {
@@ -409,6 +417,9 @@ where
             MonoOperation::Purge => {
                 inner.data.clear();
             }
+            MonoOperation::Reserve(additional) => {
+                inner.data.reserve(additional);
+            }
             MonoOperation::__Nonexhaustive => unreachable!(),
         }
     }
@@ -434,6 +445,9 @@ where
             MonoOperation::Purge => {
                 inner.data.clear();
             }
+            MonoOperation::Reserve(additional) => {
+                inner.data.reserve(additional);
+            }
             MonoOperation::__Nonexhaustive => unreachable!(),
         }
     }
@@ -0,0 +1,164 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! A cover tree over a stream that only keeps the most recent points.
+
+use crate::*;
+use errors::{MalwareBrotError, MalwareBrotResult};
+use pointcloud::labels::values::Metadata;
+use pointcloud::labels::LabelScheme;
+use std::collections::VecDeque;
+
+/// A cover tree that holds at most `max_points` points. Once it's full the oldest point is removed before each insert,
+/// first in first out, so the tree is over the most recent `max_points` points of the stream. The evicted point's index
+/// is reused by the new one, so the point cloud stays at `max_points` points too.
+///
+/// Each insert copies the point cloud's maps of names and addresses, see `CoverTreeWriter::insert`, so this is for
+/// streams that are slow relative to the size of the window. The evicted points' vectors are dropped every so often, see
+/// `PointCloud::with_replaced_point`.
+pub struct FifoCoverTree<M: Metric> {
+    builder: CoverTreeBuilder,
+    max_points: usize,
+    order: VecDeque<PointName>,
+    writer: Option<CoverTreeWriter<M>>,
+}
+
+impl<M: Metric> FifoCoverTree<M> {
+    /// An empty tree that will hold at most `max_points` points, with the builder's defaults otherwise. Panics if
+    /// `max_points` is 0.
+    pub fn with_capacity(scale_base: f64, max_points: usize) -> FifoCoverTree<M> {
+        assert!(max_points > 0, "A FifoCoverTree has to be able to hold a point");
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_scale_base(scale_base).set_verbosity(0);
+        FifoCoverTree {
+            builder,
+            max_points,
+            order: VecDeque::with_capacity(max_points),
+            writer: None,
+        }
+    }
+
+    /// Adds a point, evicting the oldest one first if the tree is full. The first point sets the dimension of the
+    /// tree. Returns `NameCollision` if a resident point has the name, and `DimensionMismatch` if the vector is the
+    /// wrong length. Nothing is evicted when the insert is refused.
+    pub fn insert(&mut self, name: PointName, point: &[M::Scalar]) -> MalwareBrotResult<()> {
+        if let Some(writer) = &self.writer {
+            // Evicted points are removed from the tree but stay in its point cloud
+            if let Some(pi) = writer.parameters.point_cloud.get_index(&name) {
+                if !writer.absent.contains(pi) && !writer.tombstones.contains(pi) {
+                    return Err(MalwareBrotError::NameCollision(name));
+                }
+            }
+            let expected = writer.parameters.point_cloud.dim();
+            if point.len() != expected {
+                return Err(MalwareBrotError::DimensionMismatch {
+                    expected,
+                    got: point.len(),
+                });
+            }
+        }
+        if self.writer.is_none() || self.max_points == 1 {
+            // There's no tree that holds nothing, so the first point, or the only one if it's replaced, is a new tree
+            let mut labels = LabelScheme::new().empty();
+            labels.push(Some(name.clone()), Metadata::new())?;
+            let point_cloud = PointCloud::<M>::from_ram(Box::from(point), point.len(), labels)?;
+            self.writer = Some(self.builder.build(point_cloud)?);
+            self.order.clear();
+            self.order.push_back(name);
+            return Ok(());
+        }
+        let writer = self.writer.as_mut().unwrap();
+        if self.order.len() == self.max_points {
+            let oldest = self.order.pop_front().unwrap();
+            writer.remove(&oldest)?;
+        }
+        writer.insert(name.clone(), point, Metadata::new())?;
+        self.order.push_back(name);
+        Ok(())
+    }
+
+    /// The number of points in the tree, never more than `capacity`.
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// If the tree has no points yet.
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// The most points the tree holds.
+    pub fn capacity(&self) -> usize {
+        self.max_points
+    }
+
+    /// The names of the points in the tree, oldest first.
+    pub fn names(&self) -> impl Iterator<Item = &PointName> {
+        self.order.iter()
+    }
+
+    /// A reader of the current tree, `None` before the first insert. Like any reader it doesn't see later inserts.
+    pub fn reader(&self) -> Option<CoverTreeReader<M>> {
+        self.writer.as_ref().map(|writer| writer.reader())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_the_most_recent_points() {
+        let max_points = 20;
+        let mut tree = FifoCoverTree::<L2>::with_capacity(2.0, max_points);
+        assert!(tree.reader().is_none());
+        let mut points = Vec::new();
+        for i in 0..100usize {
+            let point = vec![rand::random::<f32>(), rand::random::<f32>()];
+            tree.insert(format!("{}", i).into_bytes(), &point).unwrap();
+            points.push(point);
+            assert!(tree.len() <= max_points);
+
+            let reader = tree.reader().unwrap();
+            reader.validate().unwrap();
+            let resident = (i + 1).saturating_sub(max_points)..=i;
+            assert_eq!(reader.point_cloud().len(), resident.clone().count());
+            let query = [rand::random::<f32>(), rand::random::<f32>()];
+            let mut expected: Vec<(f32, PointName)> = resident
+                .map(|j| (L2::dense(&points[j], &query), format!("{}", j).into_bytes()))
+                .collect();
+            expected.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            expected.truncate(3);
+            let found: Vec<(f32, PointName)> = reader
+                .knn(&query, 3)
+                .unwrap()
+                .into_iter()
+                .map(|(d, pi)| (d, reader.point_cloud().get_name(&pi).unwrap().to_vec()))
+                .collect();
+            assert_eq!(found, expected);
+        }
+        assert_eq!(tree.len(), max_points);
+        assert_eq!(tree.names().next().unwrap(), b"80");
+        match tree.insert(b"99".to_vec(), &[0.0, 0.0]) {
+            Err(MalwareBrotError::NameCollision(_)) => {}
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+        assert_eq!(tree.names().next().unwrap(), b"80");
+    }
+}
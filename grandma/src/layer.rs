@@ -27,11 +27,18 @@
 //!
 //! Writes to the tree are written to each layer and then each layer is refreshed. You should refrain from refreshing 
 //! single layers and try to handle all write operations as a tree level function. 
+//!
+//! The nodes live in these maps, each with its own lists of children and singletons, so a tree is many small
+//! allocations from the global allocator. The maps grow as nodes are added, unless the tree was built with
+//! `CoverTreeBuilder::with_arena`. Then the maps are sized for a fixed number of nodes when the tree is built, and a
+//! change that would need more is undone and refused with `ArenaCapacityExceeded` rather than growing them. The two
+//! copies of each map are swapped on refresh so readers never wait, so the arena is a number of nodes, not one block of
+//! memory, see `with_arena` for what that costs.
 //! 
 //! There is also an experimental pair of cluster hashmaps, which need to be replaced by a data structure that
 //! respects and represents the nerve more. 
 
-use crate::evmap::monomap::{MonoReadHandle,MonoWriteHandle};
+use crate::evmap::monomap::{MonoReadHandle,MonoReadHandleFactory,MonoWriteHandle};
 use pointcloud::*;
 
 //use rayon;
@@ -45,18 +52,18 @@ use std::sync::{atomic, Arc};
 /// Actual reader, primarily contains a read head to the hash-map. 
 /// This also contains a reference to the scale_index so that it is easy to save and load. It is largely redundant, 
 /// but helps with unit tests.
-pub struct CoverLayerReader {
+pub struct CoverLayerReader<T: Scalar = f32> {
     scale_index: i32,
-    node_reader: MonoReadHandle<PointIndex, CoverNode>,
+    node_reader: MonoReadHandle<PointIndex, CoverNode<T>>,
     cluster_reader: MonoReadHandle<usize, CoverCluster>,
     cluster_index: Arc<atomic::AtomicUsize>,
 }
 
-impl CoverLayerReader {
+impl<T: Scalar> CoverLayerReader<T> {
     /// Read only access to a single node.
-    pub fn get_node_and<F, T>(&self, pi: &PointIndex, f: F) -> Option<T>
+    pub fn get_node_and<F, R>(&self, pi: &PointIndex, f: F) -> Option<R>
     where
-        F: FnOnce(&CoverNode) -> T,
+        F: FnOnce(&CoverNode<T>) -> R,
     {
         self.node_reader.get_and(pi, |n| f(n))
     }
@@ -64,7 +71,7 @@ impl CoverLayerReader {
     /// Read only access to all nodes.
     pub fn for_each_node<F>(&self, f: F)
     where
-        F: FnMut(&PointIndex, &CoverNode),
+        F: FnMut(&PointIndex, &CoverNode<T>),
     {
         self.node_reader.for_each(f)
     }
@@ -72,7 +79,7 @@ impl CoverLayerReader {
     /// Maps all nodes on the layer, useful for collecting statistics.
     pub fn map_nodes<Map, Target, Collector>(&self, f: Map) -> Collector
     where
-        Map: FnMut(&PointIndex, &CoverNode) -> Target,
+        Map: FnMut(&PointIndex, &CoverNode<T>) -> Target,
         Collector: FromIterator<Target>,
     {
         self.node_reader.map_into(f)
@@ -80,9 +87,9 @@ impl CoverLayerReader {
 
     /// Grabs all children indexes and allows you to query against them. Usually used at the tree level so that you 
     /// can access the child nodes as they are not on this layer.
-    pub fn get_node_children_and<F, T>(&self, pi: &PointIndex, f: F) -> Option<T>
+    pub fn get_node_children_and<F, R>(&self, pi: &PointIndex, f: F) -> Option<R>
     where
-        F: FnOnce(NodeAddress, &[NodeAddress]) -> T,
+        F: FnOnce(NodeAddress, &[NodeAddress]) -> R,
     {
         self.node_reader
             .get_and(pi, |n| n.children().map(|(si, c)| f((si, *pi), c)))
@@ -90,9 +97,9 @@ impl CoverLayerReader {
     }
 
     #[doc(hidden)]
-    pub fn get_cluster_and<F, T>(&self, pi: &usize, f: F) -> Option<T>
+    pub fn get_cluster_and<F, R>(&self, pi: &usize, f: F) -> Option<R>
     where
-        F: FnOnce(&CoverCluster) -> T,
+        F: FnOnce(&CoverCluster) -> R,
     {
         self.cluster_reader.get_and(pi, |n| f(n))
     }
@@ -113,7 +120,7 @@ impl CoverLayerReader {
     }
 
     /// Clones the reader, expensive!
-    pub fn reader(&self) -> CoverLayerReader {
+    pub fn reader(&self) -> CoverLayerReader<T> {
         CoverLayerReader {
             scale_index: self.scale_index,
             node_reader: self.node_reader.factory().handle(),
@@ -122,8 +129,18 @@ impl CoverLayerReader {
         }
     }
 
+    /// A `Sync` handle that can make more readers of this layer, see `CoverLayerReaderFactory`.
+    pub fn factory(&self) -> CoverLayerReaderFactory<T> {
+        CoverLayerReaderFactory {
+            scale_index: self.scale_index,
+            node_factory: self.node_reader.factory(),
+            cluster_factory: self.cluster_reader.factory(),
+            cluster_index: Arc::clone(&self.cluster_index),
+        }
+    }
+
     #[doc(hidden)]
-    pub fn get_components<M: Metric>(
+    pub fn get_components<M: Metric<Scalar = T>>(
         &self,
         mut unclustered: Vec<PointIndex>,
         point_cloud: &PointCloud<M>,
@@ -149,7 +166,7 @@ impl CoverLayerReader {
         &self,
         center: &PointIndex,
         unclustered: &mut Vec<PointIndex>,
-        adj: &AdjMatrix,
+        adj: &AdjMatrix<T>,
     ) -> Vec<PointIndex> {
         let mut unvisited = vec![*center];
         let mut component = vec![*center];
@@ -178,7 +195,7 @@ impl CoverLayerReader {
     /// Checks if this is a true cover tree layer.
     pub fn brute_check_seperability(
         &self,
-        distances: &[f32],
+        distances: &[T],
         cluster_list: &[PointIndex],
         existing_cluster: &[PointIndex],
     ) -> bool {
@@ -210,16 +227,38 @@ pub struct CoverCluster {
 }
 
 /// Primarily contains the node writer head, but also has the cluster writer head and the index head.
-pub(crate) struct CoverLayerWriter {
+pub(crate) struct CoverLayerWriter<T: Scalar = f32> {
     scale_index: i32,
-    node_writer: MonoWriteHandle<PointIndex, CoverNode>,
+    node_writer: MonoWriteHandle<PointIndex, CoverNode<T>>,
     cluster_writer: MonoWriteHandle<usize, CoverCluster>,
     cluster_index: Arc<atomic::AtomicUsize>,
 }
 
-impl CoverLayerWriter {
+/// The readers are `Send` but not `Sync`, each one tracks which of the pair of hash-maps it's reading so only one thread
+/// can use it at a time. This is `Sync`, so it can be shared between threads and each thread makes its own reader.
+/// Making a reader takes a lock, reading doesn't.
+pub struct CoverLayerReaderFactory<T: Scalar = f32> {
+    scale_index: i32,
+    node_factory: MonoReadHandleFactory<PointIndex, CoverNode<T>>,
+    cluster_factory: MonoReadHandleFactory<usize, CoverCluster>,
+    cluster_index: Arc<atomic::AtomicUsize>,
+}
+
+impl<T: Scalar> CoverLayerReaderFactory<T> {
+    /// Makes a new reader of the layer.
+    pub fn reader(&self) -> CoverLayerReader<T> {
+        CoverLayerReader {
+            scale_index: self.scale_index,
+            node_reader: self.node_factory.handle(),
+            cluster_reader: self.cluster_factory.handle(),
+            cluster_index: Arc::clone(&self.cluster_index),
+        }
+    }
+}
+
+impl<T: Scalar> CoverLayerWriter<T> {
     /// Creates a reader head. Only way to get one from a newly created layer.
-    pub(crate) fn reader(&self) -> CoverLayerReader {
+    pub(crate) fn reader(&self) -> CoverLayerReader<T> {
         CoverLayerReader {
             scale_index: self.scale_index,
             node_reader: self.node_writer.factory().handle(),
@@ -229,7 +268,7 @@ impl CoverLayerWriter {
     }
 
     /// Constructs the object. To construct a reader call `reader`.
-    pub(crate) fn new(scale_index: i32) -> CoverLayerWriter {
+    pub(crate) fn new(scale_index: i32) -> CoverLayerWriter<T> {
         let (_node_reader, node_writer) = evmap::monomap::new();
         let (_cluster_reader, cluster_writer) = evmap::monomap::new::<usize, CoverCluster>();
         CoverLayerWriter {
@@ -246,7 +285,7 @@ impl CoverLayerWriter {
     }
 
 
-    pub(crate) fn load(layer_proto: &LayerProto) -> CoverLayerWriter {
+    pub(crate) fn load(layer_proto: &LayerProto) -> CoverLayerWriter<T> {
         let scale_index = layer_proto.get_scale_index();
         let (_node_reader, mut node_writer) = evmap::monomap::new();
         let (_cluster_reader, cluster_writer) = evmap::monomap::new::<usize, CoverCluster>();
@@ -270,15 +309,58 @@ impl CoverLayerWriter {
         self.node_writer.for_each(|_pi, node| {
             node_protos.push(node.save());
         });
+        // The map's order changes from map to map, sorting keeps the encoding of a tree the same however it was made
+        node_protos.sort_by_key(|node_proto| node_proto.get_center_index());
         layer_proto.set_nodes(node_protos);
         layer_proto.set_scale_index(self.scale_index);
         layer_proto
     }
 
-    pub(crate) fn insert_raw(&mut self, index: PointIndex, node: CoverNode) {
+    /// Copies of all the nodes on this layer, ordered by center index.
+    #[cfg(feature = "serde")]
+    pub(crate) fn nodes(&self) -> Vec<CoverNode<T>> {
+        let mut nodes: Vec<CoverNode<T>> = self.node_writer.map_into(|_pi, node| node.clone());
+        nodes.sort_by_key(|n| *n.center_index());
+        nodes
+    }
+
+    /// Builds a layer out of the nodes, the serde counterpart to `load`.
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_nodes(scale_index: i32, nodes: Vec<CoverNode<T>>) -> CoverLayerWriter<T> {
+        let mut layer = CoverLayerWriter::new(scale_index);
+        for node in nodes {
+            layer.node_writer.insert(*node.center_index(), node);
+        }
+        layer.node_writer.refresh();
+        layer
+    }
+
+    pub(crate) fn insert_raw(&mut self, index: PointIndex, node: CoverNode<T>) {
         self.node_writer.insert(index, node);
     }
 
+    pub(crate) fn scale_index(&self) -> i32 {
+        self.scale_index
+    }
+
+    /// A copy of the node centered on `index` as of the last refresh, the writes since aren't visible.
+    pub(crate) fn node(&self, index: PointIndex) -> Option<CoverNode<T>> {
+        self.node_writer.get_and(&index, |node| node.clone())
+    }
+
+    pub(crate) fn node_count(&self) -> usize {
+        self.node_writer.len()
+    }
+
+    /// Makes room for `additional` more nodes, in both copies of the map once it's been refreshed twice.
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        self.node_writer.reserve(additional);
+    }
+
+    pub(crate) fn remove_raw(&mut self, index: PointIndex) {
+        self.node_writer.remove(index);
+    }
+
     pub(crate) fn refresh(&mut self) {
         self.node_writer.refresh();
         self.cluster_writer.refresh();
@@ -43,6 +43,16 @@
 //! This is mainly to stop before floating point errors become an issue. Try to choose it to result in a cutoff of about
 //! 2^-9. 
 //!
+//! ## Scalar Type
+//! The coordinates and distances of a tree are the scalar of its metric, see `Metric::Scalar`. `L2` is a metric of
+//! `f32`s and builds the trees you've always had, `L2<f64>` builds a tree with its points, scale base, radii and
+//! distances all in `f64`. The builder takes the scale base as an `f64` either way. A saved tree records the width of its
+//! scalar, and loading it with a metric of another is a `ScalarWidthMismatch`.
+//!
+//! A point cloud can also hold its vectors as `i8`s with a scale, see `PointCloud::from_quantized`. The tree only sees
+//! the dequantized scalars, so it's built and queried like any other, and the recall cost is covered in the docs of
+//! `pointcloud::datasources`.
+//!
 //! See the git readme for a description of the algo.
 //!
 
@@ -64,13 +74,19 @@ pub(crate) mod evmap;
 mod tree_file_format;
 mod builders;
 mod data_caches;
+mod fifo;
+mod payload;
+mod standardize;
 pub mod layer;
 pub mod node;
 pub mod query_tools;
 mod tree;
 pub mod utils;
 
-pub use builders::CoverTreeBuilder;
+pub use builders::{CoverTreeBuilder, InsertionStrategy, EXTERNAL_RESIDENT_POINTS};
+pub use fifo::FifoCoverTree;
+pub use payload::{Payload, PayloadCoverTree};
+pub use standardize::{StandardizedCoverTree, Standardizer};
 pub use tree::*;
 
 /// The data structure explicitly seperates the covertree by layer, and the addressing schema for nodes 
@@ -27,10 +27,13 @@ use crate::NodeAddress;
 use pointcloud::labels::MetaSummary;
 use pointcloud::*;
 use smallvec::SmallVec;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 /// The node children. This is a separate struct from the `CoverNode` to use the rust compile time type checking and 
 /// `Option` data structure to ensure that all nodes with children are valid and cover their nested child.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(crate) struct NodeChildren {
     nested_scale: i32,
     addresses: SmallVec<[NodeAddress; 10]>,
@@ -42,24 +45,27 @@ pub(crate) struct NodeChildren {
 /// memory redirect for the first 20 singleton children. The children are saved in a separate struct also consisting of a `SmallVec`
 /// (though, this is only 10 wide before we allocate on the heap), and the scale index of the nested child.
 #[derive(Debug, Clone)]
-pub struct CoverNode {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CoverNode<T: Scalar = f32> {
     /// Node address
     address: NodeAddress,
     /// Query caches
-    radius: f32,
+    #[cfg_attr(feature = "serde", serde(with = "serde_radius"))]
+    radius: T,
     cover_count: usize,
+    #[cfg_attr(feature = "serde", serde(skip))]
     singles_summary: Option<MetaSummary>,
     /// Children
     children: Option<NodeChildren>,
     singles_indexes: SmallVec<[PointIndex; 20]>,
 }
 
-impl CoverNode {
+impl<T: Scalar> CoverNode<T> {
     /// Creates a new blank node
-    pub fn new(address: NodeAddress) -> CoverNode {
+    pub fn new(address: NodeAddress) -> CoverNode<T> {
         CoverNode {
             address,
-            radius: 0.0,
+            radius: T::zero(),
             cover_count: 0,
             children: None,
             singles_indexes: SmallVec::new(),
@@ -72,8 +78,9 @@ impl CoverNode {
         self.children.is_none()
     }
 
-    /// This is currently inconsistent on inserts to children of this node
-    pub fn radius(&self) -> f32 {
+    /// The covering radius, the furthest any point this node covers is from its center. It's computed when the node is
+    /// built and `CoverTreeWriter::remove` keeps it exact. Negative infinity if the node only covers its center.
+    pub fn radius(&self) -> T {
         self.radius
     }
 
@@ -101,6 +108,21 @@ impl CoverNode {
         self.children.take()
     }
 
+    /// The bytes the node's lists hold on the heap, by their capacity. Short lists are kept inline and count as 0, they're
+    /// in `size_of::<CoverNode>()`. The metadata summary isn't counted.
+    pub(crate) fn heap_bytes(&self) -> usize {
+        let mut bytes = 0;
+        if self.singles_indexes.spilled() {
+            bytes += self.singles_indexes.capacity() * std::mem::size_of::<PointIndex>();
+        }
+        if let Some(children) = &self.children {
+            if children.addresses.spilled() {
+                bytes += children.addresses.capacity() * std::mem::size_of::<NodeAddress>();
+            }
+        }
+        bytes
+    }
+
     /// The number of singleton points attached to the node
     pub fn singleton_len(&self) -> usize {
         self.singles_indexes.len()
@@ -138,17 +160,22 @@ impl CoverNode {
 
     /// Performs the `singleton_knn` and `child_knn` with a provided query heap. If you have the distance
     /// from the query point to this you can pass it to save a distance calculation.
-    pub fn knn<M: Metric>(
+    pub fn knn<M: Metric<Scalar = T>, const COUNTED: bool>(
         &self,
-        dist_to_center: Option<f32>,
-        point: &[f32],
+        dist_to_center: Option<T>,
+        point: &[T],
         point_cloud: &PointCloud<M>,
-        query_heap: &mut KnnQueryHeap,
+        query_heap: &mut KnnQueryHeap<'_, T, COUNTED>,
     ) -> MalwareBrotResult<()> {
         self.singleton_knn(point, point_cloud, query_heap)?;
 
-        let dist_to_center =
-            dist_to_center.unwrap_or(point_cloud.distances_to_point(point, &[self.address.1])?[0]);
+        let dist_to_center = match dist_to_center {
+            Some(d) => d,
+            None => {
+                query_heap.count_distances(1);
+                point_cloud.distances_to_point(point, &[self.address.1])?[0]
+            }
+        };
         self.child_knn(Some(dist_to_center), point, point_cloud, query_heap)?;
 
         if self.children.is_none() {
@@ -158,28 +185,42 @@ impl CoverNode {
     }
 
     /// Performs a brute force knn against just the singleton children with a provided query heap.
-    pub fn singleton_knn<M: Metric>(
+    pub fn singleton_knn<M: Metric<Scalar = T>, const COUNTED: bool>(
         &self,
-        point: &[f32],
+        point: &[T],
         point_cloud: &PointCloud<M>,
-        query_heap: &mut KnnQueryHeap,
+        query_heap: &mut KnnQueryHeap<'_, T, COUNTED>,
     ) -> MalwareBrotResult<()> {
-        let distances = point_cloud.distances_to_point(point, &self.singles_indexes[..])?;
-        query_heap.push_outliers(&self.singles_indexes[..], &distances[..]);
+        let (indexes, mut distances) = query_heap.take_buffers();
+        if query_heap.ranks_singletons() {
+            point_cloud.ranks_to_point_into(point, &self.singles_indexes[..], &mut distances)?;
+            query_heap.count_distances(distances.len());
+            query_heap.push_ranked_outliers::<M>(&self.singles_indexes[..], &distances[..]);
+        } else {
+            point_cloud.distances_to_point_into(point, &self.singles_indexes[..], &mut distances)?;
+            query_heap.count_distances(distances.len());
+            query_heap.push_outliers(&self.singles_indexes[..], &distances[..]);
+        }
+        query_heap.return_buffers(indexes, distances);
         Ok(())
     }
 
     /// Performs a brute force knn against the children of the node with a provided query heap. Does nothing if this is a leaf node.
     /// If you have the distance from the query point to this you can pass it to save a distance calculation.
-    pub fn child_knn<M: Metric>(
+    pub fn child_knn<M: Metric<Scalar = T>, const COUNTED: bool>(
         &self,
-        dist_to_center: Option<f32>,
-        point: &[f32],
+        dist_to_center: Option<T>,
+        point: &[T],
         point_cloud: &PointCloud<M>,
-        query_heap: &mut KnnQueryHeap,
+        query_heap: &mut KnnQueryHeap<'_, T, COUNTED>,
     ) -> MalwareBrotResult<()> {
-        let dist_to_center =
-            dist_to_center.unwrap_or(point_cloud.distances_to_point(point, &[self.address.1])?[0]);
+        let dist_to_center = match dist_to_center {
+            Some(d) => d,
+            None => {
+                query_heap.count_distances(1);
+                point_cloud.distances_to_point(point, &[self.address.1])?[0]
+            }
+        };
 
         if let Some(children) = &self.children {
             query_heap.push_nodes(
@@ -187,10 +228,13 @@ impl CoverNode {
                 &[dist_to_center],
                 None,
             );
-            let children_indexes: Vec<PointIndex> =
-                children.addresses.iter().map(|(_si, pi)| *pi).collect();
-            let distances = point_cloud.distances_to_point(point, &children_indexes[..])?;
+            let (mut children_indexes, mut distances) = query_heap.take_buffers();
+            children_indexes.clear();
+            children_indexes.extend(children.addresses.iter().map(|(_si, pi)| *pi));
+            point_cloud.distances_to_point_into(point, &children_indexes[..], &mut distances)?;
+            query_heap.count_distances(distances.len());
             query_heap.push_nodes(&children.addresses[..], &distances, Some(self.address));
+            query_heap.return_buffers(children_indexes, distances);
         }
         Ok(())
     }
@@ -216,13 +260,43 @@ impl CoverNode {
         self.cover_count += 1;
         self.singles_indexes.push(address);
     }
+    /// Removes a singleton child from the node, returns false if it wasn't there. This does not change the coverage,
+    /// use `remove_coverage` for that and update the metasummary.
+    pub(crate) fn remove_singleton(&mut self, address: &PointIndex) -> bool {
+        match self.singles_indexes.iter().position(|pi| pi == address) {
+            Some(i) => {
+                self.singles_indexes.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Removes a routing child from the node, returns false if it wasn't there. The nested child can't be removed
+    /// this way. This does not change the coverage.
+    pub(crate) fn remove_child(&mut self, address: &NodeAddress) -> bool {
+        if let Some(children) = &mut self.children {
+            if let Some(i) = children.addresses.iter().position(|a| a == address) {
+                children.addresses.remove(i);
+                return true;
+            }
+        }
+        false
+    }
+    /// Lowers the number of points this node covers, after a point is removed from below it.
+    pub(crate) fn remove_coverage(&mut self, coverage: usize) {
+        self.cover_count = self.cover_count.saturating_sub(coverage);
+    }
+    /// Raises the number of points this node covers, after a point is added below it.
+    pub(crate) fn add_coverage(&mut self, coverage: usize) {
+        self.cover_count += coverage;
+    }
     /// Updates the radius
-    pub(crate) fn set_radius(&mut self, radius: f32) {
+    pub(crate) fn set_radius(&mut self, radius: T) {
         self.radius = radius;
     }
 
     /// Updates the metasummary of the singletons this covers. Call this after inserting or removing a singleton.
-    pub(crate) fn update_metasummary<M: Metric>(
+    pub(crate) fn update_metasummary<M: Metric<Scalar = T>>(
         &mut self,
         point_cloud: &PointCloud<M>,
     ) -> MalwareBrotResult<()> {
@@ -230,14 +304,19 @@ impl CoverNode {
         Ok(())
     }
 
-    pub(crate) fn load(scale_index: i32, node_proto: &NodeProto) -> CoverNode {
+    pub(crate) fn load(scale_index: i32, node_proto: &NodeProto) -> CoverNode<T> {
         let singles_indexes = node_proto
             .outlier_point_indexes
             .iter()
             .map(|i| *i as PointIndex)
             .collect();
         let singles_summary = Some(MetaSummary::new());
-        let radius = node_proto.get_radius();
+        // Wide trees keep their radii in `wide_radius`, the `float` field would round them
+        let radius = if T::WIDTH == 8 {
+            T::from_f64(node_proto.get_wide_radius())
+        } else {
+            T::from_f64(node_proto.get_radius() as f64)
+        };
         let address = (scale_index, node_proto.get_center_index());
         let cover_count = node_proto.get_cover_count() as usize;
         let children;
@@ -270,7 +349,11 @@ impl CoverNode {
         let mut proto = NodeProto::new();
         proto.set_cover_count(self.cover_count as u64);
         proto.set_center_index(self.address.1 as u64);
-        proto.set_radius(self.radius);
+        if T::WIDTH == 8 {
+            proto.set_wide_radius(self.radius.as_f64());
+        } else {
+            proto.set_radius(self.radius.as_f64() as f32);
+        }
         proto.set_outlier_point_indexes(self.singles_indexes.iter().map(|pi| *pi as u64).collect());
 
         match &self.children {
@@ -295,9 +378,9 @@ impl CoverNode {
 
     /// Brute force verifies that the children are separated by at least the scale provided. 
     /// The scale provided should be b^(s-1) where s is this node's scale index.
-    pub fn check_seperation<M: Metric>(
+    pub fn check_seperation<M: Metric<Scalar = T>>(
         &self,
-        scale: f32,
+        scale: T,
         point_cloud: &PointCloud<M>,
     ) -> MalwareBrotResult<bool> {
         let mut nodes = self.singles_indexes.clone();
@@ -310,6 +393,25 @@ impl CoverNode {
     }
 }
 
+/// Single point nodes have a radius of negative infinity, which JSON can't represent. We store those as a missing radius.
+#[cfg(feature = "serde")]
+mod serde_radius {
+    use pointcloud::Scalar;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<T: Scalar, S: Serializer>(radius: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        if radius.is_finite() {
+            Some(*radius).serialize(serializer)
+        } else {
+            None::<T>.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, T: Scalar, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+        Ok(Option::<T>::deserialize(deserializer)?.unwrap_or(T::neg_infinity()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -430,7 +532,7 @@ mod tests {
         assert!(results[1].1 == 3);
     }
 
-    fn brute_test_knn_node<M: Metric>(node: &CoverNode, point_cloud: &PointCloud<M>) {
+    fn brute_test_knn_node<M: Metric<Scalar = f32>>(node: &CoverNode, point_cloud: &PointCloud<M>) {
         let zeros: Vec<f32> = vec![0.0; 784];
 
         let mut all_children = Vec::from(node.singletons());
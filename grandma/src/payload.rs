@@ -0,0 +1,304 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! A cover tree that keeps a payload of your own next to each point.
+
+use crate::*;
+use errors::{MalwareBrotError, MalwareBrotResult, ParsingError};
+use std::collections::HashMap;
+use tree_file_format::{CoreProto, PayloadProto};
+
+/// Data that's kept next to a point in a `PayloadCoverTree`, a label or a timestamp. It's encoded to bytes when the tree
+/// is saved and decoded when it's loaded, so decoding what `encode` gave has to give the payload back.
+pub trait Payload: Sized {
+    /// The bytes that are saved for this payload.
+    fn encode(&self) -> Vec<u8>;
+    /// Reads a payload back from the bytes `encode` gave, a parsing error if they aren't a payload of this type.
+    fn decode(bytes: &[u8]) -> MalwareBrotResult<Self>;
+}
+
+fn malformed_payload() -> MalwareBrotError {
+    MalwareBrotError::ParsingError(ParsingError::RegularParsingError(
+        "A saved payload can't be decoded as the tree's payload type",
+    ))
+}
+
+impl Payload for () {
+    fn encode(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn decode(bytes: &[u8]) -> MalwareBrotResult<()> {
+        if bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(malformed_payload())
+        }
+    }
+}
+
+impl Payload for Vec<u8> {
+    fn encode(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn decode(bytes: &[u8]) -> MalwareBrotResult<Vec<u8>> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl Payload for String {
+    fn encode(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> MalwareBrotResult<String> {
+        String::from_utf8(bytes.to_vec()).map_err(|_| malformed_payload())
+    }
+}
+
+macro_rules! impl_number_payload {
+    ($($t:ty),*) => {
+        $(
+            impl Payload for $t {
+                fn encode(&self) -> Vec<u8> {
+                    self.to_le_bytes().to_vec()
+                }
+
+                fn decode(bytes: &[u8]) -> MalwareBrotResult<$t> {
+                    let mut buf = [0u8; std::mem::size_of::<$t>()];
+                    if bytes.len() != buf.len() {
+                        return Err(malformed_payload());
+                    }
+                    buf.copy_from_slice(bytes);
+                    Ok(<$t>::from_le_bytes(buf))
+                }
+            }
+        )*
+    };
+}
+
+impl_number_payload!(u32, u64, i32, i64, f32, f64);
+
+/// A cover tree with a payload for every point, that comes back with the point's name in query results. The tree is a
+/// plain `CoverTreeWriter`, the payloads are kept in a map beside it by point index. So they don't have to fit the point
+/// cloud's label scheme, and they cost nothing during the query.
+///
+/// The payloads are saved in the tree's protobuf by `save`, so they survive a `save_tree` and `load`. A plain tree has
+/// no payloads saved, load it with `CoverTreeWriter::load` and convert it into a tree with `()` payloads instead.
+pub struct PayloadCoverTree<P: Payload = (), M: Metric = L2> {
+    writer: CoverTreeWriter<M>,
+    payloads: HashMap<PointIndex, P>,
+}
+
+impl<M: Metric> From<CoverTreeWriter<M>> for PayloadCoverTree<(), M> {
+    fn from(writer: CoverTreeWriter<M>) -> PayloadCoverTree<(), M> {
+        let reader = writer.reader();
+        let (_addresses, resident) = reader.subtree_contents(reader.root_address());
+        PayloadCoverTree {
+            writer,
+            payloads: resident.into_iter().map(|pi| (pi, ())).collect(),
+        }
+    }
+}
+
+impl<P: Payload, M: Metric> PayloadCoverTree<P, M> {
+    /// Puts the payloads on the points of a tree, by name. Every point in the tree has to get exactly one payload.
+    /// Returns `NameNotInTree` for a name that isn't in the tree, `NameCollision` if a name has two payloads, and a
+    /// parsing error if some point is left without one.
+    pub fn new(writer: CoverTreeWriter<M>, payloads: Vec<(PointName, P)>) -> MalwareBrotResult<PayloadCoverTree<P, M>> {
+        let point_cloud = &writer.parameters.point_cloud;
+        let mut by_index = HashMap::with_capacity(payloads.len());
+        for (name, payload) in payloads {
+            let pi = match point_cloud.get_index(&name) {
+                Some(pi) => *pi,
+                None => return Err(MalwareBrotError::NameNotInTree(name)),
+            };
+            if by_index.insert(pi, payload).is_some() {
+                return Err(MalwareBrotError::NameCollision(name));
+            }
+        }
+        PayloadCoverTree::with_indexed_payloads(writer, by_index)
+    }
+
+    /// Checks the payloads are exactly for the points in the tree.
+    fn with_indexed_payloads(
+        writer: CoverTreeWriter<M>,
+        payloads: HashMap<PointIndex, P>,
+    ) -> MalwareBrotResult<PayloadCoverTree<P, M>> {
+        let reader = writer.reader();
+        let (_addresses, resident) = reader.subtree_contents(reader.root_address());
+        for pi in payloads.keys() {
+            if resident.binary_search(pi).is_err() {
+                let name = writer.parameters.point_cloud.get_name(pi).map(|name| name.to_vec()).unwrap_or_default();
+                return Err(MalwareBrotError::NameNotInTree(name));
+            }
+        }
+        if payloads.len() != resident.len() {
+            return Err(MalwareBrotError::ParsingError(ParsingError::RegularParsingError(
+                "Not every point in the tree has a payload",
+            )));
+        }
+        Ok(PayloadCoverTree { writer, payloads })
+    }
+
+    /// Adds a point with its payload, see `CoverTreeWriter::insert`. The point gets blank metadata, see
+    /// `LabelScheme::blank_metadata`. Returns the index the point got, and the same errors as `CoverTreeWriter::insert`,
+    /// in which case the payload is dropped.
+    pub fn insert(&mut self, name: PointName, point: &[M::Scalar], payload: P) -> MalwareBrotResult<PointIndex> {
+        let blank = self.writer.parameters.point_cloud.label_scheme().blank_metadata();
+        let pi = self.writer.insert(name, point, blank)?;
+        self.payloads.insert(pi, payload);
+        Ok(pi)
+    }
+
+    /// Removes a point, see `CoverTreeWriter::remove`, and hands back its payload.
+    pub fn remove<N: AsRef<[u8]>>(&mut self, name: N) -> MalwareBrotResult<P> {
+        let name = name.as_ref();
+        let pi = match self.writer.parameters.point_cloud.get_index(name) {
+            Some(pi) => *pi,
+            None => return Err(MalwareBrotError::NameNotInTree(name.to_vec())),
+        };
+        self.writer.remove(name)?;
+        self.payloads.remove(&pi).ok_or(MalwareBrotError::IndexNotInTree(pi))
+    }
+
+    /// The payload of the point with this name, `None` if it's not in the tree.
+    pub fn payload<N: AsRef<[u8]>>(&self, name: N) -> Option<&P> {
+        let pi = self.writer.parameters.point_cloud.get_index(name)?;
+        self.payloads.get(pi)
+    }
+
+    /// The KNN query, with the names and payloads of the neighbors. The order and ties are the same as `knn_names`.
+    pub fn knn(&self, point: &[M::Scalar], k: usize) -> MalwareBrotResult<Vec<(M::Scalar, PointName, &P)>> {
+        let reader = self.writer.reader();
+        reader
+            .knn(point, k)?
+            .into_iter()
+            .map(|(d, pi)| {
+                match (reader.point_cloud().get_name(&pi), self.payloads.get(&pi)) {
+                    (Some(name), Some(payload)) => Ok((d, name.to_vec(), payload)),
+                    _ => Err(MalwareBrotError::IndexNotInTree(pi)),
+                }
+            })
+            .collect()
+    }
+
+    /// The tree without the payloads. Readers from it query the points like any other tree.
+    pub fn writer(&self) -> &CoverTreeWriter<M> {
+        &self.writer
+    }
+
+    /// A reader of the current tree, see `CoverTreeWriter::reader`. It doesn't see the payloads.
+    pub fn reader(&self) -> CoverTreeReader<M> {
+        self.writer.reader()
+    }
+
+    /// Encodes the tree into a protobuf like `CoverTreeWriter::save`, with the payloads in index order.
+    pub fn save(&self) -> CoreProto {
+        let mut cover_proto = self.writer.save();
+        let mut indexes: Vec<&PointIndex> = self.payloads.keys().collect();
+        indexes.sort();
+        for pi in indexes {
+            let mut payload_proto = PayloadProto::new();
+            payload_proto.set_index(*pi);
+            payload_proto.set_payload(self.payloads[pi].encode());
+            cover_proto.mut_payloads().push(payload_proto);
+        }
+        cover_proto
+    }
+
+    /// Loads a tree and its payloads saved with `save`, see `CoverTreeWriter::load`. A payload that can't be decoded
+    /// is a parsing error, and so is a point without a payload, as in a tree saved without payloads.
+    pub fn load(cover_proto: &CoreProto, point_cloud: PointCloud<M>) -> MalwareBrotResult<PayloadCoverTree<P, M>> {
+        let writer = CoverTreeWriter::load(cover_proto, point_cloud)?;
+        let mut payloads = HashMap::with_capacity(cover_proto.get_payloads().len());
+        for payload_proto in cover_proto.get_payloads() {
+            payloads.insert(payload_proto.get_index(), P::decode(payload_proto.get_payload())?);
+        }
+        PayloadCoverTree::with_indexed_payloads(writer, payloads)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payloads_come_back_with_the_neighbors() {
+        let data: Vec<f32> = (0..200).map(|_i| rand::random::<f32>()).collect();
+        let labels = vec![0.0; 100];
+        let point_cloud = || {
+            PointCloud::<L2>::simple_from_ram(Box::from(data.clone()), 2, Box::from(labels.clone()), 1).unwrap()
+        };
+        let writer = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud()).unwrap();
+        let payloads = (0..100).map(|i| (i.to_string().into_bytes(), format!("label {}", i))).collect();
+        let mut tree = PayloadCoverTree::new(writer, payloads).unwrap();
+
+        let query = [0.5, 0.5];
+        let neighbors = tree.knn(&query, 5).unwrap();
+        let names = tree.reader().knn_names(&query, 5).unwrap();
+        assert_eq!(neighbors.len(), 5);
+        for ((d, name, payload), (name_d, other_name)) in neighbors.iter().zip(&names) {
+            assert_eq!(d, name_d);
+            assert_eq!(name, other_name);
+            assert_eq!(**payload, format!("label {}", String::from_utf8_lossy(name)));
+        }
+
+        let bytes = protobuf::Message::write_to_bytes(&tree.save()).unwrap();
+        let cover_proto: CoreProto = protobuf::parse_from_bytes(&bytes).unwrap();
+        let loaded = PayloadCoverTree::<String, L2>::load(&cover_proto, point_cloud()).unwrap();
+        assert_eq!(loaded.knn(&query, 5).unwrap(), neighbors);
+        // The payloads are only for a tree that knows their type
+        assert!(PayloadCoverTree::<u64, L2>::load(&cover_proto, point_cloud()).is_err());
+
+        tree.insert(b"new".to_vec(), &query, "the new one".to_string()).unwrap();
+        assert_eq!(tree.knn(&query, 1).unwrap()[0].2, "the new one");
+        assert_eq!(tree.remove("7").unwrap(), "label 7");
+        assert!(tree.payload("7").is_none());
+        assert_eq!(tree.payload("new").unwrap(), "the new one");
+    }
+
+    #[test]
+    fn every_point_needs_one_payload() {
+        let data: Vec<f32> = (0..20).map(|_i| rand::random::<f32>()).collect();
+        let labels = vec![0.0; 10];
+        let point_cloud = || {
+            PointCloud::<L2>::simple_from_ram(Box::from(data.clone()), 2, Box::from(labels.clone()), 1).unwrap()
+        };
+        let build = || CoverTreeBuilder::new().set_verbosity(0).build(point_cloud()).unwrap();
+        let payloads: Vec<(PointName, u64)> = (0..9).map(|i| (i.to_string().into_bytes(), i)).collect();
+        match PayloadCoverTree::new(build(), payloads.clone()) {
+            Err(MalwareBrotError::ParsingError(_)) => {}
+            _ => panic!("Expected a ParsingError"),
+        }
+        let mut doubled = payloads.clone();
+        doubled.push((b"3".to_vec(), 3));
+        match PayloadCoverTree::new(build(), doubled) {
+            Err(MalwareBrotError::NameCollision(name)) => assert_eq!(name, b"3"),
+            _ => panic!("Expected a NameCollision"),
+        }
+
+        let writer = build();
+        let cover_proto = writer.save();
+        let tree: PayloadCoverTree = writer.into();
+        assert_eq!(tree.knn(&[0.5, 0.5], 3).unwrap().len(), 3);
+        assert!(PayloadCoverTree::<u64, L2>::load(&cover_proto, point_cloud()).is_err());
+    }
+}
@@ -21,13 +21,22 @@
 
 use crate::NodeAddress;
 use pointcloud::*;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::Instant;
 use std::f32;
+use std::fmt;
 
-mod query_items;
+pub(crate) mod query_items;
 
 use query_items::{QueryAddress, QuerySingleton};
 
+/// How many nodes a query with a deadline visits between looks at the clock. A node costs at least a few distances,
+/// so reading the clock this rarely is lost in the cost of the query.
+const DEADLINE_CHECK_INTERVAL: usize = 32;
+
 /// The heaps for doing a fairly efficient KNN query. There are 3 heaps, the child min-heap, singleton min-heap, and distance max-heap. 
 /// The distance heap is for the output KNN, each node or point that's pushed onto the heap is pushed onto this distance heap. 
 /// If the heap grows past K it's popped off. This provides an estimate for the distance to the furthest nearest neighbor out of the `k`.
@@ -40,22 +49,124 @@ use query_items::{QueryAddress, QuerySingleton};
 /// To help with double inserts (easy due to a node's central point's index being repeated througout the tree), we also have a HashSet of visited points.
 /// We reject a node insert if it's central point index is in this hashset.
 ///
+/// For approximate queries the heap has a slack factor of `1+epsilon`. A node is skipped if `1+epsilon` times the minimum distance a point
+/// it covers could have is at least the current kth distance. With no slack (the default) nodes are only rejected as they're pushed.
+///
+/// A `COUNTED` heap also keeps the counts of `QueryMetrics`, for `CoverTreeReader::knn_instrumented`. It's a const
+/// parameter so the other queries, on the default uncounted heap, have the counting compiled out rather than checked.
 #[derive(Debug)]
-pub struct KnnQueryHeap {
-    child_heap: BinaryHeap<QueryAddress>,
-    singleton_heap: BinaryHeap<QueryAddress>,
+pub struct KnnQueryHeap<'a, T: Scalar = f32, const COUNTED: bool = false> {
+    child_heap: BinaryHeap<QueryAddress<T>>,
+    singleton_heap: BinaryHeap<QueryAddress<T>>,
 
     known_indexes: HashSet<PointIndex>,
-    est_min_dist: HashMap<NodeAddress,f32>,
-    dist_heap: BinaryHeap<QuerySingleton>,
+    est_min_dist: HashMap<NodeAddress,T>,
+    dist_heap: BinaryHeap<QuerySingleton<T>>,
     k: usize,
-    scale_base: f32,
+    scale_base: T,
+    slack: T,
+    radius: T,
+    nodes_visited: usize,
+    distance_computations: usize,
+    branches_pruned: usize,
+    prune_log: Option<Vec<PruneEvent<T>>>,
+    min_pruned: T,
+    encounter_log: Option<Vec<PointIndex>>,
+    tombstones: Option<Arc<HashSet<PointIndex>>>,
+    excluded: Option<PointIndex>,
+    filter: Option<PointFilter<'a>>,
+    deadline: Option<Instant>,
+    timed_out: bool,
+    rank_singletons: bool,
+    indexes: Vec<PointIndex>,
+    distances: Vec<T>,
+    open_ranks: Vec<T>,
+}
+
+/// Counts of the work a KNN query did, from `CoverTreeReader::knn_instrumented`. These are exact counts of what the
+/// traversal did, not estimates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueryMetrics {
+    /// The nodes the query looked into for their children or singletons. A node is counted once for each.
+    pub nodes_visited: usize,
+    /// The distances from the query point to a point in the tree that were computed
+    pub distance_computations: usize,
+    /// The nodes that were skipped because they couldn't cover anything closer than the current kth nearest distance
+    pub branches_pruned: usize,
+}
+
+/// How far off the results of an approximate KNN query can be, from `CoverTreeReader::knn_approx_bounded`. Both are
+/// worked out from the lower bounds of the nodes the query skipped, so they hold for every query, not just on average.
+///
+/// A point the query missed is either under a skipped node, so at least `exact_radius` away, or was pushed out by the
+/// `k` it returned, so no closer than the last of them. So the results are exactly the true neighbors up to
+/// `exact_radius`, and past it the true neighbor in each position is still at least `exact_radius` away.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ApproxBound<T: Scalar = f32> {
+    /// Every point closer than this is in the results, or is further than all of them. Infinite if nothing was skipped.
+    pub exact_radius: T,
+    /// The most a returned distance can be over the true distance of the neighbor in that position, the last returned
+    /// distance less `exact_radius`. `0.0` means the results are exact.
+    pub max_error: T,
+}
+
+/// A subtree a KNN query skipped, from `CoverTreeReader::knn_debug`. Every point the node covers is at least
+/// `lower_bound` from the query point, and that's no closer than `threshold`, the kth nearest distance so far divided by
+/// the `1+epsilon` slack of an approximate query. So nothing in the subtree could have made it into the results.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PruneEvent<T: Scalar = f32> {
+    /// The node that was skipped
+    pub address: NodeAddress,
+    /// The least distance from the query point a point under the node could have
+    pub lower_bound: T,
+    /// The distance the lower bound had to be within for the node to be searched
+    pub threshold: T,
 }
 
-impl KnnQueryHeap {
+/// The points a filtered query keeps, see `KnnQueryHeap::keep_only`.
+struct PointFilter<'a>(&'a dyn Fn(PointIndex) -> bool);
+
+impl<'a> fmt::Debug for PointFilter<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PointFilter")
+    }
+}
+
+impl<'a, T: Scalar> KnnQueryHeap<'a, T> {
     /// Creates a new KNN heap. The K is obvious, but the `scale_base` is for the 
     /// minimum distance from our query point to potential covered points of a node.
-    pub fn new(k: usize, scale_base: f32) -> KnnQueryHeap {
+    pub fn new(k: usize, scale_base: T) -> KnnQueryHeap<'a, T> {
+        KnnQueryHeap::empty(k, scale_base)
+    }
+
+    /// Creates a KNN heap for an approximate query. The returned neighbors are within a factor of `1+epsilon` of the true ones,
+    /// in the sense that anything closer than the kth returned distance divided by `1+epsilon` was found. An `epsilon` of 0 is
+    /// the same as `new`.
+    pub fn new_approx(k: usize, scale_base: T, epsilon: T) -> KnnQueryHeap<'a, T> {
+        let mut heap = KnnQueryHeap::new(k, scale_base);
+        heap.slack = T::one() + epsilon.max(T::zero());
+        heap
+    }
+}
+
+impl<'a, T: Scalar> KnnQueryHeap<'a, T, true> {
+    /// Creates a KNN heap like `new` that counts the work done with it, see `metrics`.
+    pub fn counted(k: usize, scale_base: T) -> KnnQueryHeap<'a, T, true> {
+        KnnQueryHeap::empty(k, scale_base)
+    }
+
+    /// The counts of the work done so far with this heap.
+    pub fn metrics(&self) -> QueryMetrics {
+        QueryMetrics {
+            nodes_visited: self.nodes_visited,
+            distance_computations: self.distance_computations,
+            branches_pruned: self.branches_pruned,
+        }
+    }
+}
+
+impl<'a, T: Scalar, const COUNTED: bool> KnnQueryHeap<'a, T, COUNTED> {
+    fn empty(k: usize, scale_base: T) -> KnnQueryHeap<'a, T, COUNTED> {
         KnnQueryHeap {
             child_heap: BinaryHeap::new(),
             singleton_heap: BinaryHeap::new(),
@@ -64,43 +175,242 @@ impl KnnQueryHeap {
             known_indexes: HashSet::new(),
             k,
             scale_base: scale_base,
+            slack: T::one(),
+            radius: T::max_value(),
+            nodes_visited: 0,
+            distance_computations: 0,
+            branches_pruned: 0,
+            prune_log: None,
+            min_pruned: T::infinity(),
+            encounter_log: None,
+            tombstones: None,
+            excluded: None,
+            filter: None,
+            deadline: None,
+            timed_out: false,
+            rank_singletons: false,
+            indexes: Vec::new(),
+            distances: Vec::new(),
+            open_ranks: Vec::new(),
+        }
+    }
+
+    /// Empties the heap so it can be used for another query with the given `k`, keeping the allocations it's made so far.
+    pub(crate) fn reset(&mut self, k: usize, scale_base: T) {
+        self.child_heap.clear();
+        self.singleton_heap.clear();
+        self.known_indexes.clear();
+        self.est_min_dist.clear();
+        self.dist_heap.clear();
+        self.k = k;
+        self.scale_base = scale_base;
+        self.slack = T::one();
+        self.radius = T::max_value();
+        self.nodes_visited = 0;
+        self.distance_computations = 0;
+        self.branches_pruned = 0;
+        self.prune_log = None;
+        self.min_pruned = T::infinity();
+        self.encounter_log = None;
+        self.tombstones = None;
+        self.excluded = None;
+        self.filter = None;
+        self.deadline = None;
+        self.timed_out = false;
+        self.rank_singletons = false;
+    }
+
+    /// Starts recording a `PruneEvent` for every node that's skipped from here on. Queries that don't ask for this only
+    /// pay for checking that there's no log.
+    pub(crate) fn record_pruning(&mut self) {
+        self.prune_log = Some(Vec::new());
+    }
+
+    /// The pruning decisions recorded since `record_pruning`, in the order they were made.
+    pub(crate) fn take_prune_log(&mut self) -> Vec<PruneEvent<T>> {
+        self.prune_log.take().unwrap_or_default()
+    }
+
+    /// Starts recording the index of every point the first time it's pushed onto the heap, whether or not it makes it
+    /// into the results.
+    pub(crate) fn record_encounters(&mut self) {
+        self.encounter_log = Some(Vec::new());
+    }
+
+    /// The points recorded since `record_encounters`, in the order they were first seen.
+    pub(crate) fn take_encounter_log(&mut self) -> Vec<PointIndex> {
+        self.encounter_log.take().unwrap_or_default()
+    }
+
+    /// Keeps the points further than `radius` from the query point out of the results, and skips the nodes that only
+    /// cover such points, for range queries with a cap on the results. The kth distance is never more than this.
+    pub(crate) fn set_radius(&mut self, radius: T) {
+        self.radius = radius;
+    }
+
+    /// Keeps the tombstoned points out of the results. Their nodes are still searched, as they cover other points.
+    pub(crate) fn skip_tombstones(&mut self, tombstones: &Arc<HashSet<PointIndex>>) {
+        if !tombstones.is_empty() {
+            self.tombstones = Some(Arc::clone(tombstones));
         }
     }
 
+    /// Keeps one more point out of the results, as if it weren't in the tree, for leave-one-out queries. Like a
+    /// tombstone its nodes are still searched.
+    pub(crate) fn exclude(&mut self, index: PointIndex) {
+        self.excluded = Some(index);
+    }
+
+    /// Keeps the points `filter` is false for out of the results, for filtered queries. It's asked about a point when
+    /// the point is first pushed, so only about the points the search reaches. Like tombstones the nodes of the points
+    /// that are left out are still searched.
+    pub(crate) fn keep_only(&mut self, filter: &'a dyn Fn(PointIndex) -> bool) {
+        self.filter = Some(PointFilter(filter));
+    }
+
+    /// Stops the query once the clock passes `deadline`. The clock is read every `DEADLINE_CHECK_INTERVAL` nodes, so the
+    /// query can run a little past it. After that no more nodes are handed out and the heap keeps what it's found.
+    pub(crate) fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(deadline);
+    }
+
+    /// If the query was stopped by its deadline, see `set_deadline`.
+    pub(crate) fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// Has the singletons of a node ranked with `Metric::dense_rank` before their distances are taken, see
+    /// `CoverTreeReader::set_rank_singletons`.
+    pub(crate) fn set_rank_singletons(&mut self, rank_singletons: bool) {
+        self.rank_singletons = rank_singletons;
+    }
+
+    /// If the singletons are ranked before their distances are taken.
+    #[inline]
+    pub(crate) fn ranks_singletons(&self) -> bool {
+        self.rank_singletons
+    }
+
+    #[inline]
+    fn out_of_time(&mut self) -> bool {
+        if !self.timed_out && self.nodes_visited % DEADLINE_CHECK_INTERVAL == 0 {
+            if let Some(deadline) = self.deadline {
+                self.timed_out = Instant::now() >= deadline;
+            }
+        }
+        self.timed_out
+    }
+
+    #[inline]
+    fn is_tombstone(&self, index: &PointIndex) -> bool {
+        self.excluded == Some(*index)
+            || self.tombstones.as_ref().map(|t| t.contains(index)).unwrap_or(false)
+            || self.filter.as_ref().map(|f| !(f.0)(*index)).unwrap_or(false)
+    }
+
+    #[inline]
+    fn encounter(&mut self, index: PointIndex) {
+        if let Some(log) = self.encounter_log.as_mut() {
+            log.push(index);
+        }
+    }
+
+    #[inline]
+    fn prune(&mut self, address: NodeAddress, lower_bound: T, max_dist: T) {
+        if COUNTED {
+            self.branches_pruned += 1;
+        }
+        self.min_pruned = self.min_pruned.min(lower_bound);
+        if let Some(log) = self.prune_log.as_mut() {
+            log.push(PruneEvent {
+                address,
+                lower_bound,
+                threshold: max_dist / self.slack,
+            });
+        }
+    }
+
+    /// The least lower bound of the nodes skipped so far, infinite if none were. No point closer than this was skipped.
+    pub(crate) fn min_pruned(&self) -> T {
+        self.min_pruned
+    }
+
+    /// Sets the slack of an approximate query, see `new_approx`.
+    pub(crate) fn set_epsilon(&mut self, epsilon: T) {
+        self.slack = T::one() + epsilon.max(T::zero());
+    }
+
+    /// The number of nodes that have been handed out by `closest_unvisited_child_covering_address` and
+    /// `closest_unvisited_singleton_covering_address`, so the number of nodes the query has looked into.
+    pub fn nodes_visited(&self) -> usize {
+        self.nodes_visited
+    }
+
+    /// Records that `count` distances to the query point were computed, on a `COUNTED` heap. The heap is handed
+    /// distances, it doesn't compute them, so whoever does tells it here.
+    #[inline]
+    pub fn count_distances(&mut self, count: usize) {
+        if COUNTED {
+            self.distance_computations += count;
+        }
+    }
+
+    /// The node's `min_dist` can be raised by `increase_estimated_distance`, which only orders the search and isn't a bound
+    /// on the node's singletons. So prune on the covering bound from the center instead.
+    #[inline]
+    fn prunable(&self, node: &QueryAddress<T>) -> bool {
+        self.lower_bound(node) > self.radius
+            || (self.slack > T::one() && self.lower_bound(node) * self.slack >= self.max_dist())
+    }
+
+    #[inline]
+    fn lower_bound(&self, node: &QueryAddress<T>) -> T {
+        (node.dist_to_center - self.scale_base.powi(node.address.0)).max(T::zero())
+    }
+
     /// Finds the closest node who could have a child node at least the current kth furthest distance away from the query point. 
     /// This pops that node and pushes it onto the singleton heap.
-    pub fn closest_unvisited_child_covering_address(&mut self) -> Option<(f32, NodeAddress)> {
+    pub fn closest_unvisited_child_covering_address(&mut self) -> Option<(T, NodeAddress)> {
+        if self.out_of_time() {
+            return None;
+        }
         while let Some(mut node_to_visit) = self.child_heap.pop() {
             if let Some(min_dist_update) = self.est_min_dist.remove(&node_to_visit.address) {
                 if min_dist_update > node_to_visit.min_dist {
                     node_to_visit.min_dist = min_dist_update;
                     self.child_heap.push(node_to_visit);
-                } else {
-                    self.singleton_heap.push(node_to_visit);
-                    return Some((node_to_visit.dist_to_center,node_to_visit.address));
+                    continue;
                 }
-            } else {
+            }
+            if !self.prunable(&node_to_visit) {
                 self.singleton_heap.push(node_to_visit);
+                self.nodes_visited += 1;
                 return Some((node_to_visit.dist_to_center,node_to_visit.address));
             }
+            self.prune(node_to_visit.address, self.lower_bound(&node_to_visit), self.max_dist());
         }
         None
     }
 
     /// Finds the closest node who could have a singleton at least the current kth furthest distance away from the query point. 
     /// This pops the node and sends it to oblivion.
-    pub fn closest_unvisited_singleton_covering_address(&mut self) -> Option<(f32, NodeAddress)> {
+    pub fn closest_unvisited_singleton_covering_address(&mut self) -> Option<(T, NodeAddress)> {
+        if self.out_of_time() {
+            return None;
+        }
         while let Some(mut node_to_visit) = self.singleton_heap.pop() {
             if let Some(min_dist_update) = self.est_min_dist.remove(&node_to_visit.address) {
                 if min_dist_update > node_to_visit.min_dist {
                     node_to_visit.min_dist = min_dist_update;
                     self.singleton_heap.push(node_to_visit);
-                } else {
-                    return Some((node_to_visit.dist_to_center,node_to_visit.address));
+                    continue;
                 }
-            } else {
+            }
+            if !self.prunable(&node_to_visit) {
+                self.nodes_visited += 1;
                 return Some((node_to_visit.dist_to_center,node_to_visit.address));
             }
+            self.prune(node_to_visit.address, self.lower_bound(&node_to_visit), self.max_dist());
         }
         None
     }
@@ -115,75 +425,147 @@ impl KnnQueryHeap {
         self.child_heap.len() + self.singleton_heap.len()
     }
 
-    /// The current maximum distance to the query point. If the distance heap isn't full it returns the maximum float value.
-    pub fn max_dist(&self) -> f32 {
+    /// The current maximum distance to the query point. If the distance heap isn't full it returns the maximum float value,
+    /// or the radius if one was set.
+    pub fn max_dist(&self) -> T {
         if self.len() < self.k {
-            std::f32::MAX
+            self.radius
         } else {
-            self.dist_heap.peek().map(|x| x.dist).unwrap_or(f32::MAX)
+            self.dist_heap.peek().map(|x| x.dist).unwrap_or(self.radius)
         }
     }
 
     /// Unpacks the distance heap. This consumes the query heap.
-    pub fn unpack(mut self) -> Vec<(f32, PointIndex)> {
-        let mut result = Vec::with_capacity(self.k);
+    pub fn unpack(mut self) -> Vec<(T, PointIndex)> {
+        let mut result = Vec::with_capacity(self.dist_heap.len());
         while let Some(el) = self.dist_heap.pop() {
             result.push((el.dist,el.index));
         }
         result.iter().rev().cloned().collect()
     }
 
+    /// Unpacks the distance heap into `result`, replacing what was there, and leaves the heap empty. The same order as `unpack`.
+    pub(crate) fn unpack_into(&mut self, result: &mut Vec<(T, PointIndex)>) {
+        result.clear();
+        while let Some(el) = self.dist_heap.pop() {
+            result.push((el.dist,el.index));
+        }
+        result.reverse();
+    }
+
     /// This allows you to update the minimum distance to the parent of a node, or it's siblings.
     /// If you are well within the radius of coverage of a node, this allows you to remove the parent or sibling from the
     ///  `closest_unvisited_child_covering_address` and `closest_unvisited_singleton_covering_address` queries.
-    pub fn increase_estimated_distance(&mut self, address: NodeAddress, new_estimate: f32) {
-        let d = self.est_min_dist.entry(address).or_insert(0.0);
+    pub fn increase_estimated_distance(&mut self, address: NodeAddress, new_estimate: T) {
+        let d = self.est_min_dist.entry(address).or_insert(T::zero());
         if *d < new_estimate {
             *d = new_estimate;
         }
     }
 
     /// Shove a bunch of single points onto the heap
-    pub fn push_outliers(&mut self, indexes: &[PointIndex], dists: &[f32]) {
+    pub fn push_outliers(&mut self, indexes: &[PointIndex], dists: &[T]) {
         for (i, d) in indexes.iter().zip(dists) {
-            if !self.known_indexes.contains(i) {
-                self.known_indexes.insert(*i);
-                match self.dist_heap.peek() {
-                    Some(my_dist) => {
-                        if !(my_dist.dist < *d && self.dist_heap.len() >= self.k) {
-                            self.dist_heap.push(QuerySingleton::new(*i, *d));
-                        }
+            self.push_outlier(*i, *d);
+        }
+    }
+
+    #[inline]
+    fn push_outlier(&mut self, i: PointIndex, d: T) {
+        if d <= self.radius && !self.known_indexes.contains(&i) && !self.is_tombstone(&i) {
+            self.known_indexes.insert(i);
+            self.encounter(i);
+            match self.dist_heap.peek() {
+                Some(my_dist) => {
+                    if !(my_dist.dist < d && self.dist_heap.len() >= self.k) {
+                        self.dist_heap.push(QuerySingleton::new(i, d));
                     }
-                    None => self.dist_heap.push(QuerySingleton::new(*i, *d)),
-                };
-                while self.dist_heap.len() > self.k {
-                    self.dist_heap.pop();
                 }
+                None => self.dist_heap.push(QuerySingleton::new(i, d)),
+            };
+            while self.dist_heap.len() > self.k {
+                self.dist_heap.pop();
             }
         }
     }
 
+    /// Buffers for the indexes and distances of a node's children or singletons, empty unless an earlier node or query
+    /// left its allocations behind. Hand them back with `return_buffers` for the next node.
+    pub(crate) fn take_buffers(&mut self) -> (Vec<PointIndex>, Vec<T>) {
+        (std::mem::take(&mut self.indexes), std::mem::take(&mut self.distances))
+    }
+
+    /// Keeps the buffers from `take_buffers` for the next node.
+    pub(crate) fn return_buffers(&mut self, indexes: Vec<PointIndex>, distances: Vec<T>) {
+        self.indexes = indexes;
+        self.distances = distances;
+    }
+
+    /// Pushes singletons by their `Metric::dense_rank`s, only taking the distance of the ones that can make the `k`.
+    /// Once the heap is full that's the ones within the kth distance. Before it is, it's the ones within the distance
+    /// that these singletons would fill it to.
+    pub(crate) fn push_ranked_outliers<M: Metric<Scalar = T>>(&mut self, indexes: &[PointIndex], ranks: &[T]) {
+        let bound = M::rank_bound(self.ranked_cut::<M>(indexes, ranks));
+        for (i, rank) in indexes.iter().zip(ranks) {
+            if *rank <= bound {
+                self.push_outlier(*i, M::rank_to_distance(*rank));
+            }
+        }
+    }
+
+    /// The kth distance once these singletons are pushed can't be more than this. When the heap is short by `needed`
+    /// it's the larger of what it holds and the `needed`th closest of the singletons it'd take.
+    fn ranked_cut<M: Metric<Scalar = T>>(&mut self, indexes: &[PointIndex], ranks: &[T]) -> T {
+        let max_dist = self.max_dist();
+        let needed = self.k.saturating_sub(self.len());
+        if needed == 0 {
+            return max_dist;
+        }
+        let mut open = std::mem::take(&mut self.open_ranks);
+        open.clear();
+        open.extend(
+            indexes
+                .iter()
+                .zip(ranks)
+                .filter(|(i, _rank)| !self.known_indexes.contains(i) && !self.is_tombstone(i))
+                .map(|(_i, rank)| *rank),
+        );
+        let cut = if open.len() < needed {
+            max_dist
+        } else {
+            let (_closer, nth, _further) = open.select_nth_unstable_by(needed - 1, |a, b| a.total_cmp(b));
+            let held = self.dist_heap.peek().map(|x| x.dist).unwrap_or(T::zero());
+            M::rank_to_distance(*nth).max(held).min(max_dist)
+        };
+        self.open_ranks = open;
+        cut
+    }
+
     /// Shove a bunch of nodes onto the heap. Optionally, if you pass a parent node it updates the distance to that parent node.
     pub fn push_nodes(
         &mut self,
         indexes: &[NodeAddress],
-        dists: &[f32],
+        dists: &[T],
         parent_address: Option<NodeAddress>,
     ) {
         let mut max_dist = self.max_dist();
-        let mut parent_est_dist_update = 0.0;
+        let mut parent_est_dist_update = T::zero();
         for ((si,pi), d) in indexes.iter().zip(dists) {
-            let emd = (d - self.scale_base.powi(*si)).max(0.0);
+            let emd = (*d - self.scale_base.powi(*si)).max(T::zero());
             parent_est_dist_update = emd.max(parent_est_dist_update);
-            if emd < max_dist {
+            // Keep nodes that could hold a point tied with the kth, one with a smaller index could take its place
+            if emd * self.slack <= max_dist {
                 self.child_heap.push(QueryAddress {
                     address: (*si,*pi),
                     dist_to_center: *d,
                     min_dist: emd,
                 });
+            } else {
+                self.prune((*si, *pi), emd, max_dist);
             }
-            if !self.known_indexes.contains(pi) {
+            if *d <= self.radius && !self.known_indexes.contains(pi) && !self.is_tombstone(pi) {
                 self.known_indexes.insert(*pi);
+                self.encounter(*pi);
                 match self.dist_heap.peek() {
                     Some(my_dist) => {
                         if !(my_dist.dist < *d && self.dist_heap.len() >= self.k) {
@@ -205,6 +587,216 @@ impl KnnQueryHeap {
     }
 }
 
+/// How a KNN query orders points that are the same distance from the query point, set with
+/// `CoverTreeReader::set_tie_break`. Either way the order only depends on the points, not on the shape of the tree, so
+/// it's the same across runs, rebuilds, and save and load round trips.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TieBreak {
+    /// The smaller point index comes first. This is the default, and it's free as the KNN heap keeps this order anyway.
+    Index,
+    /// The name that's first lexicographically, by its bytes, comes first. This looks up the names of the results, and
+    /// if the kth and the next point are tied the query is rerun with a larger `k` until the tie is settled.
+    Name,
+}
+
+/// Reusable buffers for KNN queries, so a loop doing a lot of them doesn't allocate a fresh set of heaps and a result
+/// vector for every query. Pass the same context to each `CoverTreeReader::knn_with` call. It grows to fit the largest
+/// query it's been used for and keeps that memory until it's dropped.
+///
+/// A context is used by one query at a time, so give each thread its own.
+#[derive(Debug)]
+pub struct QueryContext<T: Scalar = f32> {
+    pub(crate) heap: KnnQueryHeap<'static, T>,
+    pub(crate) results: Vec<(T, PointIndex)>,
+}
+
+impl<T: Scalar> QueryContext<T> {
+    /// Creates an empty context, the buffers are allocated by the first query.
+    pub fn new() -> QueryContext<T> {
+        QueryContext {
+            heap: KnnQueryHeap::new(0, T::from_f64(2.0)),
+            results: Vec::new(),
+        }
+    }
+}
+
+impl<T: Scalar> Default for QueryContext<T> {
+    fn default() -> QueryContext<T> {
+        QueryContext::new()
+    }
+}
+
+/// A least recently used cache of KNN results, for a loop that asks the same queries of a tree that isn't changing. Pass
+/// it to `CoverTreeReader::knn_cached`. It holds at most `capacity` results, and a capacity of 0, the default, turns it
+/// off so every query goes to the tree.
+///
+/// A result is keyed by a hash of the bits of each coordinate, along with `k` and the reader's `TieBreak`. So only a
+/// query that's equal bit for bit is a hit, `0.0` and `-0.0`, or two vectors a rounding error apart, are different
+/// queries and both go to the tree. Different vectors can hash the same, so each entry keeps its query vector and a
+/// hit has to match it exactly, a collision is only a miss. The cache remembers the version of the tree it was filled
+/// from, see `CoverTreeWriter::version`, and is emptied the first time it's used with a reader of another version, so
+/// make a new reader after changing the tree and the stale results are dropped. Use one cache per tree, it can't tell
+/// two trees at the same version apart, and `clear` it after a `CoverTreeWriter::merge`, which starts the version over.
+///
+/// Finding the least recently used entry to evict looks at every entry, so keep the capacity to the small sets of
+/// queries this is for.
+#[derive(Debug)]
+pub struct KnnCache<T: Scalar = f32> {
+    capacity: usize,
+    version: Option<u64>,
+    entries: HashMap<u64, Vec<CachedKnn<T>>>,
+    len: usize,
+    clock: u64,
+    hits: usize,
+    misses: usize,
+}
+
+#[derive(Debug)]
+struct CachedKnn<T: Scalar> {
+    point: Vec<T>,
+    k: usize,
+    tie_break: TieBreak,
+    results: Vec<(T, PointIndex)>,
+    last_used: u64,
+}
+
+impl<T: Scalar> CachedKnn<T> {
+    fn matches(&self, point: &[T], k: usize, tie_break: TieBreak) -> bool {
+        self.k == k
+            && self.tie_break == tie_break
+            && self.point.len() == point.len()
+            && self.point.iter().zip(point).all(|(a, b)| a.as_f64().to_bits() == b.as_f64().to_bits())
+    }
+}
+
+impl<T: Scalar> KnnCache<T> {
+    /// Creates an empty cache that holds up to `capacity` results, 0 turns it off.
+    pub fn new(capacity: usize) -> KnnCache<T> {
+        KnnCache {
+            capacity,
+            version: None,
+            entries: HashMap::new(),
+            len: 0,
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// The most results this holds
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of results this holds
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// True if this holds no results
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of queries answered from the cache
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// The number of queries that went to the tree while the cache was on
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// Drops every result, the counts of hits and misses are kept.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.len = 0;
+    }
+
+    fn key(point: &[T], k: usize, tie_break: TieBreak) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for x in point {
+            x.as_f64().to_bits().hash(&mut hasher);
+        }
+        k.hash(&mut hasher);
+        tie_break.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Empties the cache if it was filled from another version of the tree.
+    pub(crate) fn sync_version(&mut self, version: u64) {
+        if self.version != Some(version) {
+            self.clear();
+            self.version = Some(version);
+        }
+    }
+
+    pub(crate) fn get(&mut self, point: &[T], k: usize, tie_break: TieBreak) -> Option<&[(T, PointIndex)]> {
+        self.clock += 1;
+        let clock = self.clock;
+        let found = self
+            .entries
+            .get_mut(&KnnCache::<T>::key(point, k, tie_break))
+            .and_then(|bucket| bucket.iter_mut().find(|e| e.matches(point, k, tie_break)));
+        match found {
+            Some(entry) => {
+                self.hits += 1;
+                entry.last_used = clock;
+                Some(&entry.results)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub(crate) fn insert(&mut self, point: &[T], k: usize, tie_break: TieBreak, results: Vec<(T, PointIndex)>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.len >= self.capacity {
+            self.evict();
+        }
+        self.clock += 1;
+        self.entries
+            .entry(KnnCache::<T>::key(point, k, tie_break))
+            .or_insert_with(Vec::new)
+            .push(CachedKnn {
+                point: point.to_vec(),
+                k,
+                tie_break,
+                results,
+                last_used: self.clock,
+            });
+        self.len += 1;
+    }
+
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .flat_map(|(key, bucket)| bucket.iter().enumerate().map(move |(i, e)| (e.last_used, *key, i)))
+            .min();
+        if let Some((_last_used, key, i)) = oldest {
+            if let Some(bucket) = self.entries.get_mut(&key) {
+                bucket.swap_remove(i);
+                if bucket.is_empty() {
+                    self.entries.remove(&key);
+                }
+                self.len -= 1;
+            }
+        }
+    }
+}
+
+impl<T: Scalar> Default for KnnCache<T> {
+    fn default() -> KnnCache<T> {
+        KnnCache::new(0)
+    }
+}
+
 //Tested in the node file too
 #[cfg(test)]
 pub(crate) mod tests {
@@ -18,33 +18,32 @@
 */
 
 use crate::NodeAddress;
-use pointcloud::PointIndex;
+use pointcloud::{PointIndex, Scalar};
 use std::cmp::Ordering::{self, Less};
-use std::f32;
 
 #[derive(Clone, Copy, Debug)]
-pub(crate) struct QueryAddress {
-    pub(crate) min_dist: f32,
-    pub(crate) dist_to_center: f32,
+pub(crate) struct QueryAddress<T: Scalar = f32> {
+    pub(crate) min_dist: T,
+    pub(crate) dist_to_center: T,
     pub(crate) address: NodeAddress,
 }
 
-impl PartialEq for QueryAddress {
-    fn eq(&self, other: &QueryAddress) -> bool {
+impl<T: Scalar> PartialEq for QueryAddress<T> {
+    fn eq(&self, other: &QueryAddress<T>) -> bool {
         other.address == self.address
     }
 }
 
-impl Eq for QueryAddress {}
+impl<T: Scalar> Eq for QueryAddress<T> {}
 
-impl Ord for QueryAddress {
-    fn cmp(&self, other: &QueryAddress) -> Ordering {
+impl<T: Scalar> Ord for QueryAddress<T> {
+    fn cmp(&self, other: &QueryAddress<T>) -> Ordering {
         self.partial_cmp(&other).unwrap_or(Ordering::Less)
     }
 }
 
-impl PartialOrd for QueryAddress {
-    fn partial_cmp(&self, other: &QueryAddress) -> Option<Ordering> {
+impl<T: Scalar> PartialOrd for QueryAddress<T> {
+    fn partial_cmp(&self, other: &QueryAddress<T>) -> Option<Ordering> {
         // Backwards to make it a max heap.
         match other
             .min_dist
@@ -67,33 +66,37 @@ impl PartialOrd for QueryAddress {
 }
 
 #[derive(Clone, Copy, Debug)]
-pub(crate) struct QuerySingleton {
-    pub(crate) dist: f32,
+pub(crate) struct QuerySingleton<T: Scalar = f32> {
+    pub(crate) dist: T,
     pub(crate) index: PointIndex,
 }
 
-impl QuerySingleton {
-    pub(crate) fn new(index: PointIndex, dist: f32) -> QuerySingleton {
+impl<T: Scalar> QuerySingleton<T> {
+    pub(crate) fn new(index: PointIndex, dist: T) -> QuerySingleton<T> {
         QuerySingleton { dist, index }
     }
 }
 
-impl PartialEq for QuerySingleton {
-    fn eq(&self, other: &QuerySingleton) -> bool {
+impl<T: Scalar> PartialEq for QuerySingleton<T> {
+    fn eq(&self, other: &QuerySingleton<T>) -> bool {
         other.index == self.index
     }
 }
 
-impl Eq for QuerySingleton {}
+impl<T: Scalar> Eq for QuerySingleton<T> {}
 
-impl Ord for QuerySingleton {
-    fn cmp(&self, other: &QuerySingleton) -> Ordering {
+impl<T: Scalar> Ord for QuerySingleton<T> {
+    fn cmp(&self, other: &QuerySingleton<T>) -> Ordering {
         self.partial_cmp(&other).unwrap_or(Less)
     }
 }
 
-impl PartialOrd for QuerySingleton {
-    fn partial_cmp(&self, other: &QuerySingleton) -> Option<Ordering> {
-        self.dist.partial_cmp(&other.dist)
+impl<T: Scalar> PartialOrd for QuerySingleton<T> {
+    /// Ties are broken by index, so the distance heap always drops the point with the largest index among equals.
+    fn partial_cmp(&self, other: &QuerySingleton<T>) -> Option<Ordering> {
+        match self.dist.partial_cmp(&other.dist) {
+            Some(Ordering::Equal) => Some(self.index.cmp(&other.index)),
+            ordering => ordering,
+        }
     }
 }
@@ -0,0 +1,285 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+
+//! Standardizing each coordinate of the points, for data whose coordinates are on different scales.
+
+use crate::*;
+use errors::{MalwareBrotError, MalwareBrotResult, ParsingError};
+use tree_file_format::CoreProto;
+
+/// Shifts and scales each coordinate so that over the data it was fit on it has mean 0 and standard deviation 1. Without
+/// this a coordinate that's measured in the thousands swamps one that's measured in fractions, in every distance.
+///
+/// Fit it on the training data with `fit`, transform that data with `transform_all` before building the tree on it, and
+/// transform each query with `transform`. A `StandardizedCoverTree` keeps the standardizer with the tree and transforms
+/// the queries for you. A coordinate that's the same for every point has no spread to divide by, it's only shifted, so it
+/// ends up 0 for every point of the data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Standardizer {
+    means: Vec<f32>,
+    scales: Vec<f32>,
+}
+
+impl Standardizer {
+    /// Computes the mean and standard deviation of each coordinate of the points in `data`, which holds them one after
+    /// another like `PointCloud::simple_from_ram` takes them. The sums are done in `f64`. Returns `DimensionMismatch` if
+    /// the data isn't a whole number of points of dimension `dim`. No data fits the identity, that changes nothing.
+    pub fn fit(data: &[f32], dim: usize) -> MalwareBrotResult<Standardizer> {
+        if dim == 0 || data.len() % dim != 0 {
+            return Err(MalwareBrotError::DimensionMismatch {
+                expected: dim,
+                got: data.len() % dim.max(1),
+            });
+        }
+        let count = (data.len() / dim) as f64;
+        let mut means = vec![0.0f64; dim];
+        for point in data.chunks(dim) {
+            for (mean, x) in means.iter_mut().zip(point) {
+                *mean += *x as f64;
+            }
+        }
+        for mean in means.iter_mut() {
+            *mean /= count.max(1.0);
+        }
+        let mut variances = vec![0.0f64; dim];
+        for point in data.chunks(dim) {
+            for ((variance, mean), x) in variances.iter_mut().zip(&means).zip(point) {
+                *variance += (*x as f64 - mean).powi(2);
+            }
+        }
+        let scales = variances
+            .iter()
+            .map(|variance| {
+                let deviation = (variance / count.max(1.0)).sqrt() as f32;
+                if deviation > 0.0 && deviation.is_finite() {
+                    deviation
+                } else {
+                    1.0
+                }
+            })
+            .collect();
+        Ok(Standardizer {
+            means: means.iter().map(|mean| *mean as f32).collect(),
+            scales,
+        })
+    }
+
+    /// The dimension of the points this transforms.
+    pub fn dim(&self) -> usize {
+        self.means.len()
+    }
+
+    /// The mean of each coordinate over the data it was fit on.
+    pub fn means(&self) -> &[f32] {
+        &self.means
+    }
+
+    /// What each coordinate is divided by, its standard deviation over the data it was fit on or 1 if that was 0.
+    pub fn scales(&self) -> &[f32] {
+        &self.scales
+    }
+
+    /// The standardized copy of a point. Returns `DimensionMismatch` if it's not the dimension this was fit on.
+    pub fn transform(&self, point: &[f32]) -> MalwareBrotResult<Vec<f32>> {
+        let mut point = point.to_vec();
+        self.transform_all(&mut point)?;
+        Ok(point)
+    }
+
+    /// Standardizes the points in `data` in place, they're one after another like `fit` takes them. Returns
+    /// `DimensionMismatch` if the data isn't a whole number of points of the dimension this was fit on.
+    pub fn transform_all(&self, data: &mut [f32]) -> MalwareBrotResult<()> {
+        let dim = self.dim();
+        if dim == 0 || data.len() % dim != 0 {
+            return Err(MalwareBrotError::DimensionMismatch {
+                expected: dim,
+                got: data.len() % dim.max(1),
+            });
+        }
+        for point in data.chunks_mut(dim) {
+            for ((x, mean), scale) in point.iter_mut().zip(&self.means).zip(&self.scales) {
+                *x = (*x - mean) / scale;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A cover tree over standardized points, that standardizes the queries and inserted points the same way. The tree is a
+/// plain `CoverTreeWriter` built on points that went through the `Standardizer`, and the distances that come back are
+/// between standardized points.
+///
+/// The standardizer is saved in the tree's protobuf by `save`, so a loaded tree transforms its queries like the one that
+/// was saved. Readers from `reader` are readers of the plain tree, their queries have to be transformed by hand. The
+/// standardizer works in `f32`, so the metric has to be one of `f32`s.
+pub struct StandardizedCoverTree<M: Metric = L2> {
+    writer: CoverTreeWriter<M>,
+    standardizer: Standardizer,
+}
+
+impl<M: Metric<Scalar = f32>> StandardizedCoverTree<M> {
+    /// Puts a tree built on standardized points together with the standardizer they went through. Returns
+    /// `DimensionMismatch` if the standardizer isn't for points of the tree's dimension.
+    pub fn new(writer: CoverTreeWriter<M>, standardizer: Standardizer) -> MalwareBrotResult<StandardizedCoverTree<M>> {
+        let expected = writer.point_cloud().dim();
+        if standardizer.dim() != expected {
+            return Err(MalwareBrotError::DimensionMismatch {
+                expected,
+                got: standardizer.dim(),
+            });
+        }
+        Ok(StandardizedCoverTree { writer, standardizer })
+    }
+
+    /// The standardizer the points and queries go through.
+    pub fn standardizer(&self) -> &Standardizer {
+        &self.standardizer
+    }
+
+    /// The tree of standardized points.
+    pub fn writer(&self) -> &CoverTreeWriter<M> {
+        &self.writer
+    }
+
+    /// A reader of the current tree, see `CoverTreeWriter::reader`. It doesn't standardize its queries.
+    pub fn reader(&self) -> CoverTreeReader<M> {
+        self.writer.reader()
+    }
+
+    /// Adds a point, standardized, see `CoverTreeWriter::insert`.
+    pub fn insert(
+        &mut self,
+        name: PointName,
+        point: &[f32],
+        metadata: pointcloud::labels::values::Metadata,
+    ) -> MalwareBrotResult<PointIndex> {
+        let point = self.standardizer.transform(point)?;
+        self.writer.insert(name, &point, metadata)
+    }
+
+    /// The KNN query of the standardized point, see `CoverTreeReader::knn`.
+    pub fn knn(&self, point: &[f32], k: usize) -> MalwareBrotResult<Vec<(f32, PointIndex)>> {
+        self.writer.reader().knn(&self.standardizer.transform(point)?, k)
+    }
+
+    /// The KNN query of the standardized point with the names of the neighbors, see `CoverTreeReader::knn_names`.
+    pub fn knn_names(&self, point: &[f32], k: usize) -> MalwareBrotResult<Vec<(f32, PointName)>> {
+        self.writer.reader().knn_names(&self.standardizer.transform(point)?, k)
+    }
+
+    /// The range query of the standardized point, see `CoverTreeReader::range_query`. The radius is in standardized
+    /// units, about how many standard deviations away a point is.
+    pub fn range_query(&self, point: &[f32], radius: f32) -> MalwareBrotResult<Vec<(f32, PointName)>> {
+        self.writer.reader().range_query(&self.standardizer.transform(point)?, radius)
+    }
+
+    /// Encodes the tree into a protobuf like `CoverTreeWriter::save`, with the means and scales of the standardizer.
+    pub fn save(&self) -> CoreProto {
+        let mut cover_proto = self.writer.save();
+        cover_proto.set_standard_means(self.standardizer.means.clone());
+        cover_proto.set_standard_scales(self.standardizer.scales.clone());
+        cover_proto
+    }
+
+    /// Loads a tree and its standardizer saved with `save`, see `CoverTreeWriter::load`. A tree saved without a
+    /// standardizer, or with one that's not for its dimension, is a parsing error.
+    pub fn load(cover_proto: &CoreProto, point_cloud: PointCloud<M>) -> MalwareBrotResult<StandardizedCoverTree<M>> {
+        let dim = point_cloud.dim();
+        let means = cover_proto.get_standard_means();
+        let scales = cover_proto.get_standard_scales();
+        if means.len() != dim || scales.len() != dim {
+            return Err(MalwareBrotError::ParsingError(ParsingError::RegularParsingError(
+                "The tree wasn't saved with a standardizer for its dimension",
+            )));
+        }
+        let standardizer = Standardizer {
+            means: means.to_vec(),
+            scales: scales.to_vec(),
+        };
+        let writer = CoverTreeWriter::load(cover_proto, point_cloud)?;
+        StandardizedCoverTree::new(writer, standardizer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_standardizes_and_skips_flat_coordinates() {
+        // The first coordinate is in the thousands, the second in fractions, the last is the same everywhere
+        let data: Vec<f32> = (0..100)
+            .flat_map(|i| vec![1000.0 + 10.0 * i as f32, 0.01 * (i % 7) as f32, 5.0])
+            .collect();
+        let standardizer = Standardizer::fit(&data, 3).unwrap();
+        assert_eq!(standardizer.dim(), 3);
+        assert_approx_eq!(standardizer.means()[0], 1495.0, 1e-2);
+        assert_eq!(standardizer.means()[2], 5.0);
+        assert_eq!(standardizer.scales()[2], 1.0);
+
+        let mut standardized = data.clone();
+        standardizer.transform_all(&mut standardized).unwrap();
+        for d in 0..2 {
+            let column: Vec<f64> = standardized.chunks(3).map(|p| p[d] as f64).collect();
+            let mean = column.iter().sum::<f64>() / 100.0;
+            let variance = column.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / 100.0;
+            assert!(mean.abs() < 1e-4);
+            assert!((variance - 1.0).abs() < 1e-3);
+        }
+        assert!(standardized.chunks(3).all(|p| p[2] == 0.0));
+        assert_eq!(standardizer.transform(&data[3..6]).unwrap(), &standardized[3..6]);
+
+        match standardizer.transform(&[1.0, 2.0]) {
+            Err(MalwareBrotError::DimensionMismatch { expected: 3, .. }) => {}
+            _ => panic!("Expected a DimensionMismatch"),
+        }
+        assert!(Standardizer::fit(&data[..7], 3).is_err());
+    }
+
+    #[test]
+    fn queries_are_standardized_and_the_standardizer_is_saved() {
+        let data: Vec<f32> = (0..200)
+            .map(|i| if i % 2 == 0 { 1000.0 * rand::random::<f32>() } else { rand::random::<f32>() })
+            .collect();
+        let labels = vec![0.0; 100];
+        let standardizer = Standardizer::fit(&data, 2).unwrap();
+        let mut standardized = data.clone();
+        standardizer.transform_all(&mut standardized).unwrap();
+        let point_cloud = || {
+            PointCloud::<L2>::simple_from_ram(Box::from(standardized.clone()), 2, Box::from(labels.clone()), 1).unwrap()
+        };
+        let writer = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud()).unwrap();
+        let tree = StandardizedCoverTree::new(writer, standardizer.clone()).unwrap();
+
+        let query = [500.0, 0.5];
+        let neighbors = tree.knn(&query, 5).unwrap();
+        assert_eq!(neighbors, tree.reader().knn(&standardizer.transform(&query).unwrap(), 5).unwrap());
+        let (_d, nearest) = neighbors[0];
+        assert_eq!(tree.knn(&data[2 * nearest as usize..2 * nearest as usize + 2], 1).unwrap()[0].0, 0.0);
+
+        let bytes = protobuf::Message::write_to_bytes(&tree.save()).unwrap();
+        let cover_proto: CoreProto = protobuf::parse_from_bytes(&bytes).unwrap();
+        let loaded = StandardizedCoverTree::load(&cover_proto, point_cloud()).unwrap();
+        assert_eq!(loaded.standardizer(), &standardizer);
+        assert_eq!(loaded.knn(&query, 5).unwrap(), neighbors);
+        // A plain tree has no standardizer to load
+        assert!(StandardizedCoverTree::load(&tree.writer().save(), point_cloud()).is_err());
+    }
+}
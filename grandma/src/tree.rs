@@ -37,15 +37,177 @@ use crate::*;
 use layer::*;
 use node::*;
 //use pointcloud::*;
+use num_traits::{Float, One, Zero};
 
 use tree_file_format::*;
 use std::sync::{atomic, Arc};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-use crate::query_tools::KnnQueryHeap;
-use errors::MalwareBrotResult;
+use crate::builders::{build_subtree, subtree_scale_index};
+use crate::query_tools::{ApproxBound, KnnCache, KnnQueryHeap, PruneEvent, QueryContext, QueryMetrics, TieBreak};
+use errors::{MalwareBrotError, MalwareBrotResult};
 use std::iter::Iterator;
 use std::ops::Range;
 use std::slice::Iter;
+use std::fmt;
+use std::time::{Duration, Instant};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize, Serializer};
+#[cfg(feature = "ndarray")]
+use ndarray::ArrayView2;
+#[cfg(feature = "ndarray")]
+use pointcloud::labels::{values::Metadata, LabelScheme};
+
+/// The relative tolerance `CoverTreeReader::validate` compares distances with. The metrics are accurate to a few units in
+/// the last place of an `f32`, about `1e-7` relative, and this leaves room for that to add up over a few hundred dimensions.
+/// Trees of `f64`s get the same tolerance, which is looser than they need.
+pub const DEFAULT_VALIDATE_EPSILON: f32 = 1e-5;
+
+/// The format version `CoverTreeWriter::save_fast` writes as the first byte of its encoding. `load_fast` only reads
+/// this version, it's raised whenever the encoding of `CoreSerde` changes.
+#[cfg(feature = "bincode")]
+pub const FAST_FORMAT_VERSION: u8 = 3;
+
+/// How many times `k` candidates `knn_with_metric` gathers with the tree's own metric before ranking them with the other.
+pub const KNN_WITH_METRIC_OVERFETCH: usize = 4;
+
+/// The most layers a tree can have. There's a layer for each scale index from the resolution up to the root's, and one
+/// below for the leaves, so the root's scale index can be at most `MAX_LAYERS - 2` above the resolution. Each layer is a
+/// pair of maps even when it's empty, so this is what bounds the size of a tree, not the number of points. Points are
+/// indexed with a `u64` and there's no limit on them other than memory. The resolution also has to leave room for the
+/// layers between it and the ends of an `i32`. Building, inserting or loading past these is a `CapacityExceeded` error.
+///
+/// With a scale base of 2 this is over 65 thousand powers of 2, far more than an `f32` distance can span. Only scale
+/// bases very close to 1 or resolutions far below the data need this many.
+pub const MAX_LAYERS: usize = 1 << 16;
+
+/// The sequence number of the first insert into a tree over this point cloud. The build numbers its points by their
+/// index, so this is one past the largest.
+pub(crate) fn first_insertion<M: Metric>(point_cloud: &PointCloud<M>) -> u64 {
+    point_cloud.reference_indexes().iter().max().map(|pi| pi + 1).unwrap_or(0)
+}
+
+/// Checks the resolution leaves room for `MAX_LAYERS` layers above it, and one below, in an `i32`.
+pub(crate) fn check_resolution(resolution: i32) -> MalwareBrotResult<()> {
+    if resolution == i32::min_value() || resolution > i32::max_value() - MAX_LAYERS as i32 {
+        return Err(MalwareBrotError::CapacityExceeded {
+            scale_index: resolution,
+            resolution,
+        });
+    }
+    Ok(())
+}
+
+/// The CRC32 of the encoded layers, in order, that `save` writes into the `CoreProto` and `load` checks.
+fn layers_checksum(layers: &[LayerProto]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for layer in layers {
+        // Encoding only fails on unset required fields, and proto3 has none
+        if let Ok(bytes) = protobuf::Message::write_to_bytes(layer) {
+            hasher.update(&bytes);
+        }
+    }
+    hasher.finalize()
+}
+
+/// Checks a tree file can be loaded over a point cloud with these indexes without panicking or looping. Every layer is
+/// where its scale index puts it, the root and every child is a node in the file on a lower layer than its parent, and
+/// every center and singleton is in the point cloud. This is for files that may have been tampered with, so it
+/// doesn't trust anything the file says about its own size. It doesn't check the covering or separation, that's
+/// `CoverTreeReader::validate`.
+///
+/// Scale indexes below the resolution are all on the bottom layer, like `CoverTreeParameters::internal_index` has it,
+/// so they're never out of range. The bottom layer's own scale index is the resolution or below.
+fn check_core_proto(cover_proto: &CoreProto, point_indexes: &HashSet<PointIndex>) -> MalwareBrotResult<()> {
+    let invalid = |e| Err(MalwareBrotError::ParsingError(e));
+    let resolution = cover_proto.resolution as i64;
+    let layers = cover_proto.get_layers();
+    for (position, layer) in layers.iter().enumerate() {
+        let scale_index = layer.get_scale_index() as i64;
+        let in_place = match position {
+            0 => scale_index <= resolution,
+            _ => scale_index == resolution - 1 + position as i64,
+        };
+        if position >= MAX_LAYERS || !in_place {
+            return invalid(errors::ParsingError::MisplacedLayer {
+                position,
+                scale_index: layer.get_scale_index(),
+            });
+        }
+    }
+    let position = |scale_index: i32| -> Option<usize> {
+        let position = (scale_index as i64 - resolution + 1).max(0) as usize;
+        if position < layers.len() {
+            Some(position)
+        } else {
+            None
+        }
+    };
+    let nodes: HashSet<(usize, PointIndex)> = layers
+        .iter()
+        .enumerate()
+        .flat_map(|(p, l)| l.get_nodes().iter().map(move |n| (p, n.get_center_index())))
+        .collect();
+
+    let root_address = (cover_proto.get_root_scale(), cover_proto.get_root_index());
+    match position(root_address.0) {
+        None => {
+            return invalid(errors::ParsingError::ScaleOutOfRange {
+                node: root_address,
+                scale_index: root_address.0,
+            })
+        }
+        Some(p) if !nodes.contains(&(p, root_address.1)) => {
+            return invalid(errors::ParsingError::MissingNode {
+                parent: root_address,
+                child: root_address,
+            })
+        }
+        Some(_) => {}
+    }
+    for (layer_position, layer) in layers.iter().enumerate() {
+        for node in layer.get_nodes() {
+            let address = (layer.get_scale_index(), node.get_center_index());
+            let indexes = std::iter::once(&node.center_index).chain(node.get_outlier_point_indexes());
+            for index in indexes {
+                if !point_indexes.contains(index) {
+                    return invalid(errors::ParsingError::UnknownIndex { node: address, index: *index });
+                }
+            }
+            if node.get_is_leaf() {
+                continue;
+            }
+            if node.get_children_scale_indexes().len() != node.get_children_point_indexes().len() {
+                return invalid(errors::ParsingError::MismatchedChildren { node: address });
+            }
+            let nested = (node.get_nested_scale_index(), address.1);
+            let children = node
+                .get_children_scale_indexes()
+                .iter()
+                .cloned()
+                .zip(node.get_children_point_indexes().iter().cloned());
+            for child in std::iter::once(nested).chain(children) {
+                // A child on the parent's layer or above could lead a walk down the tree in a circle
+                match position(child.0) {
+                    Some(p) if p < layer_position => {
+                        if !nodes.contains(&(p, child.1)) {
+                            return invalid(errors::ParsingError::MissingNode { parent: address, child });
+                        }
+                    }
+                    _ => {
+                        return invalid(errors::ParsingError::ScaleOutOfRange {
+                            node: address,
+                            scale_index: child.0,
+                        })
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
 
 /// Container for the parameters governing the construction of the covertree
 #[derive(Debug)]
@@ -54,10 +216,10 @@ pub struct CoverTreeParameters<M: Metric> {
     /// This may not reflect what your current reader can see.
     pub total_nodes: atomic::AtomicUsize,
     /// See paper or main description, governs the number of children of each node. Higher is more.
-    pub scale_base: f32,
+    pub scale_base: M::Scalar,
     /// If a node covers less than or equal to this number of points, it becomes a leaf.
     pub cutoff: usize,
-    /// If a node has scale index less than or equal to this, it becomes a leaf
+    /// The minimum level, a node with a scale index below this is a leaf. See `CoverTreeBuilder::resolution`.
     pub resolution: i32,
     /// If you don't want singletons messing with your tree and want everything to be a node or a element of leaf node, make this true. 
     pub use_singletons: bool,
@@ -67,6 +229,15 @@ pub struct CoverTreeParameters<M: Metric> {
     pub point_cloud: PointCloud<M>,
     /// This should be replaced by a logging solution
     pub verbosity: u32,
+    /// How the points were handed out to the children when the tree was built. Loaded trees report `FirstFit`.
+    pub insertion_strategy: InsertionStrategy,
+    /// Whether inserted and query vectors are checked for NaN and infinite coordinates. Loaded trees check.
+    pub check_finite: atomic::AtomicBool,
+    /// Whether new centers are the first uncovered point rather than a random one. Loaded trees pick at random.
+    pub deterministic: bool,
+    /// Whether the leaves group their bitwise equal points as they're split off, for
+    /// `CoverTreeBuilder::build_report_duplicates`. Loaded trees don't.
+    pub report_duplicates: bool,
 }
 
 impl<M: Metric> CoverTreeParameters<M> {
@@ -75,19 +246,69 @@ impl<M: Metric> CoverTreeParameters<M> {
         if scale_index < self.resolution {
             0
         } else {
-            (scale_index - self.resolution + 1) as usize
+            (scale_index as i64 - self.resolution as i64 + 1) as usize
+        }
+    }
+
+    /// The internal index of the layer for a scale index, or `CapacityExceeded` if that's past `MAX_LAYERS`.
+    pub(crate) fn checked_internal_index(&self, scale_index: i32) -> MalwareBrotResult<usize> {
+        let index = self.internal_index(scale_index);
+        if index < MAX_LAYERS {
+            Ok(index)
+        } else {
+            Err(MalwareBrotError::CapacityExceeded {
+                scale_index,
+                resolution: self.resolution,
+            })
+        }
+    }
+}
+
+/// A summary of the shape of the tree, see `CoverTreeReader::stats`. Depth counts nested children as their own level, so
+/// the root alone has depth 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeStats {
+    /// The number of nodes on the longest path from the root to a leaf
+    pub max_depth: usize,
+    /// The total number of nodes reachable from the root
+    pub node_count: usize,
+    /// The number of nodes without children
+    pub leaf_count: usize,
+    /// The total number of singletons attached to nodes
+    pub singleton_count: usize,
+    /// The average number of children, including the nested child, of the nodes that aren't leaves
+    pub avg_children: f32,
+    /// The largest number of children of a single node
+    pub max_children: usize,
+    /// The lowest scale index with a node on it
+    pub min_scale_index: i32,
+    /// The highest scale index with a node on it, this is the root's
+    pub max_scale_index: i32,
+    /// The number of nodes at each scale index, see `CoverTreeReader::level_counts`
+    pub level_counts: BTreeMap<i32, usize>,
+}
+
+impl fmt::Display for TreeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "nodes: {}, leaves: {}, singletons: {}", self.node_count, self.leaf_count, self.singleton_count)?;
+        writeln!(f, "max depth: {}, scale indexes: {} to {}", self.max_depth, self.min_scale_index, self.max_scale_index)?;
+        writeln!(f, "children per routing node: {:.2} average, {} max", self.avg_children, self.max_children)?;
+        write!(f, "nodes per scale index:")?;
+        for (si, count) in self.level_counts.iter().rev() {
+            write!(f, " {}: {}", si, count)?;
         }
+        Ok(())
     }
 }
 
 /// Helper struct for iterating thru the reader's of the the layers. 
-pub struct LayerIter<'a> {
+pub struct LayerIter<'a, T: Scalar = f32> {
     scales: Range<i32>,
-    layers: Iter<'a, CoverLayerReader>,
+    layers: Iter<'a, CoverLayerReader<T>>,
 }
 
-impl<'a> Iterator for LayerIter<'a> {
-    type Item = (i32, &'a CoverLayerReader);
+impl<'a, T: Scalar> Iterator for LayerIter<'a, T> {
+    type Item = (i32, &'a CoverLayerReader<T>);
     fn next(&mut self) -> Option<Self::Item> {
         match (self.scales.next(), self.layers.next()) {
             (Some(a), Some(b)) => Some((a, b)),
@@ -96,6 +317,77 @@ impl<'a> Iterator for LayerIter<'a> {
     }
 }
 
+/// The points of a range query in the order the traversal finds them, see `CoverTreeReader::knn_streaming`. Each node
+/// is only visited when the points from the one before have all been taken.
+pub struct RangeStream<'a, M: Metric> {
+    reader: &'a CoverTreeReader<M>,
+    point: &'a [M::Scalar],
+    radius: M::Scalar,
+    unvisited: Vec<(M::Scalar, NodeAddress)>,
+    found: Vec<(M::Scalar, PointIndex)>,
+}
+
+impl<'a, M: Metric> RangeStream<'a, M> {
+    /// Checks the node against the radius, and queues the points it has in range and the children to visit after it.
+    fn visit(&mut self, dist: M::Scalar, address: NodeAddress) -> MalwareBrotResult<()> {
+        let point_cloud = &self.reader.parameters.point_cloud;
+        let point = self.point;
+        let radius = self.radius;
+        let unvisited = &mut self.unvisited;
+        let found = &mut self.found;
+        let node_result = self.reader.get_node_and(address, |n| -> MalwareBrotResult<()> {
+            if dist - n.radius() > radius {
+                return Ok(());
+            }
+            let distances = point_cloud.distances_to_point(point, n.singletons())?;
+            for (pi, d) in n.singletons().iter().zip(distances) {
+                if d <= radius {
+                    found.push((d, *pi));
+                }
+            }
+            if let Some((nested_scale, children)) = n.children() {
+                unvisited.push((dist, (nested_scale, address.1)));
+                let children_indexes: Vec<PointIndex> = children.iter().map(|(_si, pi)| *pi).collect();
+                let distances = point_cloud.distances_to_point(point, &children_indexes)?;
+                for (ca, d) in children.iter().zip(distances) {
+                    if d <= radius {
+                        found.push((d, ca.1));
+                    }
+                    unvisited.push((d, *ca));
+                }
+            }
+            Ok(())
+        });
+        match node_result {
+            Some(node_result) => node_result,
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a, M: Metric> Iterator for RangeStream<'a, M> {
+    type Item = MalwareBrotResult<(M::Scalar, PointName)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            while let Some((d, pi)) = self.found.pop() {
+                if self.reader.tombstones.contains(&pi) {
+                    continue;
+                }
+                return Some(match self.reader.parameters.point_cloud.get_name(&pi) {
+                    Some(name) => Ok((d, name.to_vec())),
+                    None => Err(MalwareBrotError::IndexNotInTree(pi)),
+                });
+            }
+            let (dist, address) = self.unvisited.pop()?;
+            if let Err(e) = self.visit(dist, address) {
+                // Nothing after an error can be trusted, so the stream ends with it
+                self.unvisited.clear();
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
 /// # Cover Tree Reader Head
 ///
 /// You can clone the reader head, though this is a relatively expensive operation and should not be performed lightly.
@@ -105,36 +397,136 @@ impl<'a> Iterator for LayerIter<'a> {
 ///
 /// The data structure is just a list of `CoverLayerReader`s, the parameter's object and the root address. Copies are relatively
 /// expensive as each `CoverLayerReader` contains several Arcs that need to be cloned.
-pub struct CoverTreeReader<M: Metric> {
+///
+/// The reader is `Send` but not `Sync`. All the queries take `&self` and only read the tree and the point cloud, but each
+/// layer's read head tracks which of the pair of hash-maps it's looking at, so two threads can't share one. To query from
+/// many threads give each thread its own reader, from a `CoverTreeReaderFactory` shared between them.
+pub struct CoverTreeReader<M: Metric = L2> {
+    parameters: Arc<CoverTreeParameters<M>>,
+    layers: Vec<CoverLayerReader<M::Scalar>>,
+    root_address: NodeAddress,
+    tie_break: TieBreak,
+    rank_singletons: bool,
+    tombstones: Arc<HashSet<PointIndex>>,
+    absent: Arc<HashSet<PointIndex>>,
+    version: u64,
+    point_count: usize,
+}
+
+/// A `Sync` handle that makes readers, so you can share one between your query threads and have each make its own
+/// `CoverTreeReader`. Making a reader takes a short lock on each layer, querying with it takes none. Like a reader, this
+/// has the root address and layers of the tree when it was made, if a removal changes the root make a new factory.
+pub struct CoverTreeReaderFactory<M: Metric = L2> {
     parameters: Arc<CoverTreeParameters<M>>,
-    layers: Vec<CoverLayerReader>,
+    layers: Vec<CoverLayerReaderFactory<M::Scalar>>,
     root_address: NodeAddress,
+    tie_break: TieBreak,
+    rank_singletons: bool,
+    tombstones: Arc<HashSet<PointIndex>>,
+    absent: Arc<HashSet<PointIndex>>,
+    version: u64,
+    point_count: usize,
+}
+
+impl<M: Metric> CoverTreeReaderFactory<M> {
+    /// Makes a new reader.
+    pub fn reader(&self) -> CoverTreeReader<M> {
+        CoverTreeReader {
+            parameters: Arc::clone(&self.parameters),
+            layers: self.layers.iter().map(|l| l.reader()).collect(),
+            root_address: self.root_address,
+            tie_break: self.tie_break,
+            rank_singletons: self.rank_singletons,
+            tombstones: Arc::clone(&self.tombstones),
+            absent: Arc::clone(&self.absent),
+            version: self.version,
+            point_count: self.point_count,
+        }
+    }
 }
 
 impl<M: Metric> CoverTreeReader<M> {
+    /// A `Sync` handle that can make more readers of this tree, see `CoverTreeReaderFactory`. The readers it makes have
+    /// this reader's tie break and `rank_singletons`.
+    pub fn factory(&self) -> CoverTreeReaderFactory<M> {
+        CoverTreeReaderFactory {
+            parameters: Arc::clone(&self.parameters),
+            layers: self.layers.iter().map(|l| l.factory()).collect(),
+            root_address: self.root_address,
+            tie_break: self.tie_break,
+            rank_singletons: self.rank_singletons,
+            tombstones: Arc::clone(&self.tombstones),
+            absent: Arc::clone(&self.absent),
+            version: self.version,
+            point_count: self.point_count,
+        }
+    }
+
+    /// Sets how this reader's KNN queries order points at the same distance, see `TieBreak`. The default is by index.
+    pub fn set_tie_break(&mut self, tie_break: TieBreak) -> &mut Self {
+        self.tie_break = tie_break;
+        self
+    }
+
+    /// How this reader's KNN queries order points at the same distance.
+    pub fn tie_break(&self) -> TieBreak {
+        self.tie_break
+    }
+
+    /// Has this reader's KNN queries rank the singletons of a node with `Metric::dense_rank` and only take the distance
+    /// of the ones that can still make the `k`, for `L2` that's comparing squared distances and skipping the `sqrt` of
+    /// the rest. The distances of the nodes' centers are still taken, the pruning subtracts covering radii from them
+    /// and that doesn't work on squares. The bound a rank is held to is rounded up, and the distances that are kept
+    /// are the same as `dense`, so the results are the same as without it. The default is off.
+    ///
+    /// It pays off when most of the points are singletons and the `sqrt` is a large part of the distance, low
+    /// dimensional data with a small `cutoff`. Metrics that don't override `dense_rank` get nothing from it but an extra
+    /// pass over the ranks.
+    pub fn set_rank_singletons(&mut self, rank_singletons: bool) -> &mut Self {
+        self.rank_singletons = rank_singletons;
+        self
+    }
+
+    /// If this reader's KNN queries rank singletons before taking their distances.
+    pub fn rank_singletons(&self) -> bool {
+        self.rank_singletons
+    }
+
     /// A reference to the point cloud the tree was built on.
     pub fn point_cloud(&self) -> &PointCloud<M> {
         &self.parameters.point_cloud
     }
 
+    /// The version of the tree when this reader was made, see `CoverTreeWriter::version`.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
     /// Returns a borrowed reader for a cover layer. 
     /// 
-    pub fn layer(&self, scale_index: i32) -> &CoverLayerReader {
+    pub fn layer(&self, scale_index: i32) -> &CoverLayerReader<M::Scalar> {
         &self.layers[self.parameters.internal_index(scale_index)]
     }
 
+    /// The scale base `b` the tree was built with, see `CoverTreeBuilder`.
+    pub fn scale_base(&self) -> M::Scalar {
+        self.parameters.scale_base
+    }
+
     /// simple helper to get the scale from the scale index and the scale base, this is just `b^i`
-    pub fn scale(&self, scale_index: i32) -> f32 {
+    pub fn scale(&self, scale_index: i32) -> M::Scalar {
         self.parameters.scale_base.powi(scale_index)
     }
 
     /// Read only access to the internals of a node. 
     pub fn get_node_and<F, T>(&self, node_address: (i32, PointIndex), f: F) -> Option<T>
     where
-        F: FnOnce(&CoverNode) -> T,
+        F: FnOnce(&CoverNode<M::Scalar>) -> T,
     {
-        self.layers[self.parameters.internal_index(node_address.0)]
-            .get_node_and(&node_address.1, |n| f(n))
+        // The writer can add layers on top, which an older reader won't have.
+        self.layers
+            .get(self.parameters.internal_index(node_address.0))
+            .and_then(|l| l.get_node_and(&node_address.1, |n| f(n)))
     }
 
     /// The root of the tree. Pass this to `get_node_and` to get the root node's content and start a traversal of the tree.
@@ -142,11 +534,69 @@ impl<M: Metric> CoverTreeReader<M> {
         self.root_address
     }
 
+    /// The name and vector of the root's center, a representative of the whole data set. The build centers the root on
+    /// the last point of the point cloud, it's not picked to be central. Removing that point moves the root to the
+    /// point nearest to it. `None` if the tree has no root, see `EmptyTree`, or the center has no name.
+    ///
+    /// The name is borrowed as bytes, so it works with interned names, see `NameStorage`. This is a breaking change, it
+    /// used to be a `&PointName`, call `to_vec` on it where you need one.
+    pub fn root(&self) -> Option<(&[u8], Cow<'_, [M::Scalar]>)> {
+        if self.is_empty() {
+            return None;
+        }
+        let point_cloud = &self.parameters.point_cloud;
+        let name = point_cloud.get_name(&self.root_address.1)?;
+        let point = point_cloud.get_point(self.root_address.1).ok()?;
+        Some((name, point))
+    }
+
+    /// True if the reader can't see a root node, so there's nothing to query against. A built tree always has a point
+    /// in it, this only happens to a reader whose writer has removed the root out from under it. The KNN, range and
+    /// furthest neighbor queries return no results on an empty tree, rather than an error, see `knn`.
+    pub fn is_empty(&self) -> bool {
+        self.get_node_and(self.root_address, |_n| ()).is_none()
+    }
+
+    /// The number of points in the tree, tombstoned points left out. The writer keeps count as points go in and out, so
+    /// this is just a subtraction. Like `version` it's the count when the reader was made.
+    pub fn len(&self) -> usize {
+        self.point_count.saturating_sub(self.tombstones.len())
+    }
+
+    /// True if the point with this name is in the tree, so queries can return it. It's a lookup in the point cloud's
+    /// name map, and in the sets of tombstoned points and of points the tree doesn't reference. A removed point is
+    /// still in the point cloud, the writer remembers it's out of the tree.
+    pub fn contains<N: AsRef<[u8]>>(&self, name: N) -> bool {
+        let pi = match self.parameters.point_cloud.get_index(name) {
+            Some(pi) => *pi,
+            None => return false,
+        };
+        let contained = !self.tombstones.contains(&pi) && !self.absent.contains(&pi);
+        debug_assert_eq!(
+            contained,
+            !self.tombstones.contains(&pi) && self.locate(pi).map(|l| l.is_some()).unwrap_or(false),
+            "contains disagrees with the nodes of the tree about {}",
+            pi
+        );
+        contained
+    }
+
+    /// The number of coordinates of the points in the tree, the length a query has to be, or `None` if the tree is
+    /// empty, see `is_empty`. It's the dimension stored in the point cloud, so this doesn't look at a point. Check your
+    /// queries against it after loading a tree, a query of another length is a `DimensionMismatch` from every query.
+    pub fn dimension(&self) -> Option<usize> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.parameters.point_cloud.dim())
+        }
+    }
+
     /// 
-    pub fn layers<'a>(&'a self) -> LayerIter<'a> {
+    pub fn layers<'a>(&'a self) -> LayerIter<'a, M::Scalar> {
         LayerIter {
-            scales: (self.layers.len() as i32 - self.parameters.resolution)
-                ..(self.parameters.resolution),
+            scales: (self.parameters.resolution - 1)
+                ..(self.parameters.resolution - 1 + self.layers.len() as i32),
             layers: self.layers.iter(),
         }
     }
@@ -161,6 +611,258 @@ impl<M: Metric> CoverTreeReader<M> {
         self.layers().fold(0,|a,(_si,l)| a+l.node_count())
     }
 
+    /// Iterates over the name and vector of every point the tree references, each exactly once. The point cloud can have
+    /// points the tree doesn't reference (after a `remove`), those are skipped. The points come out in index order, so the order is
+    /// the same on every call. This walks the tree up front to find the points, but the names and vectors are borrowed.
+    /// The vectors of a quantized source are dequantized copies, see `DataQuantized`.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (&'a [u8], Cow<'a, [M::Scalar]>)> + 'a {
+        let point_cloud = &self.parameters.point_cloud;
+        let (_addresses, mut indexes) = self.subtree_contents(self.root_address);
+        indexes.retain(|pi| !self.tombstones.contains(pi));
+        indexes.into_iter().filter_map(move |pi| {
+            match (point_cloud.get_name(&pi), point_cloud.get_point(pi)) {
+                (Some(name), Ok(point)) => Some((name, point)),
+                _ => None,
+            }
+        })
+    }
+
+    /// Iterates over the name and vector of the center of every leaf, the nodes with no children. Like `iter`, the points
+    /// come out in index order and the tree is walked up front.
+    ///
+    /// A node with children always has a nested child, a copy of itself at a lower scale, so each routing node's center
+    /// continues down a chain of nested nodes that ends in a leaf. That means every point that's the center of some node
+    /// is the center of exactly one leaf, and is yielded once. The points that aren't yielded are the singletons, which
+    /// hang off a node without being the center of one. With `use_singletons` off every point in the tree is a leaf.
+    pub fn leaves<'a>(&'a self) -> impl Iterator<Item = (&'a [u8], Cow<'a, [M::Scalar]>)> + 'a {
+        let point_cloud = &self.parameters.point_cloud;
+        let mut indexes: Vec<PointIndex> = self.leaf_addresses().iter().map(|(_si, pi)| *pi).collect();
+        indexes.retain(|pi| !self.tombstones.contains(pi));
+        indexes.sort();
+        indexes.into_iter().filter_map(move |pi| {
+            match (point_cloud.get_name(&pi), point_cloud.get_point(pi)) {
+                (Some(name), Ok(point)) => Some((name, point)),
+                _ => None,
+            }
+        })
+    }
+
+    fn leaf_addresses(&self) -> Vec<NodeAddress> {
+        let (addresses, _indexes) = self.subtree_contents(self.root_address);
+        addresses
+            .into_iter()
+            .filter(|address| self.get_node_and(*address, |n| n.is_leaf()).unwrap_or(false))
+            .collect()
+    }
+
+    /// Walks the tree once and summarizes its shape. This is `O(n)` in the number of nodes.
+    pub fn stats(&self) -> TreeStats {
+        let mut stats = TreeStats {
+            max_depth: 0,
+            node_count: 0,
+            leaf_count: 0,
+            singleton_count: 0,
+            avg_children: 0.0,
+            max_children: 0,
+            min_scale_index: self.root_address.0,
+            max_scale_index: self.root_address.0,
+            level_counts: BTreeMap::new(),
+        };
+        let mut total_children = 0;
+        let mut unvisited = vec![(self.root_address, 1)];
+        while let Some((address, depth)) = unvisited.pop() {
+            self.get_node_and(address, |n| {
+                stats.node_count += 1;
+                stats.singleton_count += n.singleton_len();
+                stats.max_depth = stats.max_depth.max(depth);
+                stats.min_scale_index = stats.min_scale_index.min(address.0);
+                stats.max_scale_index = stats.max_scale_index.max(address.0);
+                *stats.level_counts.entry(address.0).or_insert(0) += 1;
+                match n.children() {
+                    None => stats.leaf_count += 1,
+                    Some((nested_scale, children)) => {
+                        total_children += n.children_len();
+                        stats.max_children = stats.max_children.max(n.children_len());
+                        unvisited.push(((nested_scale, address.1), depth + 1));
+                        unvisited.extend(children.iter().map(|ca| (*ca, depth + 1)));
+                    }
+                }
+            });
+        }
+        let routing_count = stats.node_count - stats.leaf_count;
+        if routing_count > 0 {
+            stats.avg_children = total_children as f32 / routing_count as f32;
+        }
+        stats
+    }
+
+    /// The number of nodes at each scale index, from one walk of the tree. Scale indexes without a node aren't in the map.
+    /// Each level usually has a few times more nodes than the one above it. Most of the nodes piling up on a single
+    /// level means the tree has gone flat there, usually because the scale base is too large for the data.
+    pub fn level_counts(&self) -> BTreeMap<i32, usize> {
+        self.stats().level_counts
+    }
+
+    /// The tree in Graphviz's DOT language, for looking at a small tree while debugging, `dot -Tsvg` draws it. Each node
+    /// is labeled with the name of its center and its scale index, with an edge to each of its children. The edge to
+    /// the nested child, the node's own center one level down, is dashed. Singletons are plain text on a dotted edge
+    /// from their node. Names that aren't UTF-8 are shown lossily.
+    ///
+    /// This walks the whole tree into one string, so it's for trees of a few hundred points, not for production.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph cover_tree {\n");
+        let mut unvisited = vec![self.root_address];
+        while let Some(address) = unvisited.pop() {
+            self.get_node_and(address, |n| {
+                dot.push_str(&format!(
+                    "  \"{}:{}\" [label=\"{}\\nscale {}\"];\n",
+                    address.0,
+                    address.1,
+                    self.dot_name(address.1),
+                    address.0
+                ));
+                if let Some((nested_scale, children)) = n.children() {
+                    let nested = (nested_scale, address.1);
+                    dot.push_str(&format!(
+                        "  \"{}:{}\" -> \"{}:{}\" [style=dashed];\n",
+                        address.0, address.1, nested.0, nested.1
+                    ));
+                    unvisited.push(nested);
+                    for child in children {
+                        dot.push_str(&format!(
+                            "  \"{}:{}\" -> \"{}:{}\";\n",
+                            address.0, address.1, child.0, child.1
+                        ));
+                        unvisited.push(*child);
+                    }
+                }
+                for pi in n.singletons() {
+                    dot.push_str(&format!(
+                        "  \"s{}\" [label=\"{}\", shape=plaintext];\n  \"{}:{}\" -> \"s{}\" [style=dotted];\n",
+                        pi,
+                        self.dot_name(*pi),
+                        address.0,
+                        address.1,
+                        pi
+                    ));
+                }
+            });
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// The tree as indented text, a line per node with its children indented under it, for the same debugging as
+    /// `to_dot` without Graphviz. A line is the name of the node's center and its scale index, marked `nested` for a
+    /// node's copy of itself one level down, then the node's singletons if it has any. Children come after the nested
+    /// child, in the order the node holds them.
+    pub fn to_tree_string(&self) -> String {
+        let point_cloud = &self.parameters.point_cloud;
+        let name = |pi: &PointIndex| match point_cloud.get_name(pi) {
+            Some(name) => String::from_utf8_lossy(name).into_owned(),
+            None => format!("#{}", pi),
+        };
+        let mut text = String::new();
+        let mut unvisited = vec![(self.root_address, 0, false)];
+        while let Some((address, depth, nested)) = unvisited.pop() {
+            self.get_node_and(address, |n| {
+                let indent = "  ".repeat(depth);
+                text.push_str(&format!(
+                    "{}{} (scale {}{})\n",
+                    indent,
+                    name(&address.1),
+                    address.0,
+                    if nested { ", nested" } else { "" }
+                ));
+                if n.singleton_len() > 0 {
+                    let singletons: Vec<String> = n.singletons().iter().map(|pi| name(pi)).collect();
+                    text.push_str(&format!("{}  singletons: {}\n", indent, singletons.join(", ")));
+                }
+                if let Some((nested_scale, children)) = n.children() {
+                    // Pushed in reverse, so they come off the stack in order
+                    for child in children.iter().rev() {
+                        unvisited.push((*child, depth + 1, false));
+                    }
+                    unvisited.push(((nested_scale, address.1), depth + 1, true));
+                }
+            });
+        }
+        text
+    }
+
+    /// The name of a point escaped for a DOT label, or its index if it has no name.
+    fn dot_name(&self, pi: PointIndex) -> String {
+        match self.parameters.point_cloud.get_name(&pi) {
+            Some(name) => String::from_utf8_lossy(name).replace('\\', "\\\\").replace('"', "\\\""),
+            None => format!("#{}", pi),
+        }
+    }
+
+    /// An estimate of the bytes the tree holds on the heap, for working out how many trees fit in a process. It counts the
+    /// nodes reachable from the root with the capacity of their lists, see `CoverNode`, and the point cloud, see
+    /// `PointCloud::memory_footprint`. Each layer keeps two copies of its map so readers never wait on the writer, so
+    /// each node is counted twice along with a word for its key. The maps' spare capacity isn't counted, nor are the
+    /// clusters or the metadata summaries.
+    ///
+    /// It's an estimate, not a measurement, allocator overhead and fragmentation aren't in it. The point cloud is shared
+    /// by the writer and all its readers, so this is the footprint of the tree, not of each reader.
+    pub fn memory_footprint(&self) -> usize {
+        let node_bytes = 2 * (std::mem::size_of::<PointIndex>() + std::mem::size_of::<CoverNode<M::Scalar>>());
+        let mut bytes = self.parameters.point_cloud.memory_footprint();
+        let mut unvisited = vec![self.root_address];
+        while let Some(address) = unvisited.pop() {
+            self.get_node_and(address, |n| {
+                bytes += node_bytes + n.heap_bytes();
+                if let Some((nested_scale, children)) = n.children() {
+                    unvisited.push((nested_scale, address.1));
+                    unvisited.extend(children);
+                }
+            });
+        }
+        bytes
+    }
+
+    /// The distribution of nearest neighbor distances. For every point in the tree this finds the distance to its nearest
+    /// neighbor other than itself, with a `k = 2` knn, and buckets those into `bins` equal width bins between the smallest
+    /// and largest distance. Each bin is returned as its upper edge and the number of points in it, in ascending order.
+    ///
+    /// If all the distances are the same, say the dataset is one point repeated, every point is counted in the last bin.
+    /// A tree with a single point, or `bins = 0`, has no histogram and returns an empty vec.
+    pub fn nn_distance_histogram(&self, bins: usize) -> MalwareBrotResult<Vec<(M::Scalar, usize)>> {
+        let (_addresses, mut indexes) = self.subtree_contents(self.root_address);
+        indexes.retain(|pi| !self.tombstones.contains(pi));
+        let mut context = QueryContext::new();
+        let mut distances = Vec::with_capacity(indexes.len());
+        for pi in indexes {
+            let point = self.parameters.point_cloud.get_point(pi)?;
+            let neighbors = self.knn_with(&mut context, &point, 2)?;
+            if let Some((dist, _)) = neighbors.iter().find(|(_dist, ni)| *ni != pi) {
+                distances.push(*dist);
+            }
+        }
+        if bins == 0 || distances.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let min = distances.iter().cloned().fold(M::Scalar::infinity(), M::Scalar::min);
+        let max = distances.iter().cloned().fold(M::Scalar::neg_infinity(), M::Scalar::max);
+        let width = (max - min) / M::Scalar::from_f64(bins as f64);
+        let mut histogram: Vec<(M::Scalar, usize)> = (1..=bins)
+            .map(|i| (min + width * M::Scalar::from_f64(i as f64), 0))
+            .collect();
+        // Rounding can leave the last edge a hair under the max
+        histogram[bins - 1].0 = max;
+        for dist in distances {
+            let bin = if width > M::Scalar::zero() {
+                (((dist - min) / width).as_f64() as usize).min(bins - 1)
+            } else {
+                bins - 1
+            };
+            histogram[bin].1 += 1;
+        }
+        Ok(histogram)
+    }
+
     /// Returns the scale index range. It starts at the minimum resolution and ends at the top. You can reverse this for the correct order.
     pub fn scale_range(&self) -> Range<i32> {
         (self.parameters.resolution)..(self.parameters.resolution - 1 + self.layers.len() as i32)
@@ -183,316 +885,5791 @@ impl<M: Metric> CoverTreeReader<M> {
     ///
     /// See `query_tools::KnnQueryHeap` for the pair of heaps and mechanisms for tracking the minimum distance and the current knn set.
     /// See the `nodes::CoverNode::singleton_knn` and `nodes::CoverNode::child_knn` for the brute force node based knn.
-    pub fn knn(&self,point:&[f32],k:usize) -> MalwareBrotResult<Vec<(f32,PointIndex)>> {
+    ///
+    /// Points at the same distance are ordered by the reader's `TieBreak`, by index unless it's been set. That goes for
+    /// which of them make the cut at the kth place too, so the results only depend on the points and the query.
+    ///
+    /// A query that isn't the dimension of the tree's points, including an empty one, is a `DimensionMismatch`. The
+    /// dimension is checked first, so that's an error even on an empty tree, see `is_empty`. Otherwise an empty tree
+    /// gives no results. All the KNN variants, the range queries and `kfn` behave the same way.
+    ///
+    /// The query is only borrowed, it's never copied, every distance is computed straight from the slice. So a query
+    /// can be a sub-slice of a larger buffer. The heaps and results are allocated per query, use `knn_with` to reuse them,
+    /// the `borrowed_query_bench` example counts what's left.
+    pub fn knn(&self,point:&[M::Scalar],k:usize) -> MalwareBrotResult<Vec<(M::Scalar,PointIndex)>> {
         let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+        let mut results = Vec::with_capacity(k);
+        self.knn_into(point, k, M::Scalar::zero(), |_heap| {}, &mut query_heap, &mut results)?;
+        Ok(results)
+    }
 
-        let root_center = self.parameters.point_cloud.get_point(self.root_address.1)?;
-        let dist_to_root = M::dense(root_center,point);
-        query_heap.push_nodes(&[self.root_address],&[dist_to_root],None);
-        self.greedy_knn_nodes(&point,&mut query_heap);
+    /// The KNN query, reusing the buffers in `context` instead of allocating new ones. The results are the same as `knn`,
+    /// but they're borrowed from the context and are overwritten by the next query that uses it.
+    pub fn knn_with<'a>(
+        &self,
+        context: &'a mut QueryContext<M::Scalar>,
+        point: &[M::Scalar],
+        k: usize,
+    ) -> MalwareBrotResult<&'a [(M::Scalar, PointIndex)]> {
+        self.knn_into(point, k, M::Scalar::zero(), |_heap| {}, &mut context.heap, &mut context.results)?;
+        Ok(&context.results)
+    }
 
-        while let Some((_dist,address)) = query_heap.closest_unvisited_singleton_covering_address() {
-            self.get_node_and(address, |n| n.singleton_knn(point,&self.parameters.point_cloud,&mut query_heap));
-            self.greedy_knn_nodes(&point,&mut query_heap);
+    /// The KNN query, answered from `cache` if it's been asked of this version of the tree before, see `KnnCache`. The
+    /// results are the same as `knn`. A miss runs `knn` and keeps a copy of the results in the cache, evicting the least
+    /// recently used if it's full. With the cache off every query is a `knn`.
+    pub fn knn_cached(
+        &self,
+        cache: &mut KnnCache<M::Scalar>,
+        point: &[M::Scalar],
+        k: usize,
+    ) -> MalwareBrotResult<Vec<(M::Scalar, PointIndex)>> {
+        if cache.capacity() == 0 {
+            return self.knn(point, k);
         }
-        
-        Ok(query_heap.unpack())
+        cache.sync_version(self.version);
+        if let Some(results) = cache.get(point, k, self.tie_break) {
+            return Ok(results.to_vec());
+        }
+        let results = self.knn(point, k)?;
+        cache.insert(point, k, self.tie_break, results.clone());
+        Ok(results)
     }
-    
-    fn greedy_knn_nodes(&self, point: &[f32], query_heap: &mut KnnQueryHeap) {
-        loop {
-            if let Some((dist, nearest_address)) = query_heap.closest_unvisited_child_covering_address() {
-                if self
-                    .get_node_and(nearest_address, |n| n.is_leaf())
-                    .unwrap_or(true)
-                {
-                    break;
-                } else {
-                    self.get_node_and(nearest_address, |n| {
-                        n.child_knn(Some(dist), point, &self.parameters.point_cloud, query_heap)
-                    });
-                }
-            } else {
-                break;
-            }
+
+    /// # The approximate KNN query.
+    /// The same as `knn`, but a node is skipped once `1+epsilon` times the minimum distance of a point it covers is at least
+    /// the current kth nearest distance. Every returned distance is at most `1+epsilon` times the true distance of the neighbor
+    /// in that position, and an `epsilon` of `0.0` is exactly `knn`.
+    ///
+    /// Larger values visit fewer nodes, so they're faster, but the recall drops. How fast it drops depends on the data, so
+    /// measure it against `knn` on a sample of your queries before picking a value. Negative values are treated as `0.0`.
+    /// Ties are broken like `knn`, among the points the query found.
+    pub fn knn_approx(
+        &self,
+        point: &[M::Scalar],
+        k: usize,
+        epsilon: M::Scalar,
+    ) -> MalwareBrotResult<Vec<(M::Scalar, PointIndex)>> {
+        let mut query_heap = KnnQueryHeap::new_approx(k, self.parameters.scale_base, epsilon);
+        let mut results = Vec::with_capacity(k);
+        self.knn_into(point, k, epsilon, |_heap| {}, &mut query_heap, &mut results)?;
+        Ok(results)
+    }
+
+    /// The mean recall@k of `knn_approx` at this `epsilon` over the queries, for tuning `epsilon`. Each query is run
+    /// with `knn` for the truth and with `knn_approx`, and its recall is the fraction of the true neighbors the
+    /// approximate query got. A returned neighbor counts if it's no further than the kth true one, so a point tied with
+    /// the kth isn't a miss just because the queries broke the tie differently.
+    ///
+    /// This is a diagnostic, not for the hot path. It runs the exact query as well as the approximate one, so it costs
+    /// more than both. The queries are spread over the rayon pool like `knn_many`. A query with fewer than `k` points
+    /// in the tree is measured against all of them, and a query with none to find, or an empty list of queries, has a
+    /// recall of `1.0`. A query of the wrong dimension is a `DimensionMismatch`.
+    pub fn measure_recall(&self, queries: &[Vec<M::Scalar>], k: usize, epsilon: M::Scalar) -> MalwareBrotResult<f32> {
+        if queries.is_empty() {
+            return Ok(1.0);
         }
+        let factory = self.factory();
+        let chunk_size = std::cmp::max(1, queries.len() / (4 * rayon::current_num_threads()));
+        let recalls: MalwareBrotResult<Vec<Vec<f32>>> = queries
+            .par_chunks(chunk_size)
+            .map(|chunk| {
+                let reader = factory.reader();
+                chunk
+                    .iter()
+                    .map(|query| {
+                        let exact = reader.knn(query, k)?;
+                        let approx = reader.knn_approx(query, k, epsilon)?;
+                        Ok(match exact.last() {
+                            Some((kth, _pi)) => {
+                                let found = approx.iter().filter(|(d, _pi)| d <= kth).count();
+                                found.min(exact.len()) as f32 / exact.len() as f32
+                            }
+                            None => 1.0,
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+        let recalls: Vec<f32> = recalls?.into_iter().flatten().collect();
+        Ok(recalls.iter().sum::<f32>() / recalls.len() as f32)
     }
 
-    /// Checks that there are no node addresses in the child list of any node that don't reference a node in the tree. 
-    /// Please calmly panic if there are, the tree is very invalid.
-    pub(crate) fn no_dangling_refs(&self) -> bool {
-        let mut refs_to_check = vec![self.root_address];
-        while let Some(node_addr) = refs_to_check.pop() {
-            if self
-                .get_node_and(node_addr, |n| {
-                    match n.children() {
-                        None => {}
-                        Some((nested_scale, other_children)) => {
-                            refs_to_check.push((nested_scale, node_addr.1));
-                            refs_to_check.extend(&other_children[..]);
-                        }
-                    };
-                })
-                .is_none()
+    /// The approximate KNN query, along with how far off its results can be, see `ApproxBound`. The results are the same
+    /// as `knn_approx`. If the `max_error` is more than you can take, run `knn` for the exact answer.
+    ///
+    /// The bound comes from the covering radii, so it's as good as they are. Distances are rounded to the metric's
+    /// scalar, and a point can be a few units in the last place outside its node's radius, see
+    /// `validate_with_tolerance`, so the bound can be off by that.
+    pub fn knn_approx_bounded(
+        &self,
+        point: &[M::Scalar],
+        k: usize,
+        epsilon: M::Scalar,
+    ) -> MalwareBrotResult<(Vec<(M::Scalar, PointIndex)>, ApproxBound<M::Scalar>)> {
+        let mut query_heap = KnnQueryHeap::new_approx(k, self.parameters.scale_base, epsilon);
+        let mut results = Vec::with_capacity(k);
+        self.knn_into(point, k, epsilon, |_heap| {}, &mut query_heap, &mut results)?;
+        let exact_radius = query_heap.min_pruned();
+        let max_error = results.last().map(|(d, _pi)| (*d - exact_radius).max(M::Scalar::zero())).unwrap_or(M::Scalar::zero());
+        Ok((results, ApproxBound { exact_radius, max_error }))
+    }
+
+    /// The KNN query, but it gives up at `deadline` and returns what it's found so far. The `bool` is `true` if the
+    /// query was stopped, and then the results may not be complete. They're still real points of the tree with their
+    /// true distances, sorted closest first, but they're the nearest of the points the query got to, not necessarily
+    /// the true `k` nearest, and there can be fewer than `k` of them. A deadline that's already passed gets you little
+    /// more than the root. If it's `false` the results are the same as `knn`.
+    ///
+    /// The clock is read every few dozen nodes the query looks into rather than at every distance, so the check costs
+    /// next to nothing, and the query can overrun the deadline by the time it takes to search that many nodes. Ties are
+    /// broken like `knn`, among the points the query found.
+    pub fn knn_deadline(
+        &self,
+        point: &[M::Scalar],
+        k: usize,
+        deadline: Instant,
+    ) -> MalwareBrotResult<(Vec<(M::Scalar, PointIndex)>, bool)> {
+        let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+        let mut results = Vec::with_capacity(k);
+        self.knn_into(point, k, M::Scalar::zero(), |heap| heap.set_deadline(deadline), &mut query_heap, &mut results)?;
+        Ok((results, query_heap.timed_out()))
+    }
+
+    /// The KNN query among the points whose name matches `pred`, like the points of one tenant. The points that don't
+    /// match are left out as the tree is searched, like tombstones, so they don't take any of the `k` places and you
+    /// get the `k` nearest matching points, or all of them if fewer match. Names that aren't UTF-8 never match. Ties
+    /// are broken like `knn`.
+    ///
+    /// `pred` is called during the search, once for each point the search reaches, and its answer is remembered for
+    /// the rest of the query. The search prunes on the kth nearest matching distance, a node is only skipped once
+    /// everything under it is further than that. So a filter that lets few points through, or none near the query,
+    /// keeps the kth distance large and the query degrades towards a scan of the tree. If you filter on the same thing
+    /// often a tree per tenant is faster.
+    pub fn knn_filtered<F: Fn(&str) -> bool>(
+        &self,
+        point: &[M::Scalar],
+        k: usize,
+        pred: F,
+    ) -> MalwareBrotResult<Vec<(M::Scalar, PointIndex)>> {
+        self.check_dim(point)?;
+        let point_cloud = &self.parameters.point_cloud;
+        let seen: RefCell<HashMap<PointIndex, bool>> = RefCell::new(HashMap::new());
+        let matches = |pi: PointIndex| -> bool {
+            *seen.borrow_mut().entry(pi).or_insert_with(|| {
+                let name = point_cloud.get_name(&pi).unwrap_or(&[]);
+                std::str::from_utf8(name).map(&pred).unwrap_or(false)
+            })
+        };
+        let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+        let mut results = Vec::with_capacity(k);
+        self.knn_into(point, k, M::Scalar::zero(), |heap| heap.keep_only(&matches), &mut query_heap, &mut results)?;
+        Ok(results)
+    }
+
+    /// Runs the query on the heap and applies the tie break. The heap keeps ties in index order. For names, the point
+    /// after the kth is fetched too, and while it's tied with the kth there may be more tied points past it whose names
+    /// sort first, so the query is rerun with more points until the tie is settled. The heap is handed to `skip` before
+    /// each run, to leave points out of the results, see `KnnQueryHeap::exclude`. A run that's stopped by a deadline
+    /// ends the loop, and a rerun that's stopped keeps the results of the run before it.
+    fn knn_into<'a, F: Fn(&mut KnnQueryHeap<'a, M::Scalar>)>(
+        &self,
+        point: &[M::Scalar],
+        k: usize,
+        epsilon: M::Scalar,
+        skip: F,
+        query_heap: &mut KnnQueryHeap<'a, M::Scalar>,
+        results: &mut Vec<(M::Scalar, PointIndex)>,
+    ) -> MalwareBrotResult<()> {
+        // There's no kth point to compare the next one with
+        if k == 0 {
+            self.check_dim(point)?;
+            results.clear();
+            return Ok(());
+        }
+        let mut heap_k = match self.tie_break {
+            TieBreak::Index => k,
+            TieBreak::Name => k + 1,
+        };
+        loop {
+            query_heap.reset(heap_k, self.parameters.scale_base);
+            query_heap.set_epsilon(epsilon);
+            skip(query_heap);
+            self.knn_query(point, query_heap)?;
+            if query_heap.timed_out() && heap_k > k + 1 {
+                break;
+            }
+            query_heap.unpack_into(results);
+            if self.tie_break == TieBreak::Index
+                || query_heap.timed_out()
+                || results.len() < heap_k
+                || results[k].0 != results[k - 1].0
             {
-                return false;
+                break;
             }
+            heap_k = k + 2 * (heap_k - k);
         }
-        true
+        if self.tie_break == TieBreak::Name {
+            self.sort_ties_by_name(results, k);
+        }
+        Ok(())
     }
 
-    fn cluster_children(
+    /// Sorts results that are in index order among ties into name order among ties, and keeps the first `k`.
+    fn sort_ties_by_name(&self, results: &mut Vec<(M::Scalar, PointIndex)>, k: usize) {
+        let point_cloud = &self.parameters.point_cloud;
+        results.sort_by(|a, b| {
+            a.0.partial_cmp(&b.0)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| point_cloud.get_name(&a.1).cmp(&point_cloud.get_name(&b.1)))
+        });
+        results.truncate(k);
+    }
+
+    /// The KNN query, along with counts of the work it did, see `QueryMetrics`. The results are the same as `knn`. Only
+    /// this query keeps the counts, it runs on a counted `KnnQueryHeap` and the others have the counting compiled out.
+    pub fn knn_instrumented(
         &self,
-        si: i32,
-        pis: &[PointIndex],
-    ) -> MalwareBrotResult<Vec<(usize, i32, Vec<PointIndex>)>> {
-        //println!("\tClustering children of {:?}", (si,pis));
-        let mut children_addresses = Vec::new();
+        point: &[M::Scalar],
+        k: usize,
+    ) -> MalwareBrotResult<(Vec<(M::Scalar, PointIndex)>, QueryMetrics)> {
+        let mut query_heap = KnnQueryHeap::counted(k, self.parameters.scale_base);
+        self.knn_query(point, &mut query_heap)?;
+        let metrics = query_heap.metrics();
+        Ok((query_heap.unpack(), metrics))
+    }
+
+    /// The KNN query, along with every subtree it pruned, see `PruneEvent`, in the order they were pruned. The results
+    /// are the same as `knn`. A pruned node that covers a true neighbor would show up here with a lower bound that's
+    /// wrong, so this is for checking the pruning. Only this records the events, other queries don't pay for them.
+    pub fn knn_debug(
+        &self,
+        point: &[M::Scalar],
+        k: usize,
+    ) -> MalwareBrotResult<(Vec<(M::Scalar, PointIndex)>, Vec<PruneEvent<M::Scalar>>)> {
+        let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+        query_heap.record_pruning();
+        self.knn_query(point, &mut query_heap)?;
+        let events = query_heap.take_prune_log();
+        Ok((query_heap.unpack(), events))
+    }
+
+    /// # The over-fetched KNN query.
+    /// The `k * overfetch` nearest neighbors, in the order the query first came across them rather than by distance, for
+    /// reranking with a scoring function of your own. The list is the exact `k * overfetch` nearest, so the exact `k`
+    /// nearest are always in it. Points the query came across that didn't make the cut are left out. An `overfetch` of
+    /// 0 is treated as 1. Ties at the last place are broken by index, whatever the reader's `TieBreak`.
+    ///
+    /// The query holds all `k * overfetch` candidates on its heaps, and the order is kept by logging every point it
+    /// computes a distance to, so the memory grows with both the product and with how much of the tree the query has to
+    /// search to settle that many neighbors. A large factor also prunes less, so it's slower as well.
+    pub fn knn_candidates(
+        &self,
+        point: &[M::Scalar],
+        k: usize,
+        overfetch: usize,
+    ) -> MalwareBrotResult<Vec<(M::Scalar, PointIndex)>> {
+        let mut query_heap = KnnQueryHeap::new(k.saturating_mul(overfetch.max(1)), self.parameters.scale_base);
+        query_heap.record_encounters();
+        self.knn_query(point, &mut query_heap)?;
+        let encounters = query_heap.take_encounter_log();
+        let kept: HashMap<PointIndex, M::Scalar> = query_heap.unpack().into_iter().map(|(d, pi)| (pi, d)).collect();
+        Ok(encounters
+            .into_iter()
+            .filter_map(|pi| kept.get(&pi).map(|d| (*d, pi)))
+            .collect())
+    }
+
+    /// # The KNN query under another metric.
+    /// The `k` nearest neighbors of `point` by the metric `N`, rather than the metric `M` the tree was built with, for
+    /// trying a metric out without rebuilding the tree. `N` is a type parameter rather than a `&dyn Metric`, as the
+    /// methods of `Metric` don't take `self`, so call it as `reader.knn_with_metric::<L1>(&query, 5)`. The tree is
+    /// searched with `M` for the `k * KNN_WITH_METRIC_OVERFETCH` nearest points, see `knn_candidates`, those are ranked
+    /// by their `N` distance to `point`, and the nearest `k` come back with their `N` distances. Ties are broken by index.
+    ///
+    /// **The results are approximate when the metrics disagree.** The pruning bounds come from `M`, so a point that's near
+    /// by `N` but not among the candidates by `M` is never looked at, and nothing tells you it was missed. The more `N`
+    /// differs from `M` the more is missed. Only when `N` is `M` is this the exact `knn`. Build a tree with `N` when the
+    /// results matter.
+    pub fn knn_with_metric<N: Metric<Scalar = M::Scalar>>(&self, point: &[M::Scalar], k: usize) -> MalwareBrotResult<Vec<(M::Scalar, PointIndex)>> {
+        let candidates = self.knn_candidates(point, k, KNN_WITH_METRIC_OVERFETCH)?;
+        let point_cloud = &self.parameters.point_cloud;
+        let mut results = Vec::with_capacity(candidates.len());
+        for (_d, pi) in candidates {
+            results.push((N::dense(point, &point_cloud.get_point(pi)?), pi));
+        }
+        results.sort_by(|a, b| {
+            a.0.partial_cmp(&b.0)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.1.cmp(&b.1))
+        });
+        results.truncate(k);
+        Ok(results)
+    }
+
+    fn knn_query<const COUNTED: bool>(&self, point: &[M::Scalar], query_heap: &mut KnnQueryHeap<'_, M::Scalar, COUNTED>) -> MalwareBrotResult<()> {
+        self.check_dim(point)?;
+        if self.is_empty() {
+            return Ok(());
+        }
+        self.knn_query_from(point, query_heap, self.root_address)
+    }
+
+    /// The KNN query among the points under the node at `address`, the whole tree if it's the root.
+    fn knn_query_from<const COUNTED: bool>(
+        &self,
+        point: &[M::Scalar],
+        query_heap: &mut KnnQueryHeap<'_, M::Scalar, COUNTED>,
+        address: NodeAddress,
+    ) -> MalwareBrotResult<()> {
+        query_heap.skip_tombstones(&self.tombstones);
+        query_heap.set_rank_singletons(self.rank_singletons);
+        let root_center = self.parameters.point_cloud.get_point(address.1)?;
+        let dist_to_root = M::dense(&root_center,point);
+        query_heap.count_distances(1);
+        query_heap.push_nodes(&[address],&[dist_to_root],None);
+        self.greedy_knn_nodes(&point,query_heap);
+
+        while let Some((_dist,address)) = query_heap.closest_unvisited_singleton_covering_address() {
+            self.get_node_and(address, |n| n.singleton_knn(point,&self.parameters.point_cloud,query_heap));
+            self.greedy_knn_nodes(&point,query_heap);
+        }
+        
+        Ok(())
+    }
+    
+    /// The KNN query, but this looks up the names of the neighbors in the point cloud. The results are sorted by
+    /// distance, closest first. If `k` is larger than the number of points in the tree you get all of them.
+    pub fn knn_names(&self, point: &[M::Scalar], k: usize) -> MalwareBrotResult<Vec<(M::Scalar, PointName)>> {
+        self.attach_names(&self.knn(point, k)?)
+    }
+
+    /// The KNN query with the point cloud indexes first, as `usize`s for joining against other tables. No names are
+    /// looked up or cloned, unlike `knn_names`, so this allocates only the result. Use `name_of` for the names you need.
+    pub fn knn_indices(&self, point: &[M::Scalar], k: usize) -> MalwareBrotResult<Vec<(usize, M::Scalar)>> {
+        Ok(self.knn(point, k)?.into_iter().map(|(d, pi)| (pi as usize, d)).collect())
+    }
+
+    /// The name of the point at this index, `IndexNotInTree` if the point cloud has no such index. Like `root` the name is
+    /// borrowed as bytes, it used to be a `&PointName`.
+    pub fn name_of(&self, index: usize) -> MalwareBrotResult<&[u8]> {
+        let pi = index as PointIndex;
+        self.parameters
+            .point_cloud
+            .get_name(&pi)
+            .ok_or(MalwareBrotError::IndexNotInTree(pi))
+    }
+
+    /// The KNN query, along with the nodes the query descended through to reach the nearest neighbor. The path starts
+    /// at the root and ends at the node that holds the nearest neighbor, either as its center or as a singleton. Every
+    /// node on it was expanded by the query, so it's the chain of pruning decisions that led to the first result. If
+    /// there are no results, for `k = 0`, the path is empty.
+    pub fn knn_with_path(
+        &self,
+        point: &[M::Scalar],
+        k: usize,
+    ) -> MalwareBrotResult<(Vec<(M::Scalar, PointIndex)>, Vec<NodeAddress>)> {
+        let results = self.knn(point, k)?;
+        let path = match results.first() {
+            Some((_dist, pi)) => match self.locate(*pi)? {
+                Some(PointLocation::Singleton(address, mut path))
+                | Some(PointLocation::Center(address, mut path)) => {
+                    path.push(address);
+                    path
+                }
+                None => return Err(MalwareBrotError::IndexNotInTree(*pi)),
+            },
+            None => Vec::new(),
+        };
+        Ok((results, path))
+    }
+
+    /// # The batch KNN query.
+    /// Runs `knn_names` for each query on the rayon pool, the results line up with the queries. The batch is split into
+    /// chunks and each chunk gets its own reader and `QueryContext`, so the buffers are reused across a chunk.
+    ///
+    /// The queries are run in the order they're given. Sorting the batch so nearby queries are next to each other could
+    /// help the cache, but it hasn't been measured, so that's left to the caller.
+    pub fn knn_many(
+        &self,
+        queries: &[Vec<M::Scalar>],
+        k: usize,
+    ) -> MalwareBrotResult<Vec<Vec<(M::Scalar, PointName)>>> {
+        let factory = self.factory();
+        let chunk_size = std::cmp::max(1, queries.len() / (4 * rayon::current_num_threads()));
+        let chunks: MalwareBrotResult<Vec<Vec<Vec<(M::Scalar, PointName)>>>> = queries
+            .par_chunks(chunk_size)
+            .map(|chunk| {
+                let reader = factory.reader();
+                let mut context = QueryContext::new();
+                chunk
+                    .iter()
+                    .map(|query| {
+                        let results = reader.knn_with(&mut context, query, k)?;
+                        reader.attach_names(results)
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(chunks?.into_iter().flatten().collect())
+    }
+
+    /// # The KNN graph.
+    /// The `k` nearest neighbors of every point in the tree, as an adjacency list indexed by point index. The ith list
+    /// holds the neighbors of the point with index `i` as `(index, distance)` pairs, closest first. A point isn't its own
+    /// neighbor, it's left out by index like `knn_by_name` does, so exact duplicates of it are kept at distance `0.0`.
+    /// Points of the point cloud that aren't in the tree, removed or tombstoned, get an empty list, as do all the
+    /// indexes if the tree is empty. The queries are spread over the rayon pool like `knn_many`.
+    ///
+    /// With `mutual` an edge is only kept if it goes both ways, that is `j` is among the `k` nearest of `i` and `i` is
+    /// among the `k` nearest of `j`, so the graph is symmetric and the lists can be shorter than `k`.
+    ///
+    /// The graph is held in memory whole, `n * k` pairs of 16 bytes each plus a vector per point, so a million points at
+    /// `k = 10` is about 200MB.
+    pub fn knn_graph(&self, k: usize, mutual: bool) -> MalwareBrotResult<Vec<Vec<(usize, M::Scalar)>>> {
+        let (_addresses, mut indexes) = self.subtree_contents(self.root_address);
+        indexes.retain(|pi| !self.tombstones.contains(pi));
+        let factory = self.factory();
+        let chunk_size = std::cmp::max(1, indexes.len() / (4 * rayon::current_num_threads()));
+        let chunks: MalwareBrotResult<Vec<Vec<Vec<(usize, M::Scalar)>>>> = indexes
+            .par_chunks(chunk_size)
+            .map(|chunk| {
+                let reader = factory.reader();
+                let mut context = QueryContext::new();
+                chunk
+                    .iter()
+                    .map(|pi| -> MalwareBrotResult<Vec<(usize, M::Scalar)>> {
+                        let point = reader.parameters.point_cloud.get_point(*pi)?;
+                        let results = reader.knn_with(&mut context, &point, k + 1)?;
+                        Ok(results
+                            .iter()
+                            .filter(|(_d, qi)| qi != pi)
+                            .take(k)
+                            .map(|(d, qi)| (*qi as usize, *d))
+                            .collect())
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let len = self.parameters.point_cloud.reference_indexes().iter().max().map(|pi| *pi as usize + 1).unwrap_or(0);
+        let mut graph = vec![Vec::new(); len];
+        for (pi, neighbors) in indexes.iter().zip(chunks?.into_iter().flatten()) {
+            graph[*pi as usize] = neighbors;
+        }
+        if mutual {
+            let mutual_graph: Vec<Vec<(usize, M::Scalar)>> = graph
+                .iter()
+                .enumerate()
+                .map(|(i, neighbors)| {
+                    neighbors
+                        .iter()
+                        .filter(|(j, _d)| graph[*j].iter().any(|(qi, _d)| *qi == i))
+                        .cloned()
+                        .collect()
+                })
+                .collect();
+            graph = mutual_graph;
+        }
+        Ok(graph)
+    }
+
+    /// # The leave-one-out KNN query.
+    /// The `k` nearest neighbors of every point in the tree as if that point weren't in it, for evaluating a metric on
+    /// held out points without building a tree for each. The results are the same as rebuilding the tree without the
+    /// point and querying it with the point's vector, but the point is just left out of its own query as the tree is
+    /// searched. It's left out by index, so exact duplicates of it are still neighbors at distance `0.0`.
+    ///
+    /// The points come in index order, each with its neighbors closest first, and ties are broken like `knn`. Removed
+    /// and tombstoned points aren't queried. The queries are spread over the rayon pool like `knn_many`. This is the
+    /// same as `knn_graph` without `mutual`, with the neighbors in `knn`'s shape.
+    pub fn query_loo(&self, k: usize) -> MalwareBrotResult<Vec<(PointIndex, Vec<(M::Scalar, PointIndex)>)>> {
+        let (_addresses, mut indexes) = self.subtree_contents(self.root_address);
+        indexes.retain(|pi| !self.tombstones.contains(pi));
+        let factory = self.factory();
+        let tie_break = self.tie_break;
+        let chunk_size = std::cmp::max(1, indexes.len() / (4 * rayon::current_num_threads()));
+        let chunks: MalwareBrotResult<Vec<Vec<(PointIndex, Vec<(M::Scalar, PointIndex)>)>>> = indexes
+            .par_chunks(chunk_size)
+            .map(|chunk| {
+                let mut reader = factory.reader();
+                reader.set_tie_break(tie_break);
+                let mut context = QueryContext::new();
+                chunk
+                    .iter()
+                    .map(|pi| {
+                        let point = reader.parameters.point_cloud.get_point(*pi)?;
+                        reader.knn_into(&point, k, M::Scalar::zero(), |heap| heap.exclude(*pi), &mut context.heap, &mut context.results)?;
+                        Ok((*pi, context.results.clone()))
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(chunks?.into_iter().flatten().collect())
+    }
+
+    /// # The KNN query, split over threads.
+    /// The same results as `knn`, ties included, but the search of a single query is spread over a dedicated pool of
+    /// `threads` threads. The top of the tree is opened up breadth first until there are about 4 subtrees per thread,
+    /// those disjoint subtrees are searched for their own `k` nearest on the pool, each with its own reader, and the
+    /// partial results are merged with the singletons of the opened nodes. A `threads` of 0 or 1 is just `knn`.
+    ///
+    /// This is for the latency of a lone query on a large tree, use `knn_many` for batches. It costs more in total than
+    /// `knn`. A pool is started for each call, each subtree is searched without the bound the others have found so far,
+    /// so the subtrees far from the query are searched when `knn` would have pruned them, and the results have to be
+    /// merged. On a small tree, or one where `knn` is already fast, that overhead is more than the search and this is
+    /// slower than `knn`. Measure it on your tree before using it.
+    pub fn knn_parallel(&self, point: &[M::Scalar], k: usize, threads: usize) -> MalwareBrotResult<Vec<(M::Scalar, PointIndex)>> {
+        if threads <= 1 {
+            return self.knn(point, k);
+        }
+        self.check_dim(point)?;
+        if self.is_empty() || k == 0 {
+            return Ok(Vec::new());
+        }
+        let mut subtrees = std::collections::VecDeque::new();
+        subtrees.push_back(self.root_address);
+        let mut leaves = Vec::new();
+        let mut singletons = Vec::new();
+        while subtrees.len() + leaves.len() < 4 * threads {
+            let address = match subtrees.pop_front() {
+                Some(address) => address,
+                None => break,
+            };
+            let opened = self.get_node_and(address, |n| match n.children() {
+                Some((nested_scale, children)) => {
+                    singletons.extend_from_slice(n.singletons());
+                    subtrees.push_back((nested_scale, address.1));
+                    subtrees.extend(children.iter().cloned());
+                    true
+                }
+                None => false,
+            });
+            if opened != Some(true) {
+                leaves.push(address);
+            }
+        }
+        let subtrees: Vec<NodeAddress> = subtrees.into_iter().chain(leaves).collect();
+        singletons.retain(|pi| !self.tombstones.contains(pi));
+        let singleton_dists = self.parameters.point_cloud.distances_to_point(point, &singletons)?;
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(|e| MalwareBrotError::from(e))?;
+        let factory = self.factory();
+        let scale_base = self.parameters.scale_base;
+        // Like `knn_into`, name ties need the points tied with the kth, so the query is rerun with more until they're in
+        let mut heap_k = match self.tie_break {
+            TieBreak::Index => k,
+            TieBreak::Name => k + 1,
+        };
+        loop {
+            let partials: MalwareBrotResult<Vec<Vec<(M::Scalar, PointIndex)>>> = pool.install(|| {
+                subtrees
+                    .par_iter()
+                    .map(|address| {
+                        let reader = factory.reader();
+                        let mut query_heap = KnnQueryHeap::new(heap_k, scale_base);
+                        reader.knn_query_from(point, &mut query_heap, *address)?;
+                        Ok(query_heap.unpack())
+                    })
+                    .collect()
+            });
+            let mut results: Vec<(M::Scalar, PointIndex)> = partials?.into_iter().flatten().collect();
+            results.extend(singleton_dists.iter().cloned().zip(singletons.iter().cloned()));
+            results.sort_by(|a, b| {
+                a.0.partial_cmp(&b.0)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .then_with(|| a.1.cmp(&b.1))
+            });
+            results.truncate(heap_k);
+            if self.tie_break == TieBreak::Index || results.len() < heap_k || results[k].0 != results[k - 1].0 {
+                if self.tie_break == TieBreak::Name {
+                    self.sort_ties_by_name(&mut results, k);
+                }
+                return Ok(results);
+            }
+            heap_k = k + 2 * (heap_k - k);
+        }
+    }
+
+    /// The KNN query for a point that's already in the tree, by name. The point itself is left out of the results, but
+    /// exact duplicates of it under other names are kept, so their distances are `0.0`. The name can be a `PointName` or a `&str`.
+    ///
+    /// Returns `NameNotInTree` if the name isn't in the point cloud or has been removed from the tree.
+    pub fn knn_by_name<N: AsRef<[u8]>>(&self, name: N, k: usize) -> MalwareBrotResult<Vec<(M::Scalar, PointName)>> {
+        let name = name.as_ref();
+        let pi = match self.parameters.point_cloud.get_index(name) {
+            Some(pi) => *pi,
+            None => return Err(MalwareBrotError::NameNotInTree(name.to_vec())),
+        };
+        if self.tombstones.contains(&pi) || self.locate(pi)?.is_none() {
+            return Err(MalwareBrotError::NameNotInTree(name.to_vec()));
+        }
+        let point = self.parameters.point_cloud.get_point(pi)?;
+        // Filter on the index not the distance, the point may not be the first of several at distance 0.
+        let mut results = self.knn(&point, k + 1)?;
+        results.retain(|(_d, qi)| *qi != pi);
+        results.truncate(k);
+        self.attach_names(&results)
+    }
+
+    /// The distance between two points of the tree under its metric, by name. The names can be `PointName`s or `&str`s.
+    ///
+    /// Returns `NameNotInTree` with the first of the names that isn't in the point cloud or has been removed from the
+    /// tree, like `knn_by_name`.
+    pub fn distance_between<A: AsRef<[u8]>, B: AsRef<[u8]>>(&self, a: A, b: B) -> MalwareBrotResult<M::Scalar> {
+        let point_of = |name: &[u8]| -> MalwareBrotResult<Cow<'_, [M::Scalar]>> {
+            let pi = match self.parameters.point_cloud.get_index(name) {
+                Some(pi) if !self.tombstones.contains(pi) => *pi,
+                _ => return Err(MalwareBrotError::NameNotInTree(name.to_vec())),
+            };
+            if self.locate(pi)?.is_none() {
+                return Err(MalwareBrotError::NameNotInTree(name.to_vec()));
+            }
+            Ok(self.parameters.point_cloud.get_point(pi)?)
+        };
+        let a = point_of(a.as_ref())?;
+        let b = point_of(b.as_ref())?;
+        Ok(M::dense(&a, &b))
+    }
+
+    /// # The range query.
+    /// Returns all points within `radius` of the query point, along with their distances. The results are not sorted.
+    ///
+    /// This is a depth first traversal of the tree. A node is skipped if the distance to its center minus its radius
+    /// (the furthest any point it covers is from the center) is more than the query radius. A radius of `0.0` only returns
+    /// exact copies of the query point. An infinite radius is allowed, this just visits every node. An empty tree has no
+    /// points in range, and a query of the wrong dimension is a `DimensionMismatch`, like `knn`.
+    pub fn range_query(
+        &self,
+        point: &[M::Scalar],
+        radius: M::Scalar,
+    ) -> MalwareBrotResult<Vec<(M::Scalar, PointName)>> {
+        self.attach_names(&self.range_indexes(point, radius)?)
+    }
+
+    /// The range query with a cap on the number of results, for when a large radius could return too many points to hold.
+    /// The bool is true if the results were truncated, that is there are more than `max_results` points in range.
+    ///
+    /// When it's truncated you get the `max_results` closest points in range, so which points survive the cap only depends
+    /// on their distance to the query, never on where they sit in the tree. Ties are broken like `knn`. Unlike
+    /// `range_query` the results are sorted, closest first. The search skips the nodes that are out of range like
+    /// `range_query` does, and keeps at most `max_results + 1` points, so it stops looking once it has that many closer
+    /// than any node it hasn't searched.
+    pub fn range_query_capped(
+        &self,
+        point: &[M::Scalar],
+        radius: M::Scalar,
+        max_results: usize,
+    ) -> MalwareBrotResult<(Vec<(M::Scalar, PointName)>, bool)> {
+        // There can't be more than all of the points in range, this keeps the heap's `k` from overflowing
+        let k = max_results.min(self.len()) + 1;
+        let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+        let mut results = Vec::new();
+        self.knn_into(point, k, M::Scalar::zero(), |heap| heap.set_radius(radius), &mut query_heap, &mut results)?;
+        let truncated = results.len() > max_results;
+        results.truncate(max_results);
+        Ok((self.attach_names(&results)?, truncated))
+    }
+
+    /// The range query as an iterator that hands out each point as soon as the traversal finds it, for showing results
+    /// while the rest are still being looked for. The points come out in the order they're found, not by distance.
+    /// Taking every point gives the same points as `range_query`, the first ones just arrive sooner. The traversal only
+    /// goes as far as the points that are taken, so dropping the iterator early skips the rest of the work.
+    ///
+    /// The dimension is checked up front, a query of the wrong dimension is a `DimensionMismatch` like `range_query`.
+    /// An error reading the point cloud part way through is the last item.
+    pub fn knn_streaming<'a>(&'a self, point: &'a [M::Scalar], radius: M::Scalar) -> MalwareBrotResult<RangeStream<'a, M>> {
+        self.check_dim(point)?;
+        let mut stream = RangeStream {
+            reader: self,
+            point,
+            radius,
+            unvisited: Vec::new(),
+            found: Vec::new(),
+        };
+        if self.is_empty() {
+            return Ok(stream);
+        }
+        // The centers are found when their parent is visited, so the root is the only one found up front
+        let dist_to_root = M::dense(&self.parameters.point_cloud.get_point(self.root_address.1)?, point);
+        if dist_to_root <= radius {
+            stream.found.push((dist_to_root, self.root_address.1));
+        }
+        stream.unvisited.push((dist_to_root, self.root_address));
+        Ok(stream)
+    }
+
+    fn range_indexes(&self, point: &[M::Scalar], radius: M::Scalar) -> MalwareBrotResult<Vec<(M::Scalar, PointIndex)>> {
+        self.check_dim(point)?;
+        let point_cloud = &self.parameters.point_cloud;
+        let mut results = Vec::new();
+        if self.is_empty() {
+            return Ok(results);
+        }
+
+        let root_center = point_cloud.get_point(self.root_address.1)?;
+        let dist_to_root = M::dense(&root_center, point);
+        if dist_to_root <= radius {
+            results.push((dist_to_root, self.root_address.1));
+        }
+        // The centers are pushed onto the results when we first see them, so the nested child is never checked against its center.
+        let mut unvisited = vec![(dist_to_root, self.root_address)];
+        while let Some((dist, address)) = unvisited.pop() {
+            let node_result = self.get_node_and(address, |n| -> MalwareBrotResult<()> {
+                if dist - n.radius() > radius {
+                    return Ok(());
+                }
+                let distances = point_cloud.distances_to_point(point, n.singletons())?;
+                for (pi, d) in n.singletons().iter().zip(distances) {
+                    if d <= radius {
+                        results.push((d, *pi));
+                    }
+                }
+                if let Some((nested_scale, children)) = n.children() {
+                    unvisited.push((dist, (nested_scale, address.1)));
+                    let children_indexes: Vec<PointIndex> =
+                        children.iter().map(|(_si, pi)| *pi).collect();
+                    let distances = point_cloud.distances_to_point(point, &children_indexes)?;
+                    for (ca, d) in children.iter().zip(distances) {
+                        if d <= radius {
+                            results.push((d, ca.1));
+                        }
+                        unvisited.push((d, *ca));
+                    }
+                }
+                Ok(())
+            });
+            if let Some(node_result) = node_result {
+                node_result?;
+            }
+        }
+        results.retain(|(_d, pi)| !self.tombstones.contains(pi));
+        Ok(results)
+    }
+
+    /// Checks if there's any point within `radius` of the query point, the same as `!range_query(point, radius).is_empty()`
+    /// but it stops at the first point it finds. The nodes are pruned like the range query, and the closest child is
+    /// always looked into next, so when there is a point in range it's usually found after one trip down the tree.
+    pub fn exists_within(&self, point: &[M::Scalar], radius: M::Scalar) -> MalwareBrotResult<bool> {
+        self.check_dim(point)?;
+        if self.is_empty() {
+            return Ok(false);
+        }
+        let point_cloud = &self.parameters.point_cloud;
+
+        let root_center = point_cloud.get_point(self.root_address.1)?;
+        let dist_to_root = M::dense(&root_center, point);
+        let in_range = |d: M::Scalar, pi: &PointIndex| d <= radius && !self.tombstones.contains(pi);
+        if in_range(dist_to_root, &self.root_address.1) {
+            return Ok(true);
+        }
+        let mut unvisited = vec![(dist_to_root, self.root_address)];
+        while let Some((dist, address)) = unvisited.pop() {
+            let node_result = self.get_node_and(address, |n| -> MalwareBrotResult<bool> {
+                if dist - n.radius() > radius {
+                    return Ok(false);
+                }
+                let distances = point_cloud.distances_to_point(point, n.singletons())?;
+                if n.singletons().iter().zip(&distances).any(|(pi, d)| in_range(*d, pi)) {
+                    return Ok(true);
+                }
+                if let Some((nested_scale, children)) = n.children() {
+                    let children_indexes: Vec<PointIndex> =
+                        children.iter().map(|(_si, pi)| *pi).collect();
+                    let distances = point_cloud.distances_to_point(point, &children_indexes)?;
+                    if children_indexes.iter().zip(&distances).any(|(pi, d)| in_range(*d, pi)) {
+                        return Ok(true);
+                    }
+                    let mut next: Vec<(M::Scalar, NodeAddress)> =
+                        distances.into_iter().zip(children.iter().cloned()).collect();
+                    next.push((dist, (nested_scale, address.1)));
+                    // Furthest first, so the closest is popped next
+                    next.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+                    unvisited.extend(next);
+                }
+                Ok(false)
+            });
+            match node_result {
+                Some(Ok(true)) => return Ok(true),
+                Some(Err(e)) => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(false)
+    }
+
+    /// The nearest neighbor, the same as the first result of `knn(point, 1)` up to ties, without the KNN heaps. This keeps
+    /// a single best candidate and a stack of nodes to look into, closest child first, and skips a node once the distance
+    /// to its center minus its radius is at least the best distance so far. The only allocation is the stack.
+    ///
+    /// A tree always has a point in it, so this is only `None` if every point in it is tombstoned, see
+    /// `RemovalMode::Tombstone`, or if there's a bug.
+    pub fn nearest(&self, point: &[M::Scalar]) -> MalwareBrotResult<Option<(M::Scalar, PointName)>> {
+        match self.nearest_index(point)? {
+            Some((dist, pi)) => match self.parameters.point_cloud.get_name(&pi) {
+                Some(name) => Ok(Some((dist, name.to_vec()))),
+                None => Err(MalwareBrotError::IndexNotInTree(pi)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// The distance from the query to the nearest point in the tree, a novelty score for the query. This is `nearest`
+    /// without looking up the name. Returns `EmptyTree` if the reader can't see a root node to start from, rather than
+    /// an infinite distance, so an empty tree has to be handled by the caller.
+    pub fn novelty(&self, point: &[M::Scalar]) -> MalwareBrotResult<M::Scalar> {
+        match self.nearest_index(point)? {
+            Some((dist, _pi)) => Ok(dist),
+            None => Err(MalwareBrotError::EmptyTree),
+        }
+    }
+
+    fn nearest_index(&self, point: &[M::Scalar]) -> MalwareBrotResult<Option<(M::Scalar, PointIndex)>> {
+        self.check_dim(point)?;
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let point_cloud = &self.parameters.point_cloud;
+
+        let dist_to_root = M::dense(&point_cloud.get_point(self.root_address.1)?, point);
+        let mut best = if self.tombstones.contains(&self.root_address.1) {
+            (M::Scalar::infinity(), self.root_address.1)
+        } else {
+            (dist_to_root, self.root_address.1)
+        };
+        let mut unvisited = vec![(dist_to_root, self.root_address)];
+        while let Some((dist, address)) = unvisited.pop() {
+            let node_result = self.get_node_and(address, |n| -> MalwareBrotResult<()> {
+                if dist - n.radius() >= best.0 {
+                    return Ok(());
+                }
+                for pi in n.singletons() {
+                    let d = M::dense(&point_cloud.get_point(*pi)?, point);
+                    if d < best.0 && !self.tombstones.contains(pi) {
+                        best = (d, *pi);
+                    }
+                }
+                if let Some((nested_scale, children)) = n.children() {
+                    let start = unvisited.len();
+                    unvisited.push((dist, (nested_scale, address.1)));
+                    for ca in children {
+                        let d = M::dense(&point_cloud.get_point(ca.1)?, point);
+                        if d < best.0 && !self.tombstones.contains(&ca.1) {
+                            best = (d, ca.1);
+                        }
+                        unvisited.push((d, *ca));
+                    }
+                    // Furthest first, so the closest is popped next
+                    unvisited[start..]
+                        .sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+                }
+                Ok(())
+            });
+            if let Some(node_result) = node_result {
+                node_result?;
+            }
+        }
+        // Every point in the tree is tombstoned
+        if self.tombstones.contains(&best.1) {
+            return Ok(None);
+        }
+        Ok(Some(best))
+    }
+
+    /// A KNN query over the tree cut off at a scale, so the results are cluster representatives rather than points. The
+    /// nodes at scale index `si` cover the points within `scale_base^si` of their center, and the query stops descending
+    /// at the first node on each path from the root whose scale index is at most `max_level`, or at a leaf. Every point
+    /// in the tree is under exactly one of those nodes, within `scale_base^max_level` of its center. The `k` of them
+    /// with the closest centers come back, closest first, by their addresses.
+    ///
+    /// A larger `max_level` gives coarser clusters, at or above the root's scale index it's just the root. Subtrees that
+    /// can't hold a closer center than the `k`th are skipped, like in `nearest`.
+    pub fn knn_at_level(
+        &self,
+        point: &[M::Scalar],
+        k: usize,
+        max_level: i32,
+    ) -> MalwareBrotResult<Vec<(M::Scalar, NodeAddress)>> {
+        self.check_dim(point)?;
+        let point_cloud = &self.parameters.point_cloud;
+        let mut results: Vec<(M::Scalar, NodeAddress)> = Vec::with_capacity(k);
+        if k == 0 || self.is_empty() {
+            return Ok(results);
+        }
+
+        let dist_to_root = M::dense(&point_cloud.get_point(self.root_address.1)?, point);
+        let mut unvisited = vec![(dist_to_root, self.root_address)];
+        while let Some((dist, address)) = unvisited.pop() {
+            let node_result = self.get_node_and(address, |n| -> MalwareBrotResult<()> {
+                let kth = if results.len() < k { M::Scalar::max_value() } else { results[k - 1].0 };
+                if dist - n.radius() > kth {
+                    return Ok(());
+                }
+                match n.children() {
+                    Some((nested_scale, children)) if address.0 > max_level => {
+                        let start = unvisited.len();
+                        unvisited.push((dist, (nested_scale, address.1)));
+                        for ca in children {
+                            unvisited.push((M::dense(&point_cloud.get_point(ca.1)?, point), *ca));
+                        }
+                        // Furthest first, so the closest is popped next
+                        unvisited[start..]
+                            .sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+                    }
+                    _ => {
+                        if dist < kth {
+                            let position = results
+                                .iter()
+                                .position(|(d, _)| dist < *d)
+                                .unwrap_or(results.len());
+                            results.insert(position, (dist, address));
+                            results.truncate(k);
+                        }
+                    }
+                }
+                Ok(())
+            });
+            if let Some(node_result) = node_result {
+                node_result?;
+            }
+        }
+        Ok(results)
+    }
+
+    /// The node a query falls into with the tree cut off at a scale, for placing it on a map of the clusters without a
+    /// neighbor search. The nodes are the ones `knn_at_level` picks from, the first node on each path from the root
+    /// whose scale index is at most `level`, or a leaf. A node at scale index `si` covers the ball of radius
+    /// `scale_base^si` around its center, and this returns the node whose ball holds the query, or `None` if the query
+    /// is outside all of them.
+    ///
+    /// The balls of the nodes overlap, so the query can be in several. Then it's the one with the closest center, the
+    /// cluster the query would join, and on a tie the first one found. Subtrees whose nodes can't have a ball holding the
+    /// query, or a center closer than the best so far, are skipped.
+    pub fn enclosing_node(&self, point: &[M::Scalar], level: i32) -> MalwareBrotResult<Option<NodeAddress>> {
+        self.check_dim(point)?;
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let point_cloud = &self.parameters.point_cloud;
+        let scale_base = self.parameters.scale_base;
+        let mut best: Option<(M::Scalar, NodeAddress)> = None;
+        let dist_to_root = M::dense(&point_cloud.get_point(self.root_address.1)?, point);
+        let mut unvisited = vec![(dist_to_root, self.root_address)];
+        while let Some((dist, address)) = unvisited.pop() {
+            let node_result = self.get_node_and(address, |n| -> MalwareBrotResult<()> {
+                // The centers under the node are within its radius, and their balls are no bigger than its own
+                let closest = dist - n.radius();
+                if closest > scale_base.powi(address.0) || best.map(|(d, _)| closest >= d).unwrap_or(false) {
+                    return Ok(());
+                }
+                match n.children() {
+                    Some((nested_scale, children)) if address.0 > level => {
+                        unvisited.push((dist, (nested_scale, address.1)));
+                        for ca in children {
+                            unvisited.push((M::dense(&point_cloud.get_point(ca.1)?, point), *ca));
+                        }
+                    }
+                    _ => {
+                        if dist <= scale_base.powi(address.0) && best.map(|(d, _)| dist < d).unwrap_or(true) {
+                            best = Some((dist, address));
+                        }
+                    }
+                }
+                Ok(())
+            });
+            if let Some(node_result) = node_result {
+                node_result?;
+            }
+        }
+        Ok(best.map(|(_dist, address)| address))
+    }
+
+    /// # The furthest neighbor query.
+    /// The `k` points furthest from the query, furthest first, with their names. If `k` is larger than the number of
+    /// points in the tree you get all of them. This mirrors `knn_at_level`, a node can't cover a point further than the
+    /// distance to its center plus its radius, so it's skipped once that upper bound is less than the `k`th furthest
+    /// distance so far. The furthest child is always looked into next, so the bound tightens quickly.
+    ///
+    /// Most of the tree is far from a query near the edge of the data, so expect this to visit more nodes than `knn`.
+    pub fn kfn(&self, point: &[M::Scalar], k: usize) -> MalwareBrotResult<Vec<(M::Scalar, PointName)>> {
+        self.attach_names(&self.kfn_indexes(point, k)?)
+    }
+
+    fn kfn_indexes(&self, point: &[M::Scalar], k: usize) -> MalwareBrotResult<Vec<(M::Scalar, PointIndex)>> {
+        self.check_dim(point)?;
+        let point_cloud = &self.parameters.point_cloud;
+        let mut results: Vec<(M::Scalar, PointIndex)> = Vec::with_capacity(k);
+        if k == 0 || self.is_empty() {
+            return Ok(results);
+        }
+        // Furthest first, a tie goes after the points already there
+        let push_result = |results: &mut Vec<(M::Scalar, PointIndex)>, d: M::Scalar, pi: PointIndex| {
+            if self.tombstones.contains(&pi) {
+                return;
+            }
+            if results.len() < k || d > results[k - 1].0 {
+                let position = results.iter().position(|(rd, _)| d > *rd).unwrap_or(results.len());
+                results.insert(position, (d, pi));
+                results.truncate(k);
+            }
+        };
+
+        let dist_to_root = M::dense(&point_cloud.get_point(self.root_address.1)?, point);
+        push_result(&mut results, dist_to_root, self.root_address.1);
+        // Like the range query, centers are counted when we first see them, not again down their nested chain.
+        let mut unvisited = vec![(dist_to_root, self.root_address)];
+        while let Some((dist, address)) = unvisited.pop() {
+            let node_result = self.get_node_and(address, |n| -> MalwareBrotResult<()> {
+                let kth = if results.len() < k { M::Scalar::min_value() } else { results[k - 1].0 };
+                if dist + n.radius() <= kth {
+                    return Ok(());
+                }
+                let distances = point_cloud.distances_to_point(point, n.singletons())?;
+                for (pi, d) in n.singletons().iter().zip(distances) {
+                    push_result(&mut results, d, *pi);
+                }
+                if let Some((nested_scale, children)) = n.children() {
+                    let start = unvisited.len();
+                    unvisited.push((dist, (nested_scale, address.1)));
+                    let children_indexes: Vec<PointIndex> =
+                        children.iter().map(|(_si, pi)| *pi).collect();
+                    let distances = point_cloud.distances_to_point(point, &children_indexes)?;
+                    for (ca, d) in children.iter().zip(distances) {
+                        push_result(&mut results, d, ca.1);
+                        unvisited.push((d, *ca));
+                    }
+                    // Closest first, so the furthest is popped next
+                    unvisited[start..]
+                        .sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+                }
+                Ok(())
+            });
+            if let Some(node_result) = node_result {
+                node_result?;
+            }
+        }
+        Ok(results)
+    }
+
+    /// The metrics zip the coordinates together, so a point of the wrong length would quietly give wrong distances.
+    fn check_dim(&self, point: &[M::Scalar]) -> MalwareBrotResult<()> {
+        let expected = self.parameters.point_cloud.dim();
+        if point.len() != expected {
+            return Err(MalwareBrotError::DimensionMismatch {
+                expected,
+                got: point.len(),
+            });
+        }
+        check_finite(&self.parameters, point)
+    }
+
+    fn attach_names(&self, results: &[(M::Scalar, PointIndex)]) -> MalwareBrotResult<Vec<(M::Scalar, PointName)>> {
+        results
+            .iter()
+            .map(|(d, pi)| match self.parameters.point_cloud.get_name(pi) {
+                Some(name) => Ok((*d, name.to_vec())),
+                None => Err(MalwareBrotError::IndexNotInTree(*pi)),
+            })
+            .collect()
+    }
+
+    /// Finds the node that references the point. This descends into every node whose scale could cover the point,
+    /// so it's usually a narrow search.
+    fn locate(&self, pi: PointIndex) -> MalwareBrotResult<Option<PointLocation>> {
+        let point_cloud = &self.parameters.point_cloud;
+        let point = point_cloud.get_point(pi)?;
+        let dist_to_root = M::dense(&point_cloud.get_point(self.root_address.1)?, &point);
+        let mut unvisited = vec![(dist_to_root, self.root_address, Vec::new())];
+        while let Some((dist, address, path)) = unvisited.pop() {
+            // A chain of nested nodes is always entered from the top, so this is the highest node with this center.
+            if address.1 == pi {
+                return Ok(Some(PointLocation::Center(address, path)));
+            }
+            let node_result = self.get_node_and(address, |n| -> MalwareBrotResult<bool> {
+                // The children's subtrees can reach past the node's scale, the radius is how far they do reach.
+                if dist > n.radius() {
+                    return Ok(false);
+                }
+                if n.singletons().contains(&pi) {
+                    return Ok(true);
+                }
+                if let Some((nested_scale, children)) = n.children() {
+                    let mut child_path = path.clone();
+                    child_path.push(address);
+                    let children_indexes: Vec<PointIndex> =
+                        children.iter().map(|(_si, pi)| *pi).collect();
+                    let distances = point_cloud.distances_to_point(&point, &children_indexes)?;
+                    for (ca, d) in children.iter().zip(distances) {
+                        unvisited.push((d, *ca, child_path.clone()));
+                    }
+                    unvisited.push((dist, (nested_scale, address.1), child_path));
+                }
+                Ok(false)
+            });
+            match node_result {
+                Some(Ok(true)) => return Ok(Some(PointLocation::Singleton(address, path))),
+                Some(Err(e)) => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// The covering radius of every node in the tree, sorted by address. See `CoverNode::radius`.
+    pub fn node_radii(&self) -> Vec<(NodeAddress, M::Scalar)> {
+        let (mut addresses, _indexes) = self.subtree_contents(self.root_address);
+        addresses.sort();
+        addresses
+            .into_iter()
+            .filter_map(|address| self.get_node_and(address, |n| (address, n.radius())))
+            .collect()
+    }
+
+    /// All the node addresses in the subtree under the address, and all the points those nodes reference.
+    pub(crate) fn subtree_contents(&self, address: NodeAddress) -> (Vec<NodeAddress>, Vec<PointIndex>) {
+        let mut addresses = Vec::new();
+        let mut indexes = Vec::new();
+        let mut unvisited = vec![address];
+        while let Some(address) = unvisited.pop() {
+            self.get_node_and(address, |n| {
+                indexes.push(address.1);
+                indexes.extend(n.singletons());
+                if let Some((nested_scale, children)) = n.children() {
+                    unvisited.push((nested_scale, address.1));
+                    unvisited.extend(children);
+                }
+            });
+            addresses.push(address);
+        }
+        indexes.sort();
+        indexes.dedup();
+        (addresses, indexes)
+    }
+
+    fn greedy_knn_nodes<const COUNTED: bool>(&self, point: &[M::Scalar], query_heap: &mut KnnQueryHeap<'_, M::Scalar, COUNTED>) {
+        loop {
+            if let Some((dist, nearest_address)) = query_heap.closest_unvisited_child_covering_address() {
+                if self
+                    .get_node_and(nearest_address, |n| n.is_leaf())
+                    .unwrap_or(true)
+                {
+                    break;
+                } else {
+                    self.get_node_and(nearest_address, |n| {
+                        n.child_knn(Some(dist), point, &self.parameters.point_cloud, query_heap)
+                    });
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Checks that there are no node addresses in the child list of any node that don't reference a node in the tree. 
+    /// Please calmly panic if there are, the tree is very invalid.
+    pub(crate) fn no_dangling_refs(&self) -> bool {
+        let mut refs_to_check = vec![self.root_address];
+        while let Some(node_addr) = refs_to_check.pop() {
+            if self
+                .get_node_and(node_addr, |n| {
+                    match n.children() {
+                        None => {}
+                        Some((nested_scale, other_children)) => {
+                            refs_to_check.push((nested_scale, node_addr.1));
+                            refs_to_check.extend(&other_children[..]);
+                        }
+                    };
+                })
+                .is_none()
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Checks the parts of the cover tree invariants that don't need a distance. Every child a node references has to be
+    /// in the tree, and at a lower scale index than its parent. The first violation found is returned as a
+    /// `DanglingReference` or a `CoveringViolation` with a distance of `0.0`. This works on a tree loaded with
+    /// `utils::load_structure_only`, where `validate` can't compute the distances it needs.
+    pub fn validate_topology(&self) -> MalwareBrotResult<()> {
+        let mut nodes_to_check = vec![self.root_address];
+        while let Some(address) = nodes_to_check.pop() {
+            let children = match self.get_node_and(address, |n| {
+                n.children().map(|(nested_scale, children)| (nested_scale, children.to_vec()))
+            }) {
+                Some(children) => children,
+                None => continue,
+            };
+            if let Some((nested_scale, children)) = children {
+                for child in std::iter::once(&(nested_scale, address.1)).chain(&children) {
+                    if self.get_node_and(*child, |_n| ()).is_none() {
+                        return Err(MalwareBrotError::DanglingReference {
+                            parent: address,
+                            child: *child,
+                        });
+                    }
+                    if child.0 >= address.0 {
+                        return Err(MalwareBrotError::CoveringViolation {
+                            parent: address,
+                            child: child.1,
+                            distance: 0.0,
+                        });
+                    }
+                    nodes_to_check.push(*child);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the cover tree invariants over the whole tree, for when you don't trust where a tree came from, like a file
+    /// saved by an older version. A node at scale index `i` has to:
+    ///
+    /// * cover its children and singletons, they're all within `scale_base^i` of its center and the children have
+    /// lower scale indexes,
+    /// * have its children at the nested scale index `j`, its singletons and its center all at least `scale_base^j`
+    /// apart from each other. Leaves are exempt, their singletons are just the points that were left when the node
+    /// got small enough.
+    ///
+    /// The first violation found is returned as a `DanglingReference`, `CoveringViolation` or `SeparationViolation`
+    /// with the offending indexes. This computes distances between all the siblings, so it's slow on a big tree.
+    ///
+    /// The distances are compared with a relative tolerance of `DEFAULT_VALIDATE_EPSILON`, see `validate_with_tolerance`.
+    pub fn validate(&self) -> MalwareBrotResult<()> {
+        self.validate_with_tolerance(M::Scalar::from_f64(DEFAULT_VALIDATE_EPSILON as f64))
+    }
+
+    /// Checks the invariants like `validate`, but a point counts as covered if it's within `scale * (1 + epsilon)` of
+    /// the center, and as separated if it's at least `separation * (1 - epsilon)` from its siblings. The slack scales
+    /// with the distance being compared, so the same `epsilon` works for the top of the tree and the bottom.
+    ///
+    /// Comparing float distances exactly is fragile. The distance the builder used to place a point can be computed in a
+    /// different order than the one computed here, with SIMD lanes summed differently, and each order rounds differently.
+    /// The error grows with the dimension and with the size of the coordinates, so a point that's right on the edge of
+    /// a node's scale can be a few units in the last place over when it's checked. An `epsilon` of `0.0` is an exact check.
+    pub fn validate_with_tolerance(&self, epsilon: M::Scalar) -> MalwareBrotResult<()> {
+        let mut nodes_to_check = vec![self.root_address];
+        while let Some(address) = nodes_to_check.pop() {
+            nodes_to_check.extend(self.check_node(address, epsilon)?);
+        }
+        Ok(())
+    }
+
+    /// Checks the invariants of `validate_with_tolerance` for one node, against its children and singletons but not
+    /// further down. Returns the addresses of the children, the nested child first, or nothing if there's no node at
+    /// the address.
+    fn check_node(&self, address: NodeAddress, epsilon: M::Scalar) -> MalwareBrotResult<Vec<NodeAddress>> {
+        let point_cloud = &self.parameters.point_cloud;
+        let node = match self.get_node_and(address, |n| n.clone()) {
+            Some(node) => node,
+            None => return Ok(Vec::new()),
+        };
+        let scale = self.parameters.scale_base.powi(address.0) * (M::Scalar::one() + epsilon);
+        let mut covered: Vec<PointIndex> = node.singletons().to_vec();
+        let mut separated: Vec<PointIndex> = node.singletons().to_vec();
+        let mut children_to_check = Vec::new();
+        if let Some((nested_scale, children)) = node.children() {
+            separated.push(address.1);
+            for child in std::iter::once(&(nested_scale, address.1)).chain(children) {
+                if self.get_node_and(*child, |_n| ()).is_none() {
+                    return Err(MalwareBrotError::DanglingReference {
+                        parent: address,
+                        child: *child,
+                    });
+                }
+                if child.0 >= address.0 {
+                    return Err(MalwareBrotError::CoveringViolation {
+                        parent: address,
+                        child: child.1,
+                        distance: 0.0,
+                    });
+                }
+                if child.1 != address.1 {
+                    covered.push(child.1);
+                    if child.0 == nested_scale {
+                        separated.push(child.1);
+                    }
+                }
+                children_to_check.push(*child);
+            }
+
+            let separation = self.parameters.scale_base.powi(nested_scale) * (M::Scalar::one() - epsilon);
+            for (i, first) in separated.iter().enumerate() {
+                let distances = point_cloud.distances_to_point_index(*first, &separated[(i + 1)..])?;
+                for (second, distance) in separated[(i + 1)..].iter().zip(distances) {
+                    if distance < separation {
+                        return Err(MalwareBrotError::SeparationViolation {
+                            parent: address,
+                            first: *first,
+                            second: *second,
+                            distance: distance.as_f64(),
+                        });
+                    }
+                }
+            }
+        }
+
+        let distances = point_cloud.distances_to_point_index(address.1, &covered)?;
+        for (child, distance) in covered.iter().zip(distances) {
+            if distance > scale {
+                return Err(MalwareBrotError::CoveringViolation {
+                    parent: address,
+                    child: *child,
+                    distance: distance.as_f64(),
+                });
+            }
+        }
+        Ok(children_to_check)
+    }
+
+    fn cluster_children(
+        &self,
+        si: i32,
+        pis: &[PointIndex],
+    ) -> MalwareBrotResult<Vec<(usize, i32, Vec<PointIndex>)>> {
+        //println!("\tClustering children of {:?}", (si,pis));
+        let mut children_addresses = Vec::new();
         for pi in pis {
             self.layer(si).get_node_children_and(&pi, |na, nas| {
                 children_addresses.push(na);
                 children_addresses.extend(nas);
             });
         }
-        println!(
-            "\t There are {:?} total children, with an average spawn of {}",
-            children_addresses.len(),
-            (children_addresses.len() as f32) / (pis.len() as f32)
-        );
+        println!(
+            "\t There are {:?} total children, with an average spawn of {}",
+            children_addresses.len(),
+            (children_addresses.len() as f32) / (pis.len() as f32)
+        );
+
+        let mut scale_indexes: Vec<i32> = children_addresses.iter().map(|(si, _pi)| *si).collect();
+        ////println!("\t Child addresses are {:?}", children_addresses);
+        scale_indexes.dedup();
+        let mut clusters = Vec::new();
+        for child_si in scale_indexes {
+            let unclustered: Vec<PointIndex> = children_addresses
+                .iter()
+                .filter(|(osi, _pi)| osi == &child_si)
+                .map(|(_si, pi)| *pi)
+                .collect();
+            //println!("\t\t[{}] Clustering on {:?} points", child_si, unclustered.len());
+            let mut components = self
+                .layer(child_si)
+                .get_components(unclustered, &self.parameters.point_cloud)?;
+            //println!("\t\t[{}] Obtained {:?} clusters", child_si, components);
+
+            while let Some((id, component)) = components.pop() {
+                clusters.push((id, child_si, component));
+            }
+        }
+        Ok(clusters)
+    }
+}
+
+/// Every comparison with a NaN distance is false, so a single NaN coordinate quietly gives nonsense neighbors.
+fn check_finite<M: Metric>(parameters: &CoverTreeParameters<M>, point: &[M::Scalar]) -> MalwareBrotResult<()> {
+    if parameters.check_finite.load(atomic::Ordering::Relaxed) {
+        if let Some(index) = point.iter().position(|x| !x.is_finite()) {
+            return Err(MalwareBrotError::NonFiniteCoordinate { index });
+        }
+    }
+    Ok(())
+}
+
+/// The checks `insert` makes before it changes anything, so a point that can't go in leaves the tree as it was.
+fn check_new_point<M: Metric>(
+    parameters: &CoverTreeParameters<M>,
+    point: &[M::Scalar],
+    metadata: &pointcloud::labels::values::Metadata,
+) -> MalwareBrotResult<()> {
+    let expected = parameters.point_cloud.dim();
+    if point.len() != expected {
+        return Err(MalwareBrotError::DimensionMismatch {
+            expected,
+            got: point.len(),
+        });
+    }
+    check_finite(parameters, point)?;
+    parameters.point_cloud.label_scheme().check(metadata)?;
+    Ok(())
+}
+
+/// The serde form of a tree, the counterpart of `CoreProto`. It has the parameters, the nodes of each layer, and the
+/// names of the points in index order so that the name to index mapping can be checked on load. Like the protobuf it
+/// doesn't contain the point cloud, deserialize this and pass it to `CoverTreeWriter::from_serde` with the point cloud.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct CoreSerde<T: Scalar = f32> {
+    /// The bytes of `T`, 0 in trees serialized before it was, which are `f32`. It's first so `load_fast` can check it
+    /// before decoding the rest.
+    #[serde(default)]
+    scalar_width: u32,
+    scale_base: T,
+    cutoff: usize,
+    resolution: i32,
+    use_singletons: bool,
+    root_address: NodeAddress,
+    layers: Vec<(i32, Vec<CoverNode<T>>)>,
+    names: Vec<PointName>,
+    #[serde(default)]
+    tombstones: Vec<PointIndex>,
+}
+
+/// Where a point sits in the tree, along with the path of nodes from the root down to, but not including, that node.
+enum PointLocation {
+    /// The point is a singleton child of the node
+    Singleton(NodeAddress, Vec<NodeAddress>),
+    /// The point is the center of the node, and this is the highest node with that center
+    Center(NodeAddress, Vec<NodeAddress>),
+}
+
+/// What `CoverTreeWriter::remove` does with the point, set with `CoverTreeWriter::set_removal_mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemovalMode {
+    /// The point is taken out of the tree and the nodes around it are rebuilt. This is the default.
+    Restructure,
+    /// The point is marked deleted, a tombstone, and left where it is. The queries skip tombstoned points, but still
+    /// search their nodes for the points under them. A removal is then a lookup of the point, for workloads that remove
+    /// too often to pay for a rebuild each time. The tombstones slow the queries down as they pile up, check
+    /// `tombstone_ratio` and call `compact` to take them out.
+    Tombstone,
+}
+
+/// 
+pub struct CoverTreeWriter<M: Metric = L2> {
+    pub(crate) parameters: Arc<CoverTreeParameters<M>>,
+    pub(crate) layers: Vec<CoverLayerWriter<M::Scalar>>,
+    pub(crate) root_address: NodeAddress,
+    /// Bumped on each mutation, a freshly built tree is at version 0.
+    pub(crate) version: u64,
+    /// The version each node was last written or removed at. Only nodes touched after version 0 are tracked.
+    pub(crate) node_versions: HashMap<NodeAddress, u64>,
+    /// The version the tree was built, loaded or merged at. Changes from before it aren't in `node_versions`, so a
+    /// delta can't be taken since an earlier version.
+    pub(crate) base_version: u64,
+    /// How long the builder took, `None` for a tree that was loaded.
+    pub(crate) build_time: Option<Duration>,
+    pub(crate) removal_mode: RemovalMode,
+    /// The points `remove` has marked deleted in `RemovalMode::Tombstone`, shared with the readers made since.
+    pub(crate) tombstones: Arc<HashSet<PointIndex>>,
+    /// The insertion sequence number of each point inserted after the build, see `age_of`.
+    pub(crate) insertion_sequences: HashMap<PointIndex, u64>,
+    /// The sequence number the next insert gets.
+    pub(crate) next_insertion: u64,
+    /// The points of the point cloud the tree doesn't reference, those `remove` took out and those a loaded tree left
+    /// out. Shared with the readers made since, like the tombstones.
+    pub(crate) absent: Arc<HashSet<PointIndex>>,
+    /// The points the tree references, tombstones included. Each insert and removal keeps it up to date.
+    pub(crate) point_count: usize,
+    /// While an `insert_batch`, or a change to a tree with an arena, runs, the node each address written to had before
+    /// it, `None` where there wasn't one. A change that fails puts these back.
+    pub(crate) journal: Option<HashMap<NodeAddress, Option<CoverNode<M::Scalar>>>>,
+    /// The most nodes the tree can hold, see `CoverTreeBuilder::with_arena`.
+    pub(crate) arena: Option<usize>,
+}
+
+impl<M: Metric> CoverTreeWriter<M> {
+    #[doc(hidden)]
+    pub fn cluster(&mut self) -> MalwareBrotResult<()> {
+        let reader = self.reader();
+        let mut pending_clusters = vec![(0, self.root_address.0, vec![self.root_address.1])];
+        while let Some((i, si, pis)) = pending_clusters.pop() {
+            println!(
+                "Clustering children of cluster_id:{:?}, len: {}",
+                (i, si),
+                pis.len()
+            );
+            let mut children_clusters = reader.cluster_children(si, &pis[..])?;
+            let mut children_ids = Vec::new();
+            while let Some((id, nsi, npis)) = children_clusters.pop() {
+                pending_clusters.push((id, nsi, npis));
+                children_ids.push((nsi, id));
+            }
+            unsafe {self.layer(si).insert_cluster(
+                i,
+                CoverCluster {
+                    indexes: pis,
+                    children_ids,
+                },
+            );}
+        }
+        Ok(())
+    }
+
+    /// Provides a reference to a `CoverLayerWriter`. Do not use, unless you're going to leave the tree in a *valid* state.
+    pub(crate) unsafe fn layer(&mut self, scale_index: i32) -> &mut CoverLayerWriter<M::Scalar> {
+        &mut self.layers[self.parameters.internal_index(scale_index)]
+    }
+
+    /// Creates a reader for queries.
+    pub fn reader(&self) -> CoverTreeReader<M> {
+        CoverTreeReader {
+            parameters: Arc::clone(&self.parameters),
+            layers: self.layers.iter().map(|l| l.reader()).collect(),
+            root_address: self.root_address,
+            tie_break: TieBreak::Index,
+            rank_singletons: false,
+            tombstones: Arc::clone(&self.tombstones),
+            absent: Arc::clone(&self.absent),
+            version: self.version,
+            point_count: self.point_count,
+        }
+    }
+
+    /// The point cloud behind the tree, for computations the tree doesn't have a method for, without a second copy of
+    /// the vectors. Only a shared reference is given out. Changing a vector or an index under the tree would break its
+    /// covering, points are changed through the writer with `insert`, `update` and `remove`.
+    pub fn point_cloud(&self) -> &PointCloud<M> {
+        &self.parameters.point_cloud
+    }
+
+    /// Drops the tree and hands back the point cloud it was built on. Readers and factories share the point cloud with
+    /// the writer, so if any are still alive the writer is handed back unchanged as the error.
+    pub fn into_point_cloud(self) -> Result<PointCloud<M>, CoverTreeWriter<M>> {
+        if Arc::strong_count(&self.parameters) > 1 {
+            return Err(self);
+        }
+        match Arc::try_unwrap(self.parameters) {
+            Ok(parameters) => Ok(parameters.point_cloud),
+            Err(_) => unreachable!("the writer held the only reference to its parameters"),
+        }
+    }
+
+    /// How long the build of this tree took, from handing the point cloud to the builder to the tree being ready to
+    /// query. `None` for a tree that was loaded rather than built. Changes to the tree afterwards don't add to it.
+    pub fn build_time(&self) -> Option<Duration> {
+        self.build_time
+    }
+
+    pub(crate) unsafe fn insert_raw(
+        &mut self,
+        scale_index: i32,
+        point_index: PointIndex,
+        node: CoverNode<M::Scalar>,
+    ) {
+        self.touch((scale_index, point_index));
+        self.record((scale_index, point_index));
+        self.layers[self.parameters.internal_index(scale_index)].insert_raw(point_index, node);
+    }
+
+    pub(crate) unsafe fn remove_raw(&mut self, scale_index: i32, point_index: PointIndex) {
+        self.touch((scale_index, point_index));
+        self.record((scale_index, point_index));
+        self.layers[self.parameters.internal_index(scale_index)].remove_raw(point_index);
+    }
+
+    /// Notes the node at the address in the journal, if there is one and it's the first write to the address since the
+    /// journal started. The writes since the last refresh aren't visible yet, so this has to be the first.
+    fn record(&mut self, address: NodeAddress) {
+        if let Some(journal) = self.journal.as_mut() {
+            let layer = &self.layers[self.parameters.internal_index(address.0)];
+            journal.entry(address).or_insert_with(|| layer.node(address.1));
+        }
+    }
+
+    /// Panics if one of the nodes breaks the invariants `CoverTreeReader::validate` checks, only against its own
+    /// children and singletons. The mutations call this on the nodes they changed, so a metric that breaks the tree,
+    /// like one that isn't symmetric, fails at the insert or removal that did it rather than in some later query. Only
+    /// debug builds check, in release builds this is empty.
+    #[cfg(debug_assertions)]
+    fn debug_check_nodes(&self, addresses: &[NodeAddress], mutation: &str) {
+        let reader = self.reader();
+        for address in addresses {
+            if let Err(e) = reader.check_node(*address, M::Scalar::from_f64(DEFAULT_VALIDATE_EPSILON as f64)) {
+                panic!("The tree is broken after {}, at the node {:?}: {}", mutation, address, e);
+            }
+        }
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[inline(always)]
+    fn debug_check_nodes(&self, _addresses: &[NodeAddress], _mutation: &str) {}
+
+    fn touch(&mut self, address: NodeAddress) {
+        if self.version > 0 {
+            self.node_versions.insert(address, self.version);
+        }
+    }
+
+    /// The number of mutations made to this tree. It's saved with the tree and lets `save_delta` pick out the nodes
+    /// that changed since some earlier version.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Adds empty layers to the top of the tree so that there is a layer for `scale_index`.
+    pub(crate) fn reserve_layers(&mut self, scale_index: i32) -> MalwareBrotResult<()> {
+        let internal_index = self.parameters.checked_internal_index(scale_index)?;
+        while self.layers.len() <= internal_index {
+            let layer_scale = self.parameters.resolution + self.layers.len() as i32 - 1;
+            self.layers.push(CoverLayerWriter::new(layer_scale));
+        }
+        Ok(())
+    }
+
+    /// Removes the named point from the tree and refreshes. The point stays in the point cloud, the tree just no longer
+    /// references it, so it won't turn up in any query.
+    ///
+    /// If the point is a singleton it's just dropped from its node. If it's the center of a node, the whole subtree
+    /// under the highest node with that center (the nested chain and everything below it) is removed. The remaining
+    /// points of that subtree are rebuilt into a new subtree around the point closest to the removed one, and that
+    /// replaces the old subtree in the parent's children. If the new subtree's scale doesn't fit under the parent, the
+    /// parent's subtree is rebuilt instead, and so on up. So the nested children never need to be re-parented by hand
+    /// and there's at most one nested child per node. Removing the root center rebuilds the whole tree, and may change
+    /// the root and add layers, so get a new reader afterwards.
+    ///
+    /// In `RemovalMode::Tombstone` none of that happens, the point is only marked deleted and the queries of the readers
+    /// made afterwards skip it, see `compact`. Readers from before still return it. The set of tombstones is shared with
+    /// the readers, so while an older one is alive marking a point copies the set.
+    ///
+    /// Returns `NameNotInTree` if the name isn't known or was already removed or tombstoned, and `RemoveLastPoint` if
+    /// this is the only point left, in `RemovalMode::Restructure`.
+    pub fn remove<N: AsRef<[u8]>>(&mut self, name: N) -> MalwareBrotResult<()> {
+        let name = name.as_ref();
+        let pi = match self.parameters.point_cloud.get_index(name) {
+            Some(pi) => *pi,
+            None => return Err(MalwareBrotError::NameNotInTree(name.to_vec())),
+        };
+        let reader = self.reader();
+        let location = match reader.locate(pi)? {
+            Some(location) if !self.tombstones.contains(&pi) => location,
+            _ => return Err(MalwareBrotError::NameNotInTree(name.to_vec())),
+        };
+        if self.removal_mode == RemovalMode::Tombstone {
+            // The reader shares the set, let go of it so it isn't copied
+            drop(reader);
+            self.version += 1;
+            Arc::make_mut(&mut self.tombstones).insert(pi);
+            return Ok(());
+        }
+        self.within_arena(|tree, _sequences| {
+            tree.remove_located(&reader, pi, location)?;
+            drop(reader);
+            Arc::make_mut(&mut tree.absent).insert(pi);
+            Ok(())
+        })
+    }
+
+    /// Takes the point out of the tree and refreshes, see `remove`. The reader is from before the removal, and found the
+    /// point at `location`.
+    fn remove_located(
+        &mut self,
+        reader: &CoverTreeReader<M>,
+        pi: PointIndex,
+        location: PointLocation,
+    ) -> MalwareBrotResult<()> {
+        let (path, changed) = match location {
+            PointLocation::Singleton(address, path) => {
+                self.version += 1;
+                let mut node = reader.get_node_and(address, |n| n.clone()).unwrap();
+                node.remove_singleton(&pi);
+                node.remove_coverage(1);
+                self.update_radius(reader, &mut node, pi)?;
+                node.update_metasummary(&self.parameters.point_cloud)?;
+                unsafe {self.insert_raw(address.0, address.1, node);}
+                self.remove_coverage_along(reader, &path, pi)?;
+                (path, Some(address))
+            }
+            PointLocation::Center(address, mut path) => {
+                let (_addresses, mut orphans) = reader.subtree_contents(address);
+                orphans.retain(|o| *o != pi);
+                if orphans.is_empty() && path.is_empty() {
+                    return Err(MalwareBrotError::RemoveLastPoint);
+                }
+                self.version += 1;
+                let mut target = address;
+                let replacement = if orphans.is_empty() {
+                    None
+                } else {
+                    let distances = reader
+                        .point_cloud()
+                        .distances_to_point_index(pi, &orphans)?;
+                    let mut closest = 0;
+                    for (i, d) in distances.iter().enumerate() {
+                        if *d < distances[closest] {
+                            closest = i;
+                        }
+                    }
+                    let center = orphans.swap_remove(closest);
+                    let (fitted, center, orphans) =
+                        self.fit_subtree(reader, target, &mut path, center, orphans, &[pi], false)?;
+                    target = fitted;
+                    Some((center, orphans))
+                };
+                let (addresses, _indexes) = reader.subtree_contents(target);
+                for (si, ci) in addresses {
+                    unsafe {self.remove_raw(si, ci);}
+                }
+                let replacement = match replacement {
+                    Some((center, orphans)) => Some(build_subtree(self, center, orphans)?),
+                    None => None,
+                };
+                match (path.split_last(), replacement) {
+                    (Some((parent_address, ancestors)), replacement) => {
+                        let mut parent = reader.get_node_and(*parent_address, |n| n.clone()).unwrap();
+                        parent.remove_child(&target);
+                        if let Some(new_address) = replacement {
+                            parent.insert_child(new_address, 0)?;
+                        }
+                        parent.remove_coverage(1);
+                        self.update_radius(reader, &mut parent, pi)?;
+                        unsafe {self.insert_raw(parent_address.0, parent_address.1, parent);}
+                        self.remove_coverage_along(reader, ancestors, pi)?;
+                    }
+                    (None, Some(new_root)) => self.root_address = new_root,
+                    (None, None) => unreachable!(),
+                }
+                (path, replacement)
+            }
+        };
+        self.point_count -= 1;
+        self.refresh();
+        self.debug_check_nodes(&path, "a removal");
+        if let Some(address) = changed {
+            self.debug_check_nodes(&[address], "a removal");
+        }
+        Ok(())
+    }
+
+    /// Moves the point called `name` to `new_vector`, keeping its name, index and metadata. If the point is a singleton
+    /// and every node above it still covers the new vector, that is it's within `scale_base^si` of the center of each
+    /// node at scale index `si` on the path to it, and it's still separated from its siblings like `validate` checks,
+    /// this only updates the radii of those nodes and the tree keeps its shape. Otherwise the point is taken out like `remove` does, and put back by rebuilding the smallest subtree whose
+    /// chain of nodes covers the new vector, or the whole tree if the root doesn't.
+    ///
+    /// The vectors can't be written in place, so either way the point cloud is copied with the new vector, see
+    /// `PointCloud::with_replaced_point`. The vectors aren't copied, the copy shares them, but its maps of names and
+    /// addresses are, so this is `O(n)` in the number of points. Get a new reader afterwards, readers from before keep
+    /// the old point cloud. Returns `NameNotInTree` if the name isn't
+    /// known or was removed, `DimensionMismatch` if the vector is the wrong length, and `NonFiniteCoordinate` if it has a
+    /// NaN or infinite coordinate, unless `set_check_finite` turned that off.
+    pub fn update<N: AsRef<[u8]>>(&mut self, name: N, new_vector: &[M::Scalar]) -> MalwareBrotResult<()> {
+        let name = name.as_ref();
+        let pi = match self.parameters.point_cloud.get_index(name) {
+            Some(pi) => *pi,
+            None => return Err(MalwareBrotError::NameNotInTree(name.to_vec())),
+        };
+        let expected = self.parameters.point_cloud.dim();
+        if new_vector.len() != expected {
+            return Err(MalwareBrotError::DimensionMismatch {
+                expected,
+                got: new_vector.len(),
+            });
+        }
+        check_finite(&self.parameters, new_vector)?;
+        let reader = self.reader();
+        let location = match reader.locate(pi)? {
+            Some(location) if !self.tombstones.contains(&pi) => location,
+            _ => return Err(MalwareBrotError::NameNotInTree(name.to_vec())),
+        };
+        let point_cloud = self.parameters.point_cloud.with_replaced_point(pi, new_vector)?;
+
+        if let PointLocation::Singleton(address, mut path) = location {
+            path.push(address);
+            let mut in_place = true;
+            for (si, ci) in &path {
+                let center = self.parameters.point_cloud.get_point(*ci)?;
+                if M::dense(&center, new_vector) > self.parameters.scale_base.powi(*si) {
+                    in_place = false;
+                    break;
+                }
+            }
+            // The singletons of a node that isn't a leaf also have to stay separated from its other children
+            let siblings = reader.get_node_and(address, |n| {
+                n.children().map(|(nested_scale, children)| {
+                    let mut siblings: Vec<PointIndex> = n.singletons().iter().filter(|qi| **qi != pi).cloned().collect();
+                    siblings.push(address.1);
+                    siblings.extend(children.iter().filter(|ca| ca.0 == nested_scale).map(|ca| ca.1));
+                    (nested_scale, siblings)
+                })
+            });
+            if let (true, Some(Some((nested_scale, siblings)))) = (in_place, siblings) {
+                let separation = self.parameters.scale_base.powi(nested_scale);
+                for qi in siblings {
+                    if M::dense(&self.parameters.point_cloud.get_point(qi)?, new_vector) < separation {
+                        in_place = false;
+                        break;
+                    }
+                }
+            }
+            if in_place {
+                let old_vector = self.parameters.point_cloud.get_point(pi)?.to_vec();
+                self.set_point_cloud(point_cloud);
+                self.version += 1;
+                for address in &path {
+                    let mut node = reader.get_node_and(*address, |n| n.clone()).unwrap();
+                    let center = self.parameters.point_cloud.get_point(address.1)?;
+                    let (old_dist, new_dist) = (M::dense(&center, &old_vector), M::dense(&center, new_vector));
+                    if new_dist >= node.radius() {
+                        node.set_radius(new_dist);
+                    } else if old_dist >= node.radius() {
+                        // It was the furthest point, the subtree is the same so the new cloud gives the new radius
+                        let (_addresses, mut covered) = reader.subtree_contents(*address);
+                        covered.retain(|qi| *qi != address.1);
+                        let radius = self
+                            .parameters
+                            .point_cloud
+                            .distances_to_point_index(address.1, &covered)?
+                            .into_iter()
+                            .fold(M::Scalar::neg_infinity(), M::Scalar::max);
+                        node.set_radius(radius);
+                    }
+                    unsafe {self.insert_raw(address.0, address.1, node);}
+                }
+                self.refresh();
+                self.debug_check_nodes(&path, "an update");
+                return Ok(());
+            }
+        }
+
+        let (_addresses, indexes) = reader.subtree_contents(self.root_address);
+        if indexes.len() == 1 {
+            // It's the only point, there's no structure to move it in
+            self.set_point_cloud(point_cloud);
+            self.version += 1;
+            self.refresh();
+            return Ok(());
+        }
+        // Taken out for real whatever the removal mode, it's put back right after
+        let location = reader.locate(pi)?.ok_or(MalwareBrotError::IndexNotInTree(pi))?;
+        self.within_arena(|tree, _sequences| {
+            tree.remove_located(&reader, pi, location)?;
+            tree.set_point_cloud(point_cloud);
+            tree.reinsert(pi)
+        })
+    }
+
+    /// Adds a new point to the tree, and returns the index it got. It's put in like `update` puts a moved point back, by
+    /// rebuilding the smallest subtree whose chain of nodes covers it. The index of a removed point is reused, the
+    /// removed point's own if it had this name, so the point cloud doesn't grow past the most points the tree has held.
+    ///
+    /// Like `update` the point cloud is copied with the new point, see `PointCloud::with_point`, so this is `O(n)` in the
+    /// number of points, though none of their vectors are copied.
+    /// Get a new reader afterwards. Returns `NameCollision` if a point in the tree already has the name, and leaves that
+    /// point as it was, use `insert_or_replace` to overwrite it. Returns `DimensionMismatch` if the vector is the wrong
+    /// length, `NonFiniteCoordinate` like `update`, and a `MetadataMismatch` if the metadata doesn't fit the labels of
+    /// the point cloud, see `LabelScheme::check`. For a point without labels pass the cloud's
+    /// `label_scheme().blank_metadata()`. A tombstoned point with the name is taken out of the tree first, as if it had
+    /// been removed in `RemovalMode::Restructure`.
+    pub fn insert(
+        &mut self,
+        name: PointName,
+        point: &[M::Scalar],
+        metadata: pointcloud::labels::values::Metadata,
+    ) -> MalwareBrotResult<PointIndex> {
+        check_new_point(&self.parameters, point, &metadata)?;
+        self.within_arena(|tree, sequences| tree.insert_checked(name, point, metadata, sequences))
+    }
+
+    /// The body of `insert`, once the point's been checked. The insertion sequence the point had before is pushed onto
+    /// `sequences`.
+    fn insert_checked(
+        &mut self,
+        name: PointName,
+        point: &[M::Scalar],
+        metadata: pointcloud::labels::values::Metadata,
+        sequences: &mut Vec<(PointIndex, Option<u64>)>,
+    ) -> MalwareBrotResult<PointIndex> {
+        let tombstoned = self.parameters.point_cloud.get_index(&name).cloned().filter(|pi| self.tombstones.contains(pi));
+        if let Some(pi) = tombstoned {
+            let reader = self.reader();
+            if let Some(location) = reader.locate(pi)? {
+                self.remove_located(&reader, pi, location)?;
+            }
+            drop(reader);
+            Arc::make_mut(&mut self.tombstones).remove(&pi);
+        }
+        let reader = self.reader();
+        let (_addresses, resident) = reader.subtree_contents(self.root_address);
+        let point_cloud = &self.parameters.point_cloud;
+        let pi = match point_cloud.get_index(&name) {
+            Some(pi) if resident.binary_search(pi).is_ok() => {
+                return Err(MalwareBrotError::NameCollision(name))
+            }
+            Some(pi) => *pi,
+            None => {
+                let indexes = point_cloud.reference_indexes();
+                match indexes.iter().find(|qi| resident.binary_search(qi).is_err()) {
+                    Some(qi) => *qi,
+                    None => indexes.iter().max().map(|qi| qi + 1).unwrap_or(0),
+                }
+            }
+        };
+        let point_cloud = point_cloud.with_point(pi, name, point, metadata)?;
+        self.set_point_cloud(point_cloud);
+        self.reinsert(pi)?;
+        sequences.push((pi, self.insertion_sequences.insert(pi, self.next_insertion)));
+        self.next_insertion += 1;
+        Ok(pi)
+    }
+
+    /// Inserts the point like `insert` if no point in the tree has the name, otherwise the point with the name is
+    /// replaced, it's moved to the new vector like `update` and its metadata is swapped for the new metadata. A replaced
+    /// point keeps its index and its age, see `age_of`. Either way the index of the point is returned.
+    ///
+    /// A replacement copies the point cloud twice, once for the vector and once for the metadata, see `update`. Returns
+    /// `DimensionMismatch`, `NonFiniteCoordinate` and `MetadataMismatch` like `insert`, and then nothing is inserted or
+    /// replaced. They're checked before the point is moved, so a replacement isn't left half done.
+    pub fn insert_or_replace(
+        &mut self,
+        name: PointName,
+        point: &[M::Scalar],
+        metadata: pointcloud::labels::values::Metadata,
+    ) -> MalwareBrotResult<PointIndex> {
+        check_new_point(&self.parameters, point, &metadata)?;
+        let pi = match self.parameters.point_cloud.get_index(&name) {
+            Some(pi) if !self.tombstones.contains(pi) => *pi,
+            _ => return self.insert(name, point, metadata),
+        };
+        if self.reader().locate(pi)?.is_none() {
+            return self.insert(name, point, metadata);
+        }
+        self.update(&name, point)?;
+        let point_cloud = self.parameters.point_cloud.with_point(pi, name, point, metadata)?;
+        self.set_point_cloud(point_cloud);
+        Ok(pi)
+    }
+
+    /// Inserts every point the iterator yields, in order, with blank metadata, see `LabelScheme::blank_metadata`. This
+    /// is for feeding the tree from a source that isn't in memory all at once, like a database cursor or a network
+    /// stream. Each point goes in like `insert`, so each one copies the point cloud.
+    ///
+    /// Stops at the first point that can't be inserted and returns `PartialInsert`, with the number of points that went
+    /// in and the error of the one that didn't. The points before it stay in the tree, nothing is rolled back, and the
+    /// rest of the iterator isn't read.
+    pub fn extend<I, N>(&mut self, points: I) -> MalwareBrotResult<()>
+    where
+        I: IntoIterator<Item = (N, Vec<M::Scalar>)>,
+        N: Into<PointName>,
+    {
+        let blank = self.parameters.point_cloud.label_scheme().blank_metadata();
+        for (inserted, (name, point)) in points.into_iter().enumerate() {
+            if let Err(e) = self.insert(name.into(), &point, blank.clone()) {
+                return Err(MalwareBrotError::PartialInsert {
+                    inserted,
+                    error: Box::new(e),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Inserts all of the points with blank metadata, see `LabelScheme::blank_metadata`, or none of them. It's the
+    /// same as calling `insert` on each, but cheaper for more than a few points:
+    ///
+    /// * The point cloud is copied once for the whole batch, with all of the new points in one data source, see
+    ///   `PointCloud::with_points`, where a loop of `insert`s copies its maps once per point.
+    /// * Each point is routed down from the root to the subtree it lands in, and the points are grouped by it. Each
+    ///   group's subtree is then rebuilt once with all of its points, where a loop rebuilds it once per point. A group
+    ///   whose subtree was taken out by the rebuild of an earlier group, because that rebuild climbed above it, is routed
+    ///   again against the new tree.
+    ///
+    /// So `k` points that land in `g` subtrees cost one `O(n + k)` copy and `g` rebuilds, against `k` copies and `k`
+    /// rebuilds for the loop. The more the points cluster the bigger the saving. Points outside the root's cover all
+    /// land on the root, and the batch rebuilds the whole tree once for all of them where a loop would for each.
+    ///
+    /// Every point is checked before anything changes. A name that's in the tree already, or twice in the batch, is a
+    /// `NameCollision`. Like `insert` returns `DimensionMismatch` and `NonFiniteCoordinate`, a tombstoned point with one
+    /// of the names is taken out first, and removed points' indexes are reused. If something still fails part way, a
+    /// node breaking `DoubleNest` or `InsertBeforeNest` say, every node the batch wrote is put back and the tree is
+    /// left as it was before the call, at a later version. Get a new reader afterwards.
+    pub fn insert_batch<N: AsRef<[u8]>>(&mut self, points: &[(N, Vec<M::Scalar>)]) -> MalwareBrotResult<()> {
+        let blank = self.parameters.point_cloud.label_scheme().blank_metadata();
+        let mut names = HashSet::new();
+        for (name, point) in points {
+            check_new_point(&self.parameters, point, &blank)?;
+            let name = name.as_ref();
+            let resident = match self.parameters.point_cloud.get_index(name) {
+                Some(pi) => !self.absent.contains(pi) && !self.tombstones.contains(pi),
+                None => false,
+            };
+            if resident || !names.insert(name) {
+                return Err(MalwareBrotError::NameCollision(name.to_vec()));
+            }
+        }
+        if points.is_empty() {
+            return Ok(());
+        }
+
+        self.undone_on_error(|tree, sequences| {
+            tree.insert_journaled(points, blank, sequences)?;
+            tree.check_arena()
+        })
+    }
+
+    /// Makes `change`, and if it fails puts back every node it wrote, along with the rest of the state of
+    /// the tree, so the tree is left as it was before, at a later version. `change` pushes the insertion sequence each
+    /// point had before onto the vector when it gives it a new one.
+    fn undone_on_error<R, F>(&mut self, change: F) -> MalwareBrotResult<R>
+    where
+        F: FnOnce(&mut Self, &mut Vec<(PointIndex, Option<u64>)>) -> MalwareBrotResult<R>,
+    {
+        let parameters = Arc::clone(&self.parameters);
+        let root_address = self.root_address;
+        let point_count = self.point_count;
+        let absent = Arc::clone(&self.absent);
+        let tombstones = Arc::clone(&self.tombstones);
+        let next_insertion = self.next_insertion;
+        let layer_count = self.layers.len();
+        self.journal = Some(HashMap::new());
+        let mut sequences = Vec::new();
+        let result = change(self, &mut sequences);
+        let journal = self.journal.take().unwrap_or_default();
+        if result.is_err() {
+            self.version += 1;
+            for (address, node) in journal {
+                match node {
+                    Some(node) => unsafe {self.insert_raw(address.0, address.1, node)},
+                    None => unsafe {self.remove_raw(address.0, address.1)},
+                }
+            }
+            self.refresh();
+            self.layers.truncate(layer_count);
+            self.parameters = parameters;
+            self.root_address = root_address;
+            self.point_count = point_count;
+            self.absent = absent;
+            self.tombstones = tombstones;
+            self.next_insertion = next_insertion;
+            for (pi, sequence) in sequences {
+                match sequence {
+                    Some(sequence) => self.insertion_sequences.insert(pi, sequence),
+                    None => self.insertion_sequences.remove(&pi),
+                };
+            }
+        }
+        result
+    }
+
+    /// Makes `change`, and if the tree has an arena undoes it like `undone_on_error` when it fails or leaves the tree
+    /// with more nodes than the arena holds. Without an arena `change` is made as it is.
+    fn within_arena<R, F>(&mut self, change: F) -> MalwareBrotResult<R>
+    where
+        F: FnOnce(&mut Self, &mut Vec<(PointIndex, Option<u64>)>) -> MalwareBrotResult<R>,
+    {
+        if self.arena.is_none() {
+            return change(self, &mut Vec::new());
+        }
+        self.undone_on_error(|tree, sequences| {
+            let result = change(tree, sequences)?;
+            tree.check_arena()?;
+            Ok(result)
+        })
+    }
+
+    /// Returns `ArenaCapacityExceeded` if the tree has more nodes than its arena holds, as of the last refresh.
+    pub(crate) fn check_arena(&self) -> MalwareBrotResult<()> {
+        match self.arena {
+            Some(capacity) if self.node_count() > capacity => Err(MalwareBrotError::ArenaCapacityExceeded {
+                capacity,
+                needed: self.node_count(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// The number of nodes in the tree, as of the last refresh.
+    pub(crate) fn node_count(&self) -> usize {
+        self.layers.iter().map(|l| l.node_count()).sum()
+    }
+
+    /// The most nodes the tree can hold, if it was built `with_arena`, see `CoverTreeBuilder::with_arena`.
+    pub fn arena_capacity(&self) -> Option<usize> {
+        self.arena
+    }
+
+    /// Makes room for `additional` more points ahead of a big `insert_batch`, or a run of inserts, so the maps the nodes
+    /// are kept in don't have to grow while they go in. It's only a hint, the tree and the results of every query are the
+    /// same whether it's called or not, it saves the reallocations and rehashing of the maps as they fill up.
+    ///
+    /// Each layer gets room in proportion to the nodes it has, new points mostly make nodes where the old ones did, and
+    /// the insertion ages get room for every point, see `age_of`. The point cloud's maps aren't reserved, each insert
+    /// copies them into new maps of the size they need. Each layer keeps two copies of its map for the readers, the one
+    /// they see now gets the room right away and the other at the next change to the tree.
+    pub fn reserve(&mut self, additional: usize) {
+        let total = self.node_count();
+        if total > 0 {
+            for layer in &mut self.layers {
+                let share = (additional as f64 * layer.node_count() as f64 / total as f64).ceil() as usize;
+                if share > 0 {
+                    layer.reserve(share);
+                }
+            }
+        }
+        self.insertion_sequences.reserve(additional);
+        self.refresh();
+    }
+
+    /// The body of `insert_batch`, it's undone from the journal if this fails. The insertion sequence each point had
+    /// before is pushed onto `sequences` when it's given a new one.
+    fn insert_journaled<N: AsRef<[u8]>>(
+        &mut self,
+        points: &[(N, Vec<M::Scalar>)],
+        blank: pointcloud::labels::values::Metadata,
+        sequences: &mut Vec<(PointIndex, Option<u64>)>,
+    ) -> MalwareBrotResult<()> {
+        let mut tombstoned = Vec::new();
+        for (name, _point) in points {
+            if let Some(pi) = self.parameters.point_cloud.get_index(name.as_ref()) {
+                if self.tombstones.contains(pi) {
+                    tombstoned.push(*pi);
+                }
+            }
+        }
+        for pi in tombstoned {
+            let reader = self.reader();
+            if let Some(location) = reader.locate(pi)? {
+                self.remove_located(&reader, pi, location)?;
+            }
+            drop(reader);
+            Arc::make_mut(&mut self.tombstones).remove(&pi);
+            Arc::make_mut(&mut self.absent).insert(pi);
+        }
+
+        // The removed points' indexes are reused, a name's own first, like `insert` does
+        let point_cloud = &self.parameters.point_cloud;
+        let names: HashSet<&[u8]> = points.iter().map(|(name, _point)| name.as_ref()).collect();
+        let mut free: Vec<PointIndex> = self
+            .absent
+            .iter()
+            .filter(|pi| point_cloud.get_name(pi).map(|name| !names.contains(name)).unwrap_or(true))
+            .cloned()
+            .collect();
+        free.sort_unstable_by(|a, b| b.cmp(a));
+        let mut next = point_cloud.reference_indexes().iter().max().map(|pi| pi + 1).unwrap_or(0);
+        let mut indexes = Vec::with_capacity(points.len());
+        for (name, _point) in points {
+            let pi = match point_cloud.get_index(name.as_ref()) {
+                Some(pi) => *pi,
+                None => free.pop().unwrap_or_else(|| {
+                    next += 1;
+                    next - 1
+                }),
+            };
+            indexes.push(pi);
+        }
+        let point_cloud = point_cloud.with_points(
+            indexes
+                .iter()
+                .zip(points)
+                .map(|(pi, (name, point))| (*pi, name.as_ref().to_vec(), &point[..], blank.clone())),
+        )?;
+        self.set_point_cloud(point_cloud);
+
+        let mut pending = indexes.clone();
+        while !pending.is_empty() {
+            let reader = self.reader();
+            let mut groups: BTreeMap<NodeAddress, (Vec<NodeAddress>, Vec<PointIndex>)> = BTreeMap::new();
+            for pi in pending.drain(..) {
+                let (ancestors, target) = self.route(&reader, &self.parameters.point_cloud.get_point(pi)?)?;
+                groups.entry(target).or_insert((ancestors, Vec::new())).1.push(pi);
+            }
+            drop(reader);
+            let mut removed = HashSet::new();
+            for (target, (ancestors, pis)) in groups {
+                if removed.contains(&target) || ancestors.iter().any(|a| removed.contains(a)) {
+                    pending.extend(pis);
+                    continue;
+                }
+                removed.extend(self.rebuild_with(self.reader(), ancestors, target, pis)?);
+            }
+        }
+        for pi in indexes {
+            sequences.push((pi, self.insertion_sequences.insert(pi, self.next_insertion)));
+            self.next_insertion += 1;
+        }
+        Ok(())
+    }
+
+    /// Inserts the points of a CSV, laid out as the options describe, `chunk_rows` points at a time with `insert_batch`,
+    /// see `CsvChunks`. At most `chunk_rows` rows of the file are held at once, so this is for files too big to read into
+    /// ram, use `utils::cover_tree_from_csv_streaming` to build a tree out of one.
+    ///
+    /// Stops at the first row that doesn't parse, or the first chunk that can't be inserted, and returns
+    /// `PartialInsert` with the number of points that went in and the error. The `CSVReadError` of a row has its line
+    /// number in the file. The points of the rows before it are all in the tree, and it's valid and can be queried. A
+    /// chunk that can't be inserted isn't inserted at all, see `insert_batch`.
+    pub fn extend_from_csv<P: AsRef<std::path::Path>>(
+        &mut self,
+        path: P,
+        options: &pointcloud::CsvOptions,
+        chunk_rows: usize,
+    ) -> MalwareBrotResult<()> {
+        let chunks = pointcloud::CsvChunks::<M::Scalar>::open(path, options, chunk_rows)?;
+        self.insert_chunks(chunks, 0)
+    }
+
+    /// Inserts each chunk with `insert_batch`, see `extend_from_csv`. `inserted` is counted on from.
+    pub(crate) fn insert_chunks(
+        &mut self,
+        chunks: pointcloud::CsvChunks<M::Scalar>,
+        mut inserted: usize,
+    ) -> MalwareBrotResult<()> {
+        for chunk in chunks {
+            let result = chunk.map_err(MalwareBrotError::from).and_then(|chunk| {
+                self.insert_batch(&chunk)?;
+                Ok(chunk.len())
+            });
+            match result {
+                Ok(count) => inserted += count,
+                Err(e) => {
+                    return Err(MalwareBrotError::PartialInsert {
+                        inserted,
+                        error: Box::new(e),
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps a file of vectors, laid out like the data files of `PointCloud::from_memmap_files`, and inserts all of its
+    /// points into the tree without a rebuild. The points keep living in the file, the point cloud is extended with it
+    /// rather than held in ram like the points `insert` takes, so this is how an mmap backed tree takes new points. They're named
+    /// by their index and have blank metadata, see `PointCloud::with_memmap_file`. Returns the indexes of the new points,
+    /// in file order, and they're aged like inserted points, see `age_of`.
+    ///
+    /// There's no header in the file, so a file that isn't a whole number of the tree's points is a
+    /// `DimensionMismatch`, with the number of scalars in the file as what was got. Returns `NonFiniteCoordinate` like
+    /// `insert`. Nothing is added to the tree for either.
+    pub fn append_mmap_points<P: AsRef<std::path::Path>>(
+        &mut self,
+        new_vectors_path: P,
+    ) -> MalwareBrotResult<Vec<PointIndex>> {
+        let path = new_vectors_path.as_ref();
+        let expected = self.parameters.point_cloud.dim();
+        let bytes = std::fs::metadata(path)?.len() as usize;
+        if bytes % (expected * std::mem::size_of::<M::Scalar>()) != 0 {
+            return Err(MalwareBrotError::DimensionMismatch {
+                expected,
+                got: bytes / std::mem::size_of::<M::Scalar>(),
+            });
+        }
+        if bytes == 0 {
+            return Ok(Vec::new());
+        }
+        let old_len = self.parameters.point_cloud.len();
+        let point_cloud = self.parameters.point_cloud.with_memmap_file(path)?;
+        let first = self.parameters.point_cloud.reference_indexes().iter().max().map(|pi| pi + 1).unwrap_or(0);
+        let indexes: Vec<PointIndex> = (first..first + (point_cloud.len() - old_len) as PointIndex).collect();
+        for pi in &indexes {
+            check_finite(&self.parameters, &point_cloud.get_point(*pi)?)?;
+        }
+        self.within_arena(|tree, sequences| {
+            tree.set_point_cloud(point_cloud);
+            for pi in &indexes {
+                tree.reinsert(*pi)?;
+                sequences.push((*pi, tree.insertion_sequences.insert(*pi, tree.next_insertion)));
+                tree.next_insertion += 1;
+            }
+            Ok(indexes)
+        })
+    }
+
+    /// Puts `pi` into the tree after an `update` took it out or an `insert` added it to the cloud. This walks down from
+    /// the root through the nodes whose center covers the point, following nested chains, and rebuilds the subtree under
+    /// the deepest such chain with the point added. The nodes above it cover one more point and their radii are raised to reach it.
+    fn reinsert(&mut self, pi: PointIndex) -> MalwareBrotResult<()> {
+        let reader = self.reader();
+        let (ancestors, target) = self.route(&reader, &self.parameters.point_cloud.get_point(pi)?)?;
+        self.rebuild_with(reader, ancestors, target, vec![pi]).map(|_removed| ())
+    }
+
+    /// Walks down from the root like `reinsert` does, and returns the node whose subtree the point goes in and the path
+    /// to it. That's the root with an empty path if the root doesn't cover the point.
+    fn route(
+        &self,
+        reader: &CoverTreeReader<M>,
+        point: &[M::Scalar],
+    ) -> MalwareBrotResult<(Vec<NodeAddress>, NodeAddress)> {
+        let scale_base = self.parameters.scale_base;
+        let root_center = self.parameters.point_cloud.get_point(self.root_address.1)?;
+        let mut ancestors = Vec::new();
+        let mut target = self.root_address;
+        if M::dense(&root_center, point) <= scale_base.powi(target.0) {
+            // The chain of the target is walked down, looking for a child that covers the point
+            let mut chain_node = Some(target);
+            while let Some(address) = chain_node {
+                chain_node = None;
+                let next = reader.get_node_and(address, |n| -> MalwareBrotResult<_> {
+                    let mut closest: Option<(M::Scalar, NodeAddress)> = None;
+                    if let Some((nested_scale, children)) = n.children() {
+                        for ca in children {
+                            let d = M::dense(&self.parameters.point_cloud.get_point(ca.1)?, point);
+                            if d <= scale_base.powi(ca.0) && closest.map(|(cd, _)| d < cd).unwrap_or(true) {
+                                closest = Some((d, *ca));
+                            }
+                        }
+                        Ok((closest.map(|(_, ca)| ca), Some((nested_scale, address.1))))
+                    } else {
+                        Ok((None, None))
+                    }
+                });
+                match next {
+                    Some(Ok((Some(child), _))) => {
+                        ancestors.push(address);
+                        target = child;
+                        chain_node = Some(child);
+                    }
+                    Some(Ok((None, nested))) => {
+                        ancestors.push(address);
+                        chain_node = nested;
+                    }
+                    Some(Err(e)) => return Err(e),
+                    None => {}
+                }
+            }
+            // The chain nodes of the target itself get rebuilt, they aren't ancestors
+            while ancestors.last().map(|a| a.1 == target.1 && a.0 <= target.0).unwrap_or(false) {
+                ancestors.pop();
+            }
+        }
+        Ok((ancestors, target))
+    }
+
+    /// Rebuilds the subtree under `target` with the points of `pis` added, they're in the point cloud but not the tree.
+    /// `ancestors` is the path to `target` that `route` found for each of them, so every node on it covers every point.
+    /// Returns the addresses of the nodes that were taken out, the new subtree may reuse some of them.
+    fn rebuild_with(
+        &mut self,
+        reader: CoverTreeReader<M>,
+        mut ancestors: Vec<NodeAddress>,
+        target: NodeAddress,
+        pis: Vec<PointIndex>,
+    ) -> MalwareBrotResult<Vec<NodeAddress>> {
+        let (_addresses, mut indexes) = reader.subtree_contents(target);
+        indexes.retain(|qi| *qi != target.1);
+        indexes.extend(pis.iter().cloned());
+        let (target, center, indexes) =
+            self.fit_subtree(&reader, target, &mut ancestors, target.1, indexes, &pis, true)?;
+        let (addresses, _indexes) = reader.subtree_contents(target);
+        self.version += 1;
+        for (si, ci) in &addresses {
+            unsafe {self.remove_raw(*si, *ci);}
+        }
+        let new_address = build_subtree(self, center, indexes)?;
+        match ancestors.last() {
+            Some(parent_address) => {
+                for address in &ancestors {
+                    let mut node = reader.get_node_and(*address, |n| n.clone()).unwrap();
+                    if address == parent_address {
+                        node.remove_child(&target);
+                        node.insert_child(new_address, 0)?;
+                    }
+                    node.add_coverage(pis.len());
+                    let center = self.parameters.point_cloud.get_point(address.1)?;
+                    for pi in &pis {
+                        let d = M::dense(&center, &self.parameters.point_cloud.get_point(*pi)?);
+                        if d > node.radius() {
+                            node.set_radius(d);
+                        }
+                    }
+                    unsafe {self.insert_raw(address.0, address.1, node);}
+                }
+            }
+            None => self.root_address = new_address,
+        }
+        drop(reader);
+        self.point_count += pis.len();
+        if pis.iter().any(|pi| self.absent.contains(pi)) {
+            let absent = Arc::make_mut(&mut self.absent);
+            for pi in &pis {
+                absent.remove(pi);
+            }
+        }
+        self.refresh();
+        self.debug_check_nodes(&ancestors, "an insert");
+        self.debug_check_nodes(&[new_address], "an insert");
+        Ok(addresses)
+    }
+
+    /// A subtree rebuilt in place of `target` has to have a lower scale index than its parent, its center has to be
+    /// covered by the parent's, and if it lands on the parent's nested scale its center has to be separated from the
+    /// parent's other children. The rebuild picks its scale from the furthest point, and the points under a node can be
+    /// further than `scale_base^si` from its center, and a new center may be too, so it may not fit. While it doesn't
+    /// this climbs to the top of the parent's nested chain and rebuilds that instead.
+    /// `path` is the path to `target`, and is cut down to the path to the returned target. The points of `pis` are left
+    /// out of the points of a climbed to subtree, or added to them if `inserting`.
+    fn fit_subtree(
+        &self,
+        reader: &CoverTreeReader<M>,
+        mut target: NodeAddress,
+        path: &mut Vec<NodeAddress>,
+        mut center: PointIndex,
+        mut indexes: Vec<PointIndex>,
+        pis: &[PointIndex],
+        inserting: bool,
+    ) -> MalwareBrotResult<(NodeAddress, PointIndex, Vec<PointIndex>)> {
+        let mut sorted_pis = pis.to_vec();
+        sorted_pis.sort();
+        loop {
+            let parent = match path.last() {
+                Some(parent) => *parent,
+                None => return Ok((target, center, indexes)),
+            };
+            let scale_index = subtree_scale_index(&self.parameters, center, &indexes)?;
+            let point_cloud = &self.parameters.point_cloud;
+            let covered = center == parent.1
+                || M::dense(&point_cloud.get_point(parent.1)?, &point_cloud.get_point(center)?)
+                    <= self.parameters.scale_base.powi(parent.0);
+            let fits = scale_index < parent.0 && covered && {
+                // At the parent's nested scale it's checked for separation like the rest of the parent's children
+                let siblings = reader.get_node_and(parent, |n| match n.children() {
+                    Some((nested_scale, children)) if nested_scale == scale_index => {
+                        let mut siblings = n.singletons().to_vec();
+                        siblings.push(parent.1);
+                        siblings.extend(
+                            children
+                                .iter()
+                                .filter(|ca| ca.0 == nested_scale && **ca != target)
+                                .map(|ca| ca.1),
+                        );
+                        siblings
+                    }
+                    _ => Vec::new(),
+                });
+                let separation = self.parameters.scale_base.powi(scale_index);
+                let distances = self
+                    .parameters
+                    .point_cloud
+                    .distances_to_point_index(center, &siblings.unwrap_or_default())?;
+                distances.iter().all(|d| *d >= separation)
+            };
+            if fits {
+                return Ok((target, center, indexes));
+            }
+            let parent_center = parent.1;
+            while path.last().map(|a| a.1 == parent_center).unwrap_or(false) {
+                target = path.pop().unwrap();
+            }
+            center = target.1;
+            let (_addresses, contents) = reader.subtree_contents(target);
+            indexes = contents.into_iter().filter(|qi| sorted_pis.binary_search(qi).is_err() && *qi != center).collect();
+            if inserting {
+                indexes.extend(pis.iter().cloned());
+            }
+        }
+    }
+
+    /// Swaps the point cloud for one with the same indexes, keeping the rest of the parameters. Readers from before keep
+    /// the old one.
+    fn set_point_cloud(&mut self, point_cloud: PointCloud<M>) {
+        let parameters = &self.parameters;
+        self.parameters = Arc::new(CoverTreeParameters {
+            total_nodes: atomic::AtomicUsize::new(parameters.total_nodes.load(atomic::Ordering::SeqCst)),
+            scale_base: parameters.scale_base,
+            cutoff: parameters.cutoff,
+            resolution: parameters.resolution,
+            use_singletons: parameters.use_singletons,
+            cluster_min: parameters.cluster_min,
+            point_cloud,
+            verbosity: parameters.verbosity,
+            insertion_strategy: parameters.insertion_strategy,
+            check_finite: atomic::AtomicBool::new(parameters.check_finite.load(atomic::Ordering::Relaxed)),
+            deterministic: parameters.deterministic,
+            report_duplicates: false,
+        });
+    }
+
+    /// Replaces the names of the points, like `PointCloud::set_names`, for a tree that's already built or loaded. The
+    /// point cloud is copied with the new names, sharing its vectors, so readers from before keep the old names. Every
+    /// point in the cloud needs a name, tombstoned ones too, otherwise this is a parsing error and the names are left
+    /// as they were.
+    pub fn set_names(&mut self, names: Vec<(PointIndex, PointName)>) -> MalwareBrotResult<()> {
+        let point_cloud = self.parameters.point_cloud.with_names(names)?;
+        self.set_point_cloud(point_cloud);
+        Ok(())
+    }
+
+    /// Turns the check for NaN and infinite coordinates in inserted and query vectors on or off, see
+    /// `CoverTreeBuilder::set_check_finite`. The readers share the parameters, so this applies to them as well.
+    pub fn set_check_finite(&self, check_finite: bool) {
+        self.parameters.check_finite.store(check_finite, atomic::Ordering::Relaxed);
+    }
+
+    /// Merges the points of `other` into this tree. The result is the tree you'd get by building over the union of the two
+    /// point clouds with this tree's parameters, so queries on it match that tree. Only the points the trees reference
+    /// are merged, points removed or tombstoned in either are left out. Both trees have to be built with the same scale base and
+    /// dimension, and no name can be in both, that's a `ScaleBaseMismatch`, a `DimensionMismatch`, or a `NameCollision`.
+    /// The metadata of `other` has to have the same keys as this tree's.
+    ///
+    /// The point clouds can't be extended, so this copies every point into a new point cloud in ram and rebuilds the
+    /// tree over it. That's not cheap. The point indexes change, so get a new reader and save a new base afterwards,
+    /// deltas from before the merge don't apply to the merged tree. The version goes up by one like any other change, so
+    /// a `KnnCache` filled before the merge isn't used after it. The ages start over, see `age_of`, this tree's points are
+    /// older than the other's.
+    pub fn merge(&mut self, other: CoverTreeWriter<M>) -> MalwareBrotResult<()> {
+        let ours = &self.parameters;
+        let theirs = &other.parameters;
+        if ours.scale_base != theirs.scale_base {
+            return Err(MalwareBrotError::ScaleBaseMismatch {
+                ours: ours.scale_base.as_f64(),
+                theirs: theirs.scale_base.as_f64(),
+            });
+        }
+        let dim = ours.point_cloud.dim();
+        if theirs.point_cloud.dim() != dim {
+            return Err(MalwareBrotError::DimensionMismatch {
+                expected: dim,
+                got: theirs.point_cloud.dim(),
+            });
+        }
+
+        let our_reader = self.reader();
+        let their_reader = other.reader();
+        let (_addresses, mut our_indexes) = our_reader.subtree_contents(our_reader.root_address);
+        let (_addresses, mut their_indexes) = their_reader.subtree_contents(their_reader.root_address);
+        our_indexes.retain(|pi| !self.tombstones.contains(pi));
+        their_indexes.retain(|pi| !other.tombstones.contains(pi));
+        for pi in &their_indexes {
+            if let Some(name) = theirs.point_cloud.get_name(pi) {
+                if let Some(qi) = ours.point_cloud.get_index(name) {
+                    if our_indexes.binary_search(qi).is_ok() {
+                        return Err(MalwareBrotError::NameCollision(name.to_vec()));
+                    }
+                }
+            }
+        }
+
+        let mut data = Vec::with_capacity((our_indexes.len() + their_indexes.len()) * dim);
+        let mut labels = ours.point_cloud.label_scheme().empty();
+        for (point_cloud, indexes) in &[
+            (&ours.point_cloud, &our_indexes),
+            (&theirs.point_cloud, &their_indexes),
+        ] {
+            for pi in indexes.iter() {
+                let name = match point_cloud.get_name(pi) {
+                    Some(name) => name,
+                    None => return Err(MalwareBrotError::IndexNotInTree(*pi)),
+                };
+                data.extend_from_slice(&point_cloud.get_point(*pi)?);
+                labels.push(Some(name.to_vec()), point_cloud.get_metadata(*pi)?)?;
+            }
+        }
+
+        let point_cloud = PointCloud::<M>::from_ram(Box::from(data), dim, labels)?;
+        let builder = CoverTreeBuilder {
+            scale_base: ours.scale_base.as_f64(),
+            cutoff: ours.cutoff,
+            resolution: ours.resolution,
+            use_singletons: ours.use_singletons,
+            cluster_min: ours.cluster_min,
+            verbosity: ours.verbosity,
+            insertion_strategy: ours.insertion_strategy,
+            check_finite: ours.check_finite.load(atomic::Ordering::Relaxed),
+            name_storage: ours.point_cloud.name_storage(),
+            deterministic: ours.deterministic,
+            arena: self.arena,
+        };
+        let removal_mode = self.removal_mode;
+        let version = self.version;
+        *self = builder.build(point_cloud)?;
+        self.removal_mode = removal_mode;
+        self.version = version + 1;
+        self.base_version = self.version;
+        Ok(())
+    }
+
+    /// Rebuilds the tree from scratch over the points it currently references, with the bulk build and the same
+    /// parameters. The point cloud isn't touched, so names still map to the same indexes and queries return the same
+    /// results, but on a tree shaped like a fresh build. The root keeps its center.
+    ///
+    /// Each `remove` that takes out a node's center rebuilds only the subtree under it, around whatever point was closest.
+    /// After a lot of those the tree can be deeper and more lopsided than a bulk build, so queries visit more nodes. Call
+    /// this after a removal heavy workload, when `stats` show the depth or `knn_instrumented` the work per query creeping
+    /// up. It's as expensive as a build, and like `remove` the change shows up in the next delta and in new readers.
+    pub fn rebuild(&mut self) -> MalwareBrotResult<()> {
+        let reader = self.reader();
+        let center = self.root_address.1;
+        let (addresses, mut indexes) = reader.subtree_contents(self.root_address);
+        indexes.retain(|pi| *pi != center);
+        self.within_arena(|tree, _sequences| {
+            tree.version += 1;
+            for (si, ci) in addresses {
+                unsafe {tree.remove_raw(si, ci);}
+            }
+            tree.root_address = build_subtree(tree, center, indexes)?;
+            tree.refresh();
+            Ok(())
+        })
+    }
+
+    /// Sets what `remove` does, see `RemovalMode`. Points already tombstoned stay tombstoned when this goes back to
+    /// `Restructure`, until `compact` takes them out.
+    pub fn set_removal_mode(&mut self, removal_mode: RemovalMode) {
+        self.removal_mode = removal_mode;
+    }
+
+    /// What `remove` does, `RemovalMode::Restructure` unless it's been set.
+    pub fn removal_mode(&self) -> RemovalMode {
+        self.removal_mode
+    }
+
+    /// The number of points that are tombstoned, still in the tree but skipped by the queries.
+    pub fn tombstone_count(&self) -> usize {
+        self.tombstones.len()
+    }
+
+    /// The fraction of the points in the tree that are tombstoned, 0 without any. The queries still search the nodes of
+    /// tombstoned points, so the work per query goes up with this, call `compact` once it's more than you can take.
+    pub fn tombstone_ratio(&self) -> f32 {
+        if self.tombstones.is_empty() {
+            return 0.0;
+        }
+        self.tombstones.len() as f32 / self.point_count as f32
+    }
+
+    /// The insertion sequence number of the named point, how old it is. The points of the build are numbered by their
+    /// index, and each `insert` after that takes the next number, so a larger number is a younger point, even when an
+    /// insert reuses the index of a removed point. `update` moves a point, it doesn't insert it, so it keeps its number.
+    /// The numbers are saved by `save` and `to_bytes`, a tree saved before they were is numbered like a fresh build.
+    ///
+    /// Returns `NameNotInTree` if no point in the tree has the name, including points that were removed or tombstoned.
+    pub fn age_of<N: AsRef<[u8]>>(&self, name: N) -> MalwareBrotResult<u64> {
+        let name = name.as_ref();
+        let pi = match self.parameters.point_cloud.get_index(name) {
+            Some(pi) => *pi,
+            None => return Err(MalwareBrotError::NameNotInTree(name.to_vec())),
+        };
+        if self.tombstones.contains(&pi) || self.reader().locate(pi)?.is_none() {
+            return Err(MalwareBrotError::NameNotInTree(name.to_vec()));
+        }
+        Ok(*self.insertion_sequences.get(&pi).unwrap_or(&pi))
+    }
+
+    /// Takes every tombstoned point out of the tree at once, by rebuilding it over the rest like `rebuild` does. The
+    /// root keeps its center unless that's tombstoned, then the live point closest to it is the new center. The point
+    /// cloud isn't touched. It's as expensive as a build, so let the tombstones pile up and compact in one go, rather than
+    /// after every removal. The tombstones are saved by `save`, `save_delta` and `to_serde`, so a loaded tree skips the
+    /// same points, and compacting before saving only makes the file smaller.
+    ///
+    /// Returns `RemoveLastPoint` if every point in the tree is tombstoned, and leaves the tree as it was.
+    pub fn compact(&mut self) -> MalwareBrotResult<()> {
+        if self.tombstones.is_empty() {
+            return Ok(());
+        }
+        let reader = self.reader();
+        let (addresses, mut indexes) = reader.subtree_contents(self.root_address);
+        indexes.retain(|pi| !self.tombstones.contains(pi));
+        let mut center = self.root_address.1;
+        if self.tombstones.contains(&center) {
+            if indexes.is_empty() {
+                return Err(MalwareBrotError::RemoveLastPoint);
+            }
+            let distances = self.parameters.point_cloud.distances_to_point_index(center, &indexes)?;
+            let mut closest = 0;
+            for (i, d) in distances.iter().enumerate() {
+                if *d < distances[closest] {
+                    closest = i;
+                }
+            }
+            center = indexes[closest];
+        }
+        drop(reader);
+        self.within_arena(|tree, _sequences| {
+            tree.point_count = indexes.len();
+            indexes.retain(|pi| *pi != center);
+            tree.version += 1;
+            for (si, ci) in addresses {
+                unsafe {tree.remove_raw(si, ci);}
+            }
+            let tombstones = std::mem::replace(&mut tree.tombstones, Arc::new(HashSet::new()));
+            Arc::make_mut(&mut tree.absent).extend(tombstones.iter().cloned());
+            tree.root_address = build_subtree(tree, center, indexes)?;
+            tree.refresh();
+            Ok(())
+        })
+    }
+
+    fn remove_coverage_along(
+        &mut self,
+        reader: &CoverTreeReader<M>,
+        path: &[NodeAddress],
+        removed: PointIndex,
+    ) -> MalwareBrotResult<()> {
+        for address in path {
+            let mut node = reader.get_node_and(*address, |n| n.clone()).unwrap();
+            node.remove_coverage(1);
+            self.update_radius(reader, &mut node, removed)?;
+            unsafe {self.insert_raw(address.0, address.1, node);}
+        }
+        Ok(())
+    }
+
+    /// Fixes the radius of a node that no longer covers `removed`. The radius only changes if that was the furthest point,
+    /// then it's recomputed from everything else the node covers. The reader is from before the removal, so it still
+    /// sees the old subtree, which covers the same points plus `removed`.
+    fn update_radius(
+        &self,
+        reader: &CoverTreeReader<M>,
+        node: &mut CoverNode<M::Scalar>,
+        removed: PointIndex,
+    ) -> MalwareBrotResult<()> {
+        let point_cloud = &self.parameters.point_cloud;
+        let center = *node.center_index();
+        let dist = point_cloud.distances_to_point_index(center, &[removed])?[0];
+        if dist >= node.radius() {
+            let (_addresses, mut covered) = reader.subtree_contents((*node.scale_index(), center));
+            covered.retain(|pi| *pi != removed && *pi != center);
+            let radius = point_cloud
+                .distances_to_point_index(center, &covered)?
+                .into_iter()
+                .fold(M::Scalar::neg_infinity(), M::Scalar::max);
+            node.set_radius(radius);
+        }
+        Ok(())
+    }
+
+    /// Loads a tree from a protobuf. There's a `load_tree` in `utils` that handles loading from a path to a protobuf file.
+    /// If the protobuf has a checksum and the layers don't match it, the file is corrupt and this is a
+    /// `ChecksumMismatch` parsing error. The checksum is written before the layers, so a file that's cut short in them
+    /// still has it and fails the check. Files saved before checksums were added load unchecked, and quietly, it's up to
+    /// the caller to warn about them with `cover_proto.get_has_checksum()`, as `utils::load_tree` does.
+    ///
+    /// The structure is checked before anything is built, so a file that's been tampered with, checksum and all, is a
+    /// parsing error and not a panic. A layer out of order is `MisplacedLayer`, a root or child scale index with no layer
+    /// or not below its parent's is `ScaleOutOfRange`, a reference to a node that isn't in the file is `MissingNode`, a
+    /// center or singleton that isn't in the point cloud is `UnknownIndex`, and a node with more child scales than child
+    /// centers, or fewer, is `MismatchedChildren`.
+    ///
+    /// The file has to be loaded with a metric of the scalar type it was saved with, a tree of `f64`s with `L2<f64>` and
+    /// so on, anything else is `ScalarWidthMismatch`. Files from before the width was saved are `f32`.
+    pub fn load(
+        cover_proto: &CoreProto,
+        point_cloud: PointCloud<M>,
+    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        let found = match cover_proto.get_scalar_width() {
+            0 => 4,
+            width => width,
+        };
+        if found != M::Scalar::WIDTH {
+            return Err(MalwareBrotError::ParsingError(
+                errors::ParsingError::ScalarWidthMismatch {
+                    expected: M::Scalar::WIDTH,
+                    found,
+                },
+            ));
+        }
+        let scale_base = if found == 8 {
+            M::Scalar::from_f64(cover_proto.get_wide_scale_base())
+        } else {
+            M::Scalar::from_f64(cover_proto.get_scale_base() as f64)
+        };
+        check_resolution(cover_proto.resolution)?;
+        let point_indexes = point_cloud.reference_indexes().into_iter().collect();
+        check_core_proto(cover_proto, &point_indexes)?;
+        let parameters = Arc::new(CoverTreeParameters {
+            total_nodes: atomic::AtomicUsize::new(0),
+            use_singletons: cover_proto.use_singletons,
+            scale_base,
+            cutoff: cover_proto.cutoff as usize,
+            resolution: cover_proto.resolution as i32,
+            cluster_min: 5,
+            point_cloud,
+            verbosity: 2,
+            insertion_strategy: InsertionStrategy::FirstFit,
+            check_finite: atomic::AtomicBool::new(true),
+            deterministic: false,
+            report_duplicates: false,
+        });
+        if cover_proto.get_has_checksum() {
+            let found = layers_checksum(cover_proto.get_layers());
+            if found != cover_proto.get_checksum() {
+                return Err(MalwareBrotError::ParsingError(
+                    errors::ParsingError::ChecksumMismatch {
+                        expected: cover_proto.get_checksum(),
+                        found,
+                    },
+                ));
+            }
+        }
+        let root_address = (cover_proto.get_root_scale(), cover_proto.get_root_index());
+        let layers = cover_proto
+            .get_layers()
+            .par_iter()
+            .map(|l| CoverLayerWriter::load(l))
+            .collect();
+        let insertion_sequences = cover_proto
+            .get_inserted_indexes()
+            .iter()
+            .cloned()
+            .zip(cover_proto.get_insertion_sequences().iter().cloned())
+            .collect();
+        // Files from before the sequence numbers were saved number every point by its index
+        let next_insertion = match cover_proto.get_next_insertion() {
+            0 => first_insertion(&parameters.point_cloud),
+            next_insertion => next_insertion,
+        };
+
+        let mut writer = CoverTreeWriter {
+            parameters,
+            layers,
+            root_address,
+            version: cover_proto.get_version(),
+            node_versions: HashMap::new(),
+            base_version: cover_proto.get_version(),
+            build_time: None,
+            removal_mode: RemovalMode::Restructure,
+            tombstones: Arc::new(HashSet::new()),
+            absent: Arc::new(HashSet::new()),
+            insertion_sequences,
+            next_insertion,
+            point_count: 0,
+            journal: None,
+            arena: None,
+        };
+        writer.refresh();
+        writer.count_points(cover_proto.get_tombstones())?;
+        Ok(writer)
+    }
+
+    /// Encodes the tree into a protobuf. See `utils::save_tree` for saving to a file on disk.
+    pub fn save(&self) -> CoreProto {
+        let mut cover_proto = CoreProto::new();
+        cover_proto.set_scalar_width(M::Scalar::WIDTH);
+        if M::Scalar::WIDTH == 8 {
+            cover_proto.set_wide_scale_base(self.parameters.scale_base.as_f64());
+        } else {
+            cover_proto.set_scale_base(self.parameters.scale_base.as_f64() as f32);
+        }
+        cover_proto.set_cutoff(self.parameters.cutoff as u64);
+        cover_proto.set_resolution(self.parameters.resolution);
+        cover_proto.set_use_singletons(self.parameters.use_singletons);
+        cover_proto.set_dim(self.parameters.point_cloud.dim() as u64);
+        cover_proto.set_count(self.parameters.point_cloud.len() as u64);
+        cover_proto.set_root_scale(self.root_address.0);
+        cover_proto.set_root_index(self.root_address.1);
+        cover_proto.set_layers(self.layers.iter().map(|l| l.save()).collect());
+        cover_proto.set_version(self.version);
+        cover_proto.set_checksum(layers_checksum(cover_proto.get_layers()));
+        cover_proto.set_has_checksum(true);
+        let mut inserted: Vec<(PointIndex, u64)> =
+            self.insertion_sequences.iter().map(|(pi, sequence)| (*pi, *sequence)).collect();
+        inserted.sort();
+        cover_proto.set_inserted_indexes(inserted.iter().map(|(pi, _)| *pi).collect());
+        cover_proto.set_insertion_sequences(inserted.iter().map(|(_, sequence)| *sequence).collect());
+        cover_proto.set_next_insertion(self.next_insertion);
+        cover_proto.set_tombstones(self.sorted_tombstones());
+        cover_proto
+    }
+
+    /// Encodes the tree into the bytes `utils::save_tree` writes to a file, for storing the tree somewhere that isn't a
+    /// filesystem. A blob from here can be written out and loaded with `utils::load_tree`, and the reverse.
+    pub fn to_bytes(&self) -> MalwareBrotResult<Vec<u8>> {
+        Ok(protobuf::Message::write_to_bytes(&self.save())?)
+    }
+
+    /// Decodes a tree from the bytes of `to_bytes`, or of a file written by `utils::save_tree`, then loads it like `load`.
+    /// Every length in the encoding is checked against the bytes that are left, so a truncated or tampered blob is a
+    /// parsing error and never allocates more than its own size.
+    pub fn from_bytes(bytes: &[u8], point_cloud: PointCloud<M>) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        let cover_proto: CoreProto = protobuf::parse_from_bytes(bytes)?;
+        CoverTreeWriter::load(&cover_proto, point_cloud)
+    }
+
+    /// Reads a file written by `utils::save_tree` with `tokio::fs`, then decodes and loads it like `from_bytes`. The
+    /// decoding and the rebuilding of the layers are CPU-bound, so they run on a task spawned on the runtime's blocking
+    /// pool and don't stall the reactor. The errors are those of `utils::load_tree`, an `IoError` if the file can't be
+    /// read and a parsing error if it doesn't decode. A blocking task that panics or is cancelled is an `IoError` too.
+    #[cfg(feature = "async")]
+    pub async fn load_async<P: AsRef<std::path::Path>>(
+        tree_path: P,
+        point_cloud: PointCloud<M>,
+    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        let bytes = tokio::fs::read(tree_path).await?;
+        tokio::task::spawn_blocking(move || CoverTreeWriter::from_bytes(&bytes, point_cloud))
+            .await
+            .map_err(|e| MalwareBrotError::IoError(std::io::Error::new(std::io::ErrorKind::Other, e)))?
+    }
+
+    /// Encodes the nodes that were written or removed after `since_version` into a protobuf. Applying it with
+    /// `load_delta` to a copy of this tree saved at `since_version` brings that copy up to the current version. See
+    /// `utils::save_delta` for saving it next to the base file.
+    ///
+    /// The tree only knows the nodes that changed since it was built, loaded or merged, so `since_version` can't be
+    /// earlier than that. If it is this is a `VersionMismatch` parsing error, with the version the tree starts from as
+    /// the version found.
+    pub fn save_delta(&self, since_version: u64) -> MalwareBrotResult<DeltaProto> {
+        if since_version < self.base_version {
+            return Err(MalwareBrotError::ParsingError(
+                errors::ParsingError::VersionMismatch {
+                    expected: since_version,
+                    found: self.base_version,
+                },
+            ));
+        }
+        let reader = self.reader();
+        let mut changed: Vec<NodeAddress> = self
+            .node_versions
+            .iter()
+            .filter(|(_, v)| **v > since_version)
+            .map(|(address, _)| *address)
+            .collect();
+        changed.sort();
+
+        let mut delta_proto = DeltaProto::new();
+        delta_proto.set_base_version(since_version);
+        delta_proto.set_version(self.version);
+        delta_proto.set_root_scale(self.root_address.0);
+        delta_proto.set_root_index(self.root_address.1);
+        let mut layer_protos: Vec<LayerProto> = Vec::new();
+        for address in changed {
+            match reader.get_node_and(address, |n| n.save()) {
+                Some(node_proto) => {
+                    if layer_protos.last().map(|l| l.get_scale_index()) != Some(address.0) {
+                        let mut layer_proto = LayerProto::new();
+                        layer_proto.set_scale_index(address.0);
+                        layer_protos.push(layer_proto);
+                    }
+                    layer_protos.last_mut().unwrap().mut_nodes().push(node_proto);
+                }
+                None => {
+                    delta_proto.mut_removed_scale_indexes().push(address.0);
+                    delta_proto.mut_removed_point_indexes().push(address.1);
+                }
+            }
+        }
+        delta_proto.set_layers(layer_protos.into());
+        delta_proto.set_tombstones(self.sorted_tombstones());
+        Ok(delta_proto)
+    }
+
+    /// Replays a delta from `save_delta` onto this tree and refreshes. The tree has to be at the version the delta was
+    /// taken from, otherwise this is a `VersionMismatch` parsing error and the tree is left alone. The nodes in the delta
+    /// are marked as changed at the delta's version, so a later `save_delta` passes them on.
+    pub fn load_delta(&mut self, delta_proto: &DeltaProto) -> MalwareBrotResult<()> {
+        if delta_proto.get_base_version() != self.version {
+            return Err(MalwareBrotError::ParsingError(
+                errors::ParsingError::VersionMismatch {
+                    expected: delta_proto.get_base_version(),
+                    found: self.version,
+                },
+            ));
+        }
+        self.version = delta_proto.get_version();
+        let removed = delta_proto
+            .get_removed_scale_indexes()
+            .iter()
+            .zip(delta_proto.get_removed_point_indexes());
+        for (si, pi) in removed {
+            if self.parameters.internal_index(*si) < self.layers.len() {
+                unsafe {self.remove_raw(*si, *pi);}
+            }
+            self.touch((*si, *pi));
+        }
+        for layer_proto in delta_proto.get_layers() {
+            let si = layer_proto.get_scale_index();
+            self.reserve_layers(si)?;
+            for node_proto in layer_proto.get_nodes() {
+                let node = CoverNode::load(si, node_proto);
+                let pi = *node.center_index();
+                unsafe {self.insert_raw(si, pi, node);}
+                self.touch((si, pi));
+            }
+        }
+        self.root_address = (delta_proto.get_root_scale(), delta_proto.get_root_index());
+        self.refresh();
+        self.count_points(delta_proto.get_tombstones())
+    }
+
+    /// Copies the tree into its serde form. Serializing the writer directly does this for you.
+    #[cfg(feature = "serde")]
+    pub fn to_serde(&self) -> CoreSerde<M::Scalar> {
+        let point_cloud = &self.parameters.point_cloud;
+        CoreSerde {
+            scalar_width: M::Scalar::WIDTH,
+            scale_base: self.parameters.scale_base,
+            cutoff: self.parameters.cutoff,
+            resolution: self.parameters.resolution,
+            use_singletons: self.parameters.use_singletons,
+            root_address: self.root_address,
+            layers: self.layers.iter().map(|l| (l.scale_index(), l.nodes())).collect(),
+            names: point_cloud
+                .reference_indexes()
+                .iter()
+                .map(|pi| point_cloud.get_name(pi).map(|name| name.to_vec()).unwrap_or_default())
+                .collect(),
+            tombstones: self.sorted_tombstones(),
+        }
+    }
+
+    /// Rebuilds a tree from its serde form, the counterpart of `load`. The point cloud has to have the same names at
+    /// the same indexes as the one the tree was saved with, otherwise this is a parsing error. Like `load`, a tree of
+    /// another scalar type than the metric's is `ScalarWidthMismatch`.
+    #[cfg(feature = "serde")]
+    pub fn from_serde(
+        core: CoreSerde<M::Scalar>,
+        point_cloud: PointCloud<M>,
+    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        let found = match core.scalar_width {
+            0 => 4,
+            width => width,
+        };
+        if found != M::Scalar::WIDTH {
+            return Err(MalwareBrotError::ParsingError(
+                errors::ParsingError::ScalarWidthMismatch {
+                    expected: M::Scalar::WIDTH,
+                    found,
+                },
+            ));
+        }
+        let names_match = core.names.len() == point_cloud.len()
+            && core
+                .names
+                .iter()
+                .enumerate()
+                .all(|(i, name)| point_cloud.get_name(&(i as PointIndex)) == Some(&name[..]));
+        if !names_match {
+            return Err(MalwareBrotError::ParsingError(
+                errors::ParsingError::RegularParsingError(
+                    "The point cloud's names do not match the names the tree was saved with",
+                ),
+            ));
+        }
+        let parameters = Arc::new(CoverTreeParameters {
+            total_nodes: atomic::AtomicUsize::new(0),
+            use_singletons: core.use_singletons,
+            scale_base: core.scale_base,
+            cutoff: core.cutoff,
+            resolution: core.resolution,
+            cluster_min: 5,
+            point_cloud,
+            verbosity: 2,
+            insertion_strategy: InsertionStrategy::FirstFit,
+            check_finite: atomic::AtomicBool::new(true),
+            deterministic: false,
+            report_duplicates: false,
+        });
+        let layers = core
+            .layers
+            .into_iter()
+            .map(|(si, nodes)| CoverLayerWriter::from_nodes(si, nodes))
+            .collect();
+        let next_insertion = first_insertion(&parameters.point_cloud);
+        let mut writer = CoverTreeWriter {
+            parameters,
+            layers,
+            root_address: core.root_address,
+            version: 0,
+            node_versions: HashMap::new(),
+            base_version: 0,
+            build_time: None,
+            removal_mode: RemovalMode::Restructure,
+            tombstones: Arc::new(HashSet::new()),
+            absent: Arc::new(HashSet::new()),
+            insertion_sequences: HashMap::new(),
+            next_insertion,
+            point_count: 0,
+            journal: None,
+            arena: None,
+        };
+        writer.refresh();
+        writer.count_points(&core.tombstones)?;
+        Ok(writer)
+    }
+
+    /// Encodes the tree's serde form with bincode, for loading many trees quickly with `load_fast`. It's faster to write
+    /// and read than the protobuf of `to_bytes`, but it's only readable by this crate, not from other languages, and
+    /// it has what `to_serde` has, so not the version or the insertion ages. The first byte is `FAST_FORMAT_VERSION`,
+    /// the second the width of the scalar.
+    #[cfg(feature = "bincode")]
+    pub fn save_fast(&self) -> MalwareBrotResult<Vec<u8>> {
+        use bincode::Options;
+        let mut bytes = vec![FAST_FORMAT_VERSION];
+        bincode::DefaultOptions::new()
+            .serialize_into(&mut bytes, &self.to_serde())
+            .map_err(|_e| {
+                MalwareBrotError::ParsingError(errors::ParsingError::RegularParsingError(
+                    "The tree could not be encoded with bincode",
+                ))
+            })?;
+        Ok(bytes)
+    }
+
+    /// Decodes a tree from the bytes of `save_fast`, then loads it like `from_serde`. The point cloud has to have the
+    /// names the tree was saved with. Returns `UnsupportedVersion` if the bytes start with another format version,
+    /// `ScalarWidthMismatch` if they're of a tree of another scalar type than the metric's, and a parsing error if
+    /// they're truncated or don't decode. No length in the encoding can make this read past the bytes.
+    #[cfg(feature = "bincode")]
+    pub fn load_fast(bytes: &[u8], point_cloud: PointCloud<M>) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        use bincode::Options;
+        let (version, encoded) = match bytes.split_first() {
+            Some((version, encoded)) => (*version, encoded),
+            None => {
+                return Err(MalwareBrotError::ParsingError(
+                    errors::ParsingError::RegularParsingError("The encoded tree is empty"),
+                ))
+            }
+        };
+        if version != FAST_FORMAT_VERSION {
+            return Err(MalwareBrotError::ParsingError(errors::ParsingError::UnsupportedVersion {
+                found: version,
+                supported: FAST_FORMAT_VERSION,
+            }));
+        }
+        // Bincode's varints write a width in one byte, and it's the first field, so it's checked before the scalars
+        // are decoded at the wrong width
+        if let Some(found) = encoded.first() {
+            if *found as u32 != M::Scalar::WIDTH {
+                return Err(MalwareBrotError::ParsingError(errors::ParsingError::ScalarWidthMismatch {
+                    expected: M::Scalar::WIDTH,
+                    found: *found as u32,
+                }));
+            }
+        }
+        let core: CoreSerde<M::Scalar> = bincode::DefaultOptions::new()
+            .with_limit(encoded.len() as u64)
+            .deserialize(encoded)
+            .map_err(|_e| {
+                MalwareBrotError::ParsingError(errors::ParsingError::RegularParsingError(
+                    "The encoded tree is truncated or malformed",
+                ))
+            })?;
+        CoverTreeWriter::from_serde(core, point_cloud)
+    }
+
+    /// Builds a tree over the rows of the array, the ith row is named by the ith name. Returns a parsing error if there
+    /// isn't exactly one name per row, and `NameCollision` if a name is used twice. The rows are copied into the point
+    /// cloud, so the view doesn't have to be contiguous or in standard layout.
+    #[cfg(feature = "ndarray")]
+    pub fn from_ndarray(
+        data: ArrayView2<M::Scalar>,
+        names: &[String],
+        scale_base: f64,
+    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        if names.len() != data.nrows() {
+            return Err(MalwareBrotError::ParsingError(
+                errors::ParsingError::RegularParsingError(
+                    "The number of names does not match the number of rows",
+                ),
+            ));
+        }
+        let mut labels = LabelScheme::new().empty();
+        let mut seen = std::collections::HashSet::with_capacity(names.len());
+        for name in names {
+            let name: PointName = name.clone().into_bytes();
+            if !seen.insert(name.clone()) {
+                return Err(MalwareBrotError::NameCollision(name));
+            }
+            labels.push(Some(name), Metadata::new())?;
+        }
+        // `iter` walks the view in logical order whatever its strides are
+        let rows: Vec<M::Scalar> = data.iter().cloned().collect();
+        let point_cloud = PointCloud::<M>::from_ram(Box::from(rows), data.ncols(), labels)?;
+        CoverTreeBuilder::new().set_scale_base(scale_base).build(point_cloud)
+    }
+
+    /// Swaps the maps on each layer so that any `CoverTreeReaders` see the updated tree. 
+    /// Only call once you have a valid tree.
+    pub fn refresh(&mut self) {
+        self.layers.par_iter_mut().for_each(|l| l.refresh());
+    }
+
+    /// The tombstones in index order, for saving.
+    fn sorted_tombstones(&self) -> Vec<PointIndex> {
+        let mut tombstones: Vec<PointIndex> = self.tombstones.iter().cloned().collect();
+        tombstones.sort();
+        tombstones
+    }
+
+    /// Counts the points of a loaded tree, and finds the points of the cloud it doesn't reference. The saved nodes
+    /// decide which points are in the tree, so this walks them once, it's `O(n)` like the load. The saved `tombstones`
+    /// are set here too, each has to be a point in the tree, otherwise this is an `UnknownTombstone` parsing error.
+    fn count_points(&mut self, tombstones: &[PointIndex]) -> MalwareBrotResult<()> {
+        let (_addresses, indexes) = self.reader().subtree_contents(self.root_address);
+        if let Some(pi) = tombstones.iter().find(|pi| indexes.binary_search(pi).is_err()) {
+            return Err(MalwareBrotError::ParsingError(
+                errors::ParsingError::UnknownTombstone { index: *pi },
+            ));
+        }
+        self.point_count = indexes.len();
+        self.absent = Arc::new(
+            self.parameters
+                .point_cloud
+                .reference_indexes()
+                .into_iter()
+                .filter(|pi| indexes.binary_search(pi).is_err())
+                .collect(),
+        );
+        self.tombstones = Arc::new(tombstones.iter().cloned().collect());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<M: Metric> Serialize for CoverTreeWriter<M> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_serde().serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::utils::cover_tree_from_yaml;
+    use pointcloud::errors::PointCloudError;
+    use pointcloud::labels::values::{Metadata, Value, Vector};
+    use std::path::Path;
+
+    /// `len` coordinates in `[0, 1)` from a small LCG, so the same seed gives the same points every run.
+    pub(crate) fn random_coordinates(len: usize, seed: u32) -> Vec<f32> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 8) as f32 / (1 << 24) as f32
+            })
+            .collect()
+    }
+
+    /// The points of `data`, `dim` coordinates each, with a label of `0.0`.
+    pub(crate) fn cloud_of(data: &[f32], dim: usize) -> PointCloud<L2> {
+        let labels = vec![0.0; data.len() / dim];
+        PointCloud::<L2>::simple_from_ram(Box::from(data), dim, Box::from(labels), 1).unwrap()
+    }
+
+    pub(crate) fn build_mnist_tree() -> CoverTreeWriter<L2> {
+        let file_name = "data/mnist_complex.yml";
+        let path = Path::new(file_name);
+        if !path.exists() {
+            panic!(file_name.to_owned() + &" does not exist".to_string());
+        }
+        cover_tree_from_yaml(&path).unwrap()
+    }
+
+    #[test]
+    fn greedy_knn_nodes() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0];
+
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
+        let builder = CoverTreeBuilder {
+            resolution: -9,
+            use_singletons: false,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let point = [-0.5];
+
+        let mut query_heap = KnnQueryHeap::new(5, reader.parameters.scale_base);
+        let dist_to_root = reader
+            .parameters
+            .point_cloud
+            .distances_to_point(&point, &[reader.root_address().1])
+            .unwrap()[0];
+        query_heap.push_nodes(&[reader.root_address()], &[dist_to_root], None);
+
+        assert_eq!(
+            reader.root_address(),
+            query_heap.closest_unvisited_child_covering_address().unwrap().1
+        );
+
+        reader.greedy_knn_nodes(&point,&mut query_heap);
+        println!("{:#?}", query_heap);
+        println!("{:#?}",query_heap.closest_unvisited_child_covering_address());
+    }
+    
+    #[test]
+    fn knn_singletons_on() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0];
+
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
+        let builder = CoverTreeBuilder {
+            resolution: -9,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        println!("2 nearest neighbors of 0.0 are 0.48 and 0.0");
+        let zero_nbrs = reader.knn(&[0.1],2).unwrap();
+        println!("{:?}", zero_nbrs);
+        assert!(zero_nbrs[0].1 == 4);
+        assert!(zero_nbrs[1].1 == 2);
+    }
+
+    #[test]
+    fn knn_singletons_off() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0];
+
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
+        let builder = CoverTreeBuilder {
+            resolution: -9,
+            use_singletons: false,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        println!("2 nearest neighbors of 0.1 are 0.48 and 0.0");
+        let zero_nbrs = reader.knn(&[0.1],2).unwrap();
+        println!("{:?}", zero_nbrs);
+        assert!(zero_nbrs[0].1 == 4);
+        assert!(zero_nbrs[1].1 == 2);
+    }
+
+    #[test]
+    fn knn_names_sorted() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0];
+
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
+        let builder = CoverTreeBuilder {
+            resolution: -9,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        println!("Asking for more neighbors than points should give us all 5, in order");
+        let nbrs = reader.knn_names(&[0.1], 10).unwrap();
+        println!("{:?}", nbrs);
+        assert!(nbrs.len() == 5);
+        assert!(nbrs[0].1 == b"4");
+        assert!(nbrs[1].1 == b"2");
+        for i in 1..nbrs.len() {
+            assert!(nbrs[i - 1].0 <= nbrs[i].0);
+        }
+    }
+
+    #[test]
+    fn knn_with_matches_knn() {
+        let data = random_coordinates(1000, 1);
+        let point_cloud = cloud_of(&data, 2);
+        let builder = CoverTreeBuilder {
+            resolution: -9,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let mut context = QueryContext::new();
+        for i in 0..200 {
+            let point = [rand::random::<f32>(), rand::random::<f32>()];
+            let k = 1 + i % 7;
+            let expected = reader.knn(&point, k).unwrap();
+            assert_eq!(reader.knn_with(&mut context, &point, k).unwrap(), &expected[..]);
+        }
+    }
+
+    #[test]
+    fn knn_cached_hits_until_the_tree_changes() {
+        let data = random_coordinates(1000, 2);
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let queries: Vec<[f32; 2]> = (0..5).map(|_| [rand::random::<f32>(), rand::random::<f32>()]).collect();
+
+        let mut off = KnnCache::default();
+        let reader = tree.reader();
+        for query in &queries {
+            assert_eq!(reader.knn_cached(&mut off, query, 3).unwrap(), reader.knn(query, 3).unwrap());
+        }
+        assert_eq!((off.len(), off.hits(), off.misses()), (0, 0, 0));
+
+        let mut cache = KnnCache::new(4);
+        for _ in 0..2 {
+            for query in &queries {
+                assert_eq!(reader.knn_cached(&mut cache, query, 3).unwrap(), reader.knn(query, 3).unwrap());
+            }
+        }
+        // Five queries cycled through four places evict each other, the same queries with another k are new entries
+        assert_eq!((cache.len(), cache.hits(), cache.misses()), (4, 0, 10));
+        assert_eq!(reader.knn_cached(&mut cache, &queries[4], 3).unwrap(), reader.knn(&queries[4], 3).unwrap());
+        assert_eq!(cache.hits(), 1);
+        reader.knn_cached(&mut cache, &queries[4], 5).unwrap();
+        assert_eq!(cache.misses(), 11);
+
+        // Removing the nearest neighbor changes the version, so the stale results are dropped
+        let nearest = reader.knn_names(&queries[4], 1).unwrap()[0].1.clone();
+        tree.remove(&nearest).unwrap();
+        let reader = tree.reader();
+        let results = reader.knn_cached(&mut cache, &queries[4], 3).unwrap();
+        assert_eq!(results, reader.knn(&queries[4], 3).unwrap());
+        assert_eq!((cache.len(), cache.misses()), (1, 12));
+    }
+
+    #[test]
+    fn knn_with_path_descends_to_the_nearest() {
+        let data = random_coordinates(1000, 3);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        for _i in 0..50 {
+            let point = [rand::random::<f32>(), rand::random::<f32>()];
+            let (results, path) = reader.knn_with_path(&point, 3).unwrap();
+            assert_eq!(results, reader.knn(&point, 3).unwrap());
+            assert_eq!(path[0], reader.root_address());
+            for pair in path.windows(2) {
+                let (parent, child) = (pair[0], pair[1]);
+                let (nested_scale, children) = reader
+                    .get_node_and(parent, |n| n.children().map(|(s, c)| (s, c.to_vec())))
+                    .unwrap()
+                    .unwrap();
+                assert!(children.contains(&child) || child == (nested_scale, parent.1));
+            }
+            let last = *path.last().unwrap();
+            let nearest = results[0].1;
+            assert!(
+                last.1 == nearest
+                    || reader
+                        .get_node_and(last, |n| n.singletons().contains(&nearest))
+                        .unwrap()
+            );
+        }
+
+        let (results, path) = reader.knn_with_path(&[0.5, 0.5], 0).unwrap();
+        assert!(results.is_empty());
+        assert!(path.is_empty());
+    }
+
+    #[test]
+    fn queries_check_dimension() {
+        let data = vec![0.0, 0.0, 1.0, 1.0, 0.5, 0.25];
+        let labels = vec![0.0, 0.0, 1.0];
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 2, Box::from(labels), 1).unwrap();
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        assert!(reader.knn(&[0.5, 0.5], 2).is_ok());
+        match reader.knn(&[0.5, 0.5, 0.5], 2) {
+            Err(MalwareBrotError::DimensionMismatch { expected, got }) => {
+                assert_eq!(expected, 2);
+                assert_eq!(got, 3);
+            }
+            e => panic!("Expected a dimension mismatch, got {:?}", e),
+        }
+        assert!(reader.knn_approx(&[0.5], 2, 0.1).is_err());
+        assert!(reader.range_query(&[0.5], 1.0).is_err());
+    }
+
+    #[test]
+    fn knn_with_another_metric_reranks_the_candidates() {
+        let data = random_coordinates(400, 4);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+        let query = [0.3, 0.6];
+
+        // The tree's own metric gives the exact knn back
+        assert_eq!(reader.knn_with_metric::<L2>(&query, 5).unwrap(), reader.knn(&query, 5).unwrap());
+
+        let found = reader.knn_with_metric::<L1>(&query, 5).unwrap();
+        assert_eq!(found.len(), 5);
+        let candidates: Vec<PointIndex> = reader
+            .knn_candidates(&query, 5, KNN_WITH_METRIC_OVERFETCH)
+            .unwrap()
+            .iter()
+            .map(|(_d, pi)| *pi)
+            .collect();
+        for pair in found.windows(2) {
+            assert!(pair[0].0 <= pair[1].0);
+        }
+        for (d, pi) in &found {
+            assert!(candidates.contains(pi));
+            let point = reader.point_cloud().get_point(*pi).unwrap();
+            assert_approx_eq!(*d, L1::dense(&query, &point));
+        }
+    }
+
+    #[test]
+    fn memory_footprint_covers_the_data_and_the_nodes() {
+        let count = 500;
+        let data: Vec<f32> = (0..2 * count).map(|_| rand::random::<f32>()).collect();
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        assert!(tree.build_time().is_some());
+
+        let reader = tree.reader();
+        let cloud_bytes = reader.point_cloud().memory_footprint();
+        assert!(cloud_bytes >= 2 * count * std::mem::size_of::<f32>());
+        let node_count = reader.stats().node_count;
+        assert!(reader.memory_footprint() >= cloud_bytes + node_count * std::mem::size_of::<CoverNode>());
+
+        // A copy of the same cloud to load on
+        let point_cloud = reader.point_cloud().with_replaced_point(0, &reader.point_cloud().get_point(0).unwrap()).unwrap();
+        let loaded = CoverTreeWriter::load(&tree.save(), point_cloud).unwrap();
+        assert!(loaded.build_time().is_none());
+    }
+
+    #[test]
+    fn knn_parallel_matches_knn() {
+        // Coarse coordinates so there are ties at the kth place
+        let data: Vec<f32> = (0..4000).map(|_| (rand::random::<f32>() * 20.0).floor()).collect();
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let mut reader = tree.reader();
+        for tie_break in &[TieBreak::Index, TieBreak::Name] {
+            reader.set_tie_break(*tie_break);
+            for _ in 0..20 {
+                let query = [rand::random::<f32>() * 20.0, rand::random::<f32>() * 20.0];
+                for k in &[1, 7, 30] {
+                    let expected = reader.knn(&query, *k).unwrap();
+                    assert_eq!(reader.knn_parallel(&query, *k, 4).unwrap(), expected);
+                }
+            }
+        }
+        assert!(reader.knn_parallel(&[0.5], 3, 4).is_err());
+        assert!(reader.knn_parallel(&[0.5, 0.5], 0, 4).unwrap().is_empty());
+    }
+
+    #[test]
+    fn interned_names_give_the_same_results() {
+        let data = random_coordinates(1000, 5);
+        let point_cloud = || cloud_of(&data, 2);
+        let owned = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud()).unwrap();
+        let mut interned = CoverTreeBuilder::new()
+            .set_verbosity(0)
+            .set_name_storage(NameStorage::Interned)
+            .build(point_cloud())
+            .unwrap();
+        let owned_reader = owned.reader();
+        let interned_reader = interned.reader();
+        assert_eq!(interned_reader.point_cloud().name_storage(), NameStorage::Interned);
+        assert!(
+            interned_reader.point_cloud().memory_footprint() < owned_reader.point_cloud().memory_footprint()
+        );
+        for _ in 0..20 {
+            let query = [rand::random::<f32>(), rand::random::<f32>()];
+            assert_eq!(interned_reader.knn_names(&query, 5).unwrap(), owned_reader.knn_names(&query, 5).unwrap());
+        }
+        for i in 0..500 {
+            let name = format!("{}", i);
+            assert_eq!(interned_reader.point_cloud().get_index(&name), owned_reader.point_cloud().get_index(&name));
+        }
+
+        interned.remove("7").unwrap();
+        let blank = interned.reader().point_cloud().label_scheme().blank_metadata();
+        let pi = interned.insert(b"seven".to_vec(), &[2.0, 2.0], blank).unwrap();
+        let reader = interned.reader();
+        assert_eq!(reader.point_cloud().name_storage(), NameStorage::Interned);
+        assert_eq!(reader.point_cloud().get_index("seven"), Some(&pi));
+        assert_eq!(reader.point_cloud().get_name(&pi), Some(&b"seven"[..]));
+        assert_eq!(reader.knn_names(&[2.0, 2.0], 1).unwrap()[0].1, b"seven".to_vec());
+    }
+
+    #[test]
+    fn non_finite_coordinates_are_refused() {
+        let data = random_coordinates(200, 6);
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+
+        match tree.insert(b"nan".to_vec(), &[0.5, std::f32::NAN], Metadata::new()) {
+            Err(MalwareBrotError::NonFiniteCoordinate { index }) => assert_eq!(index, 1),
+            e => panic!("Expected a NonFiniteCoordinate, got {:?}", e),
+        }
+        assert!(tree.reader().point_cloud().get_index(b"nan").is_none());
+        match tree.reader().knn(&[std::f32::INFINITY, 0.5], 3) {
+            Err(MalwareBrotError::NonFiniteCoordinate { index }) => assert_eq!(index, 0),
+            e => panic!("Expected a NonFiniteCoordinate, got {:?}", e),
+        }
+        assert!(tree.update("3", &[std::f32::NEG_INFINITY, 0.0]).is_err());
+
+        // Unchecked, the query runs, whatever it finds
+        tree.set_check_finite(false);
+        match tree.reader().knn(&[std::f32::NAN, 0.5], 3) {
+            Err(MalwareBrotError::NonFiniteCoordinate { .. }) => panic!("The check was turned off"),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn empty_trees_and_empty_queries() {
+        let data = random_coordinates(200, 7);
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let empty_query: [f32; 0] = [];
+        let is_dimension_mismatch = |e: MalwareBrotError| match e {
+            MalwareBrotError::DimensionMismatch { expected: 2, got: 0 } => true,
+            _ => false,
+        };
+        let reader = tree.reader();
+        assert!(!reader.is_empty());
+        assert_eq!(reader.dimension(), Some(2));
+        assert!(is_dimension_mismatch(reader.knn(&empty_query, 3).unwrap_err()));
+        assert!(is_dimension_mismatch(reader.range_query(&empty_query, 1.0).unwrap_err()));
+        assert!(is_dimension_mismatch(reader.kfn(&empty_query, 3).unwrap_err()));
+
+        let root = reader.root_address();
+        unsafe {
+            tree.remove_raw(root.0, root.1);
+        }
+        tree.refresh();
+        let reader = tree.reader();
+        assert!(reader.is_empty());
+        assert_eq!(reader.dimension(), None);
+        let point = [0.5, 0.5];
+        assert!(reader.knn(&point, 3).unwrap().is_empty());
+        assert!(reader.knn_names(&point, 3).unwrap().is_empty());
+        assert!(reader.knn_approx(&point, 3, 0.5).unwrap().is_empty());
+        assert!(reader.knn_instrumented(&point, 3).unwrap().0.is_empty());
+        assert!(reader.range_query(&point, std::f32::INFINITY).unwrap().is_empty());
+        assert!(reader.range_query_capped(&point, std::f32::INFINITY, 10).unwrap().0.is_empty());
+        assert!(!reader.exists_within(&point, std::f32::INFINITY).unwrap());
+        assert!(reader.kfn(&point, 3).unwrap().is_empty());
+        assert!(reader.knn_at_level(&point, 3, 0).unwrap().is_empty());
+        assert!(is_dimension_mismatch(reader.knn(&empty_query, 3).unwrap_err()));
+        assert!(is_dimension_mismatch(reader.range_query(&empty_query, 1.0).unwrap_err()));
+    }
+
+    #[test]
+    fn knn_many_lines_up_with_queries() {
+        let data = random_coordinates(1000, 8);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let queries: Vec<Vec<f32>> = (0..300)
+            .map(|_i| vec![rand::random::<f32>(), rand::random::<f32>()])
+            .collect();
+        let results = reader.knn_many(&queries, 3).unwrap();
+        assert!(results.len() == queries.len());
+        for (query, result) in queries.iter().zip(&results) {
+            assert_eq!(*result, reader.knn_names(query, 3).unwrap());
+        }
+        assert!(reader.knn_many(&[], 3).unwrap().is_empty());
+        assert!(reader.knn_many(&[vec![0.5]], 3).is_err());
+    }
+
+    #[test]
+    fn knn_graph_matches_knn_by_name() {
+        let data = random_coordinates(600, 9);
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        tree.remove("17").unwrap();
+        let reader = tree.reader();
+
+        let graph = reader.knn_graph(5, false).unwrap();
+        assert_eq!(graph.len(), 300);
+        assert!(graph[17].is_empty());
+        for (i, neighbors) in graph.iter().enumerate().filter(|(i, _)| *i != 17) {
+            let expected = reader.knn_by_name(i.to_string(), 5).unwrap();
+            assert_eq!(neighbors.len(), 5);
+            for ((j, d), (expected_d, expected_name)) in neighbors.iter().zip(&expected) {
+                assert_eq!(d, expected_d);
+                assert_eq!(reader.name_of(*j).unwrap(), &expected_name[..]);
+            }
+        }
+
+        let mutual = reader.knn_graph(5, true).unwrap();
+        for (i, neighbors) in mutual.iter().enumerate() {
+            for (j, d) in neighbors {
+                assert!(graph[i].contains(&(*j, *d)));
+                assert!(mutual[*j].iter().any(|(qi, _d)| *qi == i));
+            }
+        }
+    }
+
+    #[test]
+    fn knn_by_name_skips_only_itself() {
+        let data = vec![0.48, 0.49, 0.48, -0.49, 0.0, 0.48];
+        let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
+        let builder = CoverTreeBuilder {
+            resolution: -9,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let nbrs = reader.knn_by_name("2", 3).unwrap();
+        println!("{:?}", nbrs);
+        assert!(nbrs.len() == 3);
+        assert!(nbrs.iter().all(|(_d, name)| name != b"2"));
+        assert!(nbrs[0].0 == 0.0 && nbrs[1].0 == 0.0);
+        assert!(nbrs[2].1 == b"1");
+
+        let everything = reader.knn_by_name("4", 10).unwrap();
+        assert!(everything.len() == 5);
+        assert!(reader.knn_by_name("not a name", 3).is_err());
+    }
+
+    #[test]
+    fn binary_names() {
+        let mut labels = pointcloud::labels::LabelScheme::new().empty();
+        let mut data = Vec::with_capacity(200);
+        for i in 0..100u8 {
+            data.push(i as f32);
+            data.push(0.0);
+            labels
+                .push(Some(vec![0xff, i]), pointcloud::labels::values::Metadata::new())
+                .unwrap();
+        }
+        let point_cloud = PointCloud::<L2>::from_ram(Box::from(data), 2, labels).unwrap();
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+
+        let nbrs = tree.reader().knn_by_name(&[0xff, 10][..], 2).unwrap();
+        assert_eq!(nbrs.len(), 2);
+        assert!(nbrs.iter().all(|(d, n)| *d == 1.0 && (n == &[0xff, 9] || n == &[0xff, 11])));
+
+        tree.remove(&[0xff, 10][..]).unwrap();
+        match tree.remove(&[0xff, 10][..]) {
+            Err(e @ MalwareBrotError::NameNotInTree(_)) => assert!(format!("{}", e).contains("0xff0a")),
+            _ => panic!("Expected a NameNotInTree, the point was removed"),
+        }
+        assert!(tree.reader().knn_by_name("not a name", 1).is_err());
+    }
+
+    #[test]
+    fn range_query_matches_brute_force() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0];
+
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
+        let builder = CoverTreeBuilder {
+            resolution: -9,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let mut close: Vec<PointName> = reader
+            .range_query(&[0.49], 0.015)
+            .unwrap()
+            .iter()
+            .map(|(_d, n)| n.clone())
+            .collect();
+        close.sort();
+        println!("Points within 0.015 of 0.49 should be 0.499, 0.49, 0.48: {:?}", close);
+        assert_eq!(close, vec![b"0".to_vec(), b"1".to_vec(), b"2".to_vec()]);
+
+        let exact = reader.range_query(&[0.49], 0.0).unwrap();
+        println!("Only 0.49 is exactly 0.49: {:?}", exact);
+        assert!(exact.len() == 1);
+        assert!(exact[0].1 == b"1");
+
+        let everything = reader.range_query(&[0.49], std::f32::INFINITY).unwrap();
+        assert!(everything.len() == 5);
+    }
+
+    #[test]
+    fn range_query_capped_keeps_the_closest() {
+        let data = random_coordinates(1000, 10);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        for _i in 0..20 {
+            let point = [rand::random::<f32>(), rand::random::<f32>()];
+            let mut expected = reader.range_query(&point, 0.2).unwrap();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            let (all, truncated) = reader.range_query_capped(&point, 0.2, expected.len()).unwrap();
+            assert!(!truncated);
+            assert_eq!(all.len(), expected.len());
+
+            let (unbounded, truncated) = reader.range_query_capped(&point, 0.2, usize::MAX).unwrap();
+            assert!(!truncated);
+            assert_eq!(unbounded, all);
+
+            let (capped, truncated) = reader.range_query_capped(&point, 0.2, 5).unwrap();
+            assert_eq!(truncated, expected.len() > 5);
+            assert_eq!(capped.len(), expected.len().min(5));
+            for ((d, _n), (ed, _en)) in capped.iter().zip(&expected) {
+                assert_eq!(d, ed);
+            }
+        }
+    }
+
+    #[test]
+    fn knn_candidates_hold_the_knn() {
+        let data = random_coordinates(1000, 11);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        for _i in 0..20 {
+            let point = [rand::random::<f32>(), rand::random::<f32>()];
+            let candidates = reader.knn_candidates(&point, 5, 4).unwrap();
+            assert_eq!(candidates.len(), 20);
+            let mut by_distance = candidates.clone();
+            by_distance.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert_eq!(by_distance, reader.knn(&point, 20).unwrap());
+            for neighbor in reader.knn(&point, 5).unwrap() {
+                assert!(candidates.contains(&neighbor));
+            }
+        }
+        assert_eq!(reader.knn_candidates(&[0.5, 0.5], 5, 0).unwrap().len(), 5);
+        assert_eq!(reader.knn_candidates(&[0.5, 0.5], 100, 10).unwrap().len(), 500);
+    }
+
+    #[test]
+    fn kfn_matches_brute_force() {
+        let data = random_coordinates(600, 12);
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        for i in (0..300).step_by(7) {
+            tree.remove(i.to_string()).unwrap();
+        }
+        let reader = tree.reader();
+
+        for _i in 0..20 {
+            let point = [rand::random::<f32>(), rand::random::<f32>()];
+            let mut expected: Vec<f32> = (0..300)
+                .filter(|i| i % 7 != 0)
+                .map(|i| L2::dense(&data[2 * i..2 * i + 2], &point))
+                .collect();
+            expected.sort_by(|a, b| b.partial_cmp(a).unwrap());
+            let furthest = reader.kfn(&point, 10).unwrap();
+            assert_eq!(furthest.len(), 10);
+            for ((d, _name), e) in furthest.iter().zip(&expected) {
+                assert_approx_eq!(d, e);
+            }
+        }
+        assert_eq!(reader.kfn(&[0.5, 0.5], 1000).unwrap().len(), 300 - 43);
+        assert!(reader.kfn(&[0.5, 0.5], 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn nearest_matches_knn() {
+        let data = random_coordinates(2000, 13);
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        for i in (0..1000).step_by(5) {
+            tree.remove(i.to_string()).unwrap();
+        }
+        let reader = tree.reader();
+
+        for _i in 0..100 {
+            let point = [rand::random::<f32>(), rand::random::<f32>()];
+            let (dist, _name) = reader.nearest(&point).unwrap().unwrap();
+            assert_eq!(dist, reader.knn(&point, 1).unwrap()[0].0);
+        }
+        assert!(reader.nearest(&[0.5]).is_err());
+    }
+
+    #[test]
+    fn novelty_is_the_nearest_distance() {
+        let data = random_coordinates(1000, 14);
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+        for _i in 0..50 {
+            let point = [rand::random::<f32>(), rand::random::<f32>()];
+            assert_eq!(reader.novelty(&point).unwrap(), reader.knn(&point, 1).unwrap()[0].0);
+        }
+        assert_eq!(reader.novelty(&reader.point_cloud().get_point(3).unwrap()).unwrap(), 0.0);
+        assert!(reader.novelty(&[0.5]).is_err());
+
+        let root = reader.root_address();
+        unsafe {
+            tree.remove_raw(root.0, root.1);
+        }
+        tree.refresh();
+        match tree.reader().novelty(&[0.5, 0.5]) {
+            Err(MalwareBrotError::EmptyTree) => {}
+            _ => panic!("Expected an EmptyTree"),
+        }
+    }
+
+    #[test]
+    fn root_is_the_last_point() {
+        let data = random_coordinates(200, 15);
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+        let (name, point) = reader.root().unwrap();
+        assert_eq!(name, b"99");
+        assert_eq!(point, &data[198..200]);
+
+        tree.remove("99").unwrap();
+        let reader = tree.reader();
+        let (name, point) = reader.root().unwrap();
+        assert_ne!(name, b"99");
+        assert_eq!(reader.point_cloud().get_point(reader.root_address().1).unwrap(), point);
+
+        let root = reader.root_address();
+        unsafe {
+            tree.remove_raw(root.0, root.1);
+        }
+        tree.refresh();
+        assert!(tree.reader().root().is_none());
+    }
+
+    #[test]
+    fn exists_within_agrees_with_range_query() {
+        let data = random_coordinates(1000, 16);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        for i in 0..200 {
+            let point = [2.0 * rand::random::<f32>() - 0.5, 2.0 * rand::random::<f32>() - 0.5];
+            let radius = 0.001 * (i % 50) as f32;
+            let expected = !reader.range_query(&point, radius).unwrap().is_empty();
+            assert_eq!(reader.exists_within(&point, radius).unwrap(), expected);
+        }
+        assert!(reader.exists_within(&[0.5, 0.5], 1.0).unwrap());
+        assert!(!reader.exists_within(&[10.0, 10.0], 1.0).unwrap());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_json_round_trip() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0, 0.3, -0.2, 0.25];
+        let builder = CoverTreeBuilder {
+            resolution: -9,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let point_cloud = cloud_of(&data, 1);
+        let tree = builder.build(point_cloud).unwrap();
+
+        let json = serde_json::to_string(&tree).unwrap();
+        let core: CoreSerde = serde_json::from_str(&json).unwrap();
+        let point_cloud = cloud_of(&data, 1);
+        let loaded_tree = CoverTreeWriter::from_serde(core, point_cloud).unwrap();
+
+        let reader = tree.reader();
+        let loaded_reader = loaded_tree.reader();
+        assert_eq!(reader.root_address(), loaded_reader.root_address());
+        assert_eq!(reader.node_count(), loaded_reader.node_count());
+        assert!(loaded_reader.no_dangling_refs());
+        for query in &[[0.1], [-0.3], [0.495]] {
+            assert_eq!(reader.knn(query, 3).unwrap(), loaded_reader.knn(query, 3).unwrap());
+        }
+
+        let point_cloud = PointCloud::<L2>::simple_from_ram(
+            Box::from(vec![0.0; 3]),
+            1,
+            Box::from(vec![0.0; 3]),
+            1,
+        )
+        .unwrap();
+        let core: CoreSerde = serde_json::from_str(&json).unwrap();
+        assert!(CoverTreeWriter::from_serde(core, point_cloud).is_err());
+    }
+
+    #[test]
+    fn iter_yields_every_point_once() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0];
+
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data.clone()), 1, Box::from(labels), 1).unwrap();
+        let builder = CoverTreeBuilder {
+            resolution: -9,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let mut tree = builder.build(point_cloud).unwrap();
+
+        let reader = tree.reader();
+        let points: Vec<(&[u8], Cow<[f32]>)> = reader.iter().collect();
+        println!("{:?}", points);
+        assert!(points.len() == 5);
+        for (name, point) in &points {
+            let i: usize = std::str::from_utf8(name).unwrap().parse().unwrap();
+            assert!(point[0] == data[i]);
+        }
+        let names: Vec<&[u8]> = points.iter().map(|(n, _p)| *n).collect();
+        let again: Vec<&[u8]> = reader.iter().map(|(n, _p)| n).collect();
+        assert_eq!(names, again);
+
+        tree.remove("2").unwrap();
+        let reader = tree.reader();
+        assert!(reader.iter().count() == 4);
+        assert!(reader.iter().all(|(n, _p)| n != b"2"));
+    }
+
+    #[test]
+    fn leaves_are_the_node_centers() {
+        let data = random_coordinates(1000, 17);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let leaf_addresses = reader.leaf_addresses();
+        for address in &leaf_addresses {
+            assert!(reader.get_node_and(*address, |n| n.children().is_none()).unwrap());
+        }
+        let mut centers: Vec<PointIndex> = leaf_addresses.iter().map(|(_si, pi)| *pi).collect();
+        centers.sort();
+        let count = centers.len();
+        centers.dedup();
+        assert_eq!(count, centers.len());
+
+        let leaves: Vec<(&[u8], Cow<[f32]>)> = reader.leaves().collect();
+        assert_eq!(leaves.len(), count);
+        assert_eq!(leaves.len() + reader.stats().singleton_count, reader.iter().count());
+    }
+
+    #[test]
+    fn stats_agree_with_layers() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0];
+
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
+        let builder = CoverTreeBuilder {
+            resolution: -9,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let stats = reader.stats();
+        println!("{}", stats);
+        assert_eq!(stats.node_count, reader.node_count());
+        assert_eq!(stats.max_scale_index, reader.root_address().0);
+        assert!(stats.min_scale_index <= stats.max_scale_index);
+        assert!(stats.leaf_count >= 1 && stats.leaf_count <= stats.node_count);
+        assert!(stats.max_depth >= 2);
+        assert!(stats.avg_children <= stats.max_children as f32);
+
+        let level_counts = reader.level_counts();
+        assert_eq!(level_counts, stats.level_counts);
+        assert_eq!(level_counts.values().sum::<usize>(), stats.node_count);
+        assert_eq!(level_counts.get(&stats.max_scale_index), Some(&1));
+        assert_eq!(*level_counts.keys().next().unwrap(), stats.min_scale_index);
+        for (si, layer) in reader.layers() {
+            assert_eq!(level_counts.get(&si).cloned().unwrap_or(0), layer.node_count());
+        }
+    }
+
+    #[test]
+    fn nn_distance_histogram_counts_every_point() {
+        let data = random_coordinates(1000, 18);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let histogram = reader.nn_distance_histogram(10).unwrap();
+        assert_eq!(histogram.len(), 10);
+        assert_eq!(histogram.iter().map(|(_edge, count)| count).sum::<usize>(), 500);
+        for pair in histogram.windows(2) {
+            assert!(pair[0].0 <= pair[1].0);
+        }
+        assert_eq!(reader.nn_distance_histogram(0).unwrap(), vec![]);
+
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(vec![0.5; 40]), 2, Box::from(vec![0.0; 20]), 1)
+                .unwrap();
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let histogram = tree.reader().nn_distance_histogram(4).unwrap();
+        assert_eq!(histogram, vec![(0.0, 0), (0.0, 0), (0.0, 0), (0.0, 20)]);
+    }
+
+    #[test]
+    fn knn_instrumented_counts_the_work() {
+        let data = random_coordinates(2000, 19);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        for _i in 0..20 {
+            let point = [rand::random::<f32>(), rand::random::<f32>()];
+            let (results, metrics) = reader.knn_instrumented(&point, 5).unwrap();
+            assert_eq!(results, reader.knn(&point, 5).unwrap());
+
+            let mut query_heap = KnnQueryHeap::new(5, reader.scale_base());
+            reader.knn_query(&point, &mut query_heap).unwrap();
+            assert_eq!(metrics.nodes_visited, query_heap.nodes_visited());
+            // A point is the center of at most one non-nested node or a singleton, so no distance is computed twice
+            assert!(metrics.distance_computations >= 5);
+            assert!(metrics.distance_computations <= 1000);
+            assert!(metrics.nodes_visited <= 2 * reader.node_count());
+            assert!(metrics.branches_pruned > 0);
+        }
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn from_ndarray_reads_a_transposed_view() {
+        // Each column is a point, so the transposed view has the points as rows but isn't contiguous by row
+        let columns = ndarray::arr2(&[[0.0f32, 1.0, 5.0], [0.0, 0.0, 2.0]]);
+        let names: Vec<String> = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let tree = CoverTreeWriter::<L2>::from_ndarray(columns.t(), &names, 1.5).unwrap();
+        let reader = tree.reader();
+        let nearest = reader.knn_names(&[5.0, 2.1], 1).unwrap();
+        assert_eq!(nearest[0].1, b"c".to_vec());
+        assert_approx_eq!(nearest[0].0, 0.1, 0.0001);
+
+        match CoverTreeWriter::<L2>::from_ndarray(columns.t(), &names[..2], 1.5) {
+            Err(MalwareBrotError::ParsingError(_)) => {}
+            _ => panic!("Expected a ParsingError"),
+        }
+        let repeated: Vec<String> = vec!["a".to_string(), "b".to_string(), "a".to_string()];
+        match CoverTreeWriter::<L2>::from_ndarray(columns.t(), &repeated, 1.5) {
+            Err(MalwareBrotError::NameCollision(name)) => assert_eq!(name, b"a".to_vec()),
+            _ => panic!("Expected a NameCollision"),
+        }
+    }
+
+    #[test]
+    fn knn_indices_line_up_with_the_names() {
+        let data = random_coordinates(400, 20);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let point = [0.5, 0.5];
+        let indices = reader.knn_indices(&point, 5).unwrap();
+        let names = reader.knn_names(&point, 5).unwrap();
+        assert_eq!(indices.len(), 5);
+        for ((index, d), (name_d, name)) in indices.iter().zip(&names) {
+            assert_eq!(d, name_d);
+            assert_eq!(reader.name_of(*index).unwrap(), &name[..]);
+        }
+        match reader.name_of(200) {
+            Err(MalwareBrotError::IndexNotInTree(200)) => {}
+            _ => panic!("Expected an IndexNotInTree"),
+        }
+    }
+
+    #[test]
+    fn knn_debug_reports_the_pruned_cluster() {
+        // The root is centered on the last point, and the far pair is its own child at scale index 6
+        let data = vec![0.0, 0.01, 100.0, 100.01];
+        let point_cloud = cloud_of(&data, 1);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let (results, events) = reader.knn_debug(&[100.01], 1).unwrap();
+        assert_eq!(results, reader.knn(&[100.01], 1).unwrap());
+        assert_eq!(results, vec![(0.0, 3)]);
+        let far_pair = events.iter().find(|e| e.address.1 < 2).unwrap();
+        assert_eq!(far_pair.address.0, 6);
+        assert_approx_eq!(far_pair.lower_bound, 100.0 - 64.0, 0.02);
+        assert_eq!(far_pair.threshold, 0.0);
+        for event in &events {
+            assert!(event.lower_bound > event.threshold);
+        }
+    }
+
+    #[test]
+    fn knn_approx_visits_fewer_nodes() {
+        let data = random_coordinates(2000, 21);
+        let point_cloud = cloud_of(&data, 2);
+        let builder = CoverTreeBuilder {
+            scale_base: 1.5,
+            resolution: -9,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let point = [0.5, 0.5];
+        let exact = reader.knn(&point, 10).unwrap();
+        assert_eq!(exact, reader.knn_approx(&point, 10, 0.0).unwrap());
+
+        let mut visited = Vec::new();
+        for epsilon in &[0.0, 1.0, 10.0] {
+            let mut query_heap = KnnQueryHeap::new_approx(10, reader.parameters.scale_base, *epsilon);
+            reader.knn_query(&point, &mut query_heap).unwrap();
+            visited.push(query_heap.nodes_visited());
+            let approx = query_heap.unpack();
+            assert!(approx.len() == 10);
+            for ((d, _), (true_d, _)) in approx.iter().zip(&exact) {
+                assert!(*d <= (1.0 + epsilon) * true_d + std::f32::EPSILON);
+            }
+        }
+        println!("Nodes visited for epsilon 0, 1, 10: {:?}", visited);
+        assert!(visited[0] > visited[1]);
+        assert!(visited[1] > visited[2]);
+    }
+
+    #[test]
+    fn validate_catches_violations() {
+        let mut data = random_coordinates(100, 22);
+        data[99] = 100.0;
+        let point_cloud = cloud_of(&data, 1);
+        let builder = CoverTreeBuilder {
+            resolution: -9,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let mut tree = builder.build(point_cloud).unwrap();
+        tree.reader().validate().unwrap();
+        for pi in &[3, 17, 42] {
+            tree.remove(&pi.to_string()).unwrap();
+            tree.reader().validate().unwrap();
+        }
+
+        // Stick the far away point onto a node at the bottom of the tree
+        let reader = tree.reader();
+        let mut low_address = reader.root_address();
+        for si in reader.scale_range() {
+            reader.layer(si).for_each_node(|pi, n| {
+                if si < low_address.0 && *pi != 99 && n.is_leaf() {
+                    low_address = (si, *pi);
+                }
+            });
+        }
+        let mut node = reader.get_node_and(low_address, |n| n.clone()).unwrap();
+        node.insert_singleton(99);
+        unsafe {tree.insert_raw(low_address.0, low_address.1, node);}
+        tree.refresh();
+        match tree.reader().validate() {
+            Err(MalwareBrotError::CoveringViolation { parent, child, .. }) => {
+                assert_eq!(parent, low_address);
+                assert_eq!(child, 99);
+            }
+            e => panic!("Expected a covering violation, got {:?}", e),
+        }
+
+        // With enough slack the far point is covered after all
+        let reader = tree.reader();
+        let point_cloud = reader.point_cloud();
+        let distance = L2::dense(&point_cloud.get_point(99).unwrap(), &point_cloud.get_point(low_address.1).unwrap());
+        let overshoot = distance / reader.scale(low_address.0) - 1.0;
+        assert!(reader.validate_with_tolerance(overshoot * 0.5).is_err());
+        reader.validate_with_tolerance(overshoot * 1.01).unwrap();
+    }
+
+    #[test]
+    fn concurrent_knn_from_factory() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        fn assert_send<T: Send>() {}
+        assert_send_sync::<CoverTreeReaderFactory<L2>>();
+        assert_send::<CoverTreeReader<L2>>();
+
+        let data = random_coordinates(2000, 23);
+        let point_cloud = cloud_of(&data, 2);
+        let builder = CoverTreeBuilder {
+            resolution: -9,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let queries: Vec<[f32; 2]> = (0..100)
+            .map(|_i| [rand::random::<f32>(), rand::random::<f32>()])
+            .collect();
+        let expected: Vec<Vec<(f32, PointIndex)>> = queries
+            .iter()
+            .map(|q| reader.knn(q, 5).unwrap())
+            .collect();
+
+        let factory = Arc::new(reader.factory());
+        let queries = Arc::new(queries);
+        let expected = Arc::new(expected);
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let factory = Arc::clone(&factory);
+                let queries = Arc::clone(&queries);
+                let expected = Arc::clone(&expected);
+                std::thread::spawn(move || {
+                    let reader = factory.reader();
+                    for round in 0..5 {
+                        for i in 0..queries.len() {
+                            let i = (i + t * 13 + round) % queries.len();
+                            assert_eq!(reader.knn(&queries[i], 5).unwrap(), expected[i]);
+                            let in_range = reader.range_query(&queries[i], expected[i][4].0).unwrap();
+                            assert!(in_range.len() >= 5);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn node_radii_are_the_furthest_covered_point() {
+        let data = random_coordinates(200, 24);
+        let point_cloud = cloud_of(&data, 2);
+        let builder = CoverTreeBuilder {
+            resolution: -9,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let mut tree = builder.build(point_cloud).unwrap();
+
+        fn check(reader: &CoverTreeReader<L2>) {
+            let radii = reader.node_radii();
+            assert!(radii.len() == reader.stats().node_count);
+            for (address, radius) in radii {
+                let (_addresses, mut covered) = reader.subtree_contents(address);
+                covered.retain(|pi| *pi != address.1);
+                let brute_radius = reader
+                    .point_cloud()
+                    .distances_to_point_index(address.1, &covered)
+                    .unwrap()
+                    .into_iter()
+                    .fold(-1. / 0., f32::max);
+                assert_eq!(radius, brute_radius);
+            }
+        }
+        check(&tree.reader());
+
+        // A few of these are the furthest point of some node
+        let furthest: Vec<PointIndex> = {
+            let reader = tree.reader();
+            let mut furthest = Vec::new();
+            for (address, radius) in reader.node_radii().iter().rev().take(5) {
+                let (_addresses, covered) = reader.subtree_contents(*address);
+                let dists = reader.point_cloud().distances_to_point_index(address.1, &covered).unwrap();
+                for (pi, d) in covered.iter().zip(dists) {
+                    if d == *radius && !furthest.contains(pi) {
+                        furthest.push(*pi);
+                    }
+                }
+            }
+            furthest
+        };
+        for pi in furthest.iter().chain(&[0, 1, 2]) {
+            if tree.remove(&pi.to_string()).is_ok() {
+                check(&tree.reader());
+            }
+        }
+    }
+
+    #[test]
+    fn merge_matches_a_build_on_the_union() {
+        let data = random_coordinates(1000, 25);
+        let builder = CoverTreeBuilder {
+            resolution: -9,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let shard = |range: std::ops::Range<usize>, builder: &CoverTreeBuilder| {
+            let mut labels = pointcloud::labels::LabelScheme::new().empty();
+            for i in range.clone() {
+                labels
+                    .push(Some(format!("{}", i).into_bytes()), pointcloud::labels::values::Metadata::new())
+                    .unwrap();
+            }
+            let shard_data = Box::from(&data[2 * range.start..2 * range.end]);
+            builder
+                .build(PointCloud::<L2>::from_ram(shard_data, 2, labels).unwrap())
+                .unwrap()
+        };
+
+        let mut tree = shard(0..300, &builder);
+        tree.update("0", &data[0..2]).unwrap();
+        let version = tree.version();
+        assert!(version > 0);
+        tree.merge(shard(300..500, &builder)).unwrap();
+        assert_eq!(tree.version(), version + 1);
+        // The merged tree is a new build, there's no delta from before it
+        match tree.save_delta(version) {
+            Err(MalwareBrotError::ParsingError(errors::ParsingError::VersionMismatch { expected, found })) => {
+                assert_eq!((expected, found), (version, version + 1));
+            }
+            _ => panic!("A delta from before the merge should be a version mismatch"),
+        }
+        assert!(tree.save_delta(version + 1).is_ok());
+        let union = shard(0..500, &builder);
+        let (reader, union_reader) = (tree.reader(), union.reader());
+        assert_eq!(reader.point_cloud().len(), 500);
+        reader.validate().unwrap();
+        for _i in 0..50 {
+            let point = [rand::random::<f32>(), rand::random::<f32>()];
+            assert_eq!(
+                reader.knn_names(&point, 5).unwrap(),
+                union_reader.knn_names(&point, 5).unwrap()
+            );
+        }
+
+        let mut tree = shard(0..300, &builder);
+        match tree.merge(shard(250..500, &builder)) {
+            Err(MalwareBrotError::NameCollision(name)) => assert!(name >= b"250".to_vec() && name < b"300".to_vec()),
+            Err(e) => panic!("Expected a NameCollision, got {:?}", e),
+            Ok(_) => panic!("Expected a NameCollision, 250 to 299 are in both"),
+        }
+        // A point that was removed doesn't collide
+        tree.remove("299").unwrap();
+        match tree.merge(shard(299..500, &builder)) {
+            Ok(()) => assert_eq!(tree.reader().iter().count(), 500),
+            Err(e) => panic!("Expected the merge to work, got {:?}", e),
+        }
+
+        let other_builder = CoverTreeBuilder {
+            scale_base: 1.5,
+            ..builder
+        };
+        let mut tree = shard(0..300, &builder);
+        match tree.merge(shard(300..500, &other_builder)) {
+            Err(MalwareBrotError::ScaleBaseMismatch { ours, theirs }) => assert_eq!((ours, theirs), (2.0, 1.5)),
+            Err(e) => panic!("Expected a ScaleBaseMismatch, got {:?}", e),
+            Ok(_) => panic!("Expected a ScaleBaseMismatch, the scale bases differ"),
+        }
+    }
+
+    #[test]
+    fn rebuild_keeps_the_results() {
+        let data = random_coordinates(1000, 26);
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        for i in (0..500).step_by(3) {
+            tree.remove(i.to_string()).unwrap();
+        }
+
+        let queries: Vec<[f32; 2]> = (0..50)
+            .map(|_i| [rand::random::<f32>(), rand::random::<f32>()])
+            .collect();
+        let reader = tree.reader();
+        let before: Vec<Vec<(f32, PointName)>> =
+            queries.iter().map(|q| reader.knn_names(q, 5).unwrap()).collect();
+        let root = reader.root_address();
+        let version = tree.version();
+
+        tree.rebuild().unwrap();
+        let reader = tree.reader();
+        reader.validate().unwrap();
+        assert_eq!(reader.root_address().1, root.1);
+        assert!(tree.version() > version);
+        assert_eq!(reader.iter().count(), 500 - 167);
+        for (q, expected) in queries.iter().zip(&before) {
+            assert_eq!(&reader.knn_names(q, 5).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn queries_skip_tombstones() {
+        let data = random_coordinates(1000, 27);
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        tree.set_removal_mode(RemovalMode::Tombstone);
+        let root_name = tree.reader().root().unwrap().0.to_vec();
+        let mut removed: Vec<PointName> = (0..500).step_by(3).map(|i| format!("{}", i).into_bytes()).collect();
+        removed.push(root_name);
+        removed.sort();
+        removed.dedup();
+        for name in &removed {
+            tree.remove(name).unwrap();
+        }
+        match tree.remove(&removed[0]) {
+            Err(MalwareBrotError::NameNotInTree(_)) => {}
+            r => panic!("Expected a NameNotInTree for a tombstoned point, got {:?}", r),
+        }
+        assert_eq!(tree.tombstone_count(), removed.len());
+        assert_approx_eq!(tree.tombstone_ratio(), removed.len() as f32 / 500.0);
+
+        let reader = tree.reader();
+        let is_removed = |name: &[u8]| removed.iter().any(|r| &r[..] == name);
+        for _ in 0..20 {
+            let query = [rand::random::<f32>(), rand::random::<f32>()];
+            let knn = reader.knn_names(&query, 10).unwrap();
+            assert_eq!(knn.len(), 10);
+            assert!(knn.iter().all(|(_d, name)| !is_removed(name)));
+            assert!(reader.range_query(&query, 0.3).unwrap().iter().all(|(_d, name)| !is_removed(name)));
+            assert!(reader.kfn(&query, 10).unwrap().iter().all(|(_d, name)| !is_removed(name)));
+            let (nearest_dist, nearest_name) = reader.nearest(&query).unwrap().unwrap();
+            assert!(!is_removed(&nearest_name));
+            assert_eq!(nearest_dist, knn[0].0);
+            assert!(reader.knn_parallel(&query, 10, 4).unwrap().iter().all(|(_d, pi)| {
+                !is_removed(reader.point_cloud().get_name(pi).unwrap())
+            }));
+        }
+        assert!(reader.iter().all(|(name, _point)| !is_removed(name)));
+        assert_eq!(reader.iter().count(), 500 - removed.len());
+
+        // The queries after compacting match the ones before
+        let queries: Vec<[f32; 2]> = (0..20).map(|_| [rand::random::<f32>(), rand::random::<f32>()]).collect();
+        let before: Vec<_> = queries.iter().map(|q| reader.knn_names(q, 10).unwrap()).collect();
+        tree.compact().unwrap();
+        assert_eq!(tree.tombstone_count(), 0);
+        let reader = tree.reader();
+        reader.validate().unwrap();
+        assert_eq!(reader.iter().count(), 500 - removed.len());
+        for (query, expected) in queries.iter().zip(before) {
+            assert_eq!(reader.knn_names(query, 10).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn tombstones_survive_saving() {
+        let data = random_coordinates(400, 28);
+        let point_cloud = || cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud()).unwrap();
+        tree.set_removal_mode(RemovalMode::Tombstone);
+        let base = tree.save();
+        for i in (0..200).step_by(4) {
+            tree.remove(format!("{}", i)).unwrap();
+        }
+        let queries: Vec<[f32; 2]> = (0..20).map(|_| [rand::random::<f32>(), rand::random::<f32>()]).collect();
+        let expected: Vec<_> = queries.iter().map(|q| tree.reader().knn_names(q, 10).unwrap()).collect();
+        let check = |loaded: &CoverTreeWriter<L2>| {
+            assert_eq!(loaded.tombstone_count(), 50);
+            let reader = loaded.reader();
+            assert_eq!(reader.len(), 150);
+            assert!(!reader.contains("0"));
+            for (query, expected) in queries.iter().zip(&expected) {
+                assert_eq!(&reader.knn_names(query, 10).unwrap(), expected);
+            }
+        };
+
+        check(&CoverTreeWriter::load(&tree.save(), point_cloud()).unwrap());
+        let mut loaded = CoverTreeWriter::load(&base, point_cloud()).unwrap();
+        loaded.load_delta(&tree.save_delta(base.get_version()).unwrap()).unwrap();
+        check(&loaded);
+        #[cfg(feature = "bincode")]
+        check(&CoverTreeWriter::load_fast(&tree.save_fast().unwrap(), point_cloud()).unwrap());
+
+        // Compacting clears the saved tombstones too
+        tree.compact().unwrap();
+        let loaded = CoverTreeWriter::load(&tree.save(), point_cloud()).unwrap();
+        assert_eq!(loaded.tombstone_count(), 0);
+        assert_eq!(loaded.reader().len(), 150);
+
+        let mut unknown = base.clone();
+        unknown.set_tombstones(vec![200]);
+        match CoverTreeWriter::load(&unknown, point_cloud()) {
+            Err(MalwareBrotError::ParsingError(errors::ParsingError::UnknownTombstone { index })) => {
+                assert_eq!(index, 200)
+            }
+            other => panic!("Expected an UnknownTombstone, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn remove_matches_fresh_build() {
+        let data = random_coordinates(40, 29);
+        let builder = CoverTreeBuilder {
+            resolution: -9,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+
+        let point_cloud = cloud_of(&data, 1);
+        let mut tree = builder.build(point_cloud).unwrap();
+
+        // The root, a few interior nodes (which all have nested children), and a point that could be anything.
+        let reader = tree.reader();
+        let root_center = reader.root_address().1;
+        let mut interior = Vec::new();
+        for si in reader.scale_range() {
+            reader.layer(si).for_each_node(|pi, n| {
+                if !n.is_leaf() && *pi != root_center {
+                    interior.push(*pi);
+                }
+            });
+        }
+        interior.sort();
+        interior.dedup();
+        let mut removed: Vec<PointIndex> = interior.iter().take(3).cloned().collect();
+        removed.push(root_center);
+        if !removed.contains(&7) {
+            removed.push(7);
+        }
+        println!("Removing {:?}", removed);
+
+        for pi in &removed {
+            tree.remove(&pi.to_string()).unwrap();
+            assert!(tree.reader().no_dangling_refs());
+            tree.reader().validate().unwrap();
+        }
+        assert!(tree.remove(&removed[0].to_string()).is_err());
+        assert!(tree.remove("not a name").is_err());
+
+        let remaining: Vec<f32> = data
+            .iter()
+            .enumerate()
+            .filter(|(i, _x)| !removed.contains(&(*i as PointIndex)))
+            .map(|(_i, x)| *x)
+            .collect();
+        let remaining_labels = vec![0.0; remaining.len()];
+        let point_cloud = PointCloud::<L2>::simple_from_ram(
+            Box::from(remaining.clone()),
+            1,
+            Box::from(remaining_labels),
+            1,
+        )
+        .unwrap();
+        let fresh_tree = builder.build(point_cloud).unwrap();
+
+        let reader = tree.reader();
+        let fresh_reader = fresh_tree.reader();
+        let everything = reader.knn(&[0.5], 40).unwrap();
+        assert!(everything.len() == remaining.len());
+        assert!(everything.iter().all(|(_d, pi)| !removed.contains(pi)));
+        for query in &[[0.0], [0.25], [0.5], [0.75], [1.0]] {
+            let dists: Vec<f32> = reader.knn(query, 5).unwrap().iter().map(|(d, _pi)| *d).collect();
+            let fresh_dists: Vec<f32> = fresh_reader
+                .knn(query, 5)
+                .unwrap()
+                .iter()
+                .map(|(d, _pi)| *d)
+                .collect();
+            println!("{:?} {:?}", dists, fresh_dists);
+            assert_eq!(dists, fresh_dists);
+        }
+    }
+
+    #[test]
+    fn every_point_is_located_after_moves() {
+        for _round in 0..50 {
+            let data = random_coordinates(60, 30);
+            let point_cloud = cloud_of(&data, 2);
+            let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+            for name in &["3", "11", "27"] {
+                tree.update(name, &[rand::random::<f32>(), rand::random::<f32>()]).unwrap();
+            }
+            // A subtree can reach further than its node's scale, locate has to search by the radius
+            let reader = tree.reader();
+            for pi in 0..30 {
+                assert!(reader.locate(pi).unwrap().is_some(), "point {} wasn't found", pi);
+            }
+        }
+    }
+
+    #[test]
+    fn delta_replays_removals() {
+        let data = random_coordinates(40, 31);
+        let builder = CoverTreeBuilder {
+            resolution: -9,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let point_cloud = || cloud_of(&data, 1);
+        let mut tree = builder.build(point_cloud()).unwrap();
+        let base_proto = tree.save();
+        assert_eq!(tree.version(), 0);
+
+        let root_center = tree.reader().root_address().1;
+        for pi in &[3, root_center, 11] {
+            tree.remove(&pi.to_string()).unwrap_or(());
+        }
+        assert!(tree.version() > 0);
+        let delta_bytes = protobuf::Message::write_to_bytes(&tree.save_delta(0).unwrap()).unwrap();
+        let delta_proto: DeltaProto = protobuf::parse_from_bytes(&delta_bytes).unwrap();
+
+        let mut loaded_tree = CoverTreeWriter::load(&base_proto, point_cloud()).unwrap();
+        loaded_tree.load_delta(&delta_proto).unwrap();
+        assert_eq!(loaded_tree.version(), tree.version());
+        assert!(loaded_tree.reader().no_dangling_refs());
+
+        let reader = tree.reader();
+        let loaded_reader = loaded_tree.reader();
+        for query in &[[0.0], [0.25], [0.5], [0.75], [1.0]] {
+            let knn = reader.knn(query, 5).unwrap();
+            let loaded_knn = loaded_reader.knn(query, 5).unwrap();
+            println!("{:?} {:?}", knn, loaded_knn);
+            assert_eq!(knn, loaded_knn);
+        }
+
+        match loaded_tree.load_delta(&delta_proto) {
+            Err(MalwareBrotError::ParsingError(errors::ParsingError::VersionMismatch { expected, found })) => {
+                assert_eq!(expected, 0);
+                assert_eq!(found, tree.version());
+            }
+            _ => panic!("Applying the delta twice should be a version mismatch"),
+        }
+
+        // A loaded tree doesn't know what changed before its version, but passes on what it's replayed since
+        let saved_tree = CoverTreeWriter::load(&tree.save(), point_cloud()).unwrap();
+        match saved_tree.save_delta(0) {
+            Err(MalwareBrotError::ParsingError(errors::ParsingError::VersionMismatch { expected, found })) => {
+                assert_eq!((expected, found), (0, tree.version()));
+            }
+            _ => panic!("A delta from before the load should be a version mismatch"),
+        }
+        let mut relayed_tree = CoverTreeWriter::load(&base_proto, point_cloud()).unwrap();
+        relayed_tree.load_delta(&loaded_tree.save_delta(0).unwrap()).unwrap();
+        for query in &[[0.0], [0.25], [0.5], [0.75], [1.0]] {
+            assert_eq!(relayed_tree.reader().knn(query, 5).unwrap(), reader.knn(query, 5).unwrap());
+        }
+    }
+
+    #[test]
+    fn ties_are_broken_by_index_or_name() {
+        // Every point but the first is at distance 1 from the origin, and the names sort in the opposite order to the
+        // indexes.
+        let coords = [(0.0, 0.0), (1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0), (1.0, 0.0), (-1.0, 0.0)];
+        let names = ["z", "f", "e", "d", "c", "b", "a"];
+        let mut labels = pointcloud::labels::LabelScheme::new().empty();
+        let mut data = Vec::new();
+        for ((x, y), name) in coords.iter().zip(names.iter()) {
+            data.push(*x);
+            data.push(*y);
+            labels
+                .push(Some(name.as_bytes().to_vec()), pointcloud::labels::values::Metadata::new())
+                .unwrap();
+        }
+        let point_cloud = PointCloud::<L2>::from_ram(Box::from(data), 2, labels).unwrap();
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let mut reader = tree.reader();
+        let origin = [0.0, 0.0];
+
+        let indexes = |results: Vec<(f32, PointIndex)>| results.iter().map(|(_, i)| *i).collect::<Vec<PointIndex>>();
+        assert_eq!(reader.tie_break(), TieBreak::Index);
+        assert_eq!(indexes(reader.knn(&origin, 3).unwrap()), vec![0, 1, 2]);
+        assert_eq!(indexes(reader.knn_approx(&origin, 3, 0.0).unwrap()), vec![0, 1, 2]);
+
+        reader.set_tie_break(TieBreak::Name);
+        assert_eq!(indexes(reader.knn(&origin, 3).unwrap()), vec![0, 6, 5]);
+        assert_eq!(indexes(reader.knn(&origin, 7).unwrap()), vec![0, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(indexes(reader.knn_approx(&origin, 3, 0.0).unwrap()), vec![0, 6, 5]);
+        assert!(reader.knn(&origin, 0).unwrap().is_empty());
+        assert!(reader.knn(&[0.0], 0).is_err());
+        let many = reader.knn_many(&[origin.to_vec(), vec![1.0, 0.0]], 2).unwrap();
+        assert_eq!(many[0], vec![(0.0, b"z".to_vec()), (1.0, b"a".to_vec())]);
+        assert_eq!(many[1], vec![(0.0, b"b".to_vec()), (0.0, b"f".to_vec())]);
+    }
+
+    #[test]
+    fn knn_at_level_returns_the_cut() {
+        let mut data = Vec::new();
+        for i in 0..300 {
+            data.push(rand::random::<f32>());
+            data.push(rand::random::<f32>());
+            data.push((i % 3) as f32 * 10.0);
+        }
+        let point_cloud = cloud_of(&data, 3);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+        let point = [0.5, 0.5, 10.0];
+
+        let root = reader.root_address();
+        assert_eq!(reader.knn_at_level(&point, 5, root.0).unwrap().iter().map(|r| r.1).collect::<Vec<_>>(), vec![root]);
+
+        // The brute force version, every node on the cut. The level is the first one down from the root with a few nodes
+        // on it, the scales of a random tree vary.
+        let cut_at = |level: i32| {
+            let mut cut = Vec::new();
+            let mut unvisited = vec![root];
+            while let Some(address) = unvisited.pop() {
+                reader.get_node_and(address, |n| match n.children() {
+                    Some((nested_scale, children)) if address.0 > level => {
+                        unvisited.push((nested_scale, address.1));
+                        unvisited.extend(children);
+                    }
+                    _ => cut.push(address),
+                });
+            }
+            cut
+        };
+        let mut level = root.0;
+        while cut_at(level).len() < 10 {
+            level -= 1;
+        }
+        let cut = cut_at(level);
+        let mut expected: Vec<f32> = cut
+            .iter()
+            .map(|a| L2::dense(&reader.parameters.point_cloud.get_point(a.1).unwrap(), &point))
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let results = reader.knn_at_level(&point, 3, level).unwrap();
+        assert_eq!(results.iter().map(|r| r.0).collect::<Vec<f32>>(), expected[..3].to_vec());
+        assert!(results.iter().all(|(_, a)| cut.contains(a)));
+        assert_eq!(reader.knn_at_level(&point, cut.len() + 5, level).unwrap().len(), cut.len());
+    }
+
+    #[test]
+    fn update_in_place_and_by_moving() {
+        let data = random_coordinates(1000, 32);
+        let point_cloud = cloud_of(&data, 2);
+        let builder = CoverTreeBuilder {
+            cutoff: 20,
+            verbosity: 0,
+            ..CoverTreeBuilder::new()
+        };
+        let mut tree = builder.build(point_cloud).unwrap();
+        let brute_force = |reader: &CoverTreeReader<L2>, query: &[f32]| {
+            let point_cloud = reader.point_cloud();
+            let mut distances = point_cloud
+                .distances_to_point(query, &point_cloud.reference_indexes())
+                .unwrap();
+            distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            distances.truncate(5);
+            distances
+        };
+
+        // A singleton of a leaf, moved halfway to the leaf's center, stays where it is
+        let reader = tree.reader();
+        let (pi, leaf) = (0..500)
+            .filter_map(|pi| match reader.locate(pi).unwrap() {
+                Some(PointLocation::Singleton(address, _path)) => Some((pi, address)),
+                _ => None,
+            })
+            .find(|(_pi, address)| reader.get_node_and(*address, |n| n.is_leaf()).unwrap())
+            .unwrap();
+        let (addresses, _indexes) = reader.subtree_contents(reader.root_address());
+        let point = reader.point_cloud().get_point(pi).unwrap().to_vec();
+        let center = reader.point_cloud().get_point(leaf.1).unwrap().to_vec();
+        let moved = vec![(point[0] + center[0]) / 2.0, (point[1] + center[1]) / 2.0];
+        tree.update(pi.to_string(), &moved).unwrap();
+        let reader = tree.reader();
+        reader.validate().unwrap();
+        assert_eq!(reader.subtree_contents(reader.root_address()).0, addresses);
+        assert_eq!(reader.point_cloud().get_point(pi).unwrap(), &moved[..]);
+        match reader.locate(pi).unwrap() {
+            Some(PointLocation::Singleton(address, _path)) => assert_eq!(address, leaf),
+            _ => panic!("The point should still be a singleton of the same leaf"),
+        }
+        let knn: Vec<f32> = reader.knn(&moved, 5).unwrap().iter().map(|r| r.0).collect();
+        assert_eq!(knn, brute_force(&reader, &moved));
+
+        // Moved out of the root's cover, the tree has to change shape
+        for (name, moved) in &[("7", [100.0, 100.0]), ("8", [0.5, 0.5]), (&pi.to_string()[..], [0.25, 0.75])] {
+            tree.update(name, moved).unwrap();
+            let reader = tree.reader();
+            reader.validate().unwrap();
+            assert_eq!(reader.iter().count(), 500);
+            let nearest = reader.knn_names(moved, 1).unwrap();
+            assert_eq!(nearest[0], (0.0, name.as_bytes().to_vec()));
+            for query in &[[0.5, 0.5], [0.1, 0.9], [100.0, 100.0]] {
+                let knn: Vec<f32> = reader.knn(query, 5).unwrap().iter().map(|r| r.0).collect();
+                assert_eq!(knn, brute_force(&reader, query));
+            }
+        }
+
+        match tree.update("not a name", &[0.0, 0.0]) {
+            Err(MalwareBrotError::NameNotInTree(_)) => {}
+            _ => panic!("Expected a NameNotInTree"),
+        }
+        assert!(tree.update("7", &[0.0]).is_err());
+    }
+
+    #[test]
+    fn load_checks_the_checksum() {
+        let data = random_coordinates(200, 33);
+        let point_cloud = || cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud()).unwrap();
+        let bytes = protobuf::Message::write_to_bytes(&tree.save()).unwrap();
+        let cover_proto: CoreProto = protobuf::parse_from_bytes(&bytes).unwrap();
+        assert!(cover_proto.get_has_checksum());
+        let loaded = CoverTreeWriter::load(&cover_proto, point_cloud()).unwrap();
+        assert_eq!(loaded.reader().knn(&[0.5, 0.5], 5).unwrap(), tree.reader().knn(&[0.5, 0.5], 5).unwrap());
+        assert_eq!(loaded.reader().dimension(), Some(2));
+
+        let mut corrupt = cover_proto.clone();
+        let layer = corrupt.get_layers().iter().position(|l| !l.get_nodes().is_empty()).unwrap();
+        let radius = corrupt.get_layers()[layer].get_nodes()[0].get_radius();
+        corrupt.mut_layers()[layer].mut_nodes()[0].set_radius(radius + 1.0);
+        match CoverTreeWriter::load(&corrupt, point_cloud()) {
+            Err(MalwareBrotError::ParsingError(errors::ParsingError::ChecksumMismatch { expected, found })) => {
+                assert_eq!(expected, cover_proto.get_checksum());
+                assert_ne!(found, expected);
+            }
+            _ => panic!("Expected a ChecksumMismatch"),
+        }
+
+        // Files from before checksums still load
+        let mut old = cover_proto.clone();
+        old.clear_checksum();
+        old.clear_has_checksum();
+        assert!(CoverTreeWriter::load(&old, point_cloud()).is_ok());
+    }
+
+    /// Coordinates that round differently as `f32`s, in a cloud of `L2<f64>`.
+    fn wide_cloud(data: &[f64]) -> PointCloud<L2<f64>> {
+        let labels = vec![0.0; data.len() / 2];
+        PointCloud::<L2<f64>>::simple_from_ram(Box::from(data), 2, Box::from(labels), 1).unwrap()
+    }
+
+    #[test]
+    fn f64_trees_keep_their_width() {
+        let data: Vec<f64> = random_coordinates(400, 35).iter().map(|x| *x as f64 + 1e-12).collect();
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(wide_cloud(&data)).unwrap();
+        let reader = tree.reader();
+        reader.validate().unwrap();
+        let query = [0.5 + 1e-12, 0.25];
+        let mut expected: Vec<(f64, PointIndex)> = (0..200)
+            .map(|i| (L2::<f64>::dense(&query, &data[2 * i..2 * i + 2]), i as PointIndex))
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        expected.truncate(5);
+        assert_eq!(reader.knn(&query, 5).unwrap(), expected);
+
+        let cover_proto = tree.save();
+        assert_eq!(cover_proto.get_scalar_width(), 8);
+        assert_eq!(cover_proto.get_wide_scale_base(), 2.0);
+        let loaded = CoverTreeWriter::load(&cover_proto, wide_cloud(&data)).unwrap();
+        loaded.reader().validate().unwrap();
+        assert_eq!(loaded.reader().knn(&query, 5).unwrap(), expected);
+
+        let narrow: Vec<f32> = data.iter().map(|x| *x as f32).collect();
+        match CoverTreeWriter::load(&cover_proto, cloud_of(&narrow, 2)) {
+            Err(MalwareBrotError::ParsingError(errors::ParsingError::ScalarWidthMismatch { expected, found })) => {
+                assert_eq!((expected, found), (4, 8));
+            }
+            other => panic!("Expected a ScalarWidthMismatch, got {:?}", other.map(|_t| ())),
+        }
+        let narrow_tree = CoverTreeBuilder::new().set_verbosity(0).build(cloud_of(&narrow, 2)).unwrap();
+        assert_eq!(narrow_tree.save().get_scalar_width(), 4);
+    }
+
+    #[test]
+    fn quantized_trees_work_on_the_dequantized_values() {
+        let codes: Vec<i8> = random_coordinates(400, 36).iter().map(|x| (x * 254.0 - 127.0) as i8).collect();
+        let dequantized: Vec<f32> = codes.iter().map(|c| *c as f32 * 0.01).collect();
+        let unquantized = CoverTreeBuilder::new().set_verbosity(0).build(cloud_of(&dequantized, 2)).unwrap();
+        let mut labels = unquantized.reader().point_cloud().label_scheme().empty();
+        for pi in 0..200 {
+            labels.push(None, unquantized.reader().point_cloud().get_metadata(pi).unwrap()).unwrap();
+        }
+        let point_cloud = PointCloud::<L2>::from_quantized(Box::from(codes), 0.01, 2, labels).unwrap();
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+        reader.validate().unwrap();
+
+        assert_eq!(reader.root_address(), unquantized.reader().root_address());
+        let query = [0.3, -0.2];
+        let mut expected: Vec<(f32, PointIndex)> = (0..200)
+            .map(|i| (L2::dense(&query, &dequantized[2 * i..2 * i + 2]), i as PointIndex))
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        expected.truncate(5);
+        assert_eq!(reader.knn(&query, 5).unwrap(), expected);
+    }
+
+    #[test]
+    fn ages_count_inserts_and_survive_saving() {
+        let data = random_coordinates(40, 34);
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        assert_eq!(tree.age_of("5").unwrap(), 5);
+        let blank = tree.reader().point_cloud().label_scheme().blank_metadata();
+
+        tree.insert(b"new".to_vec(), &[0.5, 0.5], blank.clone()).unwrap();
+        assert_eq!(tree.age_of("new").unwrap(), 20);
+        tree.remove("3").unwrap();
+        match tree.age_of("3") {
+            Err(MalwareBrotError::NameNotInTree(_)) => {}
+            other => panic!("Expected a removed point to have no age, got {:?}", other),
+        }
+        // The insert reuses the removed point's index, but it's still the youngest
+        let pi = tree.insert(b"again".to_vec(), &[0.25, 0.75], blank.clone()).unwrap();
+        assert_eq!(pi, 3);
+        assert_eq!(tree.age_of("again").unwrap(), 21);
+        tree.update("5", &[0.1, 0.1]).unwrap();
+        assert_eq!(tree.age_of("5").unwrap(), 5);
+
+        let cover_proto = tree.save();
+        let reader = tree.reader();
+        let point_cloud = reader.point_cloud().with_replaced_point(0, &reader.point_cloud().get_point(0).unwrap()).unwrap();
+        let mut loaded = CoverTreeWriter::load(&cover_proto, point_cloud).unwrap();
+        for name in &["5", "new", "again", "19"] {
+            assert_eq!(loaded.age_of(name).unwrap(), tree.age_of(name).unwrap());
+        }
+        loaded.insert(b"newer".to_vec(), &[0.5, 0.25], blank).unwrap();
+        assert_eq!(loaded.age_of("newer").unwrap(), 22);
+        match loaded.age_of("missing") {
+            Err(MalwareBrotError::NameNotInTree(_)) => {}
+            other => panic!("Expected an unknown name to have no age, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn dot_and_text_dumps_have_every_node() {
+        let data = random_coordinates(60, 35);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+        let stats = reader.stats();
+
+        let dot = reader.to_dot();
+        assert!(dot.starts_with("digraph cover_tree {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert_eq!(dot.matches("\\nscale ").count(), stats.node_count);
+        assert_eq!(dot.matches("[style=dashed]").count(), stats.node_count - stats.leaf_count);
+        assert_eq!(dot.matches("shape=plaintext").count(), stats.singleton_count);
+
+        let text = reader.to_tree_string();
+        let lines: Vec<&str> = text.lines().filter(|l| l.contains("(scale ")).collect();
+        assert_eq!(lines.len(), stats.node_count);
+        let root_name = String::from_utf8_lossy(reader.root().unwrap().0).into_owned();
+        assert_eq!(lines[0], format!("{} (scale {})", root_name, reader.root_address().0));
+        assert_eq!(text.matches(", nested)").count(), stats.node_count - stats.leaf_count);
+    }
+
+    #[test]
+    fn insert_refuses_names_insert_or_replace_overwrites() {
+        let data = random_coordinates(40, 36);
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let old_vector = tree.reader().point_cloud().get_point(4).unwrap().to_vec();
+        let labeled = |y: f32| -> Metadata {
+            let mut metadata = Metadata::new();
+            metadata.insert("y".to_string(), Value::Vector(Vector::Real(vec![y])));
+            metadata
+        };
+        let label_of = |tree: &CoverTreeWriter<L2>, pi: PointIndex| match tree.reader().point_cloud().get_metadata(pi).unwrap().get("y") {
+            Some(Value::Vector(Vector::Real(y))) => y[0],
+            other => panic!("Expected a real vector label, got {:?}", other),
+        };
+
+        match tree.insert(b"4".to_vec(), &[0.5, 0.5], labeled(1.0)) {
+            Err(MalwareBrotError::NameCollision(name)) => assert_eq!(name, b"4"),
+            other => panic!("Expected a NameCollision, got {:?}", other),
+        }
+        assert_eq!(tree.reader().point_cloud().get_point(4).unwrap(), &old_vector[..]);
+        // Metadata without the cloud's labels is refused before anything changes
+        match tree.insert(b"unlabeled".to_vec(), &[0.5, 0.5], Metadata::new()) {
+            Err(MalwareBrotError::PointCloudError(PointCloudError::MetadataMismatch { key, got, .. })) => {
+                assert_eq!(key, "y");
+                assert_eq!(got, "nothing");
+            }
+            other => panic!("Expected a MetadataMismatch, got {:?}", other),
+        }
+        assert!(tree.reader().point_cloud().get_index("unlabeled").is_none());
+        assert_eq!(tree.reader().iter().count(), 20);
+        let pi = tree.insert(b"fresh".to_vec(), &[0.5, 0.5], labeled(1.0)).unwrap();
+        assert_eq!(tree.reader().point_cloud().get_index("fresh"), Some(&pi));
+        assert_eq!(label_of(&tree, pi), 1.0);
+
+        let replaced = tree.insert_or_replace(b"4".to_vec(), &[2.0, 2.0], labeled(2.0)).unwrap();
+        assert_eq!(replaced, 4);
+        assert_eq!(label_of(&tree, 4), 2.0);
+        let reader = tree.reader();
+        assert_eq!(reader.point_cloud().get_point(4).unwrap(), &[2.0, 2.0][..]);
+        assert_eq!(reader.knn(&[2.0, 2.0], 1).unwrap(), vec![(0.0, 4)]);
+        assert_eq!(reader.iter().count(), 21);
+        reader.validate().unwrap();
+        // A replacement that doesn't fit the labels leaves the point where it was
+        assert!(tree.insert_or_replace(b"4".to_vec(), &[3.0, 3.0], Metadata::new()).is_err());
+        assert_eq!(tree.reader().point_cloud().get_point(4).unwrap(), &[2.0, 2.0][..]);
+        assert_eq!(label_of(&tree, 4), 2.0);
+
+        let added = tree.insert_or_replace(b"fresher".to_vec(), &[0.25, 0.25], labeled(3.0)).unwrap();
+        assert_eq!(tree.reader().point_cloud().get_index("fresher"), Some(&added));
+        assert_eq!(tree.reader().iter().count(), 22);
+        let reader = tree.reader();
+        let everything = reader.point_cloud().reference_indexes();
+        assert!(reader.point_cloud().get_metasummary(&everything).is_ok());
+    }
+
+    #[test]
+    fn extend_keeps_what_went_in_before_an_error() {
+        let data = random_coordinates(40, 37);
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+
+        let streamed = (0..5).map(|i| (format!("streamed {}", i), vec![2.0 + i as f32, 2.0]));
+        tree.extend(streamed).unwrap();
+        assert_eq!(tree.reader().iter().count(), 25);
+        let streamed_pi = *tree.reader().point_cloud().get_index("streamed 3").unwrap();
+        assert!(tree.reader().point_cloud().get_metadata(streamed_pi).unwrap().contains_key("y"));
+
+        let points = vec![
+            (b"good".to_vec(), vec![3.0, 3.0]),
+            (b"short".to_vec(), vec![3.0]),
+            (b"never".to_vec(), vec![4.0, 4.0]),
+        ];
+        match tree.extend(points) {
+            Err(MalwareBrotError::PartialInsert { inserted, error }) => {
+                assert_eq!(inserted, 1);
+                match *error {
+                    MalwareBrotError::DimensionMismatch { expected: 2, got: 1 } => {}
+                    other => panic!("Expected a DimensionMismatch, got {:?}", other),
+                }
+            }
+            other => panic!("Expected a PartialInsert, got {:?}", other),
+        }
+        let reader = tree.reader();
+        assert!(reader.point_cloud().get_index("good").is_some());
+        assert!(reader.point_cloud().get_index("never").is_none());
+        assert_eq!(reader.iter().count(), 26);
+        reader.validate().unwrap();
+    }
+
+    #[test]
+    fn insert_batch_matches_the_points_and_rolls_back() {
+        let data = random_coordinates(400, 38);
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        tree.remove("7").unwrap();
+        tree.set_removal_mode(RemovalMode::Tombstone);
+        tree.remove("8").unwrap();
+
+        // Clustered around two spots, with some outside the root's cover and the two removed names back again
+        let mut batch: Vec<(String, Vec<f32>)> = (0..300)
+            .map(|i| {
+                let spot = if i % 2 == 0 { 0.25 } else { 0.75 };
+                (format!("batch {}", i), vec![spot + rand::random::<f32>() / 50.0, spot])
+            })
+            .collect();
+        batch.push(("far".to_string(), vec![20.0, -20.0]));
+        batch.push(("7".to_string(), vec![0.5, 0.5]));
+        batch.push(("8".to_string(), vec![0.5, 0.25]));
+        tree.insert_batch(&batch).unwrap();
+        let reader = tree.reader();
+        reader.validate().unwrap();
+        assert_eq!(reader.iter().count(), 501);
+        assert_eq!(tree.tombstone_count(), 0);
+        for (name, point) in &batch {
+            let pi = *reader.point_cloud().get_index(name.as_bytes()).unwrap();
+            assert_eq!(reader.point_cloud().get_point(pi).unwrap(), &point[..]);
+            assert_eq!(reader.knn(point, 1).unwrap()[0].0, 0.0);
+            assert!(reader.point_cloud().get_metadata(pi).unwrap().contains_key("y"));
+        }
+        // The removed points' indexes are reused
+        assert_eq!(reader.point_cloud().get_index("7"), Some(&7));
+        assert_eq!(reader.point_cloud().get_index("8"), Some(&8));
+        assert_eq!(reader.point_cloud().len(), 501);
+        drop(reader);
+
+        let version = tree.version();
+        for bad in vec![
+            vec![("fine".to_string(), vec![0.5, 0.5]), ("batch 3".to_string(), vec![0.5, 0.5])],
+            vec![("twice".to_string(), vec![0.5, 0.5]), ("twice".to_string(), vec![0.25, 0.5])],
+        ] {
+            match tree.insert_batch(&bad) {
+                Err(MalwareBrotError::NameCollision(_)) => {}
+                other => panic!("Expected a NameCollision, got {:?}", other),
+            }
+        }
+        assert_eq!(tree.version(), version);
+        assert!(tree.reader().point_cloud().get_index("fine").is_none());
+
+        // A point too far away for the tree to have a layer for fails after the others have gone in
+        let before = tree.reader();
+        let queries: Vec<[f32; 2]> = (0..20).map(|_i| [rand::random::<f32>(), rand::random::<f32>()]).collect();
+        let answers: Vec<_> = queries.iter().map(|q| before.knn(q, 5).unwrap()).collect();
+        let (node_count, age) = (before.node_count(), tree.age_of("batch 5").unwrap());
+        drop(before);
+        let doomed = vec![
+            ("doomed".to_string(), vec![0.25, 0.25]),
+            ("too far".to_string(), vec![1.0e30, 1.0e30]),
+        ];
+        assert!(tree.insert_batch(&doomed).is_err());
+        let reader = tree.reader();
+        reader.validate().unwrap();
+        assert!(tree.version() > version);
+        assert_eq!(reader.node_count(), node_count);
+        assert_eq!(reader.iter().count(), 501);
+        assert!(reader.point_cloud().get_index("doomed").is_none());
+        assert_eq!(tree.age_of("batch 5").unwrap(), age);
+        for (query, answer) in queries.iter().zip(&answers) {
+            assert_eq!(&reader.knn(query, 5).unwrap(), answer);
+        }
+    }
+
+    #[test]
+    fn reserve_leaves_the_queries_alone() {
+        let data = random_coordinates(400, 39);
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let queries: Vec<[f32; 2]> = (0..20).map(|_i| [rand::random::<f32>(), rand::random::<f32>()]).collect();
+        let answers: Vec<_> = queries.iter().map(|q| tree.reader().knn(q, 5).unwrap()).collect();
+        let (version, node_count) = (tree.version(), tree.reader().node_count());
+
+        tree.reserve(10000);
+        let reader = tree.reader();
+        assert_eq!((tree.version(), reader.node_count()), (version, node_count));
+        for (query, answer) in queries.iter().zip(&answers) {
+            assert_eq!(&reader.knn(query, 5).unwrap(), answer);
+        }
+        drop(reader);
+        let batch: Vec<(String, Vec<f32>)> =
+            (0..1000).map(|i| (format!("b{}", i), vec![rand::random::<f32>(), rand::random::<f32>()])).collect();
+        tree.insert_batch(&batch).unwrap();
+        tree.reader().validate().unwrap();
+        assert_eq!(tree.reader().iter().count(), 1200);
+    }
+
+    #[test]
+    fn load_rejects_truncated_and_tampered_files() {
+        let data = random_coordinates(200, 40);
+        let point_cloud = || cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud()).unwrap();
+        let bytes = tree.to_bytes().unwrap();
+
+        // The checksum comes before the layers, so a cut at a field boundary only loads once every layer is in
+        let cover_proto = tree.save();
+        let mut fields = Vec::new();
+        let mut is = protobuf::CodedInputStream::from_bytes(&bytes);
+        while !is.eof().unwrap() {
+            let (field, wire_type) = is.read_tag_unpack().unwrap();
+            fields.push(field);
+            is.skip_field(wire_type).unwrap();
+        }
+        let checksum_at = fields.iter().position(|f| *f == 13).unwrap();
+        let layers_at = fields.iter().position(|f| *f == 11).unwrap();
+        assert!(checksum_at < layers_at);
+        for len in 0..bytes.len() {
+            if let Ok(loaded) = CoverTreeWriter::from_bytes(&bytes[..len], point_cloud()) {
+                loaded.reader().validate().unwrap();
+                let loaded_proto = loaded.save();
+                assert_eq!(loaded_proto.get_layers().len(), cover_proto.get_layers().len());
+                assert_eq!(loaded_proto.get_checksum(), cover_proto.get_checksum());
+            }
+        }
+        for _i in 0..500 {
+            let mut flipped = bytes.clone();
+            let at = rand::random::<usize>() % flipped.len();
+            flipped[at] ^= 1 << (rand::random::<u8>() % 8);
+            let _ = CoverTreeWriter::from_bytes(&flipped, point_cloud());
+        }
+
+        // Tampered files with checksums that match, so only the structure gives them away
+        let tamper = |edit: &dyn Fn(&mut CoreProto)| {
+            let mut tampered = cover_proto.clone();
+            edit(&mut tampered);
+            tampered.set_checksum(layers_checksum(tampered.get_layers()));
+            match CoverTreeWriter::load(&tampered, point_cloud()) {
+                Err(MalwareBrotError::ParsingError(e)) => e,
+                other => panic!("Expected a parsing error, got {:?}", other.map(|_| ())),
+            }
+        };
+        let (layer, node) = cover_proto
+            .get_layers()
+            .iter()
+            .enumerate()
+            .flat_map(|(l, layer)| layer.get_nodes().iter().enumerate().map(move |(n, node)| (l, n, node)))
+            .find(|(_l, _n, node)| !node.get_is_leaf() && !node.get_children_point_indexes().is_empty())
+            .map(|(l, n, _node)| (l, n))
+            .unwrap();
+
+        match tamper(&|p: &mut CoreProto| p.mut_layers()[2].set_scale_index(i32::max_value())) {
+            errors::ParsingError::MisplacedLayer { position: 2, .. } => {}
+            e => panic!("Expected a MisplacedLayer, got {:?}", e),
+        }
+        match tamper(&|p: &mut CoreProto| p.set_root_scale(i32::max_value())) {
+            errors::ParsingError::ScaleOutOfRange { scale_index, .. } => assert_eq!(scale_index, i32::max_value()),
+            e => panic!("Expected a ScaleOutOfRange, got {:?}", e),
+        }
+        match tamper(&|p: &mut CoreProto| p.set_root_index(1_000_000)) {
+            errors::ParsingError::MissingNode { .. } => {}
+            e => panic!("Expected a MissingNode, got {:?}", e),
+        }
+        match tamper(&|p: &mut CoreProto| p.mut_layers()[layer].mut_nodes()[node].mut_children_point_indexes()[0] = 1_000_000) {
+            errors::ParsingError::MissingNode { child, .. } => assert_eq!(child.1, 1_000_000),
+            e => panic!("Expected a MissingNode, got {:?}", e),
+        }
+        match tamper(&|p: &mut CoreProto| {
+            let parent_scale = p.get_layers()[layer].get_scale_index();
+            p.mut_layers()[layer].mut_nodes()[node].set_nested_scale_index(parent_scale);
+        }) {
+            errors::ParsingError::ScaleOutOfRange { .. } => {}
+            e => panic!("Expected a ScaleOutOfRange, got {:?}", e),
+        }
+        match tamper(&|p: &mut CoreProto| {
+            p.mut_layers()[layer].mut_nodes()[node].mut_children_scale_indexes().pop();
+        }) {
+            errors::ParsingError::MismatchedChildren { .. } => {}
+            e => panic!("Expected a MismatchedChildren, got {:?}", e),
+        }
+        match tamper(&|p: &mut CoreProto| p.mut_layers()[layer].mut_nodes()[node].mut_outlier_point_indexes().push(1_000_000)) {
+            errors::ParsingError::UnknownIndex { index, .. } => assert_eq!(index, 1_000_000),
+            e => panic!("Expected an UnknownIndex, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn writer_lends_and_gives_back_the_point_cloud() {
+        let data = random_coordinates(40, 41);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+
+        assert_eq!(tree.point_cloud().len(), 20);
+        assert_eq!(tree.point_cloud().get_point(3).unwrap(), &data[6..8]);
+        let distances = tree.point_cloud().distances_to_point_index(0, &[1, 2]).unwrap();
+        assert!((distances[0] - L2::dense(&data[0..2], &data[2..4])).abs() < 1e-6);
+
+        let reader = tree.reader();
+        let tree = match tree.into_point_cloud() {
+            Err(tree) => tree,
+            Ok(_) => panic!("The reader still shares the point cloud"),
+        };
+        drop(reader);
+        let point_cloud = tree.into_point_cloud().ok().unwrap();
+        assert_eq!(point_cloud.len(), 20);
+        assert_eq!(point_cloud.get_point(3).unwrap(), &data[6..8]);
+    }
 
-        let mut scale_indexes: Vec<i32> = children_addresses.iter().map(|(si, _pi)| *si).collect();
-        ////println!("\t Child addresses are {:?}", children_addresses);
-        scale_indexes.dedup();
-        let mut clusters = Vec::new();
-        for child_si in scale_indexes {
-            let unclustered: Vec<PointIndex> = children_addresses
-                .iter()
-                .filter(|(osi, _pi)| osi == &child_si)
-                .map(|(_si, pi)| *pi)
-                .collect();
-            //println!("\t\t[{}] Clustering on {:?} points", child_si, unclustered.len());
-            let mut components = self
-                .layer(child_si)
-                .get_components(unclustered, &self.parameters.point_cloud)?;
-            //println!("\t\t[{}] Obtained {:?} clusters", child_si, components);
+    #[test]
+    fn streamed_range_matches_range_query() {
+        let data = random_coordinates(400, 42);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
 
-            while let Some((id, component)) = components.pop() {
-                clusters.push((id, child_si, component));
-            }
+        for radius in &[0.0, 0.1, 0.3, 2.0] {
+            let query = [0.5, 0.5];
+            let mut streamed: Vec<(f32, PointName)> =
+                reader.knn_streaming(&query, *radius).unwrap().map(|r| r.unwrap()).collect();
+            let mut expected = reader.range_query(&query, *radius).unwrap();
+            streamed.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert_eq!(streamed, expected);
+        }
+        // Everything's in range, the first point is the root and comes before any node below it is visited
+        let mut stream = reader.knn_streaming(&[0.5, 0.5], 2.0).unwrap();
+        let (_d, first) = stream.next().unwrap().unwrap();
+        assert_eq!(&first[..], reader.root().unwrap().0);
+        assert_eq!(stream.count(), 199);
+
+        match reader.knn_streaming(&[0.5], 1.0) {
+            Err(MalwareBrotError::DimensionMismatch { expected: 2, got: 1 }) => {}
+            _ => panic!("Expected a DimensionMismatch"),
         }
-        Ok(clusters)
     }
-}
 
-/// 
-pub struct CoverTreeWriter<M: Metric> {
-    pub(crate) parameters: Arc<CoverTreeParameters<M>>,
-    pub(crate) layers: Vec<CoverLayerWriter>,
-    pub(crate) root_address: NodeAddress,
-}
+    #[test]
+    fn approx_results_are_within_their_bound() {
+        let data = random_coordinates(3000, 43);
+        let point_cloud = cloud_of(&data, 3);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+        let tolerance = 1e-5;
 
-impl<M: Metric> CoverTreeWriter<M> {
-    #[doc(hidden)]
-    pub fn cluster(&mut self) -> MalwareBrotResult<()> {
-        let reader = self.reader();
-        let mut pending_clusters = vec![(0, self.root_address.0, vec![self.root_address.1])];
-        while let Some((i, si, pis)) = pending_clusters.pop() {
-            println!(
-                "Clustering children of cluster_id:{:?}, len: {}",
-                (i, si),
-                pis.len()
-            );
-            let mut children_clusters = reader.cluster_children(si, &pis[..])?;
-            let mut children_ids = Vec::new();
-            while let Some((id, nsi, npis)) = children_clusters.pop() {
-                pending_clusters.push((id, nsi, npis));
-                children_ids.push((nsi, id));
+        let (exact, bound) = reader.knn_approx_bounded(&[0.5, 0.5, 0.5], 10, 0.0).unwrap();
+        assert_eq!(exact, reader.knn(&[0.5, 0.5, 0.5], 10).unwrap());
+        assert_eq!(bound.max_error, 0.0);
+        for epsilon in &[0.5, 1.0, 5.0, 20.0] {
+            for _i in 0..30 {
+                let query = [rand::random::<f32>(), rand::random::<f32>(), rand::random::<f32>()];
+                let (approx, bound) = reader.knn_approx_bounded(&query, 10, *epsilon).unwrap();
+                assert_eq!(approx, reader.knn_approx(&query, 10, *epsilon).unwrap());
+                let truth = reader.knn(&query, 10).unwrap();
+                assert_eq!(approx.len(), truth.len());
+                for ((d, _pi), (true_d, true_pi)) in approx.iter().zip(&truth) {
+                    assert!(d - true_d <= bound.max_error + tolerance);
+                    if *true_d < bound.exact_radius - tolerance {
+                        assert!(approx.iter().any(|(_d, pi)| pi == true_pi));
+                    }
+                }
             }
-            unsafe {self.layer(si).insert_cluster(
-                i,
-                CoverCluster {
-                    indexes: pis,
-                    children_ids,
-                },
-            );}
         }
-        Ok(())
     }
 
-    /// Provides a reference to a `CoverLayerWriter`. Do not use, unless you're going to leave the tree in a *valid* state.
-    pub(crate) unsafe fn layer(&mut self, scale_index: i32) -> &mut CoverLayerWriter {
-        &mut self.layers[self.parameters.internal_index(scale_index)]
-    }
+    #[test]
+    fn leave_one_out_matches_rebuilding_without_the_point() {
+        let data = random_coordinates(120, 44);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+        let loo = reader.query_loo(5).unwrap();
+        assert_eq!(loo.len(), 60);
 
-    /// Creates a reader for queries.
-    pub fn reader(&self) -> CoverTreeReader<M> {
-        CoverTreeReader {
-            parameters: Arc::clone(&self.parameters),
-            layers: self.layers.iter().map(|l| l.reader()).collect(),
-            root_address: self.root_address,
+        let graph = reader.knn_graph(5, false).unwrap();
+        for (pi, neighbors) in &loo {
+            assert!(neighbors.iter().all(|(_d, qi)| qi != pi));
+            let from_graph: Vec<(f32, PointIndex)> =
+                graph[*pi as usize].iter().map(|(qi, d)| (*d, *qi as PointIndex)).collect();
+            assert_eq!(neighbors, &from_graph);
+        }
+
+        for held_out in &[0usize, 17, 59] {
+            let mut rest = data.clone();
+            rest.drain(2 * held_out..2 * held_out + 2);
+            let point_cloud = cloud_of(&rest, 2);
+            let rebuilt = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+            let expected: Vec<(f32, PointIndex)> = rebuilt
+                .reader()
+                .knn(&data[2 * held_out..2 * held_out + 2], 5)
+                .unwrap()
+                .into_iter()
+                .map(|(d, qi)| if (qi as usize) < *held_out { (d, qi) } else { (d, qi + 1) })
+                .collect();
+            assert_eq!(loo[*held_out].1, expected);
         }
     }
 
-    pub(crate) unsafe fn insert_raw(
-        &mut self,
-        scale_index: i32,
-        point_index: PointIndex,
-        node: CoverNode,
-    ) {
-        self.layers[self.parameters.internal_index(scale_index)].insert_raw(point_index, node);
+    #[test]
+    fn len_and_contains_follow_inserts_and_removes() {
+        let data = random_coordinates(40, 45);
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        assert_eq!(tree.reader().len(), 20);
+        assert!(!tree.reader().is_empty());
+        assert!(tree.reader().contains("7"));
+        assert!(!tree.reader().contains("never"));
+
+        let blank = tree.reader().point_cloud().label_scheme().blank_metadata();
+        tree.insert(b"new".to_vec(), &[0.5, 0.5], blank).unwrap();
+        assert_eq!(tree.reader().len(), 21);
+        assert!(tree.reader().contains("new"));
+
+        tree.remove("7").unwrap();
+        let reader = tree.reader();
+        assert_eq!(reader.len(), 20);
+        assert!(!reader.contains("7"));
+        assert_eq!(reader.len(), reader.iter().count());
+        drop(reader);
+        let cover_proto = tree.save();
+
+        tree.set_removal_mode(RemovalMode::Tombstone);
+        tree.remove("8").unwrap();
+        assert_eq!(tree.reader().len(), 19);
+        assert!(!tree.reader().contains("8"));
+
+        tree.compact().unwrap();
+        assert_eq!(tree.reader().len(), 19);
+        assert!(!tree.reader().contains("8"));
+        assert!(tree.reader().contains("9"));
+
+        // The point cloud still has the removed point, the loaded tree knows it's not in the tree
+        let loaded = CoverTreeWriter::load(&cover_proto, tree.into_point_cloud().ok().unwrap()).unwrap();
+        assert_eq!(loaded.reader().len(), 20);
+        assert!(!loaded.reader().contains("7"));
+        assert!(loaded.reader().contains("8"));
     }
 
-    /// Loads a tree from a protobuf. There's a `load_tree` in `utils` that handles loading from a path to a protobuf file.
-    pub fn load(
-        cover_proto: &CoreProto,
-        point_cloud: PointCloud<M>,
-    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
-        let parameters = Arc::new(CoverTreeParameters {
-            total_nodes: atomic::AtomicUsize::new(0),
-            use_singletons: cover_proto.use_singletons,
-            scale_base: cover_proto.scale_base as f32,
-            cutoff: cover_proto.cutoff as usize,
-            resolution: cover_proto.resolution as i32,
-            cluster_min: 5,
-            point_cloud,
-            verbosity: 2,
-        });
-        let root_address = (cover_proto.get_root_scale(), cover_proto.get_root_index());
-        let layers = cover_proto
-            .get_layers()
-            .par_iter()
-            .map(|l| CoverLayerWriter::load(l))
+    #[test]
+    fn recall_is_one_when_exact_and_drops_with_epsilon() {
+        let data = random_coordinates(3000, 46);
+        let point_cloud = cloud_of(&data, 3);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+        let queries: Vec<Vec<f32>> = (0..50)
+            .map(|_i| vec![rand::random::<f32>(), rand::random::<f32>(), rand::random::<f32>()])
             .collect();
 
-        Ok(CoverTreeWriter {
-            parameters,
-            layers,
-            root_address,
-        })
+        assert_eq!(reader.measure_recall(&queries, 10, 0.0).unwrap(), 1.0);
+        let loose = reader.measure_recall(&queries, 10, 20.0).unwrap();
+        assert!(loose >= 0.0 && loose <= 1.0);
+        // More neighbors than points, each query is measured against the whole tree
+        assert_eq!(reader.measure_recall(&queries, 2000, 0.0).unwrap(), 1.0);
+        assert_eq!(reader.measure_recall(&[], 10, 1.0).unwrap(), 1.0);
+        assert!(reader.measure_recall(&[vec![0.5]], 10, 1.0).is_err());
     }
 
-    /// Encodes the tree into a protobuf. See `utils::save_tree` for saving to a file on disk.
-    pub fn save(&self) -> CoreProto {
-        let mut cover_proto = CoreProto::new();
-        cover_proto.set_scale_base(self.parameters.scale_base);
-        cover_proto.set_cutoff(self.parameters.cutoff as u64);
-        cover_proto.set_resolution(self.parameters.resolution);
-        cover_proto.set_use_singletons(self.parameters.use_singletons);
-        cover_proto.set_dim(self.parameters.point_cloud.dim() as u64);
-        cover_proto.set_count(self.parameters.point_cloud.len() as u64);
-        cover_proto.set_root_scale(self.root_address.0);
-        cover_proto.set_root_index(self.root_address.1);
-        cover_proto.set_layers(self.layers.iter().map(|l| l.save()).collect());
-        cover_proto
+    #[test]
+    fn appended_mmap_points_are_found_like_a_rebuild() {
+        let data = random_coordinates(200, 47);
+        let appended: Vec<f32> = (0..60).map(|_i| rand::random::<f32>()).collect();
+        let dir = std::env::temp_dir().join(format!("grandma_append_mmap_{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("appended.dat");
+        let bytes: Vec<u8> = appended.iter().flat_map(|x| x.to_le_bytes().to_vec()).collect();
+        std::fs::write(&path, &bytes).unwrap();
+
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let indexes = tree.append_mmap_points(&path).unwrap();
+        assert_eq!(indexes, (100..130).collect::<Vec<PointIndex>>());
+
+        let mut all = data.clone();
+        all.extend_from_slice(&appended);
+        let point_cloud = cloud_of(&all, 2);
+        let rebuilt = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+        reader.validate().unwrap();
+        assert_eq!(reader.len(), 130);
+        assert!(reader.contains("115"));
+        // The appended points have a blank row of labels, so summaries over the whole cloud still work
+        let blank = reader.point_cloud().label_scheme().blank_metadata();
+        let appended_metadata = reader.point_cloud().get_metadata(115).unwrap();
+        assert_eq!(appended_metadata.keys().collect::<Vec<_>>(), blank.keys().collect::<Vec<_>>());
+        assert!(reader.point_cloud().get_metasummary(&reader.point_cloud().reference_indexes()).is_ok());
+        for _i in 0..20 {
+            let query = [rand::random::<f32>(), rand::random::<f32>()];
+            assert_eq!(reader.knn(&query, 5).unwrap(), rebuilt.reader().knn(&query, 5).unwrap());
+        }
+
+        // Three floats can't be points of dimension 2
+        std::fs::write(&path, &bytes[..12]).unwrap();
+        match tree.append_mmap_points(&path) {
+            Err(MalwareBrotError::DimensionMismatch { expected: 2, got: 3 }) => {}
+            other => panic!("Expected a DimensionMismatch, got {:?}", other),
+        }
+        assert_eq!(tree.reader().len(), 130);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
-    /// Swaps the maps on each layer so that any `CoverTreeReaders` see the updated tree. 
-    /// Only call once you have a valid tree.
-    pub fn refresh(&mut self) {
-        self.layers.par_iter_mut().for_each(|l| l.refresh());
+    #[test]
+    fn filtered_knn_returns_k_matching_points() {
+        let data = random_coordinates(400, 48);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+        let tenant = |name: &str| name.parse::<usize>().map(|i| i % 10 == 3).unwrap_or(false);
+        for _i in 0..20 {
+            let query = [rand::random::<f32>(), rand::random::<f32>()];
+            let mut expected: Vec<(f32, PointIndex)> = (0..200)
+                .filter(|i| i % 10 == 3)
+                .map(|i| (L2::dense(&data[2 * i..2 * i + 2], &query), i as PointIndex))
+                .collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            expected.truncate(5);
+            assert_eq!(reader.knn_filtered(&query, 5, tenant).unwrap(), expected);
+        }
+        let query = [0.5, 0.5];
+        assert_eq!(reader.knn_filtered(&query, 50, tenant).unwrap().len(), 20);
+        assert!(reader.knn_filtered(&query, 5, |_name| false).unwrap().is_empty());
+        assert_eq!(reader.knn_filtered(&query, 5, |_name| true).unwrap(), reader.knn(&query, 5).unwrap());
+
+        // The filter is only asked about the points the search reaches, and only once about each
+        let asked = RefCell::new(Vec::new());
+        let nearest = reader.knn_filtered(&query, 1, |name| {
+            asked.borrow_mut().push(name.to_string());
+            true
+        });
+        assert_eq!(nearest.unwrap(), reader.knn(&query, 1).unwrap());
+        let mut asked = asked.into_inner();
+        assert!(asked.len() < 200);
+        let calls = asked.len();
+        asked.sort();
+        asked.dedup();
+        assert_eq!(asked.len(), calls);
     }
-}
 
-#[cfg(test)]
-pub(crate) mod tests {
-    use super::*;
-    use crate::utils::cover_tree_from_yaml;
-    use std::path::Path;
+    #[test]
+    fn distance_between_names_uses_the_metric() {
+        let data = vec![0.0, 0.0, 3.0, 4.0, 1.0, 1.0];
+        let point_cloud = cloud_of(&data, 2);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        assert_eq!(tree.reader().distance_between("0", "1").unwrap(), 5.0);
+        assert_eq!(tree.reader().distance_between(b"1".to_vec(), "1").unwrap(), 0.0);
+        match tree.reader().distance_between("0", "missing") {
+            Err(MalwareBrotError::NameNotInTree(name)) => assert_eq!(name, b"missing"),
+            other => panic!("Expected NameNotInTree, got {:?}", other),
+        }
 
-    pub(crate) fn build_mnist_tree() -> CoverTreeWriter<L2> {
-        let file_name = "data/mnist_complex.yml";
-        let path = Path::new(file_name);
-        if !path.exists() {
-            panic!(file_name.to_owned() + &" does not exist".to_string());
+        tree.remove("2").unwrap();
+        match tree.reader().distance_between("2", "0") {
+            Err(MalwareBrotError::NameNotInTree(name)) => assert_eq!(name, b"2"),
+            other => panic!("Expected NameNotInTree, got {:?}", other),
         }
-        cover_tree_from_yaml(&path).unwrap()
     }
 
     #[test]
-    fn greedy_knn_nodes() {
-        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
-        let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0];
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "The tree is broken after a removal")]
+    fn debug_builds_check_the_nodes_a_mutation_changed() {
+        let mut data = random_coordinates(100, 49);
+        data[99] = 100.0;
+        let point_cloud = cloud_of(&data, 1);
+        let mut tree = CoverTreeBuilder::new().set_cutoff(5).set_verbosity(0).build(point_cloud).unwrap();
 
-        let point_cloud =
-            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
-        let builder = CoverTreeBuilder {
-            scale_base: 2.0,
-            cutoff: 1,
-            resolution: -9,
-            use_singletons: false,
-            cluster_min: 5,
-            verbosity: 0,
-        };
-        let tree = builder.build(point_cloud).unwrap();
+        // Stick the far away point onto a leaf, then take another point out of that leaf
         let reader = tree.reader();
+        let mut leaf = None;
+        for si in reader.scale_range() {
+            reader.layer(si).for_each_node(|pi, n| {
+                if *pi != 99 && n.is_leaf() && !n.singletons().is_empty() {
+                    leaf = Some(((si, *pi), n.clone()));
+                }
+            });
+        }
+        let (address, mut node) = leaf.unwrap();
+        let removed = node.singletons()[0];
+        node.insert_singleton(99);
+        unsafe {tree.insert_raw(address.0, address.1, node);}
+        tree.refresh();
+        drop(reader);
+        tree.remove(removed.to_string()).unwrap();
+    }
 
-        let point = [-0.5];
+    #[test]
+    #[cfg(feature = "bincode")]
+    fn fast_encoding_round_trip() {
+        let data = random_coordinates(400, 50);
+        let point_cloud = || cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud()).unwrap();
+        let bytes = tree.save_fast().unwrap();
+        assert_eq!(bytes[0], FAST_FORMAT_VERSION);
 
-        let mut query_heap = KnnQueryHeap::new(5, reader.parameters.scale_base);
-        let dist_to_root = reader
-            .parameters
-            .point_cloud
-            .distances_to_point(&point, &[reader.root_address().1])
-            .unwrap()[0];
-        query_heap.push_nodes(&[reader.root_address()], &[dist_to_root], None);
+        let loaded = CoverTreeWriter::load_fast(&bytes, point_cloud()).unwrap();
+        let (reader, loaded_reader) = (tree.reader(), loaded.reader());
+        assert_eq!(reader.root_address(), loaded_reader.root_address());
+        assert_eq!(reader.node_count(), loaded_reader.node_count());
+        loaded_reader.validate().unwrap();
+        for _i in 0..20 {
+            let query = [rand::random::<f32>(), rand::random::<f32>()];
+            assert_eq!(reader.knn(&query, 5).unwrap(), loaded_reader.knn(&query, 5).unwrap());
+        }
 
-        assert_eq!(
-            reader.root_address(),
-            query_heap.closest_unvisited_child_covering_address().unwrap().1
-        );
+        let mut future = bytes.clone();
+        future[0] = FAST_FORMAT_VERSION + 1;
+        match CoverTreeWriter::load_fast(&future, point_cloud()) {
+            Err(MalwareBrotError::ParsingError(errors::ParsingError::UnsupportedVersion { found, supported })) => {
+                assert_eq!((found, supported), (FAST_FORMAT_VERSION + 1, FAST_FORMAT_VERSION));
+            }
+            other => panic!("Expected UnsupportedVersion, got {:?}", other.map(|_t| ())),
+        }
+        for len in &[0, 1, bytes.len() / 2, bytes.len() - 1] {
+            assert!(CoverTreeWriter::load_fast(&bytes[..*len], point_cloud()).is_err());
+        }
+    }
 
-        reader.greedy_knn_nodes(&point,&mut query_heap);
-        println!("{:#?}", query_heap);
-        println!("{:#?}",query_heap.closest_unvisited_child_covering_address());
+    #[test]
+    #[cfg(feature = "bincode")]
+    fn fast_encoding_checks_the_scalar_width() {
+        let data: Vec<f64> = random_coordinates(100, 51).iter().map(|x| *x as f64).collect();
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(wide_cloud(&data)).unwrap();
+        let bytes = tree.save_fast().unwrap();
+        assert_eq!(bytes[1], 8);
+        let loaded = CoverTreeWriter::load_fast(&bytes, wide_cloud(&data)).unwrap();
+        assert_eq!(loaded.reader().knn(&[0.5, 0.5], 3).unwrap(), tree.reader().knn(&[0.5, 0.5], 3).unwrap());
+
+        let narrow: Vec<f32> = data.iter().map(|x| *x as f32).collect();
+        match CoverTreeWriter::load_fast(&bytes, cloud_of(&narrow, 2)) {
+            Err(MalwareBrotError::ParsingError(errors::ParsingError::ScalarWidthMismatch { expected, found })) => {
+                assert_eq!((expected, found), (4, 8));
+            }
+            other => panic!("Expected a ScalarWidthMismatch, got {:?}", other.map(|_t| ())),
+        }
     }
-    
+
     #[test]
-    fn knn_singletons_on() {
-        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
-        let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0];
+    fn enclosing_node_is_the_closest_ball_on_the_cut() {
+        let data = random_coordinates(600, 51);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let reader = tree.reader();
+        let root = reader.root_address();
+        let scale_base = reader.scale_base();
+        let level = root.0 - 2;
 
-        let point_cloud =
-            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
-        let builder = CoverTreeBuilder {
-            scale_base: 2.0,
-            cutoff: 1,
-            resolution: -9,
-            use_singletons: true,
-            cluster_min: 5,
-            verbosity: 0,
-        };
-        let tree = builder.build(point_cloud).unwrap();
+        for _i in 0..20 {
+            let query = [rand::random::<f32>(), rand::random::<f32>()];
+            // Every node on the cut, by the distance to its center
+            let cut = reader.knn_at_level(&query, 300, level).unwrap();
+            let expected = cut.iter().find(|(d, a)| *d <= scale_base.powi(a.0));
+            let found = reader.enclosing_node(&query, level).unwrap();
+            match (found, expected) {
+                (Some(found), Some((dist, _address))) => {
+                    let found_dist = L2::dense(&reader.parameters.point_cloud.get_point(found.1).unwrap(), &query);
+                    assert!(found_dist <= scale_base.powi(found.0));
+                    assert_eq!(found_dist, *dist);
+                    assert!(cut.iter().any(|(_d, a)| *a == found));
+                }
+                (None, None) => {}
+                (found, expected) => panic!("Found {:?}, expected {:?}", found, expected),
+            }
+        }
+
+        let point = reader.parameters.point_cloud.get_point(root.1).unwrap().to_vec();
+        assert!(reader.enclosing_node(&point, level).unwrap().is_some());
+        let far = scale_base.powi(root.0) * 10.0;
+        assert_eq!(reader.enclosing_node(&[far, far], level).unwrap(), None);
+        assert!(reader.enclosing_node(&[0.5], level).is_err());
+    }
+
+    #[test]
+    fn knn_deadline_returns_what_it_found_in_time() {
+        let data = random_coordinates(2000, 52);
+        let point_cloud = cloud_of(&data, 2);
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
         let reader = tree.reader();
+        let query = [0.5, 0.5];
 
-        println!("2 nearest neighbors of 0.0 are 0.48 and 0.0");
-        let zero_nbrs = reader.knn(&[0.1],2).unwrap();
-        println!("{:?}", zero_nbrs);
-        assert!(zero_nbrs[0].1 == 4);
-        assert!(zero_nbrs[1].1 == 2);
+        let later = Instant::now() + Duration::from_secs(3600);
+        let (results, timed_out) = reader.knn_deadline(&query, 10, later).unwrap();
+        assert!(!timed_out);
+        assert_eq!(results, reader.knn(&query, 10).unwrap());
+
+        let (results, timed_out) = reader.knn_deadline(&query, 10, Instant::now()).unwrap();
+        assert!(timed_out);
+        assert!(results.len() <= 10);
+        for window in results.windows(2) {
+            assert!(window[0].0 <= window[1].0);
+        }
+        for (dist, pi) in &results {
+            let point = reader.parameters.point_cloud.get_point(*pi).unwrap();
+            assert_eq!(*dist, L2::dense(&point, &query));
+        }
     }
 
     #[test]
-    fn knn_singletons_off() {
-        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
-        let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0];
+    fn ranking_singletons_keeps_the_results() {
+        let mut data = random_coordinates(3000, 53);
+        // Ties, so the bound on the ranks is held to the kth distance exactly
+        data.extend_from_slice(&[0.5, 0.5, 0.6, 0.5, 0.5, 0.4, 0.5, 0.6, 0.5, 0.5, 0.4, 0.5]);
+        let point_cloud = cloud_of(&data, 3);
+        let mut tree = CoverTreeBuilder::new().set_verbosity(0).set_cutoff(20).build(point_cloud).unwrap();
+        // Removed points are skipped, so the first k ranks of a node aren't always the ones that make the cut
+        for i in (0..1000).step_by(7) {
+            tree.remove(format!("{}", i)).unwrap();
+        }
+        let mut reader = tree.reader();
+        assert!(!reader.rank_singletons());
 
-        let point_cloud =
-            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
-        let builder = CoverTreeBuilder {
-            scale_base: 2.0,
-            cutoff: 1,
-            resolution: -9,
-            use_singletons: false,
-            cluster_min: 5,
-            verbosity: 0,
+        let mut queries = vec![[0.5, 0.5, 0.5]];
+        for _i in 0..50 {
+            queries.push([rand::random::<f32>(), rand::random::<f32>(), rand::random::<f32>()]);
+        }
+        for k in &[1, 3, 4, 10, 50] {
+            for query in &queries {
+                reader.set_rank_singletons(false);
+                let expected = reader.knn(query, *k).unwrap();
+                reader.set_rank_singletons(true);
+                assert_eq!(reader.knn(query, *k).unwrap(), expected);
+                assert_eq!(reader.factory().reader().knn(query, *k).unwrap(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn arena_refuses_what_doesnt_fit() {
+        let data = random_coordinates(600, 54);
+        let build = |nodes| {
+            CoverTreeBuilder::with_arena(nodes)
+                .set_verbosity(0)
+                .set_deterministic(true)
+                .build(cloud_of(&data, 3))
         };
-        let tree = builder.build(point_cloud).unwrap();
-        let reader = tree.reader();
+        let nodes = CoverTreeBuilder::new()
+            .set_verbosity(0)
+            .set_deterministic(true)
+            .build(cloud_of(&data, 3))
+            .unwrap()
+            .node_count();
+        match build(nodes - 1) {
+            Err(MalwareBrotError::ArenaCapacityExceeded { capacity, needed }) => {
+                assert_eq!((capacity, needed), (nodes - 1, nodes))
+            }
+            _ => panic!("The tree was built past its arena"),
+        }
 
-        println!("2 nearest neighbors of 0.1 are 0.48 and 0.0");
-        let zero_nbrs = reader.knn(&[0.1],2).unwrap();
-        println!("{:?}", zero_nbrs);
-        assert!(zero_nbrs[0].1 == 4);
-        assert!(zero_nbrs[1].1 == 2);
+        let capacity = nodes + 20;
+        let mut tree = build(capacity).unwrap();
+        assert_eq!(tree.arena_capacity(), Some(capacity));
+        let blank = tree.point_cloud().label_scheme().blank_metadata();
+        let mut coordinates = random_coordinates(3000, 55).into_iter();
+        let mut inserted = 0;
+        loop {
+            let point: Vec<f32> = coordinates.by_ref().take(3).collect();
+            assert_eq!(point.len(), 3, "The arena never filled up");
+            let name = format!("new {}", inserted).into_bytes();
+            let (version, node_count) = (tree.version(), tree.node_count());
+            let expected = tree.reader().knn(&point, 5).unwrap();
+            match tree.insert(name.clone(), &point, blank.clone()) {
+                Ok(_) => inserted += 1,
+                Err(MalwareBrotError::ArenaCapacityExceeded { capacity: c, needed }) => {
+                    assert_eq!(c, capacity);
+                    assert!(needed > capacity);
+                    assert!(tree.version() > version);
+                    assert_eq!(tree.node_count(), node_count);
+                    assert!(tree.point_cloud().get_index(&name).is_none());
+                    let reader = tree.reader();
+                    reader.validate().unwrap();
+                    assert_eq!(reader.knn(&point, 5).unwrap(), expected);
+                    break;
+                }
+                Err(e) => panic!("Unexpected error {}", e),
+            }
+            assert!(tree.node_count() <= capacity);
+        }
+        assert!(inserted > 0);
+
+        let batch: Vec<(String, Vec<f32>)> = random_coordinates(300, 56)
+            .chunks(3)
+            .enumerate()
+            .map(|(i, point)| (format!("batch {}", i), point.to_vec()))
+            .collect();
+        let node_count = tree.node_count();
+        match tree.insert_batch(&batch) {
+            Err(MalwareBrotError::ArenaCapacityExceeded { .. }) => {}
+            _ => panic!("The batch went past the arena"),
+        }
+        assert_eq!(tree.node_count(), node_count);
+        assert!(tree.point_cloud().get_index(b"batch 0").is_none());
+        tree.reader().validate().unwrap();
     }
 }
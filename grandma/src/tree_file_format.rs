@@ -38,6 +38,7 @@ pub struct NodeProto {
     pub outlier_point_indexes: ::std::vec::Vec<u64>,
     pub outlier_summary_json: ::std::string::String,
     pub radius: f32,
+    pub wide_radius: f64,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
@@ -229,6 +230,21 @@ impl NodeProto {
     pub fn set_radius(&mut self, v: f32) {
         self.radius = v;
     }
+
+    // double wide_radius = 10;
+
+
+    pub fn get_wide_radius(&self) -> f64 {
+        self.wide_radius
+    }
+    pub fn clear_wide_radius(&mut self) {
+        self.wide_radius = 0.;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_wide_radius(&mut self, v: f64) {
+        self.wide_radius = v;
+    }
 }
 
 impl ::protobuf::Message for NodeProto {
@@ -287,6 +303,13 @@ impl ::protobuf::Message for NodeProto {
                     let tmp = is.read_float()?;
                     self.radius = tmp;
                 },
+                10 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeFixed64 {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_double()?;
+                    self.wide_radius = tmp;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -326,6 +349,9 @@ impl ::protobuf::Message for NodeProto {
         if self.radius != 0. {
             my_size += 5;
         }
+        if self.wide_radius != 0. {
+            my_size += 9;
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -359,6 +385,9 @@ impl ::protobuf::Message for NodeProto {
         if self.radius != 0. {
             os.write_float(9, self.radius)?;
         }
+        if self.wide_radius != 0. {
+            os.write_double(10, self.wide_radius)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -446,6 +475,11 @@ impl ::protobuf::Message for NodeProto {
                     |m: &NodeProto| { &m.radius },
                     |m: &mut NodeProto| { &mut m.radius },
                 ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
+                    "wide_radius",
+                    |m: &NodeProto| { &m.wide_radius },
+                    |m: &mut NodeProto| { &mut m.wide_radius },
+                ));
                 ::protobuf::reflect::MessageDescriptor::new::<NodeProto>(
                     "NodeProto",
                     fields,
@@ -477,6 +511,7 @@ impl ::protobuf::Clear for NodeProto {
         self.outlier_point_indexes.clear();
         self.outlier_summary_json.clear();
         self.radius = 0.;
+        self.wide_radius = 0.;
         self.unknown_fields.clear();
     }
 }
@@ -715,7 +750,19 @@ pub struct CoreProto {
     pub count: u64,
     pub root_scale: i32,
     pub root_index: u64,
+    pub checksum: u32,
+    pub has_checksum: bool,
     pub layers: ::protobuf::RepeatedField<LayerProto>,
+    pub version: u64,
+    pub payloads: ::protobuf::RepeatedField<PayloadProto>,
+    pub inserted_indexes: ::std::vec::Vec<u64>,
+    pub insertion_sequences: ::std::vec::Vec<u64>,
+    pub next_insertion: u64,
+    pub standard_means: ::std::vec::Vec<f32>,
+    pub standard_scales: ::std::vec::Vec<f32>,
+    pub tombstones: ::std::vec::Vec<u64>,
+    pub scalar_width: u32,
+    pub wide_scale_base: f64,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
@@ -852,6 +899,36 @@ impl CoreProto {
         self.root_index = v;
     }
 
+    // uint32 checksum = 13;
+
+
+    pub fn get_checksum(&self) -> u32 {
+        self.checksum
+    }
+    pub fn clear_checksum(&mut self) {
+        self.checksum = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_checksum(&mut self, v: u32) {
+        self.checksum = v;
+    }
+
+    // bool has_checksum = 14;
+
+
+    pub fn get_has_checksum(&self) -> bool {
+        self.has_checksum
+    }
+    pub fn clear_has_checksum(&mut self) {
+        self.has_checksum = false;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_has_checksum(&mut self, v: bool) {
+        self.has_checksum = v;
+    }
+
     // repeated .CoverTree.LayerProto layers = 11;
 
 
@@ -876,6 +953,216 @@ impl CoreProto {
     pub fn take_layers(&mut self) -> ::protobuf::RepeatedField<LayerProto> {
         ::std::mem::replace(&mut self.layers, ::protobuf::RepeatedField::new())
     }
+
+    // uint64 version = 12;
+
+
+    pub fn get_version(&self) -> u64 {
+        self.version
+    }
+    pub fn clear_version(&mut self) {
+        self.version = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_version(&mut self, v: u64) {
+        self.version = v;
+    }
+
+    // repeated .CoverTree.PayloadProto payloads = 15;
+
+
+    pub fn get_payloads(&self) -> &[PayloadProto] {
+        &self.payloads
+    }
+    pub fn clear_payloads(&mut self) {
+        self.payloads.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_payloads(&mut self, v: ::protobuf::RepeatedField<PayloadProto>) {
+        self.payloads = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_payloads(&mut self) -> &mut ::protobuf::RepeatedField<PayloadProto> {
+        &mut self.payloads
+    }
+
+    // Take field
+    pub fn take_payloads(&mut self) -> ::protobuf::RepeatedField<PayloadProto> {
+        ::std::mem::replace(&mut self.payloads, ::protobuf::RepeatedField::new())
+    }
+
+    // repeated uint64 inserted_indexes = 16;
+
+
+    pub fn get_inserted_indexes(&self) -> &[u64] {
+        &self.inserted_indexes
+    }
+    pub fn clear_inserted_indexes(&mut self) {
+        self.inserted_indexes.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_inserted_indexes(&mut self, v: ::std::vec::Vec<u64>) {
+        self.inserted_indexes = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_inserted_indexes(&mut self) -> &mut ::std::vec::Vec<u64> {
+        &mut self.inserted_indexes
+    }
+
+    // Take field
+    pub fn take_inserted_indexes(&mut self) -> ::std::vec::Vec<u64> {
+        ::std::mem::replace(&mut self.inserted_indexes, ::std::vec::Vec::new())
+    }
+
+    // repeated uint64 insertion_sequences = 17;
+
+
+    pub fn get_insertion_sequences(&self) -> &[u64] {
+        &self.insertion_sequences
+    }
+    pub fn clear_insertion_sequences(&mut self) {
+        self.insertion_sequences.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_insertion_sequences(&mut self, v: ::std::vec::Vec<u64>) {
+        self.insertion_sequences = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_insertion_sequences(&mut self) -> &mut ::std::vec::Vec<u64> {
+        &mut self.insertion_sequences
+    }
+
+    // Take field
+    pub fn take_insertion_sequences(&mut self) -> ::std::vec::Vec<u64> {
+        ::std::mem::replace(&mut self.insertion_sequences, ::std::vec::Vec::new())
+    }
+
+    // uint64 next_insertion = 18;
+
+
+    pub fn get_next_insertion(&self) -> u64 {
+        self.next_insertion
+    }
+    pub fn clear_next_insertion(&mut self) {
+        self.next_insertion = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_next_insertion(&mut self, v: u64) {
+        self.next_insertion = v;
+    }
+
+    // repeated float standard_means = 19;
+
+
+    pub fn get_standard_means(&self) -> &[f32] {
+        &self.standard_means
+    }
+    pub fn clear_standard_means(&mut self) {
+        self.standard_means.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_standard_means(&mut self, v: ::std::vec::Vec<f32>) {
+        self.standard_means = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_standard_means(&mut self) -> &mut ::std::vec::Vec<f32> {
+        &mut self.standard_means
+    }
+
+    // Take field
+    pub fn take_standard_means(&mut self) -> ::std::vec::Vec<f32> {
+        ::std::mem::replace(&mut self.standard_means, ::std::vec::Vec::new())
+    }
+
+    // repeated float standard_scales = 20;
+
+
+    pub fn get_standard_scales(&self) -> &[f32] {
+        &self.standard_scales
+    }
+    pub fn clear_standard_scales(&mut self) {
+        self.standard_scales.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_standard_scales(&mut self, v: ::std::vec::Vec<f32>) {
+        self.standard_scales = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_standard_scales(&mut self) -> &mut ::std::vec::Vec<f32> {
+        &mut self.standard_scales
+    }
+
+    // Take field
+    pub fn take_standard_scales(&mut self) -> ::std::vec::Vec<f32> {
+        ::std::mem::replace(&mut self.standard_scales, ::std::vec::Vec::new())
+    }
+
+    // repeated uint64 tombstones = 21;
+
+
+    pub fn get_tombstones(&self) -> &[u64] {
+        &self.tombstones
+    }
+    pub fn clear_tombstones(&mut self) {
+        self.tombstones.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_tombstones(&mut self, v: ::std::vec::Vec<u64>) {
+        self.tombstones = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_tombstones(&mut self) -> &mut ::std::vec::Vec<u64> {
+        &mut self.tombstones
+    }
+
+    // Take field
+    pub fn take_tombstones(&mut self) -> ::std::vec::Vec<u64> {
+        ::std::mem::replace(&mut self.tombstones, ::std::vec::Vec::new())
+    }
+
+    // uint32 scalar_width = 22;
+
+
+    pub fn get_scalar_width(&self) -> u32 {
+        self.scalar_width
+    }
+    pub fn clear_scalar_width(&mut self) {
+        self.scalar_width = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_scalar_width(&mut self, v: u32) {
+        self.scalar_width = v;
+    }
+
+    // double wide_scale_base = 23;
+
+
+    pub fn get_wide_scale_base(&self) -> f64 {
+        self.wide_scale_base
+    }
+    pub fn clear_wide_scale_base(&mut self) {
+        self.wide_scale_base = 0.;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_wide_scale_base(&mut self, v: f64) {
+        self.wide_scale_base = v;
+    }
 }
 
 impl ::protobuf::Message for CoreProto {
@@ -885,6 +1172,11 @@ impl ::protobuf::Message for CoreProto {
                 return false;
             }
         };
+        for v in &self.payloads {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
         true
     }
 
@@ -948,9 +1240,69 @@ impl ::protobuf::Message for CoreProto {
                     let tmp = is.read_uint64()?;
                     self.root_index = tmp;
                 },
+                13 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_uint32()?;
+                    self.checksum = tmp;
+                },
+                14 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_bool()?;
+                    self.has_checksum = tmp;
+                },
                 11 => {
                     ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.layers)?;
                 },
+                12 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_uint64()?;
+                    self.version = tmp;
+                },
+                15 => {
+                    ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.payloads)?;
+                },
+                16 => {
+                    ::protobuf::rt::read_repeated_uint64_into(wire_type, is, &mut self.inserted_indexes)?;
+                },
+                17 => {
+                    ::protobuf::rt::read_repeated_uint64_into(wire_type, is, &mut self.insertion_sequences)?;
+                },
+                18 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_uint64()?;
+                    self.next_insertion = tmp;
+                },
+                19 => {
+                    ::protobuf::rt::read_repeated_float_into(wire_type, is, &mut self.standard_means)?;
+                },
+                20 => {
+                    ::protobuf::rt::read_repeated_float_into(wire_type, is, &mut self.standard_scales)?;
+                },
+                21 => {
+                    ::protobuf::rt::read_repeated_uint64_into(wire_type, is, &mut self.tombstones)?;
+                },
+                22 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_uint32()?;
+                    self.scalar_width = tmp;
+                },
+                23 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeFixed64 {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_double()?;
+                    self.wide_scale_base = tmp;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -987,10 +1339,43 @@ impl ::protobuf::Message for CoreProto {
         if self.root_index != 0 {
             my_size += ::protobuf::rt::value_size(10, self.root_index, ::protobuf::wire_format::WireTypeVarint);
         }
+        if self.checksum != 0 {
+            my_size += ::protobuf::rt::value_size(13, self.checksum, ::protobuf::wire_format::WireTypeVarint);
+        }
+        if self.has_checksum != false {
+            my_size += 2;
+        }
         for value in &self.layers {
             let len = value.compute_size();
             my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
         };
+        if self.version != 0 {
+            my_size += ::protobuf::rt::value_size(12, self.version, ::protobuf::wire_format::WireTypeVarint);
+        }
+        for value in &self.payloads {
+            let len = value.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        };
+        for value in &self.inserted_indexes {
+            my_size += ::protobuf::rt::value_size(16, *value, ::protobuf::wire_format::WireTypeVarint);
+        };
+        for value in &self.insertion_sequences {
+            my_size += ::protobuf::rt::value_size(17, *value, ::protobuf::wire_format::WireTypeVarint);
+        };
+        if self.next_insertion != 0 {
+            my_size += ::protobuf::rt::value_size(18, self.next_insertion, ::protobuf::wire_format::WireTypeVarint);
+        }
+        my_size += 6 * self.standard_means.len() as u32;
+        my_size += 6 * self.standard_scales.len() as u32;
+        for value in &self.tombstones {
+            my_size += ::protobuf::rt::value_size(21, *value, ::protobuf::wire_format::WireTypeVarint);
+        };
+        if self.scalar_width != 0 {
+            my_size += ::protobuf::rt::value_size(22, self.scalar_width, ::protobuf::wire_format::WireTypeVarint);
+        }
+        if self.wide_scale_base != 0. {
+            my_size += 9;
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -1021,11 +1406,49 @@ impl ::protobuf::Message for CoreProto {
         if self.root_index != 0 {
             os.write_uint64(10, self.root_index)?;
         }
+        if self.checksum != 0 {
+            os.write_uint32(13, self.checksum)?;
+        }
+        if self.has_checksum != false {
+            os.write_bool(14, self.has_checksum)?;
+        }
         for v in &self.layers {
             os.write_tag(11, ::protobuf::wire_format::WireTypeLengthDelimited)?;
             os.write_raw_varint32(v.get_cached_size())?;
             v.write_to_with_cached_sizes(os)?;
         };
+        if self.version != 0 {
+            os.write_uint64(12, self.version)?;
+        }
+        for v in &self.payloads {
+            os.write_tag(15, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        };
+        for v in &self.inserted_indexes {
+            os.write_uint64(16, *v)?;
+        };
+        for v in &self.insertion_sequences {
+            os.write_uint64(17, *v)?;
+        };
+        if self.next_insertion != 0 {
+            os.write_uint64(18, self.next_insertion)?;
+        }
+        for v in &self.standard_means {
+            os.write_float(19, *v)?;
+        };
+        for v in &self.standard_scales {
+            os.write_float(20, *v)?;
+        };
+        for v in &self.tombstones {
+            os.write_uint64(21, *v)?;
+        };
+        if self.scalar_width != 0 {
+            os.write_uint32(22, self.scalar_width)?;
+        }
+        if self.wide_scale_base != 0. {
+            os.write_double(23, self.wide_scale_base)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -1108,11 +1531,71 @@ impl ::protobuf::Message for CoreProto {
                     |m: &CoreProto| { &m.root_index },
                     |m: &mut CoreProto| { &mut m.root_index },
                 ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeUint32>(
+                    "checksum",
+                    |m: &CoreProto| { &m.checksum },
+                    |m: &mut CoreProto| { &mut m.checksum },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeBool>(
+                    "has_checksum",
+                    |m: &CoreProto| { &m.has_checksum },
+                    |m: &mut CoreProto| { &mut m.has_checksum },
+                ));
                 fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<LayerProto>>(
                     "layers",
                     |m: &CoreProto| { &m.layers },
                     |m: &mut CoreProto| { &mut m.layers },
                 ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "version",
+                    |m: &CoreProto| { &m.version },
+                    |m: &mut CoreProto| { &mut m.version },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<PayloadProto>>(
+                    "payloads",
+                    |m: &CoreProto| { &m.payloads },
+                    |m: &mut CoreProto| { &mut m.payloads },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_vec_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "inserted_indexes",
+                    |m: &CoreProto| { &m.inserted_indexes },
+                    |m: &mut CoreProto| { &mut m.inserted_indexes },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_vec_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "insertion_sequences",
+                    |m: &CoreProto| { &m.insertion_sequences },
+                    |m: &mut CoreProto| { &mut m.insertion_sequences },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "next_insertion",
+                    |m: &CoreProto| { &m.next_insertion },
+                    |m: &mut CoreProto| { &mut m.next_insertion },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_vec_accessor::<_, ::protobuf::types::ProtobufTypeFloat>(
+                    "standard_means",
+                    |m: &CoreProto| { &m.standard_means },
+                    |m: &mut CoreProto| { &mut m.standard_means },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_vec_accessor::<_, ::protobuf::types::ProtobufTypeFloat>(
+                    "standard_scales",
+                    |m: &CoreProto| { &m.standard_scales },
+                    |m: &mut CoreProto| { &mut m.standard_scales },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_vec_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "tombstones",
+                    |m: &CoreProto| { &m.tombstones },
+                    |m: &mut CoreProto| { &mut m.tombstones },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeUint32>(
+                    "scalar_width",
+                    |m: &CoreProto| { &m.scalar_width },
+                    |m: &mut CoreProto| { &mut m.scalar_width },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
+                    "wide_scale_base",
+                    |m: &CoreProto| { &m.wide_scale_base },
+                    |m: &mut CoreProto| { &mut m.wide_scale_base },
+                ));
                 ::protobuf::reflect::MessageDescriptor::new::<CoreProto>(
                     "CoreProto",
                     fields,
@@ -1143,7 +1626,19 @@ impl ::protobuf::Clear for CoreProto {
         self.count = 0;
         self.root_scale = 0;
         self.root_index = 0;
+        self.checksum = 0;
+        self.has_checksum = false;
         self.layers.clear();
+        self.version = 0;
+        self.payloads.clear();
+        self.inserted_indexes.clear();
+        self.insertion_sequences.clear();
+        self.next_insertion = 0;
+        self.standard_means.clear();
+        self.standard_scales.clear();
+        self.tombstones.clear();
+        self.scalar_width = 0;
+        self.wide_scale_base = 0.;
         self.unknown_fields.clear();
     }
 }
@@ -1160,8 +1655,1066 @@ impl ::protobuf::reflect::ProtobufValue for CoreProto {
     }
 }
 
+#[derive(PartialEq,Clone,Default)]
+pub struct DeltaProto {
+    // message fields
+    pub base_version: u64,
+    pub version: u64,
+    pub root_scale: i32,
+    pub root_index: u64,
+    pub layers: ::protobuf::RepeatedField<LayerProto>,
+    pub removed_scale_indexes: ::std::vec::Vec<i32>,
+    pub removed_point_indexes: ::std::vec::Vec<u64>,
+    pub tombstones: ::std::vec::Vec<u64>,
+    // special fields
+    pub unknown_fields: ::protobuf::UnknownFields,
+    pub cached_size: ::protobuf::CachedSize,
+}
+
+impl<'a> ::std::default::Default for &'a DeltaProto {
+    fn default() -> &'a DeltaProto {
+        <DeltaProto as ::protobuf::Message>::default_instance()
+    }
+}
+
+impl DeltaProto {
+    pub fn new() -> DeltaProto {
+        ::std::default::Default::default()
+    }
+
+    // uint64 base_version = 1;
+
+
+    pub fn get_base_version(&self) -> u64 {
+        self.base_version
+    }
+    pub fn clear_base_version(&mut self) {
+        self.base_version = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_base_version(&mut self, v: u64) {
+        self.base_version = v;
+    }
+
+    // uint64 version = 2;
+
+
+    pub fn get_version(&self) -> u64 {
+        self.version
+    }
+    pub fn clear_version(&mut self) {
+        self.version = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_version(&mut self, v: u64) {
+        self.version = v;
+    }
+
+    // int32 root_scale = 3;
+
+
+    pub fn get_root_scale(&self) -> i32 {
+        self.root_scale
+    }
+    pub fn clear_root_scale(&mut self) {
+        self.root_scale = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_root_scale(&mut self, v: i32) {
+        self.root_scale = v;
+    }
+
+    // uint64 root_index = 4;
+
+
+    pub fn get_root_index(&self) -> u64 {
+        self.root_index
+    }
+    pub fn clear_root_index(&mut self) {
+        self.root_index = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_root_index(&mut self, v: u64) {
+        self.root_index = v;
+    }
+
+    // repeated .CoverTree.LayerProto layers = 5;
+
+
+    pub fn get_layers(&self) -> &[LayerProto] {
+        &self.layers
+    }
+    pub fn clear_layers(&mut self) {
+        self.layers.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_layers(&mut self, v: ::protobuf::RepeatedField<LayerProto>) {
+        self.layers = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_layers(&mut self) -> &mut ::protobuf::RepeatedField<LayerProto> {
+        &mut self.layers
+    }
+
+    // Take field
+    pub fn take_layers(&mut self) -> ::protobuf::RepeatedField<LayerProto> {
+        ::std::mem::replace(&mut self.layers, ::protobuf::RepeatedField::new())
+    }
+
+    // repeated int32 removed_scale_indexes = 6;
+
+
+    pub fn get_removed_scale_indexes(&self) -> &[i32] {
+        &self.removed_scale_indexes
+    }
+    pub fn clear_removed_scale_indexes(&mut self) {
+        self.removed_scale_indexes.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_removed_scale_indexes(&mut self, v: ::std::vec::Vec<i32>) {
+        self.removed_scale_indexes = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_removed_scale_indexes(&mut self) -> &mut ::std::vec::Vec<i32> {
+        &mut self.removed_scale_indexes
+    }
+
+    // Take field
+    pub fn take_removed_scale_indexes(&mut self) -> ::std::vec::Vec<i32> {
+        ::std::mem::replace(&mut self.removed_scale_indexes, ::std::vec::Vec::new())
+    }
+
+    // repeated uint64 removed_point_indexes = 7;
+
+
+    pub fn get_removed_point_indexes(&self) -> &[u64] {
+        &self.removed_point_indexes
+    }
+    pub fn clear_removed_point_indexes(&mut self) {
+        self.removed_point_indexes.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_removed_point_indexes(&mut self, v: ::std::vec::Vec<u64>) {
+        self.removed_point_indexes = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_removed_point_indexes(&mut self) -> &mut ::std::vec::Vec<u64> {
+        &mut self.removed_point_indexes
+    }
+
+    // Take field
+    pub fn take_removed_point_indexes(&mut self) -> ::std::vec::Vec<u64> {
+        ::std::mem::replace(&mut self.removed_point_indexes, ::std::vec::Vec::new())
+    }
+
+    // repeated uint64 tombstones = 8;
+
+
+    pub fn get_tombstones(&self) -> &[u64] {
+        &self.tombstones
+    }
+    pub fn clear_tombstones(&mut self) {
+        self.tombstones.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_tombstones(&mut self, v: ::std::vec::Vec<u64>) {
+        self.tombstones = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_tombstones(&mut self) -> &mut ::std::vec::Vec<u64> {
+        &mut self.tombstones
+    }
+
+    // Take field
+    pub fn take_tombstones(&mut self) -> ::std::vec::Vec<u64> {
+        ::std::mem::replace(&mut self.tombstones, ::std::vec::Vec::new())
+    }
+}
+
+impl ::protobuf::Message for DeltaProto {
+    fn is_initialized(&self) -> bool {
+        for v in &self.layers {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_uint64()?;
+                    self.base_version = tmp;
+                },
+                2 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_uint64()?;
+                    self.version = tmp;
+                },
+                3 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_int32()?;
+                    self.root_scale = tmp;
+                },
+                4 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_uint64()?;
+                    self.root_index = tmp;
+                },
+                5 => {
+                    ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.layers)?;
+                },
+                6 => {
+                    ::protobuf::rt::read_repeated_int32_into(wire_type, is, &mut self.removed_scale_indexes)?;
+                },
+                7 => {
+                    ::protobuf::rt::read_repeated_uint64_into(wire_type, is, &mut self.removed_point_indexes)?;
+                },
+                8 => {
+                    ::protobuf::rt::read_repeated_uint64_into(wire_type, is, &mut self.tombstones)?;
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        if self.base_version != 0 {
+            my_size += ::protobuf::rt::value_size(1, self.base_version, ::protobuf::wire_format::WireTypeVarint);
+        }
+        if self.version != 0 {
+            my_size += ::protobuf::rt::value_size(2, self.version, ::protobuf::wire_format::WireTypeVarint);
+        }
+        if self.root_scale != 0 {
+            my_size += ::protobuf::rt::value_size(3, self.root_scale, ::protobuf::wire_format::WireTypeVarint);
+        }
+        if self.root_index != 0 {
+            my_size += ::protobuf::rt::value_size(4, self.root_index, ::protobuf::wire_format::WireTypeVarint);
+        }
+        for value in &self.layers {
+            let len = value.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        };
+        for value in &self.removed_scale_indexes {
+            my_size += ::protobuf::rt::value_size(6, *value, ::protobuf::wire_format::WireTypeVarint);
+        };
+        for value in &self.removed_point_indexes {
+            my_size += ::protobuf::rt::value_size(7, *value, ::protobuf::wire_format::WireTypeVarint);
+        };
+        for value in &self.tombstones {
+            my_size += ::protobuf::rt::value_size(8, *value, ::protobuf::wire_format::WireTypeVarint);
+        };
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        if self.base_version != 0 {
+            os.write_uint64(1, self.base_version)?;
+        }
+        if self.version != 0 {
+            os.write_uint64(2, self.version)?;
+        }
+        if self.root_scale != 0 {
+            os.write_int32(3, self.root_scale)?;
+        }
+        if self.root_index != 0 {
+            os.write_uint64(4, self.root_index)?;
+        }
+        for v in &self.layers {
+            os.write_tag(5, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        };
+        for v in &self.removed_scale_indexes {
+            os.write_int32(6, *v)?;
+        };
+        for v in &self.removed_point_indexes {
+            os.write_uint64(7, *v)?;
+        };
+        for v in &self.tombstones {
+            os.write_uint64(8, *v)?;
+        };
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
+    }
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
+    }
+    fn into_any(self: Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> DeltaProto {
+        DeltaProto::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "base_version",
+                    |m: &DeltaProto| { &m.base_version },
+                    |m: &mut DeltaProto| { &mut m.base_version },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "version",
+                    |m: &DeltaProto| { &m.version },
+                    |m: &mut DeltaProto| { &mut m.version },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeInt32>(
+                    "root_scale",
+                    |m: &DeltaProto| { &m.root_scale },
+                    |m: &mut DeltaProto| { &mut m.root_scale },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "root_index",
+                    |m: &DeltaProto| { &m.root_index },
+                    |m: &mut DeltaProto| { &mut m.root_index },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<LayerProto>>(
+                    "layers",
+                    |m: &DeltaProto| { &m.layers },
+                    |m: &mut DeltaProto| { &mut m.layers },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_vec_accessor::<_, ::protobuf::types::ProtobufTypeInt32>(
+                    "removed_scale_indexes",
+                    |m: &DeltaProto| { &m.removed_scale_indexes },
+                    |m: &mut DeltaProto| { &mut m.removed_scale_indexes },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_vec_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "removed_point_indexes",
+                    |m: &DeltaProto| { &m.removed_point_indexes },
+                    |m: &mut DeltaProto| { &mut m.removed_point_indexes },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_vec_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "tombstones",
+                    |m: &DeltaProto| { &m.tombstones },
+                    |m: &mut DeltaProto| { &mut m.tombstones },
+                ));
+                ::protobuf::reflect::MessageDescriptor::new::<DeltaProto>(
+                    "DeltaProto",
+                    fields,
+                    file_descriptor_proto()
+                )
+            })
+        }
+    }
+
+    fn default_instance() -> &'static DeltaProto {
+        static mut instance: ::protobuf::lazy::Lazy<DeltaProto> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const DeltaProto,
+        };
+        unsafe {
+            instance.get(DeltaProto::new)
+        }
+    }
+}
+
+impl ::protobuf::Clear for DeltaProto {
+    fn clear(&mut self) {
+        self.base_version = 0;
+        self.version = 0;
+        self.root_scale = 0;
+        self.root_index = 0;
+        self.layers.clear();
+        self.removed_scale_indexes.clear();
+        self.removed_point_indexes.clear();
+        self.tombstones.clear();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for DeltaProto {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for DeltaProto {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    }
+}
+
+#[derive(PartialEq,Clone,Default)]
+pub struct NameProto {
+    // message fields
+    pub index: u64,
+    pub name: ::std::vec::Vec<u8>,
+    // special fields
+    pub unknown_fields: ::protobuf::UnknownFields,
+    pub cached_size: ::protobuf::CachedSize,
+}
+
+impl<'a> ::std::default::Default for &'a NameProto {
+    fn default() -> &'a NameProto {
+        <NameProto as ::protobuf::Message>::default_instance()
+    }
+}
+
+impl NameProto {
+    pub fn new() -> NameProto {
+        ::std::default::Default::default()
+    }
+
+    // uint64 index = 1;
+
+
+    pub fn get_index(&self) -> u64 {
+        self.index
+    }
+    pub fn clear_index(&mut self) {
+        self.index = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_index(&mut self, v: u64) {
+        self.index = v;
+    }
+
+    // bytes name = 2;
+
+
+    pub fn get_name(&self) -> &[u8] {
+        &self.name
+    }
+    pub fn clear_name(&mut self) {
+        self.name.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_name(&mut self, v: ::std::vec::Vec<u8>) {
+        self.name = v;
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_name(&mut self) -> &mut ::std::vec::Vec<u8> {
+        &mut self.name
+    }
+
+    // Take field
+    pub fn take_name(&mut self) -> ::std::vec::Vec<u8> {
+        ::std::mem::replace(&mut self.name, ::std::vec::Vec::new())
+    }
+}
+
+impl ::protobuf::Message for NameProto {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_uint64()?;
+                    self.index = tmp;
+                },
+                2 => {
+                    ::protobuf::rt::read_singular_proto3_bytes_into(wire_type, is, &mut self.name)?;
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        if self.index != 0 {
+            my_size += ::protobuf::rt::value_size(1, self.index, ::protobuf::wire_format::WireTypeVarint);
+        }
+        if !self.name.is_empty() {
+            my_size += ::protobuf::rt::bytes_size(2, &self.name);
+        }
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        if self.index != 0 {
+            os.write_uint64(1, self.index)?;
+        }
+        if !self.name.is_empty() {
+            os.write_bytes(2, &self.name)?;
+        }
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
+    }
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
+    }
+    fn into_any(self: Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> NameProto {
+        NameProto::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "index",
+                    |m: &NameProto| { &m.index },
+                    |m: &mut NameProto| { &mut m.index },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeBytes>(
+                    "name",
+                    |m: &NameProto| { &m.name },
+                    |m: &mut NameProto| { &mut m.name },
+                ));
+                ::protobuf::reflect::MessageDescriptor::new::<NameProto>(
+                    "NameProto",
+                    fields,
+                    file_descriptor_proto()
+                )
+            })
+        }
+    }
+
+    fn default_instance() -> &'static NameProto {
+        static mut instance: ::protobuf::lazy::Lazy<NameProto> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const NameProto,
+        };
+        unsafe {
+            instance.get(NameProto::new)
+        }
+    }
+}
+
+impl ::protobuf::Clear for NameProto {
+    fn clear(&mut self) {
+        self.index = 0;
+        self.name.clear();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for NameProto {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for NameProto {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    }
+}
+
+#[derive(PartialEq,Clone,Default)]
+pub struct NameMapProto {
+    // message fields
+    pub count: u64,
+    pub names: ::protobuf::RepeatedField<NameProto>,
+    // special fields
+    pub unknown_fields: ::protobuf::UnknownFields,
+    pub cached_size: ::protobuf::CachedSize,
+}
+
+impl<'a> ::std::default::Default for &'a NameMapProto {
+    fn default() -> &'a NameMapProto {
+        <NameMapProto as ::protobuf::Message>::default_instance()
+    }
+}
+
+impl NameMapProto {
+    pub fn new() -> NameMapProto {
+        ::std::default::Default::default()
+    }
+
+    // uint64 count = 1;
+
+
+    pub fn get_count(&self) -> u64 {
+        self.count
+    }
+    pub fn clear_count(&mut self) {
+        self.count = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_count(&mut self, v: u64) {
+        self.count = v;
+    }
+
+    // repeated .CoverTree.NameProto names = 2;
+
+
+    pub fn get_names(&self) -> &[NameProto] {
+        &self.names
+    }
+    pub fn clear_names(&mut self) {
+        self.names.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_names(&mut self, v: ::protobuf::RepeatedField<NameProto>) {
+        self.names = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_names(&mut self) -> &mut ::protobuf::RepeatedField<NameProto> {
+        &mut self.names
+    }
+
+    // Take field
+    pub fn take_names(&mut self) -> ::protobuf::RepeatedField<NameProto> {
+        ::std::mem::replace(&mut self.names, ::protobuf::RepeatedField::new())
+    }
+}
+
+impl ::protobuf::Message for NameMapProto {
+    fn is_initialized(&self) -> bool {
+        for v in &self.names {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_uint64()?;
+                    self.count = tmp;
+                },
+                2 => {
+                    ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.names)?;
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        if self.count != 0 {
+            my_size += ::protobuf::rt::value_size(1, self.count, ::protobuf::wire_format::WireTypeVarint);
+        }
+        for value in &self.names {
+            let len = value.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        };
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        if self.count != 0 {
+            os.write_uint64(1, self.count)?;
+        }
+        for v in &self.names {
+            os.write_tag(2, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        };
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
+    }
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
+    }
+    fn into_any(self: Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> NameMapProto {
+        NameMapProto::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "count",
+                    |m: &NameMapProto| { &m.count },
+                    |m: &mut NameMapProto| { &mut m.count },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<NameProto>>(
+                    "names",
+                    |m: &NameMapProto| { &m.names },
+                    |m: &mut NameMapProto| { &mut m.names },
+                ));
+                ::protobuf::reflect::MessageDescriptor::new::<NameMapProto>(
+                    "NameMapProto",
+                    fields,
+                    file_descriptor_proto()
+                )
+            })
+        }
+    }
+
+    fn default_instance() -> &'static NameMapProto {
+        static mut instance: ::protobuf::lazy::Lazy<NameMapProto> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const NameMapProto,
+        };
+        unsafe {
+            instance.get(NameMapProto::new)
+        }
+    }
+}
+
+impl ::protobuf::Clear for NameMapProto {
+    fn clear(&mut self) {
+        self.count = 0;
+        self.names.clear();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for NameMapProto {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for NameMapProto {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    }
+}
+
+#[derive(PartialEq,Clone,Default)]
+pub struct PayloadProto {
+    // message fields
+    pub index: u64,
+    pub payload: ::std::vec::Vec<u8>,
+    // special fields
+    pub unknown_fields: ::protobuf::UnknownFields,
+    pub cached_size: ::protobuf::CachedSize,
+}
+
+impl<'a> ::std::default::Default for &'a PayloadProto {
+    fn default() -> &'a PayloadProto {
+        <PayloadProto as ::protobuf::Message>::default_instance()
+    }
+}
+
+impl PayloadProto {
+    pub fn new() -> PayloadProto {
+        ::std::default::Default::default()
+    }
+
+    // uint64 index = 1;
+
+
+    pub fn get_index(&self) -> u64 {
+        self.index
+    }
+    pub fn clear_index(&mut self) {
+        self.index = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_index(&mut self, v: u64) {
+        self.index = v;
+    }
+
+    // bytes payload = 2;
+
+
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+    pub fn clear_payload(&mut self) {
+        self.payload.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_payload(&mut self, v: ::std::vec::Vec<u8>) {
+        self.payload = v;
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_payload(&mut self) -> &mut ::std::vec::Vec<u8> {
+        &mut self.payload
+    }
+
+    // Take field
+    pub fn take_payload(&mut self) -> ::std::vec::Vec<u8> {
+        ::std::mem::replace(&mut self.payload, ::std::vec::Vec::new())
+    }
+}
+
+impl ::protobuf::Message for PayloadProto {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_uint64()?;
+                    self.index = tmp;
+                },
+                2 => {
+                    ::protobuf::rt::read_singular_proto3_bytes_into(wire_type, is, &mut self.payload)?;
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        if self.index != 0 {
+            my_size += ::protobuf::rt::value_size(1, self.index, ::protobuf::wire_format::WireTypeVarint);
+        }
+        if !self.payload.is_empty() {
+            my_size += ::protobuf::rt::bytes_size(2, &self.payload);
+        }
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        if self.index != 0 {
+            os.write_uint64(1, self.index)?;
+        }
+        if !self.payload.is_empty() {
+            os.write_bytes(2, &self.payload)?;
+        }
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
+    }
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
+    }
+    fn into_any(self: Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> PayloadProto {
+        PayloadProto::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "index",
+                    |m: &PayloadProto| { &m.index },
+                    |m: &mut PayloadProto| { &mut m.index },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeBytes>(
+                    "payload",
+                    |m: &PayloadProto| { &m.payload },
+                    |m: &mut PayloadProto| { &mut m.payload },
+                ));
+                ::protobuf::reflect::MessageDescriptor::new::<PayloadProto>(
+                    "PayloadProto",
+                    fields,
+                    file_descriptor_proto()
+                )
+            })
+        }
+    }
+
+    fn default_instance() -> &'static PayloadProto {
+        static mut instance: ::protobuf::lazy::Lazy<PayloadProto> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const PayloadProto,
+        };
+        unsafe {
+            instance.get(PayloadProto::new)
+        }
+    }
+}
+
+impl ::protobuf::Clear for PayloadProto {
+    fn clear(&mut self) {
+        self.index = 0;
+        self.payload.clear();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for PayloadProto {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for PayloadProto {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    }
+}
+
 static file_descriptor_proto_data: &'static [u8] = b"\
-    \n\x16tree_file_format.proto\x12\tCoverTree\"\x80\x03\n\tNodeProto\x12\
+    \n\x16tree_file_format.proto\x12\tCoverTree\"\xa1\x03\n\tNodeProto\x12\
     \x1f\n\x0bcover_count\x18\x01\x20\x01(\x04R\ncoverCount\x12!\n\x0ccenter\
     _index\x18\x02\x20\x01(\x04R\x0bcenterIndex\x12\x17\n\x07is_leaf\x18\x03\
     \x20\x01(\x08R\x06isLeaf\x12,\n\x12nested_scale_index\x18\x04\x20\x01(\
@@ -1170,16 +2723,41 @@ static file_descriptor_proto_data: &'static [u8] = b"\
     \x06\x20\x03(\x05R\x14childrenScaleIndexes\x122\n\x15outlier_point_index\
     es\x18\x07\x20\x03(\x04R\x13outlierPointIndexes\x120\n\x14outlier_summar\
     y_json\x18\x08\x20\x01(\tR\x12outlierSummaryJson\x12\x16\n\x06radius\x18\
-    \t\x20\x01(\x02R\x06radius\"Y\n\nLayerProto\x12\x1f\n\x0bscale_index\x18\
-    \x01\x20\x01(\x05R\nscaleIndex\x12*\n\x05nodes\x18\x02\x20\x03(\x0b2\x14\
-    .CoverTree.NodeProtoR\x05nodes\"\x9e\x02\n\tCoreProto\x12%\n\x0euse_sing\
-    letons\x18\x01\x20\x01(\x08R\ruseSingletons\x12\x1d\n\nscale_base\x18\
-    \x02\x20\x01(\x02R\tscaleBase\x12\x16\n\x06cutoff\x18\x03\x20\x01(\x04R\
-    \x06cutoff\x12\x1e\n\nresolution\x18\x04\x20\x01(\x11R\nresolution\x12\
-    \x10\n\x03dim\x18\x07\x20\x01(\x04R\x03dim\x12\x14\n\x05count\x18\x08\
-    \x20\x01(\x04R\x05count\x12\x1d\n\nroot_scale\x18\t\x20\x01(\x05R\trootS\
-    cale\x12\x1d\n\nroot_index\x18\n\x20\x01(\x04R\trootIndex\x12-\n\x06laye\
-    rs\x18\x0b\x20\x03(\x0b2\x15.CoverTree.LayerProtoR\x06layersb\x06proto3\
+    \t\x20\x01(\x02R\x06radius\x12\x1f\n\x0bwide_radius\x18\n\x20\x01(\x01R\
+    \nwideRadius\"Y\n\nLayerProto\x12\x1f\n\x0bscale_index\x18\x01\x20\x01(\
+    \x05R\nscaleIndex\x12*\n\x05nodes\x18\x02\x20\x03(\x0b2\x14.CoverTree.No\
+    deProtoR\x05nodes\"\xea\x05\n\tCoreProto\x12%\n\x0euse_singletons\x18\
+    \x01\x20\x01(\x08R\ruseSingletons\x12\x1d\n\nscale_base\x18\x02\x20\x01(\
+    \x02R\tscaleBase\x12\x16\n\x06cutoff\x18\x03\x20\x01(\x04R\x06cutoff\x12\
+    \x1e\n\nresolution\x18\x04\x20\x01(\x11R\nresolution\x12\x10\n\x03dim\
+    \x18\x07\x20\x01(\x04R\x03dim\x12\x14\n\x05count\x18\x08\x20\x01(\x04R\
+    \x05count\x12\x1d\n\nroot_scale\x18\t\x20\x01(\x05R\trootScale\x12\x1d\n\
+    \nroot_index\x18\n\x20\x01(\x04R\trootIndex\x12\x1a\n\x08checksum\x18\r\
+    \x20\x01(\rR\x08checksum\x12!\n\x0chas_checksum\x18\x0e\x20\x01(\x08R\
+    \x0bhasChecksum\x12-\n\x06layers\x18\x0b\x20\x03(\x0b2\x15.CoverTree.Lay\
+    erProtoR\x06layers\x12\x18\n\x07version\x18\x0c\x20\x01(\x04R\x07version\
+    \x123\n\x08payloads\x18\x0f\x20\x03(\x0b2\x17.CoverTree.PayloadProtoR\
+    \x08payloads\x12)\n\x10inserted_indexes\x18\x10\x20\x03(\x04R\x0finserte\
+    dIndexes\x12/\n\x13insertion_sequences\x18\x11\x20\x03(\x04R\x12insertio\
+    nSequences\x12%\n\x0enext_insertion\x18\x12\x20\x01(\x04R\rnextInsertion\
+    \x12%\n\x0estandard_means\x18\x13\x20\x03(\x02R\rstandardMeans\x12\'\n\
+    \x0fstandard_scales\x18\x14\x20\x03(\x02R\x0estandardScales\x12\x1e\n\nt\
+    ombstones\x18\x15\x20\x03(\x04R\ntombstones\x12!\n\x0cscalar_width\x18\
+    \x16\x20\x01(\rR\x0bscalarWidth\x12&\n\x0fwide_scale_base\x18\x17\x20\
+    \x01(\x01R\rwideScaleBase\"\xbe\x02\n\nDeltaProto\x12!\n\x0cbase_version\
+    \x18\x01\x20\x01(\x04R\x0bbaseVersion\x12\x18\n\x07version\x18\x02\x20\
+    \x01(\x04R\x07version\x12\x1d\n\nroot_scale\x18\x03\x20\x01(\x05R\trootS\
+    cale\x12\x1d\n\nroot_index\x18\x04\x20\x01(\x04R\trootIndex\x12-\n\x06la\
+    yers\x18\x05\x20\x03(\x0b2\x15.CoverTree.LayerProtoR\x06layers\x122\n\
+    \x15removed_scale_indexes\x18\x06\x20\x03(\x05R\x13removedScaleIndexes\
+    \x122\n\x15removed_point_indexes\x18\x07\x20\x03(\x04R\x13removedPointIn\
+    dexes\x12\x1e\n\ntombstones\x18\x08\x20\x03(\x04R\ntombstones\"5\n\tName\
+    Proto\x12\x14\n\x05index\x18\x01\x20\x01(\x04R\x05index\x12\x12\n\x04nam\
+    e\x18\x02\x20\x01(\x0cR\x04name\"P\n\x0cNameMapProto\x12\x14\n\x05count\
+    \x18\x01\x20\x01(\x04R\x05count\x12*\n\x05names\x18\x02\x20\x03(\x0b2\
+    \x14.CoverTree.NameProtoR\x05names\">\n\x0cPayloadProto\x12\x14\n\x05ind\
+    ex\x18\x01\x20\x01(\x04R\x05index\x12\x18\n\x07payload\x18\x02\x20\x01(\
+    \x0cR\x07payloadb\x06proto3\
 ";
 
 static mut file_descriptor_proto_lazy: ::protobuf::lazy::Lazy<::protobuf::descriptor::FileDescriptorProto> = ::protobuf::lazy::Lazy {
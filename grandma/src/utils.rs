@@ -22,15 +22,19 @@
 use crate::errors::MalwareBrotResult;
 use crate::tree_file_format::*;
 use pointcloud::*;
+use pointcloud::errors::{ParsingError as PointCloudParsingError, PointCloudError};
+use num_traits::float::TotalOrder;
 use protobuf::{CodedInputStream, CodedOutputStream, Message};
+use std::borrow::Cow;
 use std::fs::File;
 use std::fs::{remove_file, OpenOptions};
+use std::io;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use yaml_rust::{Yaml, YamlLoader};
 
 use crate::builders::CoverTreeBuilder;
-use crate::errors::MalwareBrotError;
+use crate::errors::{MalwareBrotError, ParsingError};
 use crate::tree::CoverTreeWriter;
 
 /// Given a yaml file on disk, it builds a covertree.
@@ -107,12 +111,160 @@ pub fn cover_tree_from_yaml<P: AsRef<Path>>(path: P) -> MalwareBrotResult<CoverT
     Ok(builder.build(point_cloud)?)
 }
 
+/// Builds a tree over the points in a CSV, laid out as the options describe, see `PointCloud::from_csv`. The rest of the
+/// parameters are the defaults of `CoverTreeBuilder::new()`, pass a builder to `build` yourself if you need others.
+pub fn cover_tree_from_csv<P: AsRef<Path>, M: Metric>(
+    path: P,
+    options: &CsvOptions,
+    scale_base: f64,
+) -> MalwareBrotResult<CoverTreeWriter<M>> {
+    let point_cloud = PointCloud::<M>::from_csv(path, options)?;
+    let mut builder = CoverTreeBuilder::new();
+    builder.set_scale_base(scale_base);
+    Ok(builder.build(point_cloud)?)
+}
+
+/// Builds a tree over the points in a CSV, laid out as the options describe, without reading the whole file into ram.
+/// The tree is built over the first `chunk_rows` points, and the rest are inserted `chunk_rows` at a time, see
+/// `CoverTreeWriter::extend_from_csv`. So at most `chunk_rows` rows of the file are held at once, but the point cloud
+/// ends up in ram all the same, at 4 bytes a coordinate for `f32`. Building in one go with `cover_tree_from_csv` is
+/// faster for a file that fits, as each chunk rebuilds the subtrees it lands in. The rest of the parameters are the
+/// defaults of `CoverTreeBuilder::new()`.
+///
+/// Returns an error if there's no tree, because the first chunk didn't parse or there are no rows. Otherwise the tree
+/// is returned with the error that stopped the stream, if one did. That's a `PartialInsert`, usually of a `CSVReadError`
+/// with the line number of the row that didn't parse, and the tree has the points of every row before it.
+pub fn cover_tree_from_csv_streaming<P: AsRef<Path>, M: Metric>(
+    path: P,
+    options: &CsvOptions,
+    scale_base: f64,
+    chunk_rows: usize,
+) -> MalwareBrotResult<(CoverTreeWriter<M>, Option<MalwareBrotError>)> {
+    let mut chunks = CsvChunks::<M::Scalar>::open(path, options, chunk_rows)?;
+    let first = match chunks.next() {
+        Some(first) => first?,
+        None => {
+            return Err(MalwareBrotError::ParsingError(ParsingError::RegularParsingError(
+                "The CSV has no points",
+            )))
+        }
+    };
+    let inserted = first.len();
+    let mut data = Vec::with_capacity(inserted * options.feature_columns.len());
+    let mut labels = labels::LabelScheme::new().empty();
+    for (name, point) in first {
+        data.extend_from_slice(&point);
+        labels.push(Some(name), labels::values::Metadata::new())?;
+    }
+    let point_cloud = PointCloud::<M>::from_ram(Box::from(data), options.feature_columns.len(), labels)?;
+    let mut builder = CoverTreeBuilder::new();
+    builder.set_scale_base(scale_base);
+    let mut tree = builder.build(point_cloud)?;
+    let stopped = tree.insert_chunks(chunks, inserted).err();
+    Ok((tree, stopped))
+}
+
+/// Builds the same tree whatever order the points of the cloud are in, so that runs are reproducible and the tie
+/// breaks of the queries don't change when the input is shuffled. The points are copied into ram sorted by their
+/// vectors, comparing coordinate by coordinate from the first, with the name as the tie break for equal vectors. Each
+/// point's index is its position in that order, and the centers are picked in it, see
+/// `CoverTreeBuilder::set_deterministic`. Coordinates compare with the scalar's `total_cmp`, so NaNs sort after the numbers and
+/// -0 before 0, and every ordering of the same points sorts the same.
+///
+/// The names and metadata go with their points. The encoding of a tree only has indexes, so the trees over two
+/// orderings of the same vectors encode to the same bytes, see `CoverTreeWriter::to_bytes`. The rest of the parameters
+/// are the defaults of `CoverTreeBuilder::new()`.
+pub fn build_deterministic<M: Metric>(
+    point_cloud: PointCloud<M>,
+    scale_base: f64,
+) -> MalwareBrotResult<CoverTreeWriter<M>> {
+    let mut points = Vec::with_capacity(point_cloud.len());
+    for pi in point_cloud.reference_indexes() {
+        let name = point_cloud
+            .get_name(&pi)
+            .map(|name| name.to_vec())
+            .unwrap_or_else(|| format!("{}", pi).into_bytes());
+        points.push((point_cloud.get_point(pi)?, name, pi));
+    }
+    points.sort_by(|(a, a_name, _), (b, b_name, _)| {
+        a.iter()
+            .zip(b.iter())
+            .map(|(x, y)| x.total_cmp(y))
+            .find(|ordering| *ordering != std::cmp::Ordering::Equal)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a_name.cmp(b_name))
+    });
+
+    let mut data = Vec::with_capacity(points.len() * point_cloud.dim());
+    let mut labels = point_cloud.label_scheme().empty();
+    for (point, name, pi) in points {
+        data.extend_from_slice(&point);
+        labels.push(Some(name), point_cloud.get_metadata(pi)?)?;
+    }
+    let sorted = PointCloud::<M>::from_ram(Box::from(data), point_cloud.dim(), labels)?;
+    let mut builder = CoverTreeBuilder::new();
+    builder.set_scale_base(scale_base).set_deterministic(true);
+    Ok(builder.build(sorted)?)
+}
+
+/// Reads the yaml file describing a point cloud. A file that isn't yaml, or has no document in it, is a
+/// `MalformedYamlError`.
+fn read_cloud_yaml<P: AsRef<Path>>(cloud_path: P) -> MalwareBrotResult<Yaml> {
+    let mut config = String::new();
+    File::open(&cloud_path)
+        .and_then(|mut f| f.read_to_string(&mut config))
+        .map_err(|e| MalwareBrotError::from(e))?;
+    let malformed = || {
+        MalwareBrotError::ParsingError(ParsingError::MalformedYamlError {
+            file_name: cloud_path.as_ref().to_string_lossy().to_string(),
+            field: String::new(),
+        })
+    };
+    let mut params_files = YamlLoader::load_from_str(&config).map_err(|_| malformed())?;
+    if params_files.is_empty() {
+        return Err(malformed());
+    }
+    Ok(params_files.swap_remove(0))
+}
+
+/// The memmapped point cloud the yaml file at `cloud_path` describes. A required field the file doesn't have is a
+/// `MissingYamlError`, and a `data_path` or `labels_path` that isn't a glob is a `MalformedYamlError`, both naming
+/// the file.
+fn read_memmapped_cloud<P: AsRef<Path>, M: Metric>(cloud_path: P) -> MalwareBrotResult<PointCloud<M>> {
+    let file_name = || cloud_path.as_ref().to_string_lossy().to_string();
+    match PointCloud::<M>::from_yaml_memmapped(&read_cloud_yaml(&cloud_path)?) {
+        Ok(point_cloud) => Ok(point_cloud),
+        Err(PointCloudError::ParsingError(PointCloudParsingError::MissingYamlError { field, .. })) => Err(
+            MalwareBrotError::ParsingError(ParsingError::MissingYamlError { file_name: file_name(), field }),
+        ),
+        Err(PointCloudError::ParsingError(PointCloudParsingError::MalformedYamlError { field, .. })) => Err(
+            MalwareBrotError::ParsingError(ParsingError::MalformedYamlError { file_name: file_name(), field }),
+        ),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Builds a tree over a point cloud too big for ram, with `CoverTreeBuilder::build_external`. The `cloud_path` is a yaml
+/// file describing the point cloud, like `load_mmap` takes, and the data is always memmapped. The build's intermediate
+/// files go in a directory made inside `temp_dir`, removed again when this returns. The rest of the parameters are the
+/// defaults of `CoverTreeBuilder::new()`. A `cloud_path` that isn't yaml is a `MalformedYamlError`.
+pub fn build_external<P: AsRef<Path>, Q: AsRef<Path>, M: Metric>(
+    cloud_path: P,
+    scale_base: f64,
+    temp_dir: Q,
+) -> MalwareBrotResult<CoverTreeWriter<M>> {
+    let point_cloud = read_memmapped_cloud::<_, M>(cloud_path)?;
+    let mut builder = CoverTreeBuilder::new();
+    builder.set_scale_base(scale_base);
+    builder.build_external(point_cloud, temp_dir)
+}
+
 /// Helper function for the above
-pub fn read_ct_params_yaml(params: &Yaml) -> (f32, usize, i32, bool) {
+pub fn read_ct_params_yaml(params: &Yaml) -> (f64, usize, i32, bool) {
     (
         params["scale_base"]
             .as_f64()
-            .expect("Unable to read the 'scale_base' during yaml load") as f32,
+            .expect("Unable to read the 'scale_base' during yaml load"),
         params["cutoff"]
             .as_i64()
             .expect("Unable to read the 'cutoff'") as usize,
@@ -125,6 +277,150 @@ pub fn read_ct_params_yaml(params: &Yaml) -> (f32, usize, i32, bool) {
     )
 }
 
+/// The parameters for building a tree, read from a yaml file by `BuildConfig::from_yaml`.
+///
+/// ```yaml
+/// ---
+/// scale_base: 1.3
+/// metric: L2
+/// data_source: mnist.yaml
+/// cutoff: 5 (optional)
+/// resolution: -10 (optional)
+/// use_singletons: True (optional)
+/// verbosity: 2 (optional)
+/// ```
+/// The `data_source` is a yaml file describing the point cloud, in the format `PointCloud::from_file` takes. The
+/// `metric` is the name of one of the metrics in `pointcloud`, `L1`, `L2`, `Linfty`, `Cosine` or `CosineSim`. The
+/// optional fields default to the ones `CoverTreeBuilder::new()` uses.
+#[derive(Debug, Clone)]
+pub struct BuildConfig {
+    /// See `CoverTreeBuilder::scale_base`
+    pub scale_base: f64,
+    /// The name of the metric the tree is built with
+    pub metric: String,
+    /// The yaml file describing the point cloud
+    pub data_source: PathBuf,
+    /// See `CoverTreeBuilder::cutoff`
+    pub cutoff: usize,
+    /// See `CoverTreeBuilder::resolution`
+    pub resolution: i32,
+    /// See `CoverTreeBuilder::use_singletons`
+    pub use_singletons: bool,
+    /// See `CoverTreeBuilder::verbosity`
+    pub verbosity: u32,
+    file_name: String,
+}
+
+const METRIC_NAMES: [&str; 5] = [<L1>::NAME, <L2>::NAME, <Linfty>::NAME, <Cosine>::NAME, <CosineSim>::NAME];
+
+impl BuildConfig {
+    /// Reads a config from a yaml file. A required field that isn't there is a `MissingYamlError` naming it, and a
+    /// field that's the wrong type, or a metric we don't have, is a `MalformedYamlError`.
+    pub fn from_yaml<P: AsRef<Path>>(path: P) -> MalwareBrotResult<BuildConfig> {
+        let file_name = path.as_ref().to_string_lossy().to_string();
+        let mut config = String::new();
+        File::open(&path)
+            .and_then(|mut f| f.read_to_string(&mut config))
+            .map_err(|e| MalwareBrotError::from(e))?;
+        let malformed = |field: &str| {
+            MalwareBrotError::ParsingError(ParsingError::MalformedYamlError {
+                file_name: file_name.clone(),
+                field: field.to_string(),
+            })
+        };
+        let params_files = YamlLoader::load_from_str(&config).map_err(|_| malformed(""))?;
+        let params = match params_files.first() {
+            Some(params) => params,
+            None => return Err(malformed("")),
+        };
+        let field = |name: &str| -> MalwareBrotResult<Option<&Yaml>> {
+            match &params[name] {
+                Yaml::BadValue | Yaml::Null => Ok(None),
+                value => Ok(Some(value)),
+            }
+        };
+        let required = |name: &str| -> MalwareBrotResult<&Yaml> {
+            field(name)?.ok_or_else(|| {
+                MalwareBrotError::ParsingError(ParsingError::MissingYamlError {
+                    file_name: file_name.clone(),
+                    field: name.to_string(),
+                })
+            })
+        };
+
+        let scale_base_yaml = required("scale_base")?;
+        let scale_base = match scale_base_yaml.as_f64().or(scale_base_yaml.as_i64().map(|i| i as f64)) {
+            Some(x) => x,
+            None => return Err(malformed("scale_base")),
+        };
+        let metric = required("metric")?
+            .as_str()
+            .filter(|m| METRIC_NAMES.contains(m))
+            .ok_or_else(|| malformed("metric"))?
+            .to_string();
+        let data_source = PathBuf::from(
+            required("data_source")?
+                .as_str()
+                .ok_or_else(|| malformed("data_source"))?,
+        );
+
+        let defaults = CoverTreeBuilder::new();
+        let cutoff = match field("cutoff")? {
+            Some(x) => x.as_i64().filter(|x| *x >= 0).ok_or_else(|| malformed("cutoff"))? as usize,
+            None => defaults.cutoff,
+        };
+        let resolution = match field("resolution")? {
+            Some(x) => x.as_i64().ok_or_else(|| malformed("resolution"))? as i32,
+            None => defaults.resolution,
+        };
+        let use_singletons = match field("use_singletons")? {
+            Some(x) => x.as_bool().ok_or_else(|| malformed("use_singletons"))?,
+            None => defaults.use_singletons,
+        };
+        let verbosity = match field("verbosity")? {
+            Some(x) => x.as_i64().filter(|x| *x >= 0).ok_or_else(|| malformed("verbosity"))? as u32,
+            None => defaults.verbosity,
+        };
+        Ok(BuildConfig {
+            scale_base,
+            metric,
+            data_source,
+            cutoff,
+            resolution,
+            use_singletons,
+            verbosity,
+            file_name,
+        })
+    }
+
+    /// A builder with these parameters.
+    pub fn builder(&self) -> CoverTreeBuilder {
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_scale_base(self.scale_base)
+            .set_cutoff(self.cutoff)
+            .set_resolution(self.resolution)
+            .set_use_singletons(self.use_singletons)
+            .set_verbosity(self.verbosity);
+        builder
+    }
+}
+
+/// Builds a tree over the config's data source. The metric is picked by the type parameter, so the config's `metric`
+/// has to be `M::NAME`, otherwise this is a `MalformedYamlError` on the `metric` field.
+pub fn cover_tree_from_config<M: Metric>(config: &BuildConfig) -> MalwareBrotResult<CoverTreeWriter<M>> {
+    if M::NAME != config.metric {
+        return Err(MalwareBrotError::ParsingError(
+            ParsingError::MalformedYamlError {
+                file_name: config.file_name.clone(),
+                field: "metric".to_string(),
+            },
+        ));
+    }
+    let point_cloud = PointCloud::<M>::from_file(&config.data_source)?;
+    Ok(config.builder().build(point_cloud)?)
+}
+
 /// Helper function that handles the file I/O and protobuf decoding for you.
 pub fn load_tree<P: AsRef<Path>, M: Metric>(
     tree_path: P,
@@ -141,17 +437,60 @@ pub fn load_tree<P: AsRef<Path>, M: Metric>(
         panic!(tree_path_str.to_string() + &" does not exist\n".to_string());
     }
 
-    let mut cover_proto = CoreProto::new();
+    let cover_proto = read_core_proto(tree_path_ref)?;
+    warn_if_unchecked(&cover_proto);
+    CoverTreeWriter::load(&cover_proto, point_cloud)
+}
 
-    let mut file = match File::open(&tree_path_ref) {
-        Ok(file) => file,
-        Err(e) => panic!("Unable to open file {:#?}", e),
-    };
-    let mut cis = CodedInputStream::new(&mut file);
-    if let Err(e) = cover_proto.merge_from(&mut cis) {
-        panic!("Proto buff was unable to read {:#?}", e)
+/// `CoverTreeWriter::load` can't check a file saved before checksums were added, so say so.
+fn warn_if_unchecked(cover_proto: &CoreProto) {
+    if !cover_proto.get_has_checksum() {
+        println!("Warning: the tree has no checksum, it was saved before they were added. It can't be checked for corruption.");
     }
+}
+
+/// Reads the whole file before decoding it, so every length in the encoding is checked against the bytes that are
+/// actually there. Decoding straight from the file trusts the lengths, and a tampered one can ask for any allocation.
+fn read_core_proto(tree_path: &Path) -> MalwareBrotResult<CoreProto> {
+    let bytes = std::fs::read(tree_path)?;
+    Ok(protobuf::parse_from_bytes(&bytes)?)
+}
 
+/// Loads a tree saved with `save_tree` on top of a memmapped point cloud, for data that doesn't fit in ram. The
+/// `cloud_path` is a yaml file describing the point cloud, in the format `PointCloud::from_yaml` takes. The data is
+/// always memmapped, whatever `in_ram` says, so vectors are only paged in when a distance computation reads them. A
+/// `cloud_path` that isn't yaml, or is missing a field, is a `ParsingError` naming the file and the field.
+///
+/// The data files are checked against the number of points and the dimension the tree was saved with. Files that are
+/// too short, or the wrong dimension, are an `IoError`. This check happens once, when the files are mapped. If a data
+/// file is truncated while the tree is using it, reading the lost pages kills the process with a bus error, and mmap
+/// gives no way to turn that into an error. So don't shrink the files under a live tree.
+///
+/// Queries only read the data, so readers on many threads can share the mapping, see `CoverTreeReaderFactory`.
+pub fn load_mmap<P: AsRef<Path>, Q: AsRef<Path>, M: Metric>(
+    tree_path: P,
+    cloud_path: Q,
+) -> MalwareBrotResult<CoverTreeWriter<M>> {
+    let point_cloud = read_memmapped_cloud::<_, M>(cloud_path)?;
+
+    let tree_path_ref: &Path = tree_path.as_ref();
+    println!("\nLoading tree from : {}", tree_path_ref.to_string_lossy());
+    let cover_proto = read_core_proto(tree_path_ref)?;
+    if point_cloud.dim() != cover_proto.get_dim() as usize
+        || point_cloud.len() < cover_proto.get_count() as usize
+    {
+        return Err(MalwareBrotError::IoError(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "The tree has {} points of dimension {}, but the data files only have {} points of dimension {}",
+                cover_proto.get_count(),
+                cover_proto.get_dim(),
+                point_cloud.len(),
+                point_cloud.dim()
+            ),
+        )));
+    }
+    warn_if_unchecked(&cover_proto);
     CoverTreeWriter::load(&cover_proto, point_cloud)
 }
 
@@ -193,3 +532,748 @@ pub fn save_tree<P: AsRef<Path>, M: Metric>(
     cos.flush().map_err(|e| MalwareBrotError::from(e))?;
     Ok(())
 }
+
+/// Writes the name of every point in the tree's point cloud, and the index it's at, to a file of its own. The nodes
+/// only reference points by index, so these can be swapped out with `load_names` to put a different set of names on
+/// the same points.
+pub fn save_names<P: AsRef<Path>, M: Metric>(
+    names_path: P,
+    cover_tree: &CoverTreeWriter<M>,
+) -> MalwareBrotResult<()> {
+    let reader = cover_tree.reader();
+    let point_cloud = reader.point_cloud();
+    let mut names_proto = NameMapProto::new();
+    names_proto.set_count(point_cloud.len() as u64);
+    for pi in point_cloud.reference_indexes() {
+        if let Some(name) = point_cloud.get_name(&pi) {
+            let mut name_proto = NameProto::new();
+            name_proto.set_index(pi);
+            name_proto.set_name(name.to_vec());
+            names_proto.mut_names().push(name_proto);
+        }
+    }
+
+    let mut names_file = File::create(&names_path).map_err(|e| MalwareBrotError::from(e))?;
+    let mut cos = CodedOutputStream::new(&mut names_file);
+    names_proto
+        .write_to(&mut cos)
+        .map_err(|e| MalwareBrotError::from(e))?;
+    cos.flush().map_err(|e| MalwareBrotError::from(e))?;
+    Ok(())
+}
+
+/// Reads names written by `save_names` onto a point cloud, before a tree is built or loaded on it. The file has to be
+/// for a cloud with the same number of points, and name each of them once, otherwise this is a parsing error and the
+/// cloud keeps its names. A tree holds on to the cloud it's built on, so use `load_tree_names` to rename the points
+/// of a tree that's already built.
+pub fn load_names<P: AsRef<Path>, M: Metric>(
+    names_path: P,
+    point_cloud: &mut PointCloud<M>,
+) -> MalwareBrotResult<()> {
+    point_cloud.set_names(read_names(names_path.as_ref(), point_cloud.len())?)?;
+    Ok(())
+}
+
+/// Reads names written by `save_names` onto a built tree, see `CoverTreeWriter::set_names`. The file has to be for a
+/// cloud with as many points as the tree's, and name each of them once, otherwise this is a parsing error and the
+/// tree keeps its names.
+pub fn load_tree_names<P: AsRef<Path>, M: Metric>(
+    names_path: P,
+    cover_tree: &mut CoverTreeWriter<M>,
+) -> MalwareBrotResult<()> {
+    let names = read_names(names_path.as_ref(), cover_tree.reader().point_cloud().len())?;
+    cover_tree.set_names(names)
+}
+
+/// The names in a file written by `save_names`, which has to be for a cloud of `count` points.
+fn read_names(names_path: &Path, count: usize) -> MalwareBrotResult<Vec<(PointIndex, PointName)>> {
+    let mut names_proto = read_names_proto(names_path)?;
+    if names_proto.get_count() != count as u64 {
+        return Err(MalwareBrotError::ParsingError(ParsingError::RegularParsingError(
+            "The names are for a point cloud of a different size",
+        )));
+    }
+    Ok(names_proto
+        .take_names()
+        .into_iter()
+        .map(|mut n| (n.get_index(), n.take_name()))
+        .collect())
+}
+
+fn read_names_proto(names_path: &Path) -> MalwareBrotResult<NameMapProto> {
+    let mut names_proto = NameMapProto::new();
+    let mut names_file = File::open(&names_path).map_err(|e| MalwareBrotError::from(e))?;
+    let mut cis = CodedInputStream::new(&mut names_file);
+    names_proto
+        .merge_from(&mut cis)
+        .map_err(|e| MalwareBrotError::from(e))?;
+    Ok(names_proto)
+}
+
+/// Loads a tree saved with `save_tree`, and the names saved with `save_names`, without the point cloud's vectors. This
+/// is for looking at the shape of a tree that's too big, or too far away, to load with its data. The point cloud is
+/// made with `PointCloud::without_vectors`, with the dimension the tree was saved with.
+///
+/// What works is everything that only walks the nodes: `stats`, `level_counts`, `node_count`, `node_radii`,
+/// `layers`, `root_address`, `memory_footprint` and `validate_topology`, along with names, `name_of` and the point
+/// cloud's `get_index`. Anything that needs a point's vector, `knn`, `range`, `validate`, inserting and so on,
+/// returns a `PointCloudError` of `VectorsNotLoaded` instead of an answer. A names file for a different number of points
+/// than the tree is a parsing error.
+///
+/// The path is printed as the tree is loaded if `verbosity` is above 1, the level the builder prints its progress at.
+pub fn load_structure_only<P: AsRef<Path>, Q: AsRef<Path>, M: Metric>(
+    tree_path: P,
+    names_path: Q,
+    verbosity: u32,
+) -> MalwareBrotResult<CoverTreeWriter<M>> {
+    let tree_path_ref: &Path = tree_path.as_ref();
+    if verbosity > 1 {
+        println!("\nLoading the structure of the tree from : {}", tree_path_ref.to_string_lossy());
+    }
+    let cover_proto = read_core_proto(tree_path_ref)?;
+    let mut names_proto = read_names_proto(names_path.as_ref())?;
+    if names_proto.get_count() != cover_proto.get_count() {
+        return Err(MalwareBrotError::ParsingError(ParsingError::RegularParsingError(
+            "The names are for a point cloud of a different size",
+        )));
+    }
+    let names = names_proto
+        .take_names()
+        .into_iter()
+        .map(|mut n| (n.get_index(), n.take_name()))
+        .collect();
+    let point_cloud = PointCloud::<M>::without_vectors(cover_proto.get_dim() as usize, names)?;
+    if verbosity > 1 {
+        warn_if_unchecked(&cover_proto);
+    }
+    CoverTreeWriter::load(&cover_proto, point_cloud)
+}
+
+/// Writes the `k` nearest neighbors of every point in the tree to a CSV, one row per neighbor, with the header
+/// `source_name,neighbor_name,rank,distance`. The ranks start at 1 for the nearest neighbor. A point isn't its own
+/// neighbor, but exact duplicates of it under other names are, at distance `0.0`. Names that aren't UTF-8 are written
+/// lossily.
+///
+/// The points are queried in batches with `knn_many`, and each batch is written out before the next is run, so only
+/// one batch of results is held in memory. A failure to write the file is an `IoError`, and what was written before
+/// it is left on disk.
+pub fn export_knn_csv<P: AsRef<Path>, M: Metric>(
+    csv_path: P,
+    cover_tree: &CoverTreeWriter<M>,
+    k: usize,
+) -> MalwareBrotResult<()> {
+    const BATCH_SIZE: usize = 4096;
+    let reader = cover_tree.reader();
+    let mut writer = csv::Writer::from_path(&csv_path).map_err(|e| MalwareBrotError::from(io::Error::from(e)))?;
+    let write_row = |writer: &mut csv::Writer<File>, row: [&str; 4]| -> MalwareBrotResult<()> {
+        writer.write_record(&row).map_err(|e| MalwareBrotError::from(io::Error::from(e)))
+    };
+    write_row(&mut writer, ["source_name", "neighbor_name", "rank", "distance"])?;
+
+    let mut points = reader.iter().peekable();
+    while points.peek().is_some() {
+        let batch: Vec<(&[u8], Cow<[M::Scalar]>)> = points.by_ref().take(BATCH_SIZE).collect();
+        let queries: Vec<Vec<M::Scalar>> = batch.iter().map(|(_name, point)| point.to_vec()).collect();
+        let results = reader.knn_many(&queries, k + 1)?;
+        for ((source, _point), neighbors) in batch.iter().zip(results) {
+            let source_name = String::from_utf8_lossy(source);
+            let neighbors = neighbors.iter().filter(|(_d, name)| name.as_slice() != *source).take(k);
+            for (rank, (distance, name)) in neighbors.enumerate() {
+                write_row(
+                    &mut writer,
+                    [
+                        &source_name,
+                        &String::from_utf8_lossy(name),
+                        &(rank + 1).to_string(),
+                        &distance.to_string(),
+                    ],
+                )?;
+            }
+        }
+    }
+    writer.flush().map_err(|e| MalwareBrotError::from(e))?;
+    Ok(())
+}
+
+/// The sidecar file `save_delta` writes the changes since `since_version` to, `<base_path>.<since_version>.delta`.
+pub fn delta_path<P: AsRef<Path>>(base_path: P, since_version: u64) -> PathBuf {
+    let mut path = base_path.as_ref().as_os_str().to_owned();
+    path.push(format!(".{}.delta", since_version));
+    PathBuf::from(path)
+}
+
+/// Writes the nodes that changed in `cover_tree` since `since_version` to a sidecar next to the base file, see
+/// `delta_path`. Save the base with `save_tree` first, `since_version` is usually the version the base was saved at.
+/// It's a `VersionMismatch` if the tree was built, loaded or merged after `since_version`, see
+/// `CoverTreeWriter::save_delta`, and nothing is written.
+pub fn save_delta<P: AsRef<Path>, M: Metric>(
+    base_path: P,
+    cover_tree: &CoverTreeWriter<M>,
+    since_version: u64,
+) -> MalwareBrotResult<()> {
+    let delta_proto = cover_tree.save_delta(since_version)?;
+    let delta_path = delta_path(&base_path, since_version);
+    println!("Saving delta to : {}", delta_path.to_string_lossy());
+    if delta_path.exists() {
+        println!("\t \t {:?} exists, removing", delta_path);
+        remove_file(&delta_path).map_err(|e| MalwareBrotError::from(e))?;
+    }
+
+    let mut delta_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(&delta_path)
+        .map_err(|e| MalwareBrotError::from(e))?;
+    let mut cos = CodedOutputStream::new(&mut delta_file);
+    delta_proto
+        .write_to(&mut cos)
+        .map_err(|e| MalwareBrotError::from(e))?;
+    cos.flush().map_err(|e| MalwareBrotError::from(e))?;
+    Ok(())
+}
+
+/// Loads the base tree with `load_tree`, then replays the chain of sidecars `save_delta` wrote next to it. Starting
+/// at the base's version, it applies the delta saved since that version, then the one since the new version, and so
+/// on until there isn't one.
+pub fn load_with_deltas<P: AsRef<Path>, M: Metric>(
+    base_path: P,
+    point_cloud: PointCloud<M>,
+) -> MalwareBrotResult<CoverTreeWriter<M>> {
+    let mut cover_tree = load_tree(&base_path, point_cloud)?;
+    loop {
+        let delta_path = delta_path(&base_path, cover_tree.version());
+        if !delta_path.exists() {
+            break;
+        }
+        println!("Applying delta from : {}", delta_path.to_string_lossy());
+        let mut delta_proto = DeltaProto::new();
+        let mut file = File::open(&delta_path).map_err(|e| MalwareBrotError::from(e))?;
+        let mut cis = CodedInputStream::new(&mut file);
+        delta_proto
+            .merge_from(&mut cis)
+            .map_err(|e| MalwareBrotError::from(e))?;
+        if delta_proto.get_version() <= cover_tree.version() {
+            break;
+        }
+        cover_tree.load_delta(&delta_proto)?;
+    }
+    Ok(cover_tree)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use pointcloud::errors::PointCloudError;
+    use std::io::Write;
+
+    fn write_f32s(path: &Path, values: &[f32]) {
+        let mut file = File::create(path).unwrap();
+        for v in values {
+            file.write_all(&v.to_le_bytes()).unwrap();
+        }
+    }
+
+    #[test]
+    fn load_mmap_matches_ram() {
+        let mut data = Vec::with_capacity(400);
+        for _i in 0..400 {
+            data.push(rand::random::<f32>());
+        }
+        let labels = vec![0.0; 200];
+
+        let dir = env::temp_dir().join(format!("grandma_load_mmap_{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let data_path = dir.join("data.dat");
+        let labels_path = dir.join("labels.dat");
+        let cloud_path = dir.join("cloud.yaml");
+        let tree_path = dir.join("tree.tree");
+        write_f32s(&data_path, &data);
+        write_f32s(&labels_path, &labels);
+        fs::write(
+            &cloud_path,
+            format!(
+                "---\ndata_path: {}\nlabels_path: {}\ndata_dim: 2\nlabels_dim: 1\nin_ram: True\n",
+                data_path.to_string_lossy(),
+                labels_path.to_string_lossy()
+            ),
+        )
+        .unwrap();
+
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data.clone()), 2, Box::from(labels), 1).unwrap();
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        save_tree(&tree_path, &tree).unwrap();
+
+        let mmap_tree: CoverTreeWriter<L2> = load_mmap(&tree_path, &cloud_path).unwrap();
+        let reader = tree.reader();
+        let mmap_reader = mmap_tree.reader();
+        for _i in 0..20 {
+            let query = [rand::random::<f32>(), rand::random::<f32>()];
+            assert_eq!(reader.knn(&query, 5).unwrap(), mmap_reader.knn(&query, 5).unwrap());
+        }
+
+        // Lose the last few points
+        write_f32s(&data_path, &data[..390]);
+        write_f32s(&labels_path, &[0.0; 195]);
+        match load_mmap::<_, _, L2>(&tree_path, &cloud_path) {
+            Err(MalwareBrotError::IoError(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            Err(e) => panic!("Expected an IoError, got {:?}", e),
+            Ok(_) => panic!("Expected an IoError, the data file is too short"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bytes_match_the_saved_file() {
+        let data: Vec<f32> = (0..400).map(|_i| rand::random::<f32>()).collect();
+        let labels = vec![0.0; 200];
+        let point_cloud = || {
+            PointCloud::<L2>::simple_from_ram(Box::from(data.clone()), 2, Box::from(labels.clone()), 1).unwrap()
+        };
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud()).unwrap();
+
+        let dir = env::temp_dir().join(format!("grandma_bytes_{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let tree_path = dir.join("tree.tree");
+        save_tree(&tree_path, &tree).unwrap();
+        let bytes = tree.to_bytes().unwrap();
+        assert_eq!(fs::read(&tree_path).unwrap(), bytes);
+
+        let loaded = CoverTreeWriter::from_bytes(&bytes, point_cloud()).unwrap();
+        let reader = tree.reader();
+        let loaded_reader = loaded.reader();
+        for _i in 0..20 {
+            let query = [rand::random::<f32>(), rand::random::<f32>()];
+            assert_eq!(reader.knn(&query, 5).unwrap(), loaded_reader.knn(&query, 5).unwrap());
+        }
+        match CoverTreeWriter::from_bytes(&[0xff, 0xff], point_cloud()) {
+            Err(MalwareBrotError::ParsingError(ParsingError::ProtobufError(_))) => {}
+            _ => panic!("Expected a ProtobufError"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn load_async_matches_load_tree() {
+        let data: Vec<f32> = (0..400).map(|_i| rand::random::<f32>()).collect();
+        let labels = vec![0.0; 200];
+        let point_cloud = || {
+            PointCloud::<L2>::simple_from_ram(Box::from(data.clone()), 2, Box::from(labels.clone()), 1).unwrap()
+        };
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud()).unwrap();
+
+        let dir = env::temp_dir().join(format!("grandma_load_async_{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let tree_path = dir.join("tree.tree");
+        let garbage_path = dir.join("garbage.tree");
+        save_tree(&tree_path, &tree).unwrap();
+        fs::write(&garbage_path, &[0xff, 0xff]).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let loaded = runtime.block_on(CoverTreeWriter::load_async(&tree_path, point_cloud())).unwrap();
+        let reader = tree.reader();
+        let loaded_reader = loaded.reader();
+        for _i in 0..20 {
+            let query = [rand::random::<f32>(), rand::random::<f32>()];
+            assert_eq!(reader.knn(&query, 5).unwrap(), loaded_reader.knn(&query, 5).unwrap());
+        }
+        match runtime.block_on(CoverTreeWriter::load_async(dir.join("missing.tree"), point_cloud())) {
+            Err(MalwareBrotError::IoError(_)) => {}
+            _ => panic!("Expected an IoError"),
+        }
+        match runtime.block_on(CoverTreeWriter::load_async(&garbage_path, point_cloud())) {
+            Err(MalwareBrotError::ParsingError(ParsingError::ProtobufError(_))) => {}
+            _ => panic!("Expected a ProtobufError"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn build_config_names_missing_fields() {
+        let mut data = Vec::with_capacity(200);
+        for _i in 0..200 {
+            data.push(rand::random::<f32>());
+        }
+        let dir = env::temp_dir().join(format!("grandma_build_config_{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let data_path = dir.join("data.dat");
+        let labels_path = dir.join("labels.dat");
+        let cloud_path = dir.join("cloud.yaml");
+        write_f32s(&data_path, &data);
+        write_f32s(&labels_path, &[0.0; 100]);
+        fs::write(
+            &cloud_path,
+            format!(
+                "---\ndata_path: {}\nlabels_path: {}\ndata_dim: 2\nlabels_dim: 1\n",
+                data_path.to_string_lossy(),
+                labels_path.to_string_lossy()
+            ),
+        )
+        .unwrap();
+
+        let config_path = dir.join("config.yaml");
+        fs::write(
+            &config_path,
+            format!(
+                "---\nscale_base: 1.5\nmetric: L2\ndata_source: {}\nverbosity: 0\n",
+                cloud_path.to_string_lossy()
+            ),
+        )
+        .unwrap();
+        let config = BuildConfig::from_yaml(&config_path).unwrap();
+        assert_eq!(config.scale_base, 1.5);
+        assert_eq!(config.cutoff, CoverTreeBuilder::new().cutoff);
+        let tree = cover_tree_from_config::<L2>(&config).unwrap();
+        assert_eq!(tree.reader().scale_base(), 1.5);
+        match cover_tree_from_config::<L1>(&config) {
+            Err(MalwareBrotError::ParsingError(ParsingError::MalformedYamlError { field, .. })) => {
+                assert_eq!(field, "metric")
+            }
+            Err(e) => panic!("Expected a MalformedYamlError, got {:?}", e),
+            Ok(_) => panic!("Expected a MalformedYamlError, the config asks for L2"),
+        }
+
+        fs::write(&config_path, "---\nscale_base: 1.5\nmetric: L2\n").unwrap();
+        match BuildConfig::from_yaml(&config_path) {
+            Err(MalwareBrotError::ParsingError(ParsingError::MissingYamlError { file_name, field })) => {
+                assert_eq!(field, "data_source");
+                assert_eq!(file_name, config_path.to_string_lossy());
+            }
+            Err(e) => panic!("Expected a MissingYamlError, got {:?}", e),
+            Ok(_) => panic!("Expected a MissingYamlError, there's no data_source"),
+        }
+
+        fs::write(&config_path, "---\nscale_base: 1.5\nmetric: L7\ndata_source: here\n").unwrap();
+        match BuildConfig::from_yaml(&config_path) {
+            Err(MalwareBrotError::ParsingError(ParsingError::MalformedYamlError { field, .. })) => {
+                assert_eq!(field, "metric")
+            }
+            Err(e) => panic!("Expected a MalformedYamlError, got {:?}", e),
+            Ok(_) => panic!("Expected a MalformedYamlError, there's no L7 metric"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn csv_options_pick_the_columns() {
+        let dir = env::temp_dir().join(format!("grandma_csv_options_{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let csv_path = dir.join("points.csv");
+        let mut csv = "x;name;y;z\n".to_string();
+        for i in 0..50 {
+            csv.push_str(&format!("{};p{};{};{}\n", i, i, 0.5 * i as f32, i % 7));
+        }
+        fs::write(&csv_path, &csv).unwrap();
+
+        let mut options = CsvOptions::new(2..4);
+        options
+            .set_delimiter(b';')
+            .set_name_column(CsvColumn::Header("name".to_string()));
+        let tree = cover_tree_from_csv::<_, L2>(&csv_path, &options, 1.5).unwrap();
+        let reader = tree.reader();
+        assert_eq!(reader.scale_base(), 1.5);
+        assert_eq!(reader.point_cloud().dim(), 2);
+        let pi = *reader.point_cloud().get_index("p10").unwrap();
+        assert_eq!(reader.point_cloud().get_point(pi).unwrap(), &[5.0, 3.0][..]);
+
+        // The name column is missing on the fourth line
+        csv = "x;name;y;z\n0;p0;0;0\n1;p1;1;1\n2\n".to_string();
+        fs::write(&csv_path, &csv).unwrap();
+        options.feature_columns = 0..1;
+        match cover_tree_from_csv::<_, L2>(&csv_path, &options, 1.5) {
+            Err(MalwareBrotError::PointCloudError(PointCloudError::ParsingError(
+                pointcloud::errors::ParsingError::CSVReadError { line_number, key, .. },
+            ))) => {
+                assert_eq!(line_number, 4);
+                assert_eq!(key, "name");
+            }
+            Err(e) => panic!("Expected a CSVReadError, got {:?}", e),
+            Ok(_) => panic!("Expected a CSVReadError, a row is missing its name"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn csv_streaming_keeps_the_rows_before_a_bad_one() {
+        let dir = env::temp_dir().join(format!("grandma_csv_streaming_{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let csv_path = dir.join("points.csv");
+        let mut csv = "name,x,y\n".to_string();
+        for i in 0..1000 {
+            csv.push_str(&format!("p{},{},{}\n", i, rand::random::<f32>(), rand::random::<f32>()));
+        }
+        fs::write(&csv_path, &csv).unwrap();
+        let mut options = CsvOptions::new(1..3);
+        options.set_name_column(CsvColumn::Index(0));
+
+        let (tree, stopped) = cover_tree_from_csv_streaming::<_, L2>(&csv_path, &options, 1.5, 64).unwrap();
+        assert!(stopped.is_none());
+        let streamed = tree.reader();
+        streamed.validate().unwrap();
+        assert_eq!(streamed.iter().count(), 1000);
+        let built = cover_tree_from_csv::<_, L2>(&csv_path, &options, 1.5).unwrap();
+        let built = built.reader();
+        for name in &["p0", "p63", "p64", "p999"] {
+            let pi = *streamed.point_cloud().get_index(name).unwrap();
+            let point = streamed.point_cloud().get_point(pi).unwrap();
+            assert_eq!(built.point_cloud().get_point(*built.point_cloud().get_index(name).unwrap()).unwrap(), point);
+            assert_eq!(streamed.knn(&point, 1).unwrap(), vec![(0.0, pi)]);
+        }
+
+        // The row of p700 is on line 702, after the header and the 700 rows before it
+        csv = csv.replacen("p700,", "p700,not a number,", 1);
+        fs::write(&csv_path, &csv).unwrap();
+        let (tree, stopped) = cover_tree_from_csv_streaming::<_, L2>(&csv_path, &options, 1.5, 64).unwrap();
+        match stopped {
+            Some(MalwareBrotError::PartialInsert { inserted, error }) => {
+                assert_eq!(inserted, 700);
+                match *error {
+                    MalwareBrotError::PointCloudError(PointCloudError::ParsingError(
+                        pointcloud::errors::ParsingError::CSVReadError { line_number, key, .. },
+                    )) => {
+                        assert_eq!(line_number, 702);
+                        assert_eq!(key, "x");
+                    }
+                    other => panic!("Expected a CSVReadError, got {:?}", other),
+                }
+            }
+            other => panic!("Expected a PartialInsert, got {:?}", other),
+        }
+        let reader = tree.reader();
+        reader.validate().unwrap();
+        assert_eq!(reader.iter().count(), 700);
+        assert!(reader.point_cloud().get_index("p699").is_some());
+        assert!(reader.point_cloud().get_index("p700").is_none());
+
+        options.set_missing_value_policy(MissingValuePolicy::MeanFill);
+        assert!(cover_tree_from_csv_streaming::<_, L2>(&csv_path, &options, 1.5, 64).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn csv_missing_values_follow_the_policy() {
+        let dir = env::temp_dir().join(format!("grandma_csv_missing_{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let csv_path = dir.join("points.csv");
+        fs::write(&csv_path, "x,y\n1,2\n,4\n3, \n5,6\n").unwrap();
+        let mut options = CsvOptions::new(0..2);
+        let points = |options: &CsvOptions| {
+            let point_cloud = PointCloud::<L2>::from_csv(&csv_path, options).unwrap();
+            let mut points: Vec<(PointName, Vec<f32>)> = point_cloud
+                .reference_indexes()
+                .iter()
+                .map(|pi| {
+                    let name = point_cloud.get_name(pi).unwrap().to_vec();
+                    (name, point_cloud.get_point(*pi).unwrap().to_vec())
+                })
+                .collect();
+            points.sort_by(|a, b| a.0.cmp(&b.0));
+            points
+        };
+        let named = |points: &[(&str, [f32; 2])]| {
+            points.iter().map(|(n, p)| (n.as_bytes().to_vec(), p.to_vec())).collect::<Vec<_>>()
+        };
+
+        match PointCloud::<L2>::from_csv(&csv_path, &options) {
+            Err(PointCloudError::ParsingError(PointCloudParsingError::CSVReadError {
+                line_number,
+                key,
+                ..
+            })) => {
+                assert_eq!(line_number, 3);
+                assert_eq!(key, "x");
+            }
+            Err(e) => panic!("Expected a CSVReadError, got {:?}", e),
+            Ok(_) => panic!("Expected a CSVReadError, the third line has an empty cell"),
+        }
+        options.set_missing_value_policy(MissingValuePolicy::ZeroFill);
+        assert_eq!(points(&options), named(&[("0", [1.0, 2.0]), ("1", [0.0, 4.0]), ("2", [3.0, 0.0]), ("3", [5.0, 6.0])]));
+        options.set_missing_value_policy(MissingValuePolicy::MeanFill);
+        assert_eq!(points(&options), named(&[("0", [1.0, 2.0]), ("1", [3.0, 4.0]), ("2", [3.0, 4.0]), ("3", [5.0, 6.0])]));
+        options.set_missing_value_policy(MissingValuePolicy::Skip);
+        assert_eq!(points(&options), named(&[("0", [1.0, 2.0]), ("3", [5.0, 6.0])]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn export_knn_csv_matches_knn_by_name() {
+        let data: Vec<f32> = (0..200).map(|_i| rand::random::<f32>()).collect();
+        let labels = vec![0.0; 100];
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 2, Box::from(labels), 1).unwrap();
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let dir = env::temp_dir().join(format!("grandma_export_knn_{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let csv_path = dir.join("knn.csv");
+        export_knn_csv(&csv_path, &tree, 3).unwrap();
+
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("source_name,neighbor_name,rank,distance"));
+        let rows: Vec<Vec<&str>> = lines.map(|l| l.split(',').collect()).collect();
+        assert_eq!(rows.len(), 300);
+        let reader = tree.reader();
+        for row in rows.iter().step_by(7) {
+            let rank: usize = row[2].parse().unwrap();
+            let (distance, name) = &reader.knn_by_name(row[0], 3).unwrap()[rank - 1];
+            assert_eq!(row[1].as_bytes(), name.as_slice());
+            assert_eq!(row[3].parse::<f32>().unwrap(), *distance);
+        }
+
+        // A directory can't be written to as a file
+        match export_knn_csv(&dir, &tree, 3) {
+            Err(MalwareBrotError::IoError(_)) => {}
+            _ => panic!("Expected an IoError"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn names_round_trip_onto_another_cloud() {
+        let mut data = Vec::with_capacity(200);
+        for _i in 0..200 {
+            data.push(rand::random::<f32>());
+        }
+        let labels = vec![0.0; 100];
+        let dir = env::temp_dir().join(format!("grandma_names_{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let names_path = dir.join("names.map");
+
+        let mut renamed =
+            PointCloud::<L2>::simple_from_ram(Box::from(data.clone()), 2, Box::from(labels.clone()), 1).unwrap();
+        renamed
+            .set_names((0..100).map(|i| (i, format!("point {}", i).into_bytes())).collect())
+            .unwrap();
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(renamed).unwrap();
+        save_names(&names_path, &tree).unwrap();
+
+        let mut point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data.clone()), 2, Box::from(labels), 1).unwrap();
+        load_names(&names_path, &mut point_cloud).unwrap();
+        assert_eq!(point_cloud.get_index("point 7"), Some(&7));
+        let other = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        let query = [0.5, 0.5];
+        assert_eq!(
+            tree.reader().knn_names(&query, 3).unwrap(),
+            other.reader().knn_names(&query, 3).unwrap()
+        );
+
+        // A smaller cloud can't take these names
+        let mut small =
+            PointCloud::<L2>::simple_from_ram(Box::from(&data[..100]), 2, Box::from(vec![0.0; 50]), 1).unwrap();
+        match load_names(&names_path, &mut small) {
+            Err(MalwareBrotError::ParsingError(_)) => {}
+            Err(e) => panic!("Expected a parsing error, got {:?}", e),
+            Ok(_) => panic!("Expected a parsing error, the names are for 100 points"),
+        }
+        assert_eq!(small.get_index("0"), Some(&0));
+
+        // A built tree takes them too, the readers from before keep the old names
+        let mut plain = CoverTreeBuilder::new()
+            .set_verbosity(0)
+            .build(PointCloud::<L2>::simple_from_ram(Box::from(data), 2, Box::from(vec![0.0; 100]), 1).unwrap())
+            .unwrap();
+        let before = plain.reader();
+        load_tree_names(&names_path, &mut plain).unwrap();
+        assert_eq!(plain.reader().knn_names(&query, 3).unwrap(), tree.reader().knn_names(&query, 3).unwrap());
+        assert_eq!(plain.reader().name_of(7).unwrap(), b"point 7".to_vec());
+        assert_eq!(before.point_cloud().get_index("7"), Some(&7));
+        let mut small_tree = CoverTreeBuilder::new().set_verbosity(0).build(small).unwrap();
+        match load_tree_names(&names_path, &mut small_tree) {
+            Err(MalwareBrotError::ParsingError(_)) => {}
+            _ => panic!("Expected a parsing error, the names are for 100 points"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn structure_only_trees_walk_but_dont_measure() {
+        let mut data = Vec::with_capacity(600);
+        for _i in 0..600 {
+            data.push(rand::random::<f32>());
+        }
+        let labels = vec![0.0; 200];
+        let dir = env::temp_dir().join(format!("grandma_structure_{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let tree_path = dir.join("tree.tree");
+        let names_path = dir.join("names.map");
+
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 3, Box::from(labels), 1).unwrap();
+        let tree = CoverTreeBuilder::new().set_verbosity(0).build(point_cloud).unwrap();
+        save_tree(&tree_path, &tree).unwrap();
+        save_names(&names_path, &tree).unwrap();
+
+        let structure: CoverTreeWriter<L2> = load_structure_only(&tree_path, &names_path, 0).unwrap();
+        let reader = tree.reader();
+        let structure_reader = structure.reader();
+        assert_eq!(reader.stats(), structure_reader.stats());
+        assert_eq!(reader.node_radii(), structure_reader.node_radii());
+        assert_eq!(structure_reader.dimension(), Some(3));
+        assert_eq!(structure_reader.name_of(7).unwrap(), reader.name_of(7).unwrap());
+        structure_reader.validate_topology().unwrap();
+
+        match structure_reader.knn(&[0.5, 0.5, 0.5], 3) {
+            Err(MalwareBrotError::PointCloudError(PointCloudError::VectorsNotLoaded { .. })) => {}
+            Err(e) => panic!("Expected the vectors to be missing, got {:?}", e),
+            Ok(_) => panic!("A tree without vectors can't answer a knn query"),
+        }
+        assert!(structure_reader.validate().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn deterministic_build_ignores_the_order_of_the_points() {
+        use rand::seq::SliceRandom;
+        let mut points: Vec<Vec<f32>> = (0..300).map(|_i| vec![rand::random::<f32>(), rand::random::<f32>()]).collect();
+        // A few copies, so that some of the order comes down to the tie break
+        for i in 0..10 {
+            let copy = points[i].clone();
+            points.push(copy);
+        }
+        let build = |points: &[Vec<f32>]| {
+            let data: Vec<f32> = points.iter().flatten().cloned().collect();
+            let labels = vec![0.0; points.len()];
+            let point_cloud = PointCloud::<L2>::simple_from_ram(Box::from(data), 2, Box::from(labels), 1).unwrap();
+            build_deterministic(point_cloud, 1.5).unwrap()
+        };
+
+        let tree = build(&points);
+        let bytes = tree.to_bytes().unwrap();
+        tree.reader().validate().unwrap();
+        assert_eq!(build(&points).to_bytes().unwrap(), bytes);
+        for _i in 0..3 {
+            points.shuffle(&mut rand::thread_rng());
+            assert_eq!(build(&points).to_bytes().unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn build_external_rejects_a_bad_cloud_file() {
+        let path = env::temp_dir().join(format!("grandma_bad_cloud_{}.yaml", rand::random::<u32>()));
+        fs::write(&path, b"data_path: [unclosed").unwrap();
+        match build_external::<_, _, L2>(&path, 1.5, env::temp_dir()) {
+            Err(MalwareBrotError::ParsingError(ParsingError::MalformedYamlError { .. })) => {}
+            _ => panic!("Expected a MalformedYamlError"),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn load_mmap_names_the_missing_field() {
+        let path = env::temp_dir().join(format!("grandma_missing_dim_{}.yaml", rand::random::<u32>()));
+        fs::write(&path, b"data_path: data.dat\nlabels_path: labels.dat\nlabels_dim: 1\n").unwrap();
+        match load_mmap::<_, _, L2>(env::temp_dir().join("no_tree.tree"), &path) {
+            Err(MalwareBrotError::ParsingError(ParsingError::MissingYamlError { file_name, field })) => {
+                assert_eq!(file_name, path.to_string_lossy());
+                assert_eq!(field, "data_dim");
+            }
+            _ => panic!("Expected a MissingYamlError for the data_dim"),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,64 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Times the L2 distance on 128 and 512 dimensional vectors. Run it with and without the `simd` feature to see the
+//! speedup:
+//!
+//! ```text
+//! cargo run --release -p pointcloud --example l2_bench
+//! cargo run --release -p pointcloud --example l2_bench --no-default-features
+//! ```
+
+extern crate pointcloud;
+use pointcloud::{Metric, L2};
+use std::time::Instant;
+
+fn main() {
+    let simd = if cfg!(feature = "simd") { "on" } else { "off" };
+    let mut state: u32 = 12345;
+    let mut random = || {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        (state >> 8) as f32 / (1 << 24) as f32
+    };
+    for dim in &[128, 512] {
+        let count = 1000;
+        let points: Vec<Vec<f32>> = (0..count)
+            .map(|_| (0..*dim).map(|_| random()).collect())
+            .collect();
+        let rounds = 10_000_000 / (count * dim);
+        let start = Instant::now();
+        let mut total = 0.0;
+        for _ in 0..rounds {
+            for pair in points.windows(2) {
+                total += L2::dense(&pair[0], &pair[1]);
+            }
+        }
+        let elapsed = start.elapsed();
+        let calls = rounds * (count - 1);
+        println!(
+            "dim {}, simd {}: {} distances in {:?}, {:.1} ns each (checksum {})",
+            dim,
+            simd,
+            calls,
+            elapsed,
+            elapsed.as_nanos() as f64 / calls as f64,
+            total
+        );
+    }
+}
@@ -0,0 +1,362 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! The layout of a CSV that holds the vectors themselves, see `PointCloud::from_csv`.
+
+use crate::errors::{ParsingError, PointCloudError};
+use crate::labels::LabelScheme;
+use crate::labels::values::Metadata;
+use crate::labels::MetadataList;
+use crate::PointName;
+use crate::distances::Scalar;
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::Read;
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::path::Path;
+
+extern crate csv;
+use self::csv::{Reader, ReaderBuilder, StringRecord};
+
+/// A column of a CSV, by position or by its header.
+#[derive(Debug, Clone)]
+pub enum CsvColumn {
+    /// The position of the column, starting at 0
+    Index(usize),
+    /// The header of the column, this needs a header row
+    Header(String),
+}
+
+/// What to do with an empty feature cell, see `CsvOptions::missing_value_policy`. A missing value has to become some
+/// number for the point to have a distance to anything, and whichever number it becomes moves the point, so the policy
+/// changes the distances and with them the neighbors. `Skip` leaves the data as it is, at the cost of the rows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MissingValuePolicy {
+    /// An empty cell is a `CSVReadError` at its line and column, the default
+    Error,
+    /// An empty cell is read as 0
+    ZeroFill,
+    /// An empty cell is read as the mean of the cells of its column that aren't empty, or 0 if they all are. The
+    /// means are only known once every row is read, so the cells are filled in after the read.
+    MeanFill,
+    /// A row with an empty feature cell is left out. Without a name column the other rows keep the names of their
+    /// rows in the file, so the names skip the left out rows.
+    Skip,
+}
+
+/// How to read points out of a CSV. Every row is a point, the features are the columns in `feature_columns`, parsed as
+/// the scalar type of the metric, `f32`s by default, and the name is the `name_column`. Without a name column points are named by their row, starting at 0.
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    /// The field delimiter, `b','` by default
+    pub delimiter: u8,
+    /// If the first row is a header row, true by default. Columns can only be picked by header if there is one.
+    pub has_headers: bool,
+    /// The column with the names of the points, if there is one
+    pub name_column: Option<CsvColumn>,
+    /// The positions of the feature columns, starting at 0
+    pub feature_columns: Range<usize>,
+    /// What to do with an empty feature cell, `Error` by default. Only cells that are empty, or only whitespace, are
+    /// missing values. A row that ends before the last feature column is still an error.
+    pub missing_value_policy: MissingValuePolicy,
+}
+
+impl CsvOptions {
+    /// Comma delimited with a header row and no name column, the features are the given columns.
+    pub fn new(feature_columns: Range<usize>) -> CsvOptions {
+        CsvOptions {
+            delimiter: b',',
+            has_headers: true,
+            name_column: None,
+            feature_columns,
+            missing_value_policy: MissingValuePolicy::Error,
+        }
+    }
+
+    /// Sets the delimiter
+    pub fn set_delimiter(&mut self, x: u8) -> &mut Self {
+        self.delimiter = x;
+        self
+    }
+
+    /// Sets whether the first row is a header row
+    pub fn set_has_headers(&mut self, x: bool) -> &mut Self {
+        self.has_headers = x;
+        self
+    }
+
+    /// Sets the name column
+    pub fn set_name_column(&mut self, x: CsvColumn) -> &mut Self {
+        self.name_column = Some(x);
+        self
+    }
+
+    /// Sets what to do with empty feature cells
+    pub fn set_missing_value_policy(&mut self, x: MissingValuePolicy) -> &mut Self {
+        self.missing_value_policy = x;
+        self
+    }
+}
+
+/// Reads the vectors and names out of the CSV. Gzipped files are read if they end in `.gz`, like the label CSVs.
+pub(crate) fn read_points<P: AsRef<Path>, T: Scalar>(
+    path: P,
+    options: &CsvOptions,
+) -> Result<(Vec<T>, MetadataList), PointCloudError> {
+    let mut rows = PointRows::open(path.as_ref(), options)?;
+    let mut data = Vec::new();
+    // The positions in `data` of the cells that were empty, they're 0 until they're filled in
+    let mut missing = Vec::new();
+    let mut labels = LabelScheme::new().empty();
+    while let Some(name) = rows.next_point(&mut data, &mut missing)? {
+        labels.push(Some(name), Metadata::new())?;
+    }
+    if options.missing_value_policy == MissingValuePolicy::MeanFill && !missing.is_empty() {
+        fill_means(&mut data, &missing, options.feature_columns.len());
+    }
+    Ok((data, labels))
+}
+
+/// Reads the points of a CSV laid out as `CsvOptions` describes a chunk of rows at a time, for files too big to read
+/// into ram at once. Each chunk is the names and vectors of at most `chunk_rows` points, in file order, and no more
+/// rows than that are held at once. The rows are parsed like `PointCloud::from_csv` parses them, except that
+/// `MissingValuePolicy::MeanFill` needs every row before it can fill any in, so it can't be streamed.
+///
+/// A row that doesn't parse is a `CSVReadError` with its line number, like `from_csv`. The points of the chunk before
+/// it are still returned, as a chunk of their own, then the error, then nothing more.
+///
+/// The features are parsed as `T`s, use `CsvChunks::<f64>::open` for the points of an `L2<f64>` cloud.
+pub struct CsvChunks<T: Scalar = f32> {
+    rows: PointRows,
+    chunk_rows: usize,
+    error: Option<PointCloudError>,
+    done: bool,
+    scalar: PhantomData<T>,
+}
+
+impl<T: Scalar> CsvChunks<T> {
+    /// Opens the CSV and reads the header row, if there is one. Returns a parsing error if `chunk_rows` is 0 or the
+    /// options fill missing values with means, and the errors of `PointCloud::from_csv` for the header.
+    pub fn open<P: AsRef<Path>>(path: P, options: &CsvOptions, chunk_rows: usize) -> Result<CsvChunks<T>, PointCloudError> {
+        if chunk_rows == 0 {
+            return Err(PointCloudError::ParsingError(ParsingError::RegularParsingError(
+                "A chunk has to have at least one row",
+            )));
+        }
+        if options.missing_value_policy == MissingValuePolicy::MeanFill {
+            return Err(PointCloudError::ParsingError(ParsingError::RegularParsingError(
+                "Filling missing values with the means needs every row, it can't be streamed",
+            )));
+        }
+        Ok(CsvChunks {
+            rows: PointRows::open(path.as_ref(), options)?,
+            chunk_rows,
+            error: None,
+            done: false,
+            scalar: PhantomData,
+        })
+    }
+}
+
+impl<T: Scalar> Iterator for CsvChunks<T> {
+    type Item = Result<Vec<(PointName, Vec<T>)>, PointCloudError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+        if self.done {
+            return None;
+        }
+        let dim = self.rows.options.feature_columns.len();
+        let mut data = Vec::with_capacity(dim);
+        let mut missing = Vec::new();
+        let mut chunk = Vec::with_capacity(self.chunk_rows.min(1024));
+        while chunk.len() < self.chunk_rows {
+            match self.rows.next_point(&mut data, &mut missing) {
+                Ok(Some(name)) => chunk.push((name, data.split_off(0))),
+                Ok(None) => {
+                    self.done = true;
+                    break;
+                }
+                Err(e) => {
+                    self.done = true;
+                    if chunk.is_empty() {
+                        return Some(Err(e));
+                    }
+                    self.error = Some(e);
+                    break;
+                }
+            }
+        }
+        if chunk.is_empty() {
+            None
+        } else {
+            Some(Ok(chunk))
+        }
+    }
+}
+
+/// The rows of a CSV of points, parsed one at a time.
+struct PointRows {
+    rdr: Reader<Box<dyn Read>>,
+    file_name: String,
+    options: CsvOptions,
+    headers: Option<StringRecord>,
+    name_index: Option<usize>,
+    record: StringRecord,
+    row: usize,
+}
+
+impl PointRows {
+    fn open(path: &Path, options: &CsvOptions) -> Result<PointRows, PointCloudError> {
+        let file = File::open(path)?;
+        let mut builder = ReaderBuilder::new();
+        builder
+            .delimiter(options.delimiter)
+            .has_headers(options.has_headers)
+            .flexible(true);
+        let reader: Box<dyn Read> = if path.extension().map(|e| e == "gz").unwrap_or(false) {
+            Box::new(GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        let mut rows = PointRows {
+            rdr: builder.from_reader(reader),
+            file_name: path.to_string_lossy().to_string(),
+            options: options.clone(),
+            headers: None,
+            name_index: None,
+            record: StringRecord::new(),
+            row: 0,
+        };
+        if options.has_headers {
+            let headers = match rows.rdr.headers() {
+                Ok(headers) => headers.clone(),
+                Err(_) => return Err(rows.read_error(1, "")),
+            };
+            rows.headers = Some(headers);
+        }
+        rows.name_index = match &options.name_column {
+            None => None,
+            Some(CsvColumn::Index(i)) => Some(*i),
+            Some(CsvColumn::Header(name)) => {
+                match rows.headers.as_ref().and_then(|h| h.iter().position(|c| c == name)) {
+                    Some(i) => Some(i),
+                    None => return Err(rows.read_error(1, name)),
+                }
+            }
+        };
+        Ok(rows)
+    }
+
+    fn read_error(&self, line_number: usize, key: &str) -> PointCloudError {
+        PointCloudError::ParsingError(ParsingError::CSVReadError {
+            file_name: self.file_name.clone(),
+            line_number,
+            key: key.to_string(),
+        })
+    }
+
+    /// The key we report a column by, the header if there is one
+    fn column_key(&self, i: usize) -> String {
+        match self.headers.as_ref().and_then(|h| h.get(i)) {
+            Some(header) => header.to_string(),
+            None => i.to_string(),
+        }
+    }
+
+    /// Reads the next point that isn't skipped, pushing its features onto `data` and the positions in `data` of its
+    /// empty cells onto `missing`, and returns its name. Returns `None` at the end of the file.
+    fn next_point<T: Scalar>(
+        &mut self,
+        data: &mut Vec<T>,
+        missing: &mut Vec<usize>,
+    ) -> Result<Option<PointName>, PointCloudError> {
+        loop {
+            let line_number = self.rdr.position().line() as usize;
+            match self.rdr.read_record(&mut self.record) {
+                Ok(true) => {}
+                Ok(false) => return Ok(None),
+                Err(e) => {
+                    let line_number = e.position().map(|p| p.line() as usize).unwrap_or(line_number);
+                    return Err(self.read_error(line_number, ""));
+                }
+            }
+            let line_number = self.record.position().map(|p| p.line() as usize).unwrap_or(line_number);
+            let row_start = data.len();
+            let missing_start = missing.len();
+            let policy = self.options.missing_value_policy;
+            let mut skip = false;
+            for i in self.options.feature_columns.clone() {
+                match self.record.get(i).map(|x| x.trim()) {
+                    Some("") if policy != MissingValuePolicy::Error => {
+                        if policy == MissingValuePolicy::Skip {
+                            skip = true;
+                            break;
+                        }
+                        missing.push(data.len());
+                        data.push(T::zero());
+                    }
+                    Some(x) => match x.parse::<T>() {
+                        Ok(x) => data.push(x),
+                        Err(_) => return Err(self.read_error(line_number, &self.column_key(i))),
+                    },
+                    None => return Err(self.read_error(line_number, &self.column_key(i))),
+                }
+            }
+            let row = self.row;
+            self.row += 1;
+            if skip {
+                data.truncate(row_start);
+                missing.truncate(missing_start);
+                continue;
+            }
+            let name = match self.name_index {
+                Some(i) => match self.record.get(i) {
+                    Some(name) => name.as_bytes().to_vec(),
+                    None => return Err(self.read_error(line_number, &self.column_key(i))),
+                },
+                None => format!("{}", row).into_bytes(),
+            };
+            return Ok(Some(name));
+        }
+    }
+}
+
+/// Sets each missing cell to the mean of the cells of its column that weren't missing.
+fn fill_means<T: Scalar>(data: &mut [T], missing: &[usize], dim: usize) {
+    let mut sums = vec![0.0f64; dim];
+    let mut counts = vec![0usize; dim];
+    for (i, x) in data.iter().enumerate() {
+        sums[i % dim] += x.as_f64();
+        counts[i % dim] += 1;
+    }
+    // The missing cells are 0, so they only need taking out of the counts
+    for i in missing {
+        counts[i % dim] -= 1;
+    }
+    for i in missing {
+        let column = i % dim;
+        if counts[column] > 0 {
+            data[*i] = T::from_f64(sums[column] / counts[column] as f64);
+        }
+    }
+}
@@ -24,18 +24,25 @@ use crate::errors::PointCloudError;
 use std::fs::OpenOptions;
 use std::path::Path;
 use super::DataSource;
+use crate::distances::Scalar;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::slice;
+use std::borrow::Cow;
 
 /// This is a thin wrapper around `memmapf32` to give it dimensionality, and name so that if there are errors in this memmap we can notify the user.
+/// The file is read as packed `T`s in native byte order.
 #[derive(Debug)]
-pub struct DataMemmap {
+pub struct DataMemmap<T: Scalar = f32> {
     name: String,
     data: Mmapf32,
     dim: usize,
+    scalar: PhantomData<T>,
 }
 
-impl DataMemmap {
+impl<T: Scalar> DataMemmap<T> {
     /// Creates a new one from a path. The name is the path.
-    pub fn new(dim: usize, path: &Path) -> Result<DataMemmap, PointCloudError> {
+    pub fn new(dim: usize, path: &Path) -> Result<DataMemmap<T>, PointCloudError> {
         let name = path.to_string_lossy().to_string();
         if !path.exists() {
             panic!("data file {:?} does not exist", path);
@@ -47,25 +54,29 @@ impl DataMemmap {
             }
         };
         let data = unsafe { Mmapf32::map(&file).map_err(|e| PointCloudError::from(e)) }?;
-        Ok(DataMemmap { name, data, dim })
+        Ok(DataMemmap { name, data, dim, scalar: PhantomData })
     }
 
     /// Reads and consumes this memmap and copies it into ram.
-    pub fn convert_to_ram(self) -> DataRam {
-        let dim = self.dim;
-        let name = self.name;
-        let mut data = Vec::with_capacity(self.data.len());
-        data.extend_from_slice(&*self.data);
-        DataRam { name, data: Box::from(data), dim }
+    pub fn convert_to_ram(self) -> DataRam<T> {
+        let data = Box::from(self.values());
+        DataRam { name: self.name, data, dim: self.dim }
+    }
+
+    /// The map as `T`s. The map is page aligned, so it's aligned for any scalar.
+    #[inline]
+    fn values(&self) -> &[T] {
+        let bytes = self.data.len() * size_of::<f32>();
+        unsafe { slice::from_raw_parts(self.data.as_ptr() as *const T, bytes / size_of::<T>()) }
     }
 }
 
-impl DataSource for DataMemmap {
+impl<T: Scalar> DataSource<T> for DataMemmap<T> {
     #[inline]
-    fn get(&self, i: usize) -> Result<&[f32], PointCloudError> {
-        match self.data.get(self.dim * i..(self.dim * i + self.dim)) {
+    fn get(&self, i: usize) -> Result<Cow<'_, [T]>, PointCloudError> {
+        match self.values().get(self.dim * i..(self.dim * i + self.dim)) {
             None => Err(PointCloudError::data_access(i, self.name.clone())),
-            Some(x) => Ok(x),
+            Some(x) => Ok(Cow::Borrowed(x)),
         }
     }
     #[inline]
@@ -74,37 +85,40 @@ impl DataSource for DataMemmap {
     }
     #[inline]
     fn len(&self) -> usize {
-        self.data.len() / self.dim
+        self.values().len() / self.dim
     }
     #[inline]
     fn name(&self) -> String {
         self.name.clone()
     }
+    fn heap_bytes(&self) -> usize {
+        self.name.capacity()
+    }
 }
 
-/// A thin wrapper to give a `Box<[f32]>` dimensionality.
+/// A thin wrapper to give a `Box<[T]>` dimensionality.
 #[derive(Debug)]
-pub struct DataRam {
+pub struct DataRam<T: Scalar = f32> {
     name: String,
-    data: Box<[f32]>,
+    data: Box<[T]>,
     dim: usize,
 }
 
-impl DataRam {
+impl<T: Scalar> DataRam<T> {
     /// Consumes your box and dimension and gives a dimensioned box. 
-    pub fn new(dim: usize, data: Box<[f32]>) -> Result<DataRam, PointCloudError> {
+    pub fn new(dim: usize, data: Box<[T]>) -> Result<DataRam<T>, PointCloudError> {
         assert!(data.len()%dim == 0);
         let name = "RAM".to_string();
         Ok(DataRam { name, data, dim })
     }
 }
 
-impl DataSource for DataRam {
+impl<T: Scalar> DataSource<T> for DataRam<T> {
     #[inline]
-    fn get(&self, i: usize) -> Result<&[f32], PointCloudError> {
+    fn get(&self, i: usize) -> Result<Cow<'_, [T]>, PointCloudError> {
         match self.data.get(self.dim * i..(self.dim * i + self.dim)) {
             None => Err(PointCloudError::data_access(i, self.name.clone())),
-            Some(x) => Ok(x),
+            Some(x) => Ok(Cow::Borrowed(x)),
         }
     }
     #[inline]
@@ -119,4 +133,41 @@ impl DataSource for DataRam {
     fn name(&self) -> String {
         self.name.clone()
     }
+    fn heap_bytes(&self) -> usize {
+        self.name.capacity() + self.data.len() * size_of::<T>()
+    }
+}
+
+/// Stands in for the vectors of a point cloud that was loaded without them, see `PointCloud::without_vectors`. It has
+/// a dimension and a length, but every `get` is a `VectorsNotLoaded` error.
+#[derive(Debug)]
+pub struct DataAbsent {
+    dim: usize,
+    len: usize,
+}
+
+impl DataAbsent {
+    /// A source of `len` points of dimension `dim` that has none of their values.
+    pub fn new(dim: usize, len: usize) -> DataAbsent {
+        DataAbsent { dim, len }
+    }
+}
+
+impl<T: Scalar> DataSource<T> for DataAbsent {
+    #[inline]
+    fn get(&self, i: usize) -> Result<Cow<'_, [T]>, PointCloudError> {
+        Err(PointCloudError::VectorsNotLoaded { index: i })
+    }
+    #[inline]
+    fn dim(&self) -> usize {
+        self.dim
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        self.len
+    }
+    #[inline]
+    fn name(&self) -> String {
+        "ABSENT".to_string()
+    }
 }
@@ -18,26 +18,46 @@
 */
 
 //! Some data sources and a trait to dimension and uniformly reference the data contained. 
-//! The only currently supported are memmaps and ram blobs.
+//! The only currently supported are memmaps, ram blobs and quantized ram blobs.
+//!
+//! A source holds the scalar type of the metric it's read with, `f32` unless you build on something like `L2<f64>`,
+//! and `get` hands out each vector as a `Cow<[T]>`. Memmaps and ram blobs lend the stored vector, `DataQuantized`
+//! stores `i8`s with a scale and dequantizes a copy on each access, a quarter of the memory of `f32`s for a copy
+//! per point read.
+//!
+//! Everything past the source sees the dequantized values, so the metric, the tree's scale base and its radii are all in
+//! the units of the original data, and the tree is exact for the dequantized vectors, not the originals. With a step
+//! of `s` each coordinate is off by up to `s/2`, so the L2 distance from a query to a stored point is off by up to
+//! `s * sqrt(dim) / 2`, and twice that between two stored points. Neighbors the originals have closer together than
+//! that can swap places, so the recall of a query drops with the step and the dimension, and coarse steps on tightly
+//! clustered data lose the most. Check the recall against the unquantized vectors on a sample before relying on it.
 
+use std::borrow::Cow;
 use std::fmt::Debug;
 use crate::errors::*;
+use crate::distances::Scalar;
 
 #[allow(dead_code)]
 mod memmapf32;
 mod memmap;
+mod quantized;
 
 #[doc(hidden)]
 pub use memmap::*;
+pub use quantized::DataQuantized;
 
-/// The trait for uniform access across many data types
-pub trait DataSource: Send + Sync + Debug {
+/// The trait for uniform access across many data types. `T` is the scalar type of the vectors, see `Metric::Scalar`.
+pub trait DataSource<T: Scalar = f32>: Send + Sync + Debug {
     /// Make this panic with `DataAccessError` when implementing, 
-    fn get(&self, i: usize) -> Result<&[f32], PointCloudError>;
+    fn get(&self, i: usize) -> Result<Cow<'_, [T]>, PointCloudError>;
     /// The dimension that this dimensioned data respects
     fn dim(&self) -> usize;
     /// This should always be the number of points contained in this portion of the dataset.
     fn len(&self) -> usize;
     /// The name of this portion of the dataset. Useful for reporting errors to the user. 
     fn name(&self) -> String;
+    /// An estimate of the bytes this holds on the heap. Memory the OS maps in, like a memmap's pages, isn't counted.
+    fn heap_bytes(&self) -> usize {
+        0
+    }
 }
\ No newline at end of file
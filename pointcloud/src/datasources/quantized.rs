@@ -0,0 +1,85 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Quantized data in ram.
+
+use super::DataSource;
+use crate::distances::Scalar;
+use crate::errors::PointCloudError;
+use std::borrow::Cow;
+
+/// Holds each coordinate as an `i8` code, the value being the code times a scale shared by the whole source. A quarter
+/// of the memory of a `DataRam<f32>`, at the cost of copying each vector out on `get`. See the module docs for the
+/// error this adds to the distances.
+#[derive(Debug)]
+pub struct DataQuantized<T: Scalar = f32> {
+    name: String,
+    codes: Box<[i8]>,
+    scale: T,
+    dim: usize,
+}
+
+impl<T: Scalar> DataQuantized<T> {
+    /// Consumes your codes and gives a dimensioned source whose values are the codes times `scale`.
+    pub fn new(dim: usize, codes: Box<[i8]>, scale: T) -> Result<DataQuantized<T>, PointCloudError> {
+        assert!(codes.len() % dim == 0);
+        assert!(scale.is_finite() && scale > T::zero());
+        let name = "QUANTIZED".to_string();
+        Ok(DataQuantized { name, codes, scale, dim })
+    }
+
+    /// Quantizes the values with the step that takes the largest absolute value to 127, each one rounded to the
+    /// nearest code. The values have to be finite.
+    pub fn quantize(dim: usize, data: &[T]) -> Result<DataQuantized<T>, PointCloudError> {
+        let max = data.iter().fold(T::zero(), |max, x| max.max(x.abs()));
+        let scale = if max > T::zero() { max / T::from_f64(127.0) } else { T::one() };
+        let codes: Vec<i8> = data.iter().map(|x| (*x / scale).round().as_f64() as i8).collect();
+        DataQuantized::new(dim, Box::from(codes), scale)
+    }
+
+    /// The step between codes, each value is its code times this.
+    pub fn scale(&self) -> T {
+        self.scale
+    }
+}
+
+impl<T: Scalar> DataSource<T> for DataQuantized<T> {
+    #[inline]
+    fn get(&self, i: usize) -> Result<Cow<'_, [T]>, PointCloudError> {
+        match self.codes.get(self.dim * i..(self.dim * i + self.dim)) {
+            None => Err(PointCloudError::data_access(i, self.name.clone())),
+            Some(x) => Ok(Cow::Owned(x.iter().map(|c| T::from_f64(*c as f64) * self.scale).collect())),
+        }
+    }
+    #[inline]
+    fn dim(&self) -> usize {
+        self.dim
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        self.codes.len() / self.dim
+    }
+    #[inline]
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn heap_bytes(&self) -> usize {
+        self.name.capacity() + self.codes.len()
+    }
+}
@@ -19,75 +19,210 @@
 
 //! Supported distances
 
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
+use std::marker::PhantomData;
+use std::str::FromStr;
+use num_traits::Float;
+use num_traits::float::TotalOrder;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::errors::{PointCloudError, PointCloudResult};
+#[cfg(feature = "simd")]
 use packed_simd::*;
 
-/// The trait that enables a metric
+/// The type of the coordinates of the points, and of the distances between them, `f32` or `f64`. The metrics take it
+/// as a type parameter, `L2<f64>` say, and the point cloud and the tree follow their metric's, so a tree built with
+/// `L2<f64>` holds its vectors, radii and scale base in `f64` and does all of its distance math in it. `f32` is the
+/// default everywhere. Only `f32` has the SIMD kernels, `f64` is done a coordinate at a time and takes twice the memory
+/// for the vectors, it's for data whose distances `f32` rounds together.
+pub trait Scalar:
+    Float + TotalOrder + Default + Debug + Display + FromStr + Serialize + DeserializeOwned + Send + Sync + 'static
+{
+    /// The bytes each coordinate takes. It's saved with a tree, so a tree can only be loaded with the scalar it was built
+    /// with.
+    const WIDTH: u32;
+    /// The value nearest to `x`.
+    fn from_f64(x: f64) -> Self;
+    /// The value as an `f64`, which holds both scalars exactly.
+    fn as_f64(self) -> f64;
+    /// The sum of the squared differences.
+    #[inline]
+    fn squared_l2(x: &[Self], y: &[Self]) -> Self {
+        squared_l2_scalar(x, y)
+    }
+    /// The sum of the squares.
+    #[inline]
+    fn squared_norm(x: &[Self]) -> Self {
+        squared_norm_scalar(x)
+    }
+    /// The largest absolute difference.
+    #[inline]
+    fn linfty(x: &[Self], y: &[Self]) -> Self {
+        linfty_scalar(x, y)
+    }
+    /// The largest absolute value.
+    #[inline]
+    fn linfty_norm(x: &[Self]) -> Self {
+        linfty_norm_scalar(x)
+    }
+    /// The sum of the absolute differences.
+    #[inline]
+    fn l1(x: &[Self], y: &[Self]) -> Self {
+        l1_scalar(x, y)
+    }
+    /// The sum of the absolute values.
+    #[inline]
+    fn l1_norm(x: &[Self]) -> Self {
+        l1_norm_scalar(x)
+    }
+    /// The cosine of the angle between the vectors.
+    #[inline]
+    fn cosine_sim(x: &[Self], y: &[Self]) -> Self {
+        let (acc, x_sum, y_sum) = cosine_sums_scalar(x, y);
+        acc / (x_sum.sqrt() * y_sum.sqrt()).max(Self::from_f64(0.00001))
+    }
+}
+
+impl Scalar for f32 {
+    const WIDTH: u32 = 4;
+
+    #[inline]
+    fn from_f64(x: f64) -> f32 {
+        x as f32
+    }
+
+    #[inline]
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+
+    #[inline]
+    fn squared_l2(x: &[f32], y: &[f32]) -> f32 {
+        squared_l2(x, y)
+    }
+
+    #[inline]
+    fn squared_norm(x: &[f32]) -> f32 {
+        squared_norm(x)
+    }
+
+    #[inline]
+    fn linfty(x: &[f32], y: &[f32]) -> f32 {
+        linfty(x, y)
+    }
+
+    #[inline]
+    fn linfty_norm(x: &[f32]) -> f32 {
+        linfty_norm(x)
+    }
+
+    #[inline]
+    fn l1(x: &[f32], y: &[f32]) -> f32 {
+        l1(x, y)
+    }
+
+    #[inline]
+    fn l1_norm(x: &[f32]) -> f32 {
+        l1_norm(x)
+    }
+
+    #[inline]
+    fn cosine_sim(x: &[f32], y: &[f32]) -> f32 {
+        cosine_sim(x, y)
+    }
+}
+
+impl Scalar for f64 {
+    const WIDTH: u32 = 8;
+
+    #[inline]
+    fn from_f64(x: f64) -> f64 {
+        x
+    }
+
+    #[inline]
+    fn as_f64(self) -> f64 {
+        self
+    }
+}
+
+/// The trait that enables a metric. The cover tree's pruning relies on the triangle inequality, so for exact queries an
+/// implementor has to be a true metric: `dense(x,y) >= 0`, `dense(x,x) == 0`, it's symmetric, and
+/// `dense(x,z) <= dense(x,y) + dense(y,z)`. Implement this on an empty struct and pass it as the type parameter of the
+/// `PointCloud`, the tree picks it up from there. Trees default to `L2`. The metric decides the `Scalar` of the
+/// coordinates and the distances, the ones here are generic over it and default to `f32`.
 pub trait Metric: 'static + Send + Sync + Debug {
+    /// The type of the coordinates and of the distances, see `Scalar`.
+    type Scalar: Scalar;
+    /// The name the metric goes by in configs, like `"L2"`. It has to be unique among the metrics a config can pick.
+    const NAME: &'static str;
     /// Dense calculation
-    fn dense(x: &[f32], y: &[f32]) -> f32;
+    fn dense(x: &[Self::Scalar], y: &[Self::Scalar]) -> Self::Scalar;
     /// Sparse calculation, we assume that the index slices are in accending order and 
     /// that the values correspond to the indexes
-    fn sparse(x_ind: &[u32], x_val: &[f32], y_ind: &[u32], y_val: &[f32]) -> f32;
+    fn sparse(x_ind: &[u32], x_val: &[Self::Scalar], y_ind: &[u32], y_val: &[Self::Scalar]) -> Self::Scalar;
     /// The norm, dense(x,x)
-    fn norm(x: &[f32]) -> f32;
+    fn norm(x: &[Self::Scalar]) -> Self::Scalar;
+    /// A cheaper value that orders pairs the same way as `dense`, for ranking many points against a bound, like the
+    /// squared distance for `L2`. It has to be `dense` passed through a strictly increasing function, that
+    /// `rank_to_distance` undoes exactly. The default is `dense` itself.
+    #[inline]
+    fn dense_rank(x: &[Self::Scalar], y: &[Self::Scalar]) -> Self::Scalar {
+        Self::dense(x, y)
+    }
+    /// Turns a `dense_rank` back into the distance, so `rank_to_distance(dense_rank(x,y)) == dense(x,y)`.
+    #[inline]
+    fn rank_to_distance(rank: Self::Scalar) -> Self::Scalar {
+        rank
+    }
+    /// The largest rank a pair at distance `dist` or closer can have. It can round up, but not down, a pair whose rank is
+    /// over it has to be further than `dist`.
+    #[inline]
+    fn rank_bound(dist: Self::Scalar) -> Self::Scalar {
+        dist
+    }
 }
 
 /// L2 norm, the square root of the sum of squares
 #[derive(Debug)]
-pub struct L2 {}
-
-impl Metric for L2 {
-    #[inline]
-    fn dense(mut x: &[f32], mut y: &[f32]) -> f32 {
-        let mut d_acc_16 = f32x16::splat(0.0);
-        while y.len() > 16 {
-            let x_simd = f32x16::from_slice_unaligned(x);
-            let y_simd = f32x16::from_slice_unaligned(y);
-            let diff = x_simd - y_simd;
-            d_acc_16 += diff * diff;
-            y = &y[16..];
-            x = &x[16..];
-        }
-        let mut d_acc_8 = f32x8::splat(0.0);
-        if y.len() > 8 {
-            let x_simd = f32x8::from_slice_unaligned(x);
-            let y_simd = f32x8::from_slice_unaligned(y);
-            let diff = x_simd - y_simd;
-            d_acc_8 += diff * diff;
-            y = &y[8..];
-            x = &x[8..];
-        }
-        let leftover = y
-            .iter()
-            .zip(x)
-            .map(|(xi, yi)| (xi - yi) * (xi - yi))
-            .fold(0.0, |acc, y| acc + y);
-        (leftover + d_acc_8.sum() + d_acc_16.sum()).sqrt()
+pub struct L2<T: Scalar = f32> {
+    scalar: PhantomData<T>,
+}
+
+impl<T: Scalar> Metric for L2<T> {
+    type Scalar = T;
+    const NAME: &'static str = "L2";
+
+    #[inline]
+    fn dense(x: &[T], y: &[T]) -> T {
+        T::squared_l2(x, y).sqrt()
     }
 
     #[inline]
-    fn norm(mut x: &[f32]) -> f32 {
-        let mut d_acc_16 = f32x16::splat(0.0);
-        while x.len() > 16 {
-            let x_simd = f32x16::from_slice_unaligned(x);
-            d_acc_16 += x_simd * x_simd;
-            x = &x[16..];
-        }
-        let mut d_acc_8 = f32x8::splat(0.0);
-        if x.len() > 8 {
-            let x_simd = f32x8::from_slice_unaligned(x);
-            d_acc_8 += x_simd * x_simd;
-            x = &x[8..];
-        }
-        let leftover = x.iter().map(|xi| xi * xi).fold(0.0, |acc, xi| acc + xi);
-        (leftover + d_acc_8.sum() + d_acc_16.sum()).sqrt()
+    fn norm(x: &[T]) -> T {
+        T::squared_norm(x).sqrt()
     }
 
-    fn sparse(x_ind: &[u32], x_val: &[f32], y_ind: &[u32], y_val: &[f32]) -> f32 {
+    #[inline]
+    fn dense_rank(x: &[T], y: &[T]) -> T {
+        T::squared_l2(x, y)
+    }
+
+    #[inline]
+    fn rank_to_distance(rank: T) -> T {
+        rank.sqrt()
+    }
+
+    /// The square, rounded up a couple of units in the last place, as the `sqrt` in `dense` rounds too.
+    #[inline]
+    fn rank_bound(dist: T) -> T {
+        dist * dist * (T::one() + T::from_f64(4.0) * T::epsilon())
+    }
+
+    fn sparse(x_ind: &[u32], x_val: &[T], y_ind: &[u32], y_val: &[T]) -> T {
         if x_val.len() == 0 || y_val.len() == 0 {
             if x_val.len() == 0 && y_val.len() == 0 {
-                return 0.0;
+                return T::zero();
             }
             if x_val.len() > 0 && y_val.len() == 0 {
                 Self::norm(x_val)
@@ -95,7 +230,7 @@ impl Metric for L2 {
                 Self::norm(y_val)
             }
         } else {
-            let mut total = 0.0;
+            let mut total = T::zero();
             let mut long_iter;
             let short_iter;
             if x_ind.len() > y_ind.len() {
@@ -106,11 +241,11 @@ impl Metric for L2 {
                 short_iter = x_ind.iter().zip(x_val);
             }
 
-            let mut l_tr: Option<(&u32, &f32)> = long_iter.next();
+            let mut l_tr: Option<(&u32, &T)> = long_iter.next();
             for (si, sv) in short_iter {
                 while let Some((li, lv)) = l_tr {
                     if li < si {
-                        total += lv * lv;
+                        total = total + *lv * *lv;
                         l_tr = long_iter.next();
                     } else {
                         break;
@@ -118,18 +253,18 @@ impl Metric for L2 {
                 }
                 if let Some((li, lv)) = l_tr {
                     if li == si {
-                        let val = sv - lv;
-                        total += val * val;
+                        let val = *sv - *lv;
+                        total = total + val * val;
                         l_tr = long_iter.next();
                     } else {
-                        total += sv * sv;
+                        total = total + *sv * *sv;
                     }
                 } else {
-                    total += sv * sv;
+                    total = total + *sv * *sv;
                 }
             }
             while let Some((_li, lv)) = l_tr {
-                total += lv * lv;
+                total = total + *lv * *lv;
                 l_tr = long_iter.next();
             }
             total.sqrt()
@@ -137,61 +272,85 @@ impl Metric for L2 {
     }
 }
 
+/// The sum of the squared differences. With the `simd` feature this runs 8 lanes at a time and the last `len % 8`
+/// elements are done one at a time, without it it's all done one at a time.
+#[cfg(feature = "simd")]
+#[inline]
+fn squared_l2(mut x: &[f32], mut y: &[f32]) -> f32 {
+    let mut d_acc_8 = f32x8::splat(0.0);
+    while y.len() >= 8 {
+        let x_simd = f32x8::from_slice_unaligned(x);
+        let y_simd = f32x8::from_slice_unaligned(y);
+        let diff = x_simd - y_simd;
+        d_acc_8 += diff * diff;
+        y = &y[8..];
+        x = &x[8..];
+    }
+    d_acc_8.sum() + squared_l2_scalar(x, y)
+}
+
+#[cfg(not(feature = "simd"))]
+#[inline]
+fn squared_l2(x: &[f32], y: &[f32]) -> f32 {
+    squared_l2_scalar(x, y)
+}
+
+#[inline]
+fn squared_l2_scalar<T: Float>(x: &[T], y: &[T]) -> T {
+    y.iter()
+        .zip(x)
+        .map(|(xi, yi)| (*xi - *yi) * (*xi - *yi))
+        .fold(T::zero(), |acc, y| acc + y)
+}
+
+/// The sum of the squares, done like `squared_l2`.
+#[cfg(feature = "simd")]
+#[inline]
+fn squared_norm(mut x: &[f32]) -> f32 {
+    let mut d_acc_8 = f32x8::splat(0.0);
+    while x.len() >= 8 {
+        let x_simd = f32x8::from_slice_unaligned(x);
+        d_acc_8 += x_simd * x_simd;
+        x = &x[8..];
+    }
+    d_acc_8.sum() + squared_norm_scalar(x)
+}
+
+#[cfg(not(feature = "simd"))]
+#[inline]
+fn squared_norm(x: &[f32]) -> f32 {
+    squared_norm_scalar(x)
+}
+
+#[inline]
+fn squared_norm_scalar<T: Float>(x: &[T]) -> T {
+    x.iter().map(|xi| *xi * *xi).fold(T::zero(), |acc, xi| acc + xi)
+}
+
 /// L infity norm, the max of the absolute values of the elements
 #[derive(Debug)]
-pub struct Linfty {}
-
-impl Metric for Linfty {
-    #[inline]
-    fn dense(mut x: &[f32], mut y: &[f32]) -> f32 {
-        let mut d_acc_16 = f32x16::splat(0.0);
-        while y.len() > 16 {
-            let y_simd = f32x16::from_slice_unaligned(y);
-            let x_simd = f32x16::from_slice_unaligned(x);
-            let diff = (x_simd - y_simd).abs();
-            d_acc_16 = d_acc_16.max(diff);
-            y = &y[16..];
-            x = &x[16..];
-        }
-        let mut d_acc_8 = f32x8::splat(0.0);
-        if y.len() > 8 {
-            let y_simd = f32x8::from_slice_unaligned(y);
-            let x_simd = f32x8::from_slice_unaligned(x);
-            let diff = (x_simd - y_simd).abs();
-            d_acc_8 = d_acc_8.max(diff);
-            y = &y[8..];
-            x = &x[8..];
-        }
-        let leftover = y
-            .iter()
-            .zip(x)
-            .map(|(xi, yi)| (xi - yi).abs())
-            .fold(0.0, |acc: f32, y| acc.max(y));
-        leftover.max(d_acc_8.max_element().max(d_acc_16.max_element()))
+pub struct Linfty<T: Scalar = f32> {
+    scalar: PhantomData<T>,
+}
+
+impl<T: Scalar> Metric for Linfty<T> {
+    type Scalar = T;
+    const NAME: &'static str = "Linfty";
+
+    #[inline]
+    fn dense(x: &[T], y: &[T]) -> T {
+        T::linfty(x, y)
     }
 
     #[inline]
-    fn norm(mut x: &[f32]) -> f32 {
-        let mut d_acc_16 = f32x16::splat(0.0);
-        while x.len() > 16 {
-            let x_simd = f32x16::from_slice_unaligned(x);
-            d_acc_16 = d_acc_16.max(x_simd);
-            x = &x[16..];
-        }
-        let mut d_acc_8 = f32x8::splat(0.0);
-        if x.len() > 8 {
-            let x_simd = f32x8::from_slice_unaligned(x);
-            d_acc_8 = d_acc_8.max(x_simd);
-            x = &x[8..];
-        }
-        let leftover = x.iter().map(|xi| xi.abs()).fold(0.0, |acc:f32, xi| acc.max(xi));
-        leftover.max(d_acc_8.max_element().max(d_acc_16.max_element()))
+    fn norm(x: &[T]) -> T {
+        T::linfty_norm(x)
     }
 
-    fn sparse(x_ind: &[u32], x_val: &[f32], y_ind: &[u32], y_val: &[f32]) -> f32 {
+    fn sparse(x_ind: &[u32], x_val: &[T], y_ind: &[u32], y_val: &[T]) -> T {
         if x_val.len() == 0 || y_val.len() == 0 {
             if x_val.len() == 0 && y_val.len() == 0 {
-                return 0.0;
+                return T::zero();
             }
             if x_val.len() > 0 && y_val.len() == 0 {
                 Self::norm(x_val)
@@ -199,7 +358,7 @@ impl Metric for Linfty {
                 Self::norm(y_val)
             }
         } else {
-            let mut max_val:f32 = 0.0;
+            let mut max_val = T::zero();
             let mut long_iter;
             let short_iter;
             if x_ind.len() > y_ind.len() {
@@ -210,11 +369,11 @@ impl Metric for Linfty {
                 short_iter = x_ind.iter().zip(x_val);
             }
 
-            let mut l_tr: Option<(&u32, &f32)> = long_iter.next();
+            let mut l_tr: Option<(&u32, &T)> = long_iter.next();
             for (si, sv) in short_iter {
                 while let Some((li, lv)) = l_tr {
                     if li < si {
-                        max_val = max_val.max(lv * lv);
+                        max_val = max_val.max(*lv * *lv);
                         l_tr = long_iter.next();
                     } else {
                         break;
@@ -222,18 +381,18 @@ impl Metric for Linfty {
                 }
                 if let Some((li, lv)) = l_tr {
                     if li == si {
-                        let val = sv - lv;
+                        let val = *sv - *lv;
                         max_val = max_val.max(val * val);
                         l_tr = long_iter.next();
                     } else {
-                        max_val = max_val.max(sv * sv);
+                        max_val = max_val.max(*sv * *sv);
                     }
                 } else {
-                    max_val = max_val.max(sv * sv);
+                    max_val = max_val.max(*sv * *sv);
                 }
             }
             while let Some((_li, lv)) = l_tr {
-                max_val = max_val.max(lv * lv);
+                max_val = max_val.max(*lv * *lv);
                 l_tr = long_iter.next();
             }
             max_val.sqrt()
@@ -241,61 +400,100 @@ impl Metric for Linfty {
     }
 }
 
+/// The largest absolute difference. With the `simd` feature this runs 16 then 8 lanes at a time and the rest are done
+/// one at a time, without it it's all done one at a time.
+#[cfg(feature = "simd")]
+#[inline]
+fn linfty(mut x: &[f32], mut y: &[f32]) -> f32 {
+    let mut d_acc_16 = f32x16::splat(0.0);
+    while y.len() > 16 {
+        let y_simd = f32x16::from_slice_unaligned(y);
+        let x_simd = f32x16::from_slice_unaligned(x);
+        let diff = (x_simd - y_simd).abs();
+        d_acc_16 = d_acc_16.max(diff);
+        y = &y[16..];
+        x = &x[16..];
+    }
+    let mut d_acc_8 = f32x8::splat(0.0);
+    if y.len() > 8 {
+        let y_simd = f32x8::from_slice_unaligned(y);
+        let x_simd = f32x8::from_slice_unaligned(x);
+        let diff = (x_simd - y_simd).abs();
+        d_acc_8 = d_acc_8.max(diff);
+        y = &y[8..];
+        x = &x[8..];
+    }
+    linfty_scalar(x, y).max(d_acc_8.max_element().max(d_acc_16.max_element()))
+}
+
+#[cfg(not(feature = "simd"))]
+#[inline]
+fn linfty(x: &[f32], y: &[f32]) -> f32 {
+    linfty_scalar(x, y)
+}
+
+#[inline]
+fn linfty_scalar<T: Float>(x: &[T], y: &[T]) -> T {
+    y.iter()
+        .zip(x)
+        .map(|(xi, yi)| (*xi - *yi).abs())
+        .fold(T::zero(), |acc, y| acc.max(y))
+}
+
+/// The largest absolute value, done like `linfty`.
+#[cfg(feature = "simd")]
+#[inline]
+fn linfty_norm(mut x: &[f32]) -> f32 {
+    let mut d_acc_16 = f32x16::splat(0.0);
+    while x.len() > 16 {
+        let x_simd = f32x16::from_slice_unaligned(x);
+        d_acc_16 = d_acc_16.max(x_simd.abs());
+        x = &x[16..];
+    }
+    let mut d_acc_8 = f32x8::splat(0.0);
+    if x.len() > 8 {
+        let x_simd = f32x8::from_slice_unaligned(x);
+        d_acc_8 = d_acc_8.max(x_simd.abs());
+        x = &x[8..];
+    }
+    linfty_norm_scalar(x).max(d_acc_8.max_element().max(d_acc_16.max_element()))
+}
+
+#[cfg(not(feature = "simd"))]
+#[inline]
+fn linfty_norm(x: &[f32]) -> f32 {
+    linfty_norm_scalar(x)
+}
+
+#[inline]
+fn linfty_norm_scalar<T: Float>(x: &[T]) -> T {
+    x.iter().map(|xi| xi.abs()).fold(T::zero(), |acc, xi| acc.max(xi))
+}
+
 /// L1 norm, the sum of absolute values
 #[derive(Debug)]
-pub struct L1 {}
-
-impl Metric for L1 {
-    #[inline]
-    fn dense(mut x: &[f32], mut y: &[f32]) -> f32 {
-        let mut d_acc_16 = f32x16::splat(0.0);
-        while y.len() > 16 {
-            let y_simd = f32x16::from_slice_unaligned(y);
-            let x_simd = f32x16::from_slice_unaligned(x);
-            let diff = x_simd - y_simd;
-            d_acc_16 += diff.abs();
-            y = &y[16..];
-            x = &x[16..];
-        }
-        let mut d_acc_8 = f32x8::splat(0.0);
-        if y.len() > 8 {
-            let y_simd = f32x8::from_slice_unaligned(y);
-            let x_simd = f32x8::from_slice_unaligned(x);
-            let diff = x_simd - y_simd;
-            d_acc_8 += diff.abs();
-            y = &y[8..];
-            x = &x[8..];
-        }
-        let leftover = y
-            .iter()
-            .zip(x)
-            .map(|(xi, yi)| (xi - yi).abs())
-            .fold(0.0, |acc, y| acc + y);
-        leftover + d_acc_8.sum() + d_acc_16.sum()
+pub struct L1<T: Scalar = f32> {
+    scalar: PhantomData<T>,
+}
+
+impl<T: Scalar> Metric for L1<T> {
+    type Scalar = T;
+    const NAME: &'static str = "L1";
+
+    #[inline]
+    fn dense(x: &[T], y: &[T]) -> T {
+        T::l1(x, y)
     }
 
     #[inline]
-    fn norm(mut x: &[f32]) -> f32 {
-        let mut d_acc_16 = f32x16::splat(0.0);
-        while x.len() > 16 {
-            let x_simd = f32x16::from_slice_unaligned(x);
-            d_acc_16 += x_simd.abs();
-            x = &x[16..];
-        }
-        let mut d_acc_8 = f32x8::splat(0.0);
-        if x.len() > 8 {
-            let x_simd = f32x8::from_slice_unaligned(x);
-            d_acc_8 += x_simd.abs();
-            x = &x[8..];
-        }
-        let leftover = x.iter().map(|xi| xi.abs()).fold(0.0, |acc, xi| acc+xi);
-        leftover + d_acc_8.sum() + d_acc_16.sum()
+    fn norm(x: &[T]) -> T {
+        T::l1_norm(x)
     }
 
-    fn sparse(x_ind: &[u32], x_val: &[f32], y_ind: &[u32], y_val: &[f32]) -> f32 {
+    fn sparse(x_ind: &[u32], x_val: &[T], y_ind: &[u32], y_val: &[T]) -> T {
         if x_val.len() == 0 || y_val.len() == 0 {
             if x_val.len() == 0 && y_val.len() == 0 {
-                return 0.0;
+                return T::zero();
             }
             if x_val.len() > 0 && y_val.len() == 0 {
                 Self::norm(x_val)
@@ -303,7 +501,7 @@ impl Metric for L1 {
                 Self::norm(y_val)
             }
         } else {
-            let mut total = 0.0;
+            let mut total = T::zero();
             let mut long_iter;
             let short_iter;
             if x_ind.len() > y_ind.len() {
@@ -314,11 +512,11 @@ impl Metric for L1 {
                 short_iter = x_ind.iter().zip(x_val);
             }
 
-            let mut l_tr: Option<(&u32, &f32)> = long_iter.next();
+            let mut l_tr: Option<(&u32, &T)> = long_iter.next();
             for (si, sv) in short_iter {
                 while let Some((li, lv)) = l_tr {
                     if li < si {
-                        total += lv.abs();
+                        total = total + lv.abs();
                         l_tr = long_iter.next();
                     } else {
                         break;
@@ -326,18 +524,18 @@ impl Metric for L1 {
                 }
                 if let Some((li, lv)) = l_tr {
                     if li == si {
-                        let val = sv - lv;
-                        total += val.abs();
+                        let val = *sv - *lv;
+                        total = total + val.abs();
                         l_tr = long_iter.next();
                     } else {
-                        total += sv.abs();
+                        total = total + sv.abs();
                     }
                 } else {
-                    total += sv.abs();
+                    total = total + sv.abs();
                 }
             }
             while let Some((_li, lv)) = l_tr {
-                total += lv.abs();
+                total = total + lv.abs();
                 l_tr = long_iter.next();
             }
             total
@@ -345,59 +543,101 @@ impl Metric for L1 {
     }
 }
 
-/// Not a norm! Still, helpful for document clouds and the like
+/// The sum of the absolute differences. With the `simd` feature this runs 16 then 8 lanes at a time and the rest are
+/// done one at a time, without it it's all done one at a time.
+#[cfg(feature = "simd")]
+#[inline]
+fn l1(mut x: &[f32], mut y: &[f32]) -> f32 {
+    let mut d_acc_16 = f32x16::splat(0.0);
+    while y.len() > 16 {
+        let y_simd = f32x16::from_slice_unaligned(y);
+        let x_simd = f32x16::from_slice_unaligned(x);
+        let diff = x_simd - y_simd;
+        d_acc_16 += diff.abs();
+        y = &y[16..];
+        x = &x[16..];
+    }
+    let mut d_acc_8 = f32x8::splat(0.0);
+    if y.len() > 8 {
+        let y_simd = f32x8::from_slice_unaligned(y);
+        let x_simd = f32x8::from_slice_unaligned(x);
+        let diff = x_simd - y_simd;
+        d_acc_8 += diff.abs();
+        y = &y[8..];
+        x = &x[8..];
+    }
+    l1_scalar(x, y) + d_acc_8.sum() + d_acc_16.sum()
+}
+
+#[cfg(not(feature = "simd"))]
+#[inline]
+fn l1(x: &[f32], y: &[f32]) -> f32 {
+    l1_scalar(x, y)
+}
+
+#[inline]
+fn l1_scalar<T: Float>(x: &[T], y: &[T]) -> T {
+    y.iter()
+        .zip(x)
+        .map(|(xi, yi)| (*xi - *yi).abs())
+        .fold(T::zero(), |acc, y| acc + y)
+}
+
+/// The sum of the absolute values, done like `l1`.
+#[cfg(feature = "simd")]
+#[inline]
+fn l1_norm(mut x: &[f32]) -> f32 {
+    let mut d_acc_16 = f32x16::splat(0.0);
+    while x.len() > 16 {
+        let x_simd = f32x16::from_slice_unaligned(x);
+        d_acc_16 += x_simd.abs();
+        x = &x[16..];
+    }
+    let mut d_acc_8 = f32x8::splat(0.0);
+    if x.len() > 8 {
+        let x_simd = f32x8::from_slice_unaligned(x);
+        d_acc_8 += x_simd.abs();
+        x = &x[8..];
+    }
+    l1_norm_scalar(x) + d_acc_8.sum() + d_acc_16.sum()
+}
+
+#[cfg(not(feature = "simd"))]
+#[inline]
+fn l1_norm(x: &[f32]) -> f32 {
+    l1_norm_scalar(x)
+}
+
+#[inline]
+fn l1_norm_scalar<T: Float>(x: &[T]) -> T {
+    x.iter().map(|xi| xi.abs()).fold(T::zero(), |acc, xi| acc+xi)
+}
+
+/// Not a norm! Still, helpful for document clouds and the like. This is the similarity, larger is closer, so it
+/// can't be used to build a tree. Use `Cosine` for that.
 #[derive(Debug)]
-pub struct CosineSim {}
-
-impl Metric for CosineSim {
-    #[inline]
-    fn dense(mut x: &[f32], mut y: &[f32]) -> f32 {
-        let mut d_acc_16 = f32x16::splat(0.0);
-        let mut x_acc_16 = f32x16::splat(0.0);
-        let mut y_acc_16 = f32x16::splat(0.0);
-        while y.len() > 16 {
-            let y_simd = f32x16::from_slice_unaligned(y);
-            let x_simd = f32x16::from_slice_unaligned(x);
-            d_acc_16 += x_simd * y_simd;
-            x_acc_16 += x_simd * x_simd;
-            y_acc_16 += y_simd * y_simd;
-            y = &y[16..];
-            x = &x[16..];
-        }
-        let mut d_acc_8 = f32x8::splat(0.0);
-        let mut x_acc_8 = f32x8::splat(0.0);
-        let mut y_acc_8 = f32x8::splat(0.0);
-        if y.len() > 8 {
-            let y_simd = f32x8::from_slice_unaligned(y);
-            let x_simd = f32x8::from_slice_unaligned(x);
-            d_acc_8 += x_simd * y_simd;
-            x_acc_8 += x_simd * x_simd;
-            y_acc_8 += y_simd * y_simd;
-            y = &y[8..];
-            x = &x[8..];
-        }
-        let acc_leftover = y
-            .iter()
-            .zip(x)
-            .map(|(xi, yi)| xi * yi)
-            .fold(0.0, |acc, y| acc + y);
-        let y_leftover = y.iter().map(|yi| yi * yi).fold(0.0, |acc, yi| acc + yi);
-        let x_leftover = x.iter().map(|xi| xi * xi).fold(0.0, |acc, xi| acc + xi);
-        let acc = acc_leftover + d_acc_8.sum() + d_acc_16.sum();
-        let xnm = (x_leftover + x_acc_8.sum() + x_acc_16.sum()).sqrt();
-        let ynm = (y_leftover + y_acc_8.sum() + y_acc_16.sum()).sqrt();
-        acc / (xnm * ynm).max(0.00001)
+pub struct CosineSim<T: Scalar = f32> {
+    scalar: PhantomData<T>,
+}
+
+impl<T: Scalar> Metric for CosineSim<T> {
+    type Scalar = T;
+    const NAME: &'static str = "CosineSim";
+
+    #[inline]
+    fn dense(x: &[T], y: &[T]) -> T {
+        T::cosine_sim(x, y)
     }
 
-    fn norm(_x: &[f32]) -> f32 {
-        0.0
+    fn norm(_x: &[T]) -> T {
+        T::zero()
     }
 
-    fn sparse(x_ind: &[u32], x_val: &[f32], y_ind: &[u32], y_val: &[f32]) -> f32 {
+    fn sparse(x_ind: &[u32], x_val: &[T], y_ind: &[u32], y_val: &[T]) -> T {
         if x_val.len() == 0 || y_val.len() == 0 {
-            0.0
+            T::zero()
         } else {
-            let mut dotprod = 0.0;
+            let mut dotprod = T::zero();
             let mut long_iter;
             let short_iter;
             if x_ind.len() > y_ind.len() {
@@ -408,21 +648,427 @@ impl Metric for CosineSim {
                 short_iter = x_ind.iter().zip(x_val);
             }
 
-            let mut l_tr: Option<(&u32, &f32)> = long_iter.next();
+            let mut l_tr: Option<(&u32, &T)> = long_iter.next();
             for (si, sv) in short_iter {
-                while let Some((_li, _lv)) = l_tr {
-                    l_tr = long_iter.next();
+                while let Some((li, _lv)) = l_tr {
+                    if li < si {
+                        l_tr = long_iter.next();
+                    } else {
+                        break;
+                    }
                 }
                 if let Some((li, lv)) = l_tr {
                     if li == si {
-                        dotprod += sv * lv;
+                        dotprod = dotprod + *sv * *lv;
                         l_tr = long_iter.next();
                     }
                 }
             }
-            let xnm = L2::norm(x_val);
-            let ynm = L2::norm(y_val);
-            dotprod / (xnm * ynm).max(0.00001)
+            let xnm = L2::<T>::norm(x_val);
+            let ynm = L2::<T>::norm(y_val);
+            dotprod / (xnm * ynm).max(T::from_f64(0.00001))
+        }
+    }
+}
+
+/// The cosine of the angle between the vectors. With the `simd` feature the dot product and the norms run 16 then 8
+/// lanes at a time and the rest are done one at a time, without it it's all done one at a time.
+#[cfg(feature = "simd")]
+#[inline]
+fn cosine_sim(mut x: &[f32], mut y: &[f32]) -> f32 {
+    let mut d_acc_16 = f32x16::splat(0.0);
+    let mut x_acc_16 = f32x16::splat(0.0);
+    let mut y_acc_16 = f32x16::splat(0.0);
+    while y.len() > 16 {
+        let y_simd = f32x16::from_slice_unaligned(y);
+        let x_simd = f32x16::from_slice_unaligned(x);
+        d_acc_16 += x_simd * y_simd;
+        x_acc_16 += x_simd * x_simd;
+        y_acc_16 += y_simd * y_simd;
+        y = &y[16..];
+        x = &x[16..];
+    }
+    let mut d_acc_8 = f32x8::splat(0.0);
+    let mut x_acc_8 = f32x8::splat(0.0);
+    let mut y_acc_8 = f32x8::splat(0.0);
+    if y.len() > 8 {
+        let y_simd = f32x8::from_slice_unaligned(y);
+        let x_simd = f32x8::from_slice_unaligned(x);
+        d_acc_8 += x_simd * y_simd;
+        x_acc_8 += x_simd * x_simd;
+        y_acc_8 += y_simd * y_simd;
+        y = &y[8..];
+        x = &x[8..];
+    }
+    let (acc_leftover, x_leftover, y_leftover) = cosine_sums_scalar(x, y);
+    let acc = acc_leftover + d_acc_8.sum() + d_acc_16.sum();
+    let xnm = (x_leftover + x_acc_8.sum() + x_acc_16.sum()).sqrt();
+    let ynm = (y_leftover + y_acc_8.sum() + y_acc_16.sum()).sqrt();
+    acc / (xnm * ynm).max(0.00001)
+}
+
+#[cfg(not(feature = "simd"))]
+#[inline]
+fn cosine_sim(x: &[f32], y: &[f32]) -> f32 {
+    let (acc, x_sum, y_sum) = cosine_sums_scalar(x, y);
+    acc / (x_sum.sqrt() * y_sum.sqrt()).max(0.00001)
+}
+
+/// The dot product and the two sums of squares.
+#[inline]
+fn cosine_sums_scalar<T: Float>(x: &[T], y: &[T]) -> (T, T, T) {
+    let acc = y
+        .iter()
+        .zip(x)
+        .map(|(xi, yi)| *xi * *yi)
+        .fold(T::zero(), |acc, y| acc + y);
+    let y_sum = y.iter().map(|yi| *yi * *yi).fold(T::zero(), |acc, yi| acc + yi);
+    let x_sum = x.iter().map(|xi| *xi * *xi).fold(T::zero(), |acc, xi| acc + xi);
+    (acc, x_sum, y_sum)
+}
+
+/// The angular distance, the angle in radians between the two vectors. Unlike `1 - CosineSim` this is a true metric
+/// (on the unit sphere), so it's safe to build a tree with it. Scale doesn't matter, `x` and `2x` are distance 0 apart.
+#[derive(Debug)]
+pub struct Cosine<T: Scalar = f32> {
+    scalar: PhantomData<T>,
+}
+
+impl<T: Scalar> Metric for Cosine<T> {
+    type Scalar = T;
+    const NAME: &'static str = "Cosine";
+
+    #[inline]
+    fn dense(x: &[T], y: &[T]) -> T {
+        CosineSim::<T>::dense(x, y).max(-T::one()).min(T::one()).acos()
+    }
+
+    fn norm(_x: &[T]) -> T {
+        T::zero()
+    }
+
+    fn sparse(x_ind: &[u32], x_val: &[T], y_ind: &[u32], y_val: &[T]) -> T {
+        CosineSim::<T>::sparse(x_ind, x_val, y_ind, y_val)
+            .max(-T::one())
+            .min(T::one())
+            .acos()
+    }
+}
+
+/// The Mahalanobis distance, `sqrt((x-y)^T P (x-y))` for a positive semi-definite precision matrix `P`. With a diagonal
+/// `P` this is L2 with each dimension weighted, so features on very different scales can be evened out.
+///
+/// A metric is a type parameter without any state, so the matrix comes from the type `P`, see `PrecisionMatrix`. Each
+/// matrix is its own metric type, so a tree built with one can't be queried with another, and the matrix can't change
+/// under a built tree. With a `P` that's only semi-definite distinct points can be distance 0 apart, the tree treats
+/// them like copies of one point.
+///
+/// The vectors have to have the dimension of the matrix, and are `f32`s.
+#[derive(Debug)]
+pub struct Mahalanobis<P: PrecisionMatrix> {
+    precision: PhantomData<P>,
+}
+
+/// Supplies the matrix of a `Mahalanobis` metric. Implement this on an empty struct, build the `Precision` once, with
+/// `Precision::weights` or `Precision::matrix`, and hand out a reference to it, from a `std::sync::OnceLock` say.
+///
+/// ```
+/// # use pointcloud::*;
+/// use std::sync::OnceLock;
+/// #[derive(Debug)]
+/// struct Scaled {}
+/// impl PrecisionMatrix for Scaled {
+///     fn precision() -> &'static Precision {
+///         static PRECISION: OnceLock<Precision> = OnceLock::new();
+///         PRECISION.get_or_init(|| Precision::weights(&[4.0, 1.0]).unwrap())
+///     }
+/// }
+/// assert_eq!(Mahalanobis::<Scaled>::dense(&[1.0, 0.0], &[0.0, 0.0]), 2.0);
+/// ```
+pub trait PrecisionMatrix: 'static + Send + Sync + Debug {
+    /// The matrix. It has to be the same every time it's asked for.
+    fn precision() -> &'static Precision;
+}
+
+/// A validated precision matrix for `Mahalanobis`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Precision {
+    /// A diagonal matrix, by its diagonal
+    Diagonal(Vec<f32>),
+    /// A lower triangular `L`, row major, with `P = L L^T`
+    Factor {
+        /// The dimension of the matrix
+        dim: usize,
+        /// The entries of `L`, row major
+        lower: Vec<f32>,
+    },
+}
+
+impl Precision {
+    /// The diagonal matrix with these weights. They have to be finite and non-negative, otherwise this is an
+    /// `InvalidMetric` error.
+    pub fn weights(weights: &[f32]) -> PointCloudResult<Precision> {
+        if weights.iter().any(|w| !(w.is_finite() && *w >= 0.0)) {
+            return Err(PointCloudError::InvalidMetric {
+                message: "the weights have to be finite and non-negative",
+            });
+        }
+        Ok(Precision::Diagonal(weights.to_vec()))
+    }
+
+    /// The `dim` by `dim` row major `matrix`. It has to be symmetric and positive semi-definite, otherwise this is an
+    /// `InvalidMetric` error. It's factored here, as `L L^T` with a lower triangular `L`, so a distance is
+    /// `||L^T (x-y)||` which can't come out negative from rounding.
+    pub fn matrix(matrix: &[f32], dim: usize) -> PointCloudResult<Precision> {
+        if matrix.len() != dim * dim || matrix.iter().any(|x| !x.is_finite()) {
+            return Err(PointCloudError::InvalidMetric {
+                message: "the precision matrix has to be dim by dim and finite",
+            });
+        }
+        let scale = matrix.iter().fold(0.0f64, |m, x| m.max((*x as f64).abs()));
+        let tolerance = 1e-6 * scale.max(std::f64::MIN_POSITIVE);
+        for i in 0..dim {
+            for j in 0..i {
+                if ((matrix[i * dim + j] - matrix[j * dim + i]) as f64).abs() > tolerance {
+                    return Err(PointCloudError::InvalidMetric {
+                        message: "the precision matrix has to be symmetric",
+                    });
+                }
+            }
+        }
+        // A Cholesky factorization that allows zero pivots. A negative pivot, or a zero one with the rest of its column
+        // not zero, means the matrix has a negative eigenvalue.
+        let mut lower = vec![0.0f64; dim * dim];
+        for j in 0..dim {
+            let pivot = matrix[j * dim + j] as f64 - (0..j).map(|k| lower[j * dim + k].powi(2)).sum::<f64>();
+            if pivot < -tolerance {
+                return Err(PointCloudError::InvalidMetric {
+                    message: "the precision matrix has to be positive semi-definite",
+                });
+            }
+            let root = if pivot > tolerance { pivot.sqrt() } else { 0.0 };
+            lower[j * dim + j] = root;
+            for i in (j + 1)..dim {
+                let rest = matrix[i * dim + j] as f64
+                    - (0..j).map(|k| lower[i * dim + k] * lower[j * dim + k]).sum::<f64>();
+                if root > 0.0 {
+                    lower[i * dim + j] = rest / root;
+                } else if rest.abs() > tolerance {
+                    return Err(PointCloudError::InvalidMetric {
+                        message: "the precision matrix has to be positive semi-definite",
+                    });
+                }
+            }
+        }
+        Ok(Precision::Factor {
+            dim,
+            lower: lower.into_iter().map(|x| x as f32).collect(),
+        })
+    }
+
+    /// The dimension of the matrix
+    pub fn dim(&self) -> usize {
+        match self {
+            Precision::Diagonal(weights) => weights.len(),
+            Precision::Factor { dim, .. } => *dim,
         }
     }
-}
\ No newline at end of file
+
+    fn squared_norm_of<I: Iterator<Item = f32>>(&self, diff: I, dim: usize) -> f32 {
+        assert_eq!(self.dim(), dim, "The vectors don't have the dimension of the precision matrix");
+        match self {
+            Precision::Diagonal(weights) => diff.zip(weights).map(|(d, w)| w * d * d).sum(),
+            Precision::Factor { lower, .. } => {
+                let diff: Vec<f32> = diff.collect();
+                // (L^T d)_j is the sum down the jth column of L, from the diagonal
+                (0..dim)
+                    .map(|j| {
+                        let x: f32 = (j..dim).map(|i| lower[i * dim + j] * diff[i]).sum();
+                        x * x
+                    })
+                    .sum()
+            }
+        }
+    }
+
+    /// Spreads a sparse vector out to a dense one of the matrix's dimension.
+    fn densify(&self, ind: &[u32], val: &[f32]) -> Vec<f32> {
+        let mut dense = vec![0.0; self.dim()];
+        for (i, v) in ind.iter().zip(val) {
+            dense[*i as usize] = *v;
+        }
+        dense
+    }
+}
+
+impl<P: PrecisionMatrix> Metric for Mahalanobis<P> {
+    type Scalar = f32;
+    const NAME: &'static str = "Mahalanobis";
+
+    fn dense(x: &[f32], y: &[f32]) -> f32 {
+        P::precision()
+            .squared_norm_of(x.iter().zip(y).map(|(a, b)| a - b), x.len())
+            .sqrt()
+    }
+
+    fn norm(x: &[f32]) -> f32 {
+        P::precision().squared_norm_of(x.iter().cloned(), x.len()).sqrt()
+    }
+
+    fn sparse(x_ind: &[u32], x_val: &[f32], y_ind: &[u32], y_val: &[f32]) -> f32 {
+        let precision = P::precision();
+        let x = precision.densify(x_ind, x_val);
+        let y = precision.densify(y_ind, y_val);
+        Self::dense(&x, &y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+    use std::sync::OnceLock;
+
+    #[test]
+    fn cosine_is_the_angle() {
+        let x = [1.0, 0.0];
+        let y = [0.0, 3.0];
+        let z = [1.0, 1.0];
+        assert_approx_eq!(Cosine::dense(&x, &x), 0.0);
+        assert_approx_eq!(Cosine::dense(&x, &[2.0, 0.0]), 0.0);
+        assert_approx_eq!(Cosine::dense(&x, &y), PI / 2.0);
+        assert_approx_eq!(Cosine::dense(&x, &[-1.0, 0.0]), PI);
+        assert_approx_eq!(Cosine::dense(&x, &z) + Cosine::dense(&z, &y), Cosine::dense(&x, &y));
+    }
+
+    #[test]
+    fn cosine_sparse_matches_dense() {
+        let x = [1.0, 0.0, 2.0, 0.0, 1.0];
+        let y = [0.0, 3.0, 1.0, 0.0, 1.0];
+        let sparse = Cosine::sparse(&[0, 2, 4], &[1.0, 2.0, 1.0], &[1, 2, 4], &[3.0, 1.0, 1.0]);
+        assert_approx_eq!(sparse, Cosine::dense(&x, &y));
+    }
+
+    #[test]
+    fn l2_matches_the_scalar_sums() {
+        // A small LCG so the vectors are random but the same every run
+        let mut state: u32 = 12345;
+        let mut random = || {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        for dim in (0..40).chain(vec![128, 512, 1000]) {
+            let x: Vec<f32> = (0..dim).map(|_| random()).collect();
+            let y: Vec<f32> = (0..dim).map(|_| random()).collect();
+            let tolerance = 1.0e-5 * (1.0 + dim as f32).sqrt();
+            assert_approx_eq!(L2::dense(&x, &y), squared_l2_scalar(&x, &y).sqrt(), tolerance);
+            assert_approx_eq!(L2::norm(&x), squared_norm_scalar(&x).sqrt(), tolerance);
+        }
+    }
+
+    #[test]
+    fn l1_linfty_and_cosine_match_the_scalar_loops() {
+        let mut state: u32 = 23456;
+        let mut random = || {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        for dim in (1..40).chain(vec![127, 513, 1001]) {
+            let x: Vec<f32> = (0..dim).map(|_| random()).collect();
+            let y: Vec<f32> = (0..dim).map(|_| random()).collect();
+            let tolerance = 1.0e-5 * (1.0 + dim as f32);
+            assert_approx_eq!(l1(&x, &y), l1_scalar(&x, &y), tolerance);
+            assert_approx_eq!(l1_norm(&x), l1_norm_scalar(&x), tolerance);
+            assert_eq!(linfty(&x, &y), linfty_scalar(&x, &y));
+            assert_eq!(linfty_norm(&x), linfty_norm_scalar(&x));
+            let (acc, x_sum, y_sum) = cosine_sums_scalar(&x, &y);
+            assert_approx_eq!(cosine_sim(&x, &y), acc / (x_sum.sqrt() * y_sum.sqrt()), 1.0e-4);
+        }
+    }
+
+    #[test]
+    fn l2_ranks_are_squares_that_undo_to_the_distance() {
+        let mut state: u32 = 54321;
+        let mut random = || {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        for dim in (1..20).chain(vec![128, 1000]) {
+            let x: Vec<f32> = (0..dim).map(|_| random()).collect();
+            let y: Vec<f32> = (0..dim).map(|_| random()).collect();
+            let rank = L2::dense_rank(&x, &y);
+            assert_eq!(L2::rank_to_distance(rank), L2::dense(&x, &y));
+            assert!(rank <= L2::rank_bound(L2::dense(&x, &y)));
+        }
+        assert_eq!(L1::rank_to_distance(L1::dense_rank(&[1.0, 2.0], &[0.0, 0.0])), 3.0);
+    }
+
+    #[test]
+    fn f64_metrics_match_the_f32_ones() {
+        let mut state: u32 = 65432;
+        let mut random = || {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        for dim in (1..20).chain(vec![128, 1000]) {
+            let x: Vec<f32> = (0..dim).map(|_| random()).collect();
+            let y: Vec<f32> = (0..dim).map(|_| random()).collect();
+            let wide_x: Vec<f64> = x.iter().map(|v| *v as f64).collect();
+            let wide_y: Vec<f64> = y.iter().map(|v| *v as f64).collect();
+            let tolerance = 1.0e-5 * (1.0 + dim as f64);
+            assert_approx_eq!(L2::<f64>::dense(&wide_x, &wide_y), L2::<f32>::dense(&x, &y) as f64, tolerance);
+            assert_approx_eq!(L1::<f64>::dense(&wide_x, &wide_y), L1::<f32>::dense(&x, &y) as f64, tolerance);
+            assert_approx_eq!(Linfty::<f64>::dense(&wide_x, &wide_y), Linfty::<f32>::dense(&x, &y) as f64, tolerance);
+            assert_approx_eq!(Cosine::<f64>::dense(&wide_x, &wide_y), Cosine::<f32>::dense(&x, &y) as f64, 1.0e-3);
+            let rank = L2::<f64>::dense_rank(&wide_x, &wide_y);
+            assert!(rank <= L2::<f64>::rank_bound(L2::<f64>::dense(&wide_x, &wide_y)));
+        }
+        // Past the precision of an f32
+        assert_eq!(L1::<f64>::dense(&[1.0e9 + 1.0], &[1.0e9]), 1.0);
+    }
+
+    #[derive(Debug)]
+    struct Weighted {}
+    impl PrecisionMatrix for Weighted {
+        fn precision() -> &'static Precision {
+            static PRECISION: OnceLock<Precision> = OnceLock::new();
+            PRECISION.get_or_init(|| Precision::weights(&[4.0, 1.0]).unwrap())
+        }
+    }
+
+    #[derive(Debug)]
+    struct Correlated {}
+    impl PrecisionMatrix for Correlated {
+        fn precision() -> &'static Precision {
+            static PRECISION: OnceLock<Precision> = OnceLock::new();
+            PRECISION.get_or_init(|| Precision::matrix(&[2.0, 1.0, 1.0, 2.0], 2).unwrap())
+        }
+    }
+
+    #[derive(Debug)]
+    struct Degenerate {}
+    impl PrecisionMatrix for Degenerate {
+        fn precision() -> &'static Precision {
+            static PRECISION: OnceLock<Precision> = OnceLock::new();
+            PRECISION.get_or_init(|| Precision::matrix(&[1.0, 1.0, 1.0, 1.0], 2).unwrap())
+        }
+    }
+
+    #[test]
+    fn mahalanobis_weights_and_matrices() {
+        assert!(Precision::weights(&[1.0, -1.0]).is_err());
+        assert!(Precision::matrix(&[1.0, 2.0, 2.0, 1.0], 2).is_err());
+        assert!(Precision::matrix(&[1.0, 0.5, 0.0, 1.0], 2).is_err());
+        assert!(Precision::matrix(&[1.0, 0.0, 0.0], 2).is_err());
+
+        assert_approx_eq!(Mahalanobis::<Weighted>::dense(&[1.0, 0.0], &[0.0, 0.0]), 2.0);
+        assert_approx_eq!(Mahalanobis::<Weighted>::dense(&[0.0, 1.0], &[0.0, 0.0]), 1.0);
+        assert_approx_eq!(Mahalanobis::<Weighted>::sparse(&[0], &[1.0], &[1], &[1.0]), 5.0f32.sqrt());
+
+        assert_approx_eq!(Mahalanobis::<Correlated>::dense(&[1.0, 1.0], &[0.0, 0.0]), 6.0f32.sqrt());
+        assert_approx_eq!(Mahalanobis::<Correlated>::norm(&[1.0, -1.0]), 2.0f32.sqrt());
+        // Only semi-definite, so the two points are distance 0 apart
+        assert_approx_eq!(Mahalanobis::<Degenerate>::dense(&[1.0, -1.0], &[0.0, 0.0]), 0.0);
+        assert_approx_eq!(Mahalanobis::<Degenerate>::dense(&[1.0, 1.0], &[0.0, 0.0]), 2.0);
+    }
+}
@@ -47,6 +47,26 @@ pub enum PointCloudError {
         /// Exact nesting error
         message: &'static str,
     },
+    /// The parameters given to a metric don't make a metric, like a precision matrix that isn't positive semi-definite
+    InvalidMetric {
+        /// What's wrong with the parameters
+        message: &'static str,
+    },
+    /// The point cloud was loaded without its vectors, so the point at this index can't be read and no distance can be
+    /// computed, see `PointCloud::without_vectors`
+    VectorsNotLoaded {
+        /// Index of the point that was asked for
+        index: usize,
+    },
+    /// The metadata of a new point doesn't fit the cloud's labels, see `LabelScheme::check`
+    MetadataMismatch {
+        /// The key of the label scheme that doesn't fit
+        key: String,
+        /// The kind of value the scheme has at the key
+        expected: String,
+        /// The kind of value the metadata has at the key, `"nothing"` if it has none
+        got: String,
+    },
 }
 
 impl fmt::Display for PointCloudError {
@@ -64,6 +84,15 @@ impl fmt::Display for PointCloudError {
             &PointCloudError::NodeNestingError { .. } => {
                 write!(f,"There is a temporary node in a working tree")
             }
+            &PointCloudError::InvalidMetric { message } => {
+                write!(f,"the metric's parameters are invalid, {}", message)
+            }
+            &PointCloudError::VectorsNotLoaded { index } => {
+                write!(f,"the point cloud was loaded without its vectors, point {} can't be read. Load the tree with its data to compute distances", index)
+            }
+            &PointCloudError::MetadataMismatch { ref key, ref expected, ref got } => {
+                write!(f,"the metadata doesn't fit the labels, the label {} is {} but the metadata has {}", key, expected, got)
+            }
         }
     }
 }
@@ -84,6 +113,15 @@ impl Error for PointCloudError {
             &PointCloudError::NodeNestingError { .. } => {
                 "There is a temporary node in a working tree"
             }
+            &PointCloudError::InvalidMetric { .. } => {
+                "the metric's parameters are invalid"
+            }
+            &PointCloudError::VectorsNotLoaded { .. } => {
+                "the point cloud was loaded without its vectors"
+            }
+            &PointCloudError::MetadataMismatch { .. } => {
+                "the metadata doesn't fit the labels"
+            }
         }
     }
 
@@ -94,6 +132,9 @@ impl Error for PointCloudError {
             &PointCloudError::DataAccessError { .. } => None,
             &PointCloudError::NameNotInTree { .. } => None,
             &PointCloudError::NodeNestingError { .. } => None,
+            &PointCloudError::InvalidMetric { .. } => None,
+            &PointCloudError::VectorsNotLoaded { .. } => None,
+            &PointCloudError::MetadataMismatch { .. } => None,
         }
     }
 }
@@ -220,12 +220,9 @@ impl MetadataList {
         }
     }
 
-    pub(crate) fn get_name(&self, i: usize) -> Option<String> {
-        if self.names.len() > 0 {
-            Some(self.names[&i].clone())
-        } else {
-            None
-        }
+    /// Hands over the names, leaving the list without any. The point cloud keeps the names in its own table.
+    pub(crate) fn take_names(&mut self) -> IndexMap<usize, PointName> {
+        std::mem::replace(&mut self.names, IndexMap::new())
     }
 
     /// Grabs the correct value from each columnar data list and adds them to an index map, then returns it to the user.
@@ -259,25 +256,23 @@ impl MetadataList {
     }
 
     /// Appends a the values contained in a metadata list to our metadata.
-    /// Errors out if you are missing a key that the map has. 
+    /// Errors out if you are missing a key that the map has, and then nothing is appended.
     pub fn push(
         &mut self,
         name: Option<PointName>,
         label: Metadata,
     ) -> Result<(), PointCloudError> {
+        if self.lists.keys().any(|k| !label.contains_key(k)) {
+            return Err(PointCloudError::data_access(
+                0,
+                "label access error, no key".to_string(),
+            ));
+        }
         if let Some(n) = name {
             self.names.insert(self.count, n.clone());
         }
         for (k, list) in self.lists.iter_mut() {
-            match label.get(k) {
-                Some(val) => list.push(val.clone()),
-                None => {
-                    return Err(PointCloudError::data_access(
-                        0,
-                        "label access error, no key".to_string(),
-                    ));
-                }
-            };
+            list.push(label[k].clone());
         }
         self.count += 1;
         Ok(())
@@ -126,6 +126,31 @@ impl LabelScheme {
         self.schema.insert(name, Value::Vector(v));
     }
 
+    /// A row of metadata with a blank value for each key, `false`, zero, an empty string or a vector of zeros, for a
+    /// point that has no labels of its own.
+    pub fn blank_metadata(&self) -> Metadata {
+        self.schema.iter().map(|(k, v)| (k.clone(), v.blank())).collect()
+    }
+
+    /// Checks that the metadata has a value for each key of the scheme, of the same kind, and for vectors of the same
+    /// length. Keys the scheme doesn't have are ignored, like `MetadataList::push` does. Returns a `MetadataMismatch`
+    /// for the first key that doesn't fit.
+    pub fn check(&self, metadata: &Metadata) -> Result<(), PointCloudError> {
+        for (k, v) in self.schema.iter() {
+            match metadata.get(k) {
+                Some(value) if v.fits(value) => {}
+                got => {
+                    return Err(PointCloudError::MetadataMismatch {
+                        key: k.clone(),
+                        expected: v.describe(),
+                        got: got.map(|value| value.describe()).unwrap_or_else(|| "nothing".to_string()),
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+
     #[doc(hidden)]
     pub fn empty(&self) -> MetadataList {
         let mut metalist = MetadataList::new();
@@ -168,7 +193,7 @@ impl LabelScheme {
         let count = label.len();
         let mut label_in_ram = Vec::new();
         for i in 0..count {
-            label_in_ram.extend_from_slice(label.get(i).unwrap());
+            label_in_ram.extend_from_slice(&label.get(i).unwrap());
         }
         let mut list = MetadataList::new();
         list.insert(name.clone(), VectorList::from_f32(label_in_ram, labels_dim));
@@ -200,7 +225,7 @@ impl LabelScheme {
     ) -> Result<MetadataList, PointCloudError> {
         let mut count = 0;
         let mut internal_vals: Vec<(String, usize, ValueList)> = Vec::new();
-        let mut names: IndexMap<usize, String> = IndexMap::new();
+        let mut names: IndexMap<usize, crate::PointName> = IndexMap::new();
         let has_name: bool = self.name_column != "";
         let name_index: usize;
         // Get the y indexes
@@ -266,7 +291,7 @@ impl LabelScheme {
             if has_name {
                 match record.get(name_index) {
                     Some(y) => {
-                        names.insert(count, y.to_string());
+                        names.insert(count, y.as_bytes().to_vec());
                     }
                     None => {
                         return Err(PointCloudError::ParsingError(ParsingError::CSVReadError {
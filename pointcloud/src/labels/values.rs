@@ -81,6 +81,49 @@ impl Value {
             },
         }
     }
+    /// The same kind of value with nothing in it, `false`, zero, an empty string, or a vector of zeros of the same length.
+    pub(crate) fn blank(&self) -> Value {
+        match self {
+            Value::Null => Value::Null,
+            Value::Bool(..) => Value::Bool(false),
+            Value::Number(Number::Real(..)) => Value::Number(Number::Real(0.0)),
+            Value::Number(Number::Natural(..)) => Value::Number(Number::Natural(0)),
+            Value::Number(Number::Integer(..)) => Value::Number(Number::Integer(0)),
+            Value::String(..) => Value::String(String::new()),
+            Value::Vector(Vector::Real(v)) => Value::Vector(Vector::Real(vec![0.0; v.len()])),
+            Value::Vector(Vector::Natural(v)) => Value::Vector(Vector::Natural(vec![0; v.len()])),
+            Value::Vector(Vector::Integer(v)) => Value::Vector(Vector::Integer(vec![0; v.len()])),
+        }
+    }
+
+    /// If `other` can go in a list of values like this one. The numbers have to be the same kind, and the vectors the
+    /// same kind and length, the lists panic on anything else.
+    pub(crate) fn fits(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Null, _) => true,
+            (Value::Bool(..), Value::Bool(..)) => true,
+            (Value::String(..), Value::String(..)) => true,
+            (Value::Number(a), Value::Number(b)) => std::mem::discriminant(a) == std::mem::discriminant(b),
+            (Value::Vector(a), Value::Vector(b)) => {
+                std::mem::discriminant(a) == std::mem::discriminant(b) && a.len() == b.len()
+            }
+            _ => false,
+        }
+    }
+
+    /// What kind of value this is, with the kind of number and the length of a vector, for errors.
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            Value::Number(Number::Real(..)) => "a Real number".to_string(),
+            Value::Number(Number::Natural(..)) => "a Natural number".to_string(),
+            Value::Number(Number::Integer(..)) => "an Integer number".to_string(),
+            Value::Vector(Vector::Real(v)) => format!("a Real vector of length {}", v.len()),
+            Value::Vector(Vector::Natural(v)) => format!("a Natural vector of length {}", v.len()),
+            Value::Vector(Vector::Integer(v)) => format!("an Integer vector of length {}", v.len()),
+            other => format!("a {}", other.value_type()),
+        }
+    }
+
     /// String name of the value
     pub fn value_type(&self) -> &str {
         match self {
@@ -27,6 +27,9 @@
 
 #[macro_use]
 extern crate serde;
+#[cfg(test)]
+#[macro_use]
+extern crate assert_approx_eq;
 
 mod point_cloud;
 #[doc(inline)]
@@ -39,6 +42,12 @@ pub mod errors;
 pub mod labels;
 pub mod utils;
 
+mod csv_options;
+pub use csv_options::{CsvChunks, CsvColumn, CsvOptions, MissingValuePolicy};
+
+mod names;
+pub use names::NameStorage;
+
 
 pub mod datasources;
 pub use datasources::DataSource;
@@ -46,6 +55,7 @@ pub use datasources::DataSource;
 /// To make things more obvious, we type the point index.
 /// This is abstracted over the files that were used to build the point cloud
 pub type PointIndex = u64;
-/// To make things more obvious, we type the point name that we pull from the label CSV
-pub type PointName = String;
+/// To make things more obvious, we type the point name that we pull from the label CSV. Names are raw bytes so binary
+/// ids, like hashes, can be used. A textual name is its utf8 bytes, and anything that takes a name takes a `&str` too.
+pub type PointName = Vec<u8>;
 
@@ -0,0 +1,289 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! The names of the points of a `PointCloud`, kept as a vector each or interned into one pool.
+
+use crate::{PointIndex, PointName};
+use indexmap::IndexMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::mem;
+
+/// How a `PointCloud` keeps the names of its points, see `PointCloud::set_name_storage`. Queries return the same names
+/// either way, this only changes the memory they take.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NameStorage {
+    /// Each name is a `Vec<u8>` of its own, kept twice, once for each direction of the lookup. The default. Names are
+    /// looked up in both directions with a hash.
+    Owned,
+    /// The names are copied end to end into one shared buffer, and each point keeps the span of its name there. A
+    /// name is then stored once, without the two vector headers and two allocations per point. Finding a point by its
+    /// name goes through a map from a 64 bit hash of the name to the point, so it's still a hash and one comparison of
+    /// the name. For names of a few dozen bytes, like hex digests or paths, this is less than half the memory of `Owned`.
+    /// Interning costs a copy of every name, once, when the names are interned.
+    Interned,
+}
+
+/// The estimated heap bytes of an `IndexMap`, its entries are counted as their keys and values plus two words.
+pub(crate) fn index_map_bytes<K, V>(map: &IndexMap<K, V>) -> usize {
+    map.capacity() * (mem::size_of::<K>() + mem::size_of::<V>() + 2 * mem::size_of::<usize>())
+}
+
+/// The names of the points in both directions, in either `NameStorage`.
+#[derive(Debug, Clone)]
+pub(crate) enum NameTable {
+    Owned {
+        names_to_indexes: IndexMap<PointName, PointIndex>,
+        indexes_to_names: IndexMap<PointIndex, PointName>,
+    },
+    Interned(NamePool),
+}
+
+impl NameTable {
+    /// An empty table with owned names
+    pub(crate) fn new() -> NameTable {
+        NameTable::Owned {
+            names_to_indexes: IndexMap::new(),
+            indexes_to_names: IndexMap::new(),
+        }
+    }
+
+    pub(crate) fn storage(&self) -> NameStorage {
+        match self {
+            NameTable::Owned { .. } => NameStorage::Owned,
+            NameTable::Interned(_) => NameStorage::Interned,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            NameTable::Owned { indexes_to_names, .. } => indexes_to_names.len(),
+            NameTable::Interned(pool) => pool.spans.len(),
+        }
+    }
+
+    pub(crate) fn get_name(&self, pi: &PointIndex) -> Option<&[u8]> {
+        match self {
+            NameTable::Owned { indexes_to_names, .. } => indexes_to_names.get(pi).map(|name| &name[..]),
+            NameTable::Interned(pool) => pool.name(pi),
+        }
+    }
+
+    pub(crate) fn get_index(&self, name: &[u8]) -> Option<&PointIndex> {
+        match self {
+            NameTable::Owned { names_to_indexes, .. } => names_to_indexes.get(name),
+            NameTable::Interned(pool) => pool.index(name),
+        }
+    }
+
+    /// Names the point, replacing its old name if it had one. The caller checks no other point has the name.
+    pub(crate) fn insert(&mut self, pi: PointIndex, name: PointName) {
+        match self {
+            NameTable::Owned {
+                names_to_indexes,
+                indexes_to_names,
+            } => {
+                if let Some(old_name) = indexes_to_names.insert(pi, name.clone()) {
+                    names_to_indexes.swap_remove(&old_name);
+                }
+                names_to_indexes.insert(name, pi);
+            }
+            NameTable::Interned(pool) => pool.insert(pi, &name),
+        }
+    }
+
+    /// All the names, in the order their points were named
+    pub(crate) fn names(&self) -> Vec<PointName> {
+        match self {
+            NameTable::Owned { names_to_indexes, .. } => names_to_indexes.keys().cloned().collect(),
+            NameTable::Interned(pool) => pool.spans.keys().filter_map(|pi| pool.name(pi)).map(|n| n.to_vec()).collect(),
+        }
+    }
+
+    /// The same names kept the other way, or these if they're already kept that way.
+    pub(crate) fn into_storage(self, storage: NameStorage) -> NameTable {
+        if self.storage() == storage {
+            return self;
+        }
+        match self {
+            NameTable::Owned { indexes_to_names, .. } => NameTable::Interned(NamePool::from_names(indexes_to_names)),
+            NameTable::Interned(pool) => {
+                let mut table = NameTable::new();
+                for pi in pool.spans.keys() {
+                    if let Some(name) = pool.name(pi) {
+                        table.insert(*pi, name.to_vec());
+                    }
+                }
+                table
+            }
+        }
+    }
+
+    /// An estimate of the bytes the names hold on the heap
+    pub(crate) fn heap_bytes(&self) -> usize {
+        match self {
+            NameTable::Owned {
+                names_to_indexes,
+                indexes_to_names,
+            } => {
+                index_map_bytes(names_to_indexes)
+                    + index_map_bytes(indexes_to_names)
+                    + names_to_indexes.keys().map(|name| name.capacity()).sum::<usize>()
+                    + indexes_to_names.values().map(|name| name.capacity()).sum::<usize>()
+            }
+            NameTable::Interned(pool) => {
+                pool.bytes.capacity()
+                    + index_map_bytes(&pool.spans)
+                    + pool.by_hash.capacity() * (mem::size_of::<u64>() + mem::size_of::<PointIndex>() + 1)
+                    + pool.collisions.values().map(|c| c.capacity() * mem::size_of::<PointIndex>()).sum::<usize>()
+            }
+        }
+    }
+}
+
+/// The interned names. A renamed point's old name is left in the buffer until half of it is stale, then the buffer is
+/// rewritten with only the live names.
+#[derive(Debug, Clone)]
+pub(crate) struct NamePool {
+    bytes: Vec<u8>,
+    /// The start and end of each point's name in `bytes`
+    spans: IndexMap<PointIndex, (usize, usize)>,
+    /// A point for each hash of a name
+    by_hash: HashMap<u64, PointIndex>,
+    /// The other points, when names hash the same
+    collisions: HashMap<u64, Vec<PointIndex>>,
+    stale_bytes: usize,
+}
+
+fn hash_name(name: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl NamePool {
+    fn from_names(names: IndexMap<PointIndex, PointName>) -> NamePool {
+        let mut pool = NamePool {
+            bytes: Vec::with_capacity(names.values().map(|name| name.len()).sum()),
+            spans: IndexMap::with_capacity(names.len()),
+            by_hash: HashMap::with_capacity(names.len()),
+            collisions: HashMap::new(),
+            stale_bytes: 0,
+        };
+        for (pi, name) in names {
+            pool.insert(pi, &name);
+        }
+        pool
+    }
+
+    fn name(&self, pi: &PointIndex) -> Option<&[u8]> {
+        self.spans.get(pi).map(|(start, end)| &self.bytes[*start..*end])
+    }
+
+    fn index(&self, name: &[u8]) -> Option<&PointIndex> {
+        let hash = hash_name(name);
+        let first = self.by_hash.get(&hash)?;
+        if self.name(first) == Some(name) {
+            return Some(first);
+        }
+        self.collisions.get(&hash)?.iter().find(|qi| self.name(qi) == Some(name))
+    }
+
+    fn unhash(&mut self, pi: PointIndex, hash: u64) {
+        if self.by_hash.get(&hash) == Some(&pi) {
+            let next = self.collisions.get_mut(&hash).and_then(|others| others.pop());
+            if self.collisions.get(&hash).map(|others| others.is_empty()).unwrap_or(false) {
+                self.collisions.remove(&hash);
+            }
+            match next {
+                Some(qi) => self.by_hash.insert(hash, qi),
+                None => self.by_hash.remove(&hash),
+            };
+        } else if let Some(others) = self.collisions.get_mut(&hash) {
+            others.retain(|qi| *qi != pi);
+            if others.is_empty() {
+                self.collisions.remove(&hash);
+            }
+        }
+    }
+
+    fn insert(&mut self, pi: PointIndex, name: &[u8]) {
+        if let Some((start, end)) = self.spans.get(&pi).cloned() {
+            let old_hash = hash_name(&self.bytes[start..end]);
+            self.unhash(pi, old_hash);
+            self.stale_bytes += end - start;
+        }
+        let start = self.bytes.len();
+        self.bytes.extend_from_slice(name);
+        self.spans.insert(pi, (start, self.bytes.len()));
+        let hash = hash_name(name);
+        match self.by_hash.entry(hash) {
+            Entry::Occupied(_) => self.collisions.entry(hash).or_default().push(pi),
+            Entry::Vacant(slot) => {
+                slot.insert(pi);
+            }
+        }
+        if 2 * self.stale_bytes > self.bytes.len() {
+            self.compact();
+        }
+    }
+
+    fn compact(&mut self) {
+        let mut bytes = Vec::with_capacity(self.bytes.len() - self.stale_bytes);
+        for span in self.spans.values_mut() {
+            let start = bytes.len();
+            bytes.extend_from_slice(&self.bytes[span.0..span.1]);
+            *span = (start, bytes.len());
+        }
+        self.bytes = bytes;
+        self.stale_bytes = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interned_names_look_up_like_owned_ones() {
+        let mut owned = NameTable::new();
+        for pi in 0..1000 {
+            owned.insert(pi, format!("{:064x}", pi * 7919).into_bytes());
+        }
+        let mut interned = owned.clone().into_storage(NameStorage::Interned);
+        assert!(2 * interned.heap_bytes() < owned.heap_bytes());
+        // Renaming leaves stale bytes behind until the pool compacts
+        for round in 0..3 {
+            for pi in (0..1000).step_by(3) {
+                let name = format!("renamed {} {}", round, pi).into_bytes();
+                owned.insert(pi, name.clone());
+                interned.insert(pi, name);
+            }
+        }
+        for pi in 0..1000 {
+            let name = owned.get_name(&pi).unwrap();
+            assert_eq!(interned.get_name(&pi), Some(name));
+            assert_eq!(interned.get_index(name), Some(&pi));
+        }
+        assert_eq!(interned.get_index(b"renamed 0 0"), None);
+        assert_eq!(interned.len(), 1000);
+    }
+}
@@ -26,13 +26,16 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::fmt;
+use std::borrow::Cow;
 
 use glob::{glob_with, MatchOptions};
-use std::io::Read;
+use std::io::{self, Read};
 use yaml_rust::{Yaml, YamlLoader};
 use std::marker::PhantomData;
+use std::mem;
 use std::cmp::min;
 use rayon::prelude::*;
+use num_traits::Zero;
 
 use super::*;
 use super::errors::*;
@@ -42,19 +45,20 @@ use crate::datasources::*;
 use crate::datasources::DataSource;
 use super::distances::*;
 use crate::utils::*;
+use crate::csv_options::read_points;
+use crate::names::{index_map_bytes, NameStorage, NameTable};
 
 /// This abstracts away data access and the distance calculation. It handles both the labels and
 /// points.
 ///
-pub struct PointCloud<M: Metric> {
+pub struct PointCloud<M: Metric = L2> {
     addresses: IndexMap<PointIndex, (usize,usize)>,
-    names_to_indexes: IndexMap<PointName, PointIndex>,
-    indexes_to_names: IndexMap<PointIndex, PointName>,
+    names: NameTable,
 
-    data_sources: Vec<Box<dyn DataSource>>,
-    label_sources: Vec<MetadataList>,
+    data_sources: Vec<Arc<dyn DataSource<M::Scalar>>>,
+    label_sources: Vec<Arc<MetadataList>>,
 
-    loaded_centers: Mutex<IndexMap<PointIndex, Arc<Vec<f32>>>>,
+    loaded_centers: Mutex<IndexMap<PointIndex, Arc<Vec<M::Scalar>>>>,
     data_dim: usize,
     labels_scheme: LabelScheme,
     chunk:usize,
@@ -82,48 +86,51 @@ impl<M: Metric> PointCloud<M> {
         ram: bool,
     ) -> PointCloudResult<PointCloud<M>> {
         if data_path.len() != labels_path.len() {
-            panic!(
-                "Mismatch of label and data paths Data: {:?}, Labels: {:?}",
-                data_path, labels_path
-            );
+            return Err(PointCloudError::IoError(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Mismatch of label and data paths Data: {:?}, Labels: {:?}", data_path, labels_path),
+            )));
         }
         let mut addresses = IndexMap::new();
-        let mut names_to_indexes: IndexMap<PointName, PointIndex> = IndexMap::new();
-        let mut indexes_to_names: IndexMap<PointIndex, PointName> = IndexMap::new();
+        let mut names = NameTable::new();
         let mut current_count: u64 = 0;
         let mut data_sources = Vec::new();
         let mut label_sources = Vec::new();
         for (i,(dp,lp)) in data_path.iter().zip(labels_path).enumerate() {
-            let new_data: Box<dyn DataSource>;
+            let new_data: Arc<dyn DataSource<M::Scalar>>;
             if ram {
-                new_data = Box::new((DataMemmap::new(data_dim, &dp)?).convert_to_ram());
+                new_data = Arc::new((DataMemmap::new(data_dim, &dp)?).convert_to_ram());
             } else {
-                new_data = Box::new(DataMemmap::new(data_dim, &dp)?);
+                new_data = Arc::new(DataMemmap::new(data_dim, &dp)?);
             }
-            let new_labels = labels_scheme.open(&lp)?;
+            let mut new_labels = labels_scheme.open(&lp)?;
             if new_data.len() != new_labels.len() {
-                panic!("The data count {:?} differs from the label count {:?} for the {}th data and label files", new_data.len(), new_labels.len(), i);
+                return Err(PointCloudError::IoError(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("The data count {:?} differs from the label count {:?} for the {}th data and label files", new_data.len(), new_labels.len(), i),
+                )));
             }
+            // The names move into the name table, so they aren't kept twice
+            let mut list_names = new_labels.take_names();
             for j in 0..new_data.len() {
                 let x = (i, j);
-                let name = new_labels
-                    .get_name(j)
-                    .unwrap_or_else(|| format!("{}", current_count));
+                let name = list_names
+                    .swap_remove(&j)
+                    .unwrap_or_else(|| format!("{}", current_count).into_bytes());
 
-                if names_to_indexes.contains_key(&name) {
+                if names.get_index(&name).is_some() {
                     println!(
                         "Duplicate {:?} on line {} of file {:?}",
-                        &name, j, labels_path[i]
+                        String::from_utf8_lossy(&name), j, labels_path[i]
                     );
                 } else {
-                    names_to_indexes.insert(name.clone(), current_count);
-                    indexes_to_names.insert(current_count, name.clone());
+                    names.insert(current_count, name);
                     addresses.insert(current_count, x);
                 }
                 current_count += 1;
             }
             data_sources.push(new_data);
-            label_sources.push(new_labels);
+            label_sources.push(Arc::new(new_labels));
         }
 
         // This could possibly be improved to be architecture specific. It depends on the CPU cache size
@@ -131,8 +138,7 @@ impl<M: Metric> PointCloud<M> {
         Ok(PointCloud {
             data_sources: data_sources,
             label_sources: label_sources,
-            names_to_indexes: names_to_indexes,
-            indexes_to_names: indexes_to_names,
+            names,
             addresses: addresses,
             data_dim,
             labels_scheme,
@@ -145,33 +151,50 @@ impl<M: Metric> PointCloud<M> {
     /// Builds the point cloud from data in ram.
     /// This is for complex metadata
     pub fn from_ram(
-        data: Box<[f32]>,
+        data: Box<[M::Scalar]>,
+        data_dim: usize,
+        labels: MetadataList,
+    ) -> PointCloudResult<PointCloud<M>> {
+        PointCloud::from_source(Arc::new(DataRam::new(data_dim, data)?), data_dim, labels)
+    }
+
+    /// Builds the point cloud from `i8` codes in ram, each value being its code times `scale`, see `DataQuantized`. The
+    /// metric and any tree built on the cloud work on the dequantized values. Points added or replaced later are held
+    /// unquantized, and so is everything once the cloud is compacted.
+    pub fn from_quantized(
+        codes: Box<[i8]>,
+        scale: M::Scalar,
+        data_dim: usize,
+        labels: MetadataList,
+    ) -> PointCloudResult<PointCloud<M>> {
+        PointCloud::from_source(Arc::new(DataQuantized::new(data_dim, codes, scale)?), data_dim, labels)
+    }
+
+    fn from_source(
+        data_source: Arc<dyn DataSource<M::Scalar>>,
         data_dim: usize,
         labels: MetadataList,
     ) -> PointCloudResult<PointCloud<M>> {
         let mut addresses = IndexMap::new();
-        let data_source = Box::new(DataRam::new(data_dim, data)?);
         let labels_scheme = labels.scheme()?;
-        let label_source = labels;
-        let mut names_to_indexes: IndexMap<PointName, PointIndex> = IndexMap::new();
-        let mut indexes_to_names: IndexMap<PointIndex, PointName> = IndexMap::new();
+        let mut label_source = labels;
+        let mut names = NameTable::new();
+        let mut list_names = label_source.take_names();
 
         for j in 0..(data_source.len()) {
-            let name = label_source.get_name(j).unwrap_or_else(|| format!("{}", j));
-            if names_to_indexes.contains_key(&name) {
-                println!("Duplicate {:?} on line {} of file", &name, j);
+            let name = list_names.swap_remove(&j).unwrap_or_else(|| format!("{}", j).into_bytes());
+            if names.get_index(&name).is_some() {
+                println!("Duplicate {:?} on line {} of file", String::from_utf8_lossy(&name), j);
             } else {
-                names_to_indexes.insert(name.clone(), j as PointIndex);
-                indexes_to_names.insert(j as PointIndex, name.clone());
+                names.insert(j as PointIndex, name);
                 addresses.insert(j as u64, (0,j));
             }
         }
         let chunk = min(15000/data_dim,20);
         Ok(PointCloud {
             data_sources: vec![data_source],
-            label_sources: vec![label_source],
-            names_to_indexes: names_to_indexes,
-            indexes_to_names: indexes_to_names,
+            label_sources: vec![Arc::new(label_source)],
+            names,
             addresses: addresses,
             data_dim,
             loaded_centers: Mutex::new(IndexMap::new()),
@@ -206,37 +229,57 @@ impl<M: Metric> PointCloud<M> {
     ///    string: String
     ///    boolean: bool
     /// ```
+    /// A required field that's missing is a `MissingYamlError`. The yaml doesn't know the file it came from, so the
+    /// error's file name is left empty.
     pub fn from_yaml(params: &Yaml) -> PointCloudResult<PointCloud<M>> {
+        let ram_bool = match params["in_ram"].as_bool() {
+            Some(b) => b,
+            None => true,
+        };
+        PointCloud::<M>::from_yaml_with_ram(params, ram_bool)
+    }
+
+    /// The same as `from_yaml`, but the data is always left memmapped and `in_ram` is ignored. The vectors are paged in
+    /// by the OS as they're read, so the data files can be larger than ram.
+    pub fn from_yaml_memmapped(params: &Yaml) -> PointCloudResult<PointCloud<M>> {
+        PointCloud::<M>::from_yaml_with_ram(params, false)
+    }
+
+    fn from_yaml_with_ram(params: &Yaml, ram: bool) -> PointCloudResult<PointCloud<M>> {
+        let missing = |field: &str| {
+            PointCloudError::ParsingError(ParsingError::MissingYamlError {
+                file_name: String::new(),
+                field: field.to_string(),
+            })
+        };
         let data_paths = &get_file_list(
             params["data_path"]
                 .as_str()
-                .expect("Unable to read the 'labels_path'"),
-        );
+                .ok_or_else(|| missing("data_path"))?,
+            "data_path",
+        )?;
         let labels_paths = &get_file_list(
             params["labels_path"]
                 .as_str()
-                .expect("Unable to read the 'labels_path'"),
-        );
+                .ok_or_else(|| missing("labels_path"))?,
+            "labels_path",
+        )?;
         let data_dim = params["data_dim"]
             .as_i64()
-            .expect("Unable to read the 'data_dim'") as usize;
+            .ok_or_else(|| missing("data_dim"))? as usize;
 
         let mut deser = LabelScheme::new();
         if params["schema"].is_badvalue() {
             let labels_dim = params["labels_dim"]
                 .as_i64()
-                .expect("Unable to read the 'labels_dim' or the 'schema'")
+                .ok_or_else(|| missing("labels_dim"))?
                 as usize;
             deser.add_vector("y".to_string(), labels_dim, "f32");
         } else {
             build_label_schema_yaml(&mut deser, &params["schema"]);
         }
 
-        let ram_bool = match params["in_ram"].as_bool() {
-            Some(b) => b,
-            None => true,
-        };
-        PointCloud::<M>::from_memmap_files(data_dim, deser, data_paths, labels_paths, ram_bool)
+        PointCloud::<M>::from_memmap_files(data_dim, deser, data_paths, labels_paths, ram)
     }
 
     /// Runs `from_yaml` on the file at a given path
@@ -256,7 +299,7 @@ impl<M: Metric> PointCloud<M> {
 
     /// Builds the point cloud from data in ram. This is for quick things with simple metadata
     pub fn simple_from_ram(
-        data: Box<[f32]>,
+        data: Box<[M::Scalar]>,
         data_dim: usize,
         labels: Box<[f32]>,
         labels_dim: usize,
@@ -266,9 +309,54 @@ impl<M: Metric> PointCloud<M> {
         PointCloud::<M>::from_ram(data, data_dim, list)
     }
 
+    /// Builds the point cloud from a CSV where each row is a point, laid out as described by the options. The vectors are
+    /// read into ram. A row that's too short for one of the columns, or a feature that isn't a number, is a
+    /// `CSVReadError` with that row's line number and the column's header, or its position if there's no header. So is
+    /// an empty feature cell, unless the options' `missing_value_policy` fills it in or skips the row.
+    pub fn from_csv<P: AsRef<Path>>(path: P, options: &CsvOptions) -> PointCloudResult<PointCloud<M>> {
+        let data_dim = options.feature_columns.len();
+        if data_dim == 0 {
+            return Err(PointCloudError::ParsingError(ParsingError::RegularParsingError(
+                "The CSV options have no feature columns",
+            )));
+        }
+        let (data, labels) = read_points::<_, M::Scalar>(path, options)?;
+        PointCloud::<M>::from_ram(Box::from(data), data_dim, labels)
+    }
+
+    /// A point cloud with the names and indexes of its points, but none of their vectors, for looking at the shape of a
+    /// tree without paying for its data. Its `len` and `dim` are what the vectors would have, and names resolve as
+    /// usual, but reading a point, and so computing any distance, is a `VectorsNotLoaded` error. There's no metadata.
+    ///
+    /// The names have to use each index and each name once, otherwise this is a parsing error like `set_names`.
+    pub fn without_vectors(
+        data_dim: usize,
+        names: Vec<(PointIndex, PointName)>,
+    ) -> PointCloudResult<PointCloud<M>> {
+        let mut addresses = IndexMap::new();
+        for (j, (pi, _name)) in names.iter().enumerate() {
+            addresses.insert(*pi, (0, j));
+        }
+        let labels_scheme = LabelScheme::new();
+        let chunk = min(15000/data_dim.max(1),20);
+        let mut point_cloud = PointCloud {
+            data_sources: vec![Arc::new(DataAbsent::new(data_dim, names.len())) as Arc<dyn DataSource<M::Scalar>>],
+            label_sources: vec![Arc::new(labels_scheme.empty())],
+            names: NameTable::new(),
+            addresses,
+            data_dim,
+            loaded_centers: Mutex::new(IndexMap::new()),
+            labels_scheme,
+            chunk,
+            metric: PhantomData,
+        };
+        point_cloud.set_names(names)?;
+        Ok(point_cloud)
+    }
+
     /// Total number of points in the point cloud
     pub fn len(&self) -> usize {
-        self.data_sources.iter().fold(0, |acc, mm| acc + mm.len())
+        self.addresses.len()
     }
 
     /// Dimension of the data in the point cloud
@@ -276,15 +364,213 @@ impl<M: Metric> PointCloud<M> {
         self.data_dim
     }
 
+    /// A copy of this point cloud with the vector of `pi` swapped for `point`. Every point keeps its index, name and
+    /// metadata. Like `with_memmap_file` this doesn't copy any vectors, the copy shares the data sources of this cloud
+    /// and adds one holding just the new vector. The maps of names and addresses are copied though, so it's still
+    /// `O(n)` in the number of points, but not in their dimension. The old vector stays in the shared source until
+    /// the replaced vectors outnumber the points, then the copy is compacted into a single ram source, memmapped
+    /// vectors too. So the vectors are copied once every `n` or so changes. Returns a `DataAccessError` if `pi` isn't in
+    /// the cloud or `point` isn't `dim` long.
+    pub fn with_replaced_point(&self, pi: PointIndex, point: &[M::Scalar]) -> PointCloudResult<PointCloud<M>> {
+        if !self.addresses.contains_key(&pi) || point.len() != self.data_dim {
+            return Err(PointCloudError::DataAccessError {
+                index: pi as usize,
+                slice_name: "replacement point".to_string(),
+            });
+        }
+        self.with_source_for(vec![(pi, point, self.get_metadata(pi)?)], self.names.clone())
+    }
+
+    /// A copy of this point cloud with the point at `pi` set to `point`, `name` and `metadata`. If `pi` is already in the
+    /// cloud its vector, name and metadata are all replaced, otherwise it's added after the other points. Every other
+    /// point keeps its index, name and metadata. Like `with_replaced_point` this shares the data sources of this cloud
+    /// and copies the maps of names and addresses. Returns a `DataAccessError` if `point` isn't `dim` long, a parsing
+    /// error if another point has the name, and a `MetadataMismatch` if the metadata doesn't fit the label scheme, see
+    /// `LabelScheme::check`. Use `LabelScheme::blank_metadata` for a point without labels.
+    pub fn with_point(
+        &self,
+        pi: PointIndex,
+        name: PointName,
+        point: &[M::Scalar],
+        metadata: Metadata,
+    ) -> PointCloudResult<PointCloud<M>> {
+        if point.len() != self.data_dim {
+            return Err(PointCloudError::DataAccessError {
+                index: pi as usize,
+                slice_name: "new point".to_string(),
+            });
+        }
+        self.labels_scheme.check(&metadata)?;
+        if self.names.get_index(&name).map(|qi| *qi != pi).unwrap_or(false) {
+            return Err(PointCloudError::ParsingError(ParsingError::RegularParsingError(
+                "A name is used twice",
+            )));
+        }
+        let mut names = self.names.clone();
+        names.insert(pi, name);
+        self.with_source_for(vec![(pi, point, metadata)], names)
+    }
+
+    /// A copy of this point cloud with each of the points set like `with_point` sets one. They all go into one new data
+    /// source, so the maps of names and addresses are copied once for all of them rather than once each. Returns the
+    /// errors of `with_point`, and a parsing error if two of the points have the same name. Nothing is copied if one
+    /// of them is an error.
+    pub fn with_points<'a, I>(&self, points: I) -> PointCloudResult<PointCloud<M>>
+    where
+        I: IntoIterator<Item = (PointIndex, PointName, &'a [M::Scalar], Metadata)>,
+    {
+        let mut names = self.names.clone();
+        let mut new_points = Vec::new();
+        for (pi, name, point, metadata) in points {
+            if point.len() != self.data_dim {
+                return Err(PointCloudError::DataAccessError {
+                    index: pi as usize,
+                    slice_name: "new point".to_string(),
+                });
+            }
+            self.labels_scheme.check(&metadata)?;
+            // The names of the points before this one are already in, so this catches a name used twice among them too
+            if names.get_index(&name).map(|qi| *qi != pi).unwrap_or(false) {
+                return Err(PointCloudError::ParsingError(ParsingError::RegularParsingError(
+                    "A name is used twice",
+                )));
+            }
+            names.insert(pi, name);
+            new_points.push((pi, point, metadata));
+        }
+        self.with_source_for(new_points, names)
+    }
+
+    /// A copy of this point cloud with each `pi` addressed to a new data source holding just the new points and their
+    /// metadata, in order. The other data and label sources are shared with this cloud.
+    fn with_source_for(
+        &self,
+        points: Vec<(PointIndex, &[M::Scalar], Metadata)>,
+        names: NameTable,
+    ) -> PointCloudResult<PointCloud<M>> {
+        let mut new_labels = self.labels_scheme.empty();
+        let mut new_data = Vec::with_capacity(points.len() * self.data_dim);
+        let mut addresses = self.addresses.clone();
+        for (j, (pi, point, metadata)) in points.into_iter().enumerate() {
+            new_labels.push(None, metadata)?;
+            new_data.extend_from_slice(point);
+            addresses.insert(pi, (self.data_sources.len(), j));
+        }
+        let mut data_sources = self.data_sources.clone();
+        data_sources.push(Arc::new(DataRam::new(self.data_dim, Box::from(new_data))?));
+        let mut label_sources = self.label_sources.clone();
+        label_sources.push(Arc::new(new_labels));
+        let point_cloud = PointCloud {
+            data_sources,
+            label_sources,
+            names,
+            addresses,
+            data_dim: self.data_dim,
+            loaded_centers: Mutex::new(IndexMap::new()),
+            labels_scheme: self.labels_scheme.clone(),
+            chunk: self.chunk,
+            metric: PhantomData,
+        };
+        // Once the replaced vectors outnumber the live ones they're dropped, so each change copies `O(1)` vectors on
+        // average and the sources never hold more than twice the points
+        let rows: usize = point_cloud.data_sources.iter().map(|source| source.len()).sum();
+        if rows > 2 * point_cloud.len() {
+            point_cloud.compacted()
+        } else {
+            Ok(point_cloud)
+        }
+    }
+
+    /// A copy of this point cloud with all of the vectors and metadata copied into a single ram source, in address
+    /// order. Every point keeps its index, name and metadata.
+    fn compacted(&self) -> PointCloudResult<PointCloud<M>> {
+        let mut data = Vec::with_capacity(self.addresses.len() * self.data_dim);
+        let mut labels = self.labels_scheme.empty();
+        let mut addresses = IndexMap::new();
+        for (j, qi) in self.addresses.keys().enumerate() {
+            data.extend_from_slice(&self.get_point(*qi)?);
+            labels.push(None, self.get_metadata(*qi)?)?;
+            addresses.insert(*qi, (0, j));
+        }
+        Ok(PointCloud {
+            data_sources: vec![Arc::new(DataRam::new(self.data_dim, Box::from(data))?)],
+            label_sources: vec![Arc::new(labels)],
+            names: self.names.clone(),
+            addresses,
+            data_dim: self.data_dim,
+            loaded_centers: Mutex::new(IndexMap::new()),
+            labels_scheme: self.labels_scheme.clone(),
+            chunk: self.chunk,
+            metric: PhantomData,
+        })
+    }
+
+    /// A copy of this point cloud with the vectors of a memmap file added after the other points. The new points take
+    /// the indexes after the largest index in the cloud, in the order they're in the file, and are named by their index
+    /// like the points of a label file without names. Their metadata is blank, see `LabelScheme::blank_metadata`.
+    /// Unlike `with_point` this doesn't copy any vectors, the copy shares the data sources of this cloud and maps the
+    /// file. The maps of names and addresses are copied though, so it's still `O(n)` in the size of the cloud.
+    ///
+    /// Returns a parsing error if the file isn't a whole number of points of this cloud's dimension, or if one of the
+    /// new names is already taken, and an `IoError` if the file can't be read.
+    pub fn with_memmap_file(&self, data_path: &Path) -> PointCloudResult<PointCloud<M>> {
+        let bytes = std::fs::metadata(data_path)?.len() as usize;
+        if bytes % (self.data_dim * mem::size_of::<M::Scalar>()) != 0 {
+            return Err(PointCloudError::ParsingError(ParsingError::RegularParsingError(
+                "The data file isn't a whole number of points",
+            )));
+        }
+        let new_data = DataMemmap::new(self.data_dim, data_path)?;
+        let mut names = self.names.clone();
+        let mut addresses = self.addresses.clone();
+        let first = self.addresses.keys().max().map(|pi| pi + 1).unwrap_or(0);
+        for j in 0..new_data.len() {
+            let pi = first + j as PointIndex;
+            let name = format!("{}", pi).into_bytes();
+            if names.get_index(&name).is_some() {
+                return Err(PointCloudError::ParsingError(ParsingError::RegularParsingError(
+                    "A name is used twice",
+                )));
+            }
+            names.insert(pi, name);
+            addresses.insert(pi, (self.data_sources.len(), j));
+        }
+        let mut new_labels = self.labels_scheme.empty();
+        let blank = self.labels_scheme.blank_metadata();
+        for _j in 0..new_data.len() {
+            new_labels.push(None, blank.clone())?;
+        }
+        let mut data_sources = self.data_sources.clone();
+        data_sources.push(Arc::new(new_data));
+        let mut label_sources = self.label_sources.clone();
+        label_sources.push(Arc::new(new_labels));
+        Ok(PointCloud {
+            data_sources,
+            label_sources,
+            names,
+            addresses,
+            data_dim: self.data_dim,
+            loaded_centers: Mutex::new(IndexMap::new()),
+            labels_scheme: self.labels_scheme.clone(),
+            chunk: self.chunk,
+            metric: PhantomData,
+        })
+    }
+
     /// The names of the data are currently a shallow wrapper around a usize.
     pub fn reference_indexes(&self) -> Vec<PointIndex> {
         self.addresses.keys().cloned().collect()
     }
 
+    /// The same indexes as `reference_indexes`, in the same order, without collecting them into a `Vec`.
+    pub fn iter_reference_indexes(&self) -> impl DoubleEndedIterator<Item = PointIndex> + ExactSizeIterator + '_ {
+        self.addresses.keys().cloned()
+    }
+
     /// Returns a arc that points to a AVX2 packed point. This also acts like a cache for these center
     /// points to ensure that we don't load multiple copies into memory. Used for heavily
     /// referenced points, like centers.
-    pub fn get_center(&self, pn: PointIndex) -> PointCloudResult<Arc<Vec<f32>>> {
+    pub fn get_center(&self, pn: PointIndex) -> PointCloudResult<Arc<Vec<M::Scalar>>> {
         let mut loaded_centers = self.loaded_centers.lock().unwrap();
         Ok(Arc::clone(
             loaded_centers
@@ -303,24 +589,102 @@ impl<M: Metric> PointCloud<M> {
 
     /// Returns a slice corresponding to the point in question. Used for rarely referenced points,
     /// like outliers or leaves.
-    pub fn get_point(&self, pn: PointIndex) -> PointCloudResult<&[f32]> {
+    pub fn get_point(&self, pn: PointIndex) -> PointCloudResult<Cow<'_, [M::Scalar]>> {
         let (i,j) = self.get_address(pn)?;
         self.data_sources[i].get(j)
     }
 
-    /// Gets the name from an index
-    pub fn get_name(&self, pi: &PointIndex) -> Option<&PointName> {
-        self.indexes_to_names.get(pi)
+    /// Gets the name from an index. It's borrowed as bytes, as interned names aren't vectors of their own, use
+    /// `to_vec` for a `PointName`. This is a breaking change, it used to return an `Option<&PointName>`.
+    pub fn get_name(&self, pi: &PointIndex) -> Option<&[u8]> {
+        self.names.get_name(pi)
+    }
+
+    /// Gets the index from the name, this takes textual names as well as `PointName`s
+    pub fn get_index<N: AsRef<[u8]>>(&self, pn: N) -> Option<&PointIndex> {
+        self.names.get_index(pn.as_ref())
+    }
+
+    /// How the names of the points are kept, see `NameStorage`.
+    pub fn name_storage(&self) -> NameStorage {
+        self.names.storage()
+    }
+
+    /// Changes how the names of the points are kept, see `NameStorage`. Interning copies every name into the pool, so
+    /// while it runs both copies of the names are in memory. It's a no-op if the names
+    /// are already kept that way. Copies of the cloud, like `with_point`, keep the names the same way.
+    pub fn set_name_storage(&mut self, storage: NameStorage) {
+        let names = mem::replace(&mut self.names, NameTable::new());
+        self.names = names.into_storage(storage);
     }
 
-    /// Gets the index from the name
-    pub fn get_index(&self, pn: &PointName) -> Option<&PointIndex> {
-        self.names_to_indexes.get(pn)
+    /// Replaces the names of the points. Every point in the cloud has to get exactly one name and no name can be used
+    /// twice, otherwise this is a parsing error and the names are left as they were.
+    pub fn set_names(&mut self, names: Vec<(PointIndex, PointName)>) -> PointCloudResult<()> {
+        let mut table = NameTable::new();
+        for (pi, name) in names {
+            if !self.addresses.contains_key(&pi) {
+                return Err(PointCloudError::ParsingError(ParsingError::RegularParsingError(
+                    "A name is for an index that is not in the point cloud",
+                )));
+            }
+            if table.get_index(&name).is_some() || table.get_name(&pi).is_some() {
+                return Err(PointCloudError::ParsingError(ParsingError::RegularParsingError(
+                    "A name or an index is used twice",
+                )));
+            }
+            table.insert(pi, name);
+        }
+        if table.len() != self.addresses.len() {
+            return Err(PointCloudError::ParsingError(ParsingError::RegularParsingError(
+                "Not every point in the point cloud has a name",
+            )));
+        }
+        self.names = table.into_storage(self.names.storage());
+        Ok(())
+    }
+
+    /// A copy of this point cloud with the names replaced like `set_names` does, for a cloud that's shared and can't
+    /// be changed in place. The copy shares the data and label sources, only the map of addresses is copied. Returns
+    /// the errors of `set_names`.
+    pub fn with_names(&self, names: Vec<(PointIndex, PointName)>) -> PointCloudResult<PointCloud<M>> {
+        let mut point_cloud = PointCloud {
+            data_sources: self.data_sources.clone(),
+            label_sources: self.label_sources.clone(),
+            names: NameTable::new().into_storage(self.names.storage()),
+            addresses: self.addresses.clone(),
+            data_dim: self.data_dim,
+            loaded_centers: Mutex::new(IndexMap::new()),
+            labels_scheme: self.labels_scheme.clone(),
+            chunk: self.chunk,
+            metric: PhantomData,
+        };
+        point_cloud.set_names(names)?;
+        Ok(point_cloud)
     }
 
     /// Gets all names in the point cloud
     pub fn get_names(&self) -> Vec<PointName> {
-        self.names_to_indexes.keys().cloned().collect()
+        self.names.names()
+    }
+
+    /// An estimate of the bytes this point cloud holds on the heap. It counts the capacity of the index and name maps,
+    /// the names, the vectors of data sources held in ram, and the cached centers. The names are only kept in the name
+    /// maps, the metadata lists hand theirs over when the cloud is made. Memmapped data isn't counted, the OS pages it in
+    /// and out, and neither are the metadata values. The map
+    /// entries are counted as their keys and values plus two words, close to but not exactly what `IndexMap` uses.
+    pub fn memory_footprint(&self) -> usize {
+        let mut bytes = index_map_bytes(&self.addresses) + self.names.heap_bytes();
+        bytes += self.data_sources.capacity() * mem::size_of::<Arc<dyn DataSource<M::Scalar>>>();
+        bytes += self.data_sources.iter().map(|source| source.heap_bytes()).sum::<usize>();
+        let loaded_centers = self.loaded_centers.lock().unwrap();
+        bytes += index_map_bytes(&*loaded_centers);
+        bytes += loaded_centers
+            .values()
+            .map(|center| center.capacity() * mem::size_of::<M::Scalar>())
+            .sum::<usize>();
+        bytes += self.label_sources.capacity() * mem::size_of::<Arc<MetadataList>>();
+        bytes
     }
 
     /// Gets a schema to use
@@ -328,6 +692,11 @@ impl<M: Metric> PointCloud<M> {
         self.labels_scheme.schema_json()
     }
 
+    /// The scheme of the labels, use `LabelScheme::empty` on this to start a metadata list for a cloud with the same labels
+    pub fn label_scheme(&self) -> &LabelScheme {
+        &self.labels_scheme
+    }
+
     /// Returns the label of a point.
     ///
     /// This will be changed to return a label structure that can contain many different pieces of info.
@@ -356,8 +725,8 @@ impl<M: Metric> PointCloud<M> {
         &self,
         is: &[PointIndex],
         js: &[PointIndex],
-    ) -> PointCloudResult<Vec<f32>> {
-        let mut dists: Vec<f32> = vec![0.0;is.len()*js.len()];
+    ) -> PointCloudResult<Vec<M::Scalar>> {
+        let mut dists: Vec<M::Scalar> = vec![M::Scalar::zero();is.len()*js.len()];
         if is.len()*js.len() > self.chunk  {
             let dist_iter = dists.par_chunks_mut(js.len());
             let indexes_iter = is.par_iter().map(|i| (i,js));
@@ -367,7 +736,7 @@ impl<M: Metric> PointCloud<M> {
                     Ok(x) => {
                         for (d,j) in chunk_dists.iter_mut().zip(chunk_indexes) {
                             match self.get_point(*j) {
-                                Ok(y) => *d = (M::dense)(x, y),
+                                Ok(y) => *d = (M::dense)(&x, &y),
                                 Err(e) => {
                                     *error.lock().unwrap() = Err(e);
                                 }
@@ -386,7 +755,7 @@ impl<M: Metric> PointCloud<M> {
                 let x = self.get_point(*i)?;
                 for (l,j) in js.iter().enumerate() {
                     let y = self.get_point(*j)?;
-                    dists[k*js.len() + l] = (M::dense)(x, y);
+                    dists[k*js.len() + l] = (M::dense)(&x, &y);
                 }
             }
         }
@@ -398,14 +767,14 @@ impl<M: Metric> PointCloud<M> {
         &self,
         i: PointIndex,
         indexes: &[PointIndex],
-    ) -> PointCloudResult<Vec<f32>> {
-        self.distances_to_point(self.get_point(i)?,indexes)
+    ) -> PointCloudResult<Vec<M::Scalar>> {
+        self.distances_to_point(&self.get_point(i)?,indexes)
     }
 
     /// Create and adjacency matrix
     pub fn adj(&self,
         mut indexes: &[PointIndex],
-    ) -> PointCloudResult<AdjMatrix> {
+    ) -> PointCloudResult<AdjMatrix<M::Scalar>> {
         let mut vals = HashMap::new();
         while indexes.len() > 1 {
             let i = indexes[0];
@@ -425,35 +794,77 @@ impl<M: Metric> PointCloud<M> {
     /// The main distance function. This paralizes if there are more than 100 points.
     pub fn distances_to_point(
         &self,
-        x: &[f32],
+        x: &[M::Scalar],
+        indexes: &[PointIndex],
+    ) -> PointCloudResult<Vec<M::Scalar>> {
+        let mut dists = Vec::new();
+        self.map_to_point(x, indexes, M::dense, &mut dists)?;
+        Ok(dists)
+    }
+
+    /// `distances_to_point`, written into `dists` so a query can reuse the buffer. It's cleared first.
+    pub fn distances_to_point_into(
+        &self,
+        x: &[M::Scalar],
+        indexes: &[PointIndex],
+        dists: &mut Vec<M::Scalar>,
+    ) -> PointCloudResult<()> {
+        self.map_to_point(x, indexes, M::dense, dists)
+    }
+
+    /// The `Metric::dense_rank` of each point against `x`, the squared distances for `L2`. They order the points like
+    /// `distances_to_point` and are cheaper, use `Metric::rank_to_distance` on the ones you keep.
+    pub fn ranks_to_point(
+        &self,
+        x: &[M::Scalar],
+        indexes: &[PointIndex],
+    ) -> PointCloudResult<Vec<M::Scalar>> {
+        let mut ranks = Vec::new();
+        self.map_to_point(x, indexes, M::dense_rank, &mut ranks)?;
+        Ok(ranks)
+    }
+
+    /// `ranks_to_point`, written into `ranks` so a query can reuse the buffer. It's cleared first.
+    pub fn ranks_to_point_into(
+        &self,
+        x: &[M::Scalar],
         indexes: &[PointIndex],
-    ) -> PointCloudResult<Vec<f32>> {
+        ranks: &mut Vec<M::Scalar>,
+    ) -> PointCloudResult<()> {
+        self.map_to_point(x, indexes, M::dense_rank, ranks)
+    }
+
+    fn map_to_point(
+        &self,
+        x: &[M::Scalar],
+        indexes: &[PointIndex],
+        dense: fn(&[M::Scalar], &[M::Scalar]) -> M::Scalar,
+        dists: &mut Vec<M::Scalar>,
+    ) -> PointCloudResult<()> {
         let len = indexes.len();
+        dists.clear();
         if len > self.chunk * 3 {
-            let mut dists: Vec<f32> = vec![0.0;len];
+            dists.resize(len, M::Scalar::zero());
             let dist_iter = dists.par_chunks_mut(self.chunk);
             let indexes_iter = indexes.par_chunks(self.chunk);
             let error: Mutex<Result<(), PointCloudError>> = Mutex::new(Ok(()));
             dist_iter.zip(indexes_iter).for_each(|(chunk_dists,chunk_indexes)| {
                 for (d,i) in chunk_dists.iter_mut().zip(chunk_indexes) {
                     match self.get_point(*i) {
-                        Ok(y) => *d = (M::dense)(x, y),
+                        Ok(y) => *d = dense(x, &y),
                         Err(e) => {
                             *error.lock().unwrap() = Err(e);
                         }
                     }
                 }
             });
-            (error.into_inner().unwrap())?;
-            Ok(dists)
+            error.into_inner().unwrap()
         } else {
-            indexes
-                .iter()
-                .map(|i| {
-                    let y = self.get_point(*i)?;
-                    Ok((M::dense)(x, y))
-                })
-                .collect()
+            for i in indexes {
+                let y = self.get_point(*i)?;
+                dists.push(dense(x, &y));
+            }
+            Ok(())
         }
     }
 }
@@ -480,7 +891,8 @@ fn build_label_schema_yaml(label_scheme: &mut LabelScheme, schema_yaml: &Yaml) {
     }
 }
 
-fn get_file_list(files_reg: &str) -> Vec<PathBuf> {
+/// The files matching the glob in the yaml `field`.
+fn get_file_list(files_reg: &str, field: &str) -> PointCloudResult<Vec<PathBuf>> {
     let options = MatchOptions {
         case_sensitive: false,
         ..Default::default()
@@ -488,16 +900,21 @@ fn get_file_list(files_reg: &str) -> Vec<PathBuf> {
     let mut paths = Vec::new();
     let glob_paths = match glob_with(files_reg, &options) {
         Ok(expr) => expr,
-        Err(e) => panic!("Pattern reading error {:?}", e),
+        Err(_) => {
+            return Err(PointCloudError::ParsingError(ParsingError::MalformedYamlError {
+                file_name: String::new(),
+                field: field.to_string(),
+            }))
+        }
     };
     for entry in glob_paths {
         let path = match entry {
             Ok(expr) => expr,
-            Err(e) => panic!("Error reading path {:?}", e),
+            Err(e) => return Err(PointCloudError::IoError(io::Error::new(e.error().kind(), e.to_string()))),
         };
         paths.push(path)
     }
-    paths
+    Ok(paths)
 }
 
 /*
@@ -517,3 +934,91 @@ mod tests {
     }
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaced_points_share_the_sources_until_compacted() {
+        let data: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        let mut point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 2, Box::from(vec![0.0; 4]), 1).unwrap();
+        let metadata = format!("{:?}", point_cloud.get_metadata(1).unwrap());
+        for round in 0..10 {
+            let point = [round as f32, -1.0];
+            point_cloud = point_cloud.with_replaced_point(1, &point).unwrap();
+            assert_eq!(point_cloud.len(), 4);
+            assert_eq!(point_cloud.get_point(1).unwrap(), &point[..]);
+            assert_eq!(point_cloud.get_point(2).unwrap(), &[4.0, 5.0][..]);
+            assert_eq!(format!("{:?}", point_cloud.get_metadata(1).unwrap()), metadata);
+            // The replaced vectors are dropped once they outnumber the points
+            let rows: usize = point_cloud.data_sources.iter().map(|source| source.len()).sum();
+            assert!(rows <= 2 * point_cloud.len());
+        }
+        assert!(point_cloud.data_sources.len() < 6);
+    }
+
+    #[test]
+    fn added_points_share_one_source() {
+        let data: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        let point_cloud = PointCloud::<L2>::simple_from_ram(Box::from(data), 2, Box::from(vec![0.0; 4]), 1).unwrap();
+        let blank = point_cloud.label_scheme().blank_metadata();
+        let points = [[10.0, 11.0], [12.0, 13.0], [14.0, 15.0]];
+        let new_points = (0..3).map(|i| {
+            let name = format!("new{}", i).into_bytes();
+            (4 + i as PointIndex, name, &points[i][..], blank.clone())
+        });
+        let added = point_cloud.with_points(new_points).unwrap();
+        assert_eq!(added.len(), 7);
+        assert_eq!(added.data_sources.len(), point_cloud.data_sources.len() + 1);
+        for i in 0..3 {
+            let pi = *added.get_index(format!("new{}", i).as_bytes()).unwrap();
+            assert_eq!(pi, 4 + i as PointIndex);
+            assert_eq!(added.get_point(pi).unwrap(), &points[i][..]);
+        }
+        assert_eq!(added.get_point(2).unwrap(), &[4.0, 5.0][..]);
+
+        let twice = vec![
+            (4, b"twice".to_vec(), &points[0][..], blank.clone()),
+            (5, b"twice".to_vec(), &points[1][..], blank),
+        ];
+        assert!(point_cloud.with_points(twice).is_err());
+    }
+
+    #[test]
+    fn quantized_clouds_measure_the_dequantized_values() {
+        let codes: Vec<i8> = vec![0, 0, 3, 4, -127, 127, 10, -10];
+        let labels = MetadataList::simple_vec(Box::from(vec![0.0; 4]), 1);
+        let point_cloud = PointCloud::<L2>::from_quantized(Box::from(codes), 0.5, 2, labels).unwrap();
+        assert_eq!(point_cloud.get_point(1).unwrap(), &[1.5, 2.0][..]);
+        assert_eq!(point_cloud.get_point(2).unwrap(), &[-63.5, 63.5][..]);
+        assert_eq!(point_cloud.distances_to_point_index(0, &[1, 3]).unwrap(), vec![2.5, 50.0f32.sqrt()]);
+
+        let data: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        let in_ram = PointCloud::<L2>::simple_from_ram(Box::from(data.clone()), 2, Box::from(vec![0.0; 4]), 1).unwrap();
+        assert!(point_cloud.memory_footprint() < in_ram.memory_footprint());
+
+        let quantized = DataQuantized::quantize(2, &data).unwrap();
+        assert_eq!(quantized.scale(), 7.0 / 127.0);
+        for i in 0..4 {
+            let point = quantized.get(i).unwrap();
+            for (x, y) in point.iter().zip(&data[2 * i..2 * i + 2]) {
+                assert!((x - y).abs() <= quantized.scale() / 2.0);
+            }
+        }
+    }
+
+    #[test]
+    fn f64_clouds_keep_their_precision() {
+        let data: Vec<f64> = vec![1.0e9, 0.0, 1.0e9 + 1.0, 0.0, 0.0, 1.0];
+        let point_cloud = PointCloud::<L2<f64>>::simple_from_ram(Box::from(data), 2, Box::from(vec![0.0; 3]), 1).unwrap();
+        assert_eq!(point_cloud.get_point(1).unwrap(), &[1.0e9 + 1.0, 0.0][..]);
+        let dists = point_cloud.distances_to_point_index(0, &[1, 2]).unwrap();
+        assert_eq!(dists[0], 1.0);
+        assert_approx_eq!(dists[1], (1.0e18f64 + 1.0).sqrt());
+        let blank = point_cloud.label_scheme().blank_metadata();
+        let added = point_cloud.with_point(3, b"new".to_vec(), &[2.0e9, 0.0], blank).unwrap();
+        assert_eq!(added.distances_to_point(&[2.0e9 + 1.0, 0.0], &[3]).unwrap(), vec![1.0]);
+    }
+}
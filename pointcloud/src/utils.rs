@@ -28,14 +28,14 @@ use std::collections::HashMap;
 ///
 /// It assumes your data is symmetric and only stores the upper triangular matrix
 #[derive(Debug)]
-pub struct AdjMatrix {
-    pub(crate) vals: HashMap<(PointIndex, PointIndex), f32>,
+pub struct AdjMatrix<T: Scalar = f32> {
+    pub(crate) vals: HashMap<(PointIndex, PointIndex), T>,
 }
 
-impl AdjMatrix {
+impl<T: Scalar> AdjMatrix<T> {
     /// This gets by passing the smaller of the two indexes as the first element of
     /// the pair and the larger as the second.
-    pub fn get(&self, i: PointIndex, j: PointIndex) -> Option<&f32> {
+    pub fn get(&self, i: PointIndex, j: PointIndex) -> Option<&T> {
         if i < j {
             self.vals.get(&(i, j))
         } else {
@@ -44,8 +44,8 @@ impl AdjMatrix {
     }
 
     /// Iterates over all distances and gets the minimum.
-    pub fn min(&self) -> f32 {
-        self.vals.iter().fold(1.0/0.0,|a,(_k,v)| {
+    pub fn min(&self) -> T {
+        self.vals.iter().fold(T::infinity(),|a,(_k,v)| {
             if v < &a {
                 *v
             } else {